@@ -0,0 +1,3 @@
+DROP INDEX comms_status_idx;
+ALTER TABLE comms DROP COLUMN status;
+DROP TYPE comm_status;
@@ -0,0 +1,7 @@
+CREATE TYPE comm_status AS ENUM ('pending', 'approved', 'spam', 'trash');
+
+ALTER TABLE comms ADD COLUMN status comm_status NOT NULL DEFAULT 'pending';
+-- comms written before moderation existed were already live
+UPDATE comms SET status = 'approved';
+
+CREATE INDEX comms_status_idx ON comms(status);
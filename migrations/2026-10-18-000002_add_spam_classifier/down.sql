@@ -0,0 +1,4 @@
+ALTER TABLE comms DROP COLUMN spam_trained;
+ALTER TABLE comms DROP COLUMN spam_score;
+DROP TABLE spam_corpus;
+DROP TABLE spam_tokens;
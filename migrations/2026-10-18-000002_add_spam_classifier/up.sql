@@ -0,0 +1,22 @@
+CREATE TABLE spam_tokens(
+   token VARCHAR(64) PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   spam_count BIGINT NOT NULL DEFAULT 0,
+   ham_count BIGINT NOT NULL DEFAULT 0
+);
+
+-- a single row holding how many comms each class was trained on
+CREATE TABLE spam_corpus(
+   id SMALLINT PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   spam_docs BIGINT NOT NULL DEFAULT 0,
+   ham_docs BIGINT NOT NULL DEFAULT 0
+);
+
+INSERT INTO spam_corpus (id) VALUES (1);
+
+ALTER TABLE comms ADD COLUMN spam_score DOUBLE PRECISION;
+-- NULL while untrained, otherwise whether the comm was learned as spam
+ALTER TABLE comms ADD COLUMN spam_trained BOOLEAN;
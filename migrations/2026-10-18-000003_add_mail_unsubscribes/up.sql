@@ -0,0 +1,6 @@
+-- recipients who followed the unsubscribe link of a notification mail
+CREATE TABLE mail_unsubscribes(
+   email VARCHAR(256) PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,3 @@
+DROP INDEX notify_outbox_due_idx;
+DROP TABLE notify_outbox;
+DROP TYPE outbox_status;
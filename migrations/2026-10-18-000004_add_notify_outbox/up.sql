@@ -0,0 +1,17 @@
+CREATE TYPE outbox_status AS ENUM ('pending', 'delivered', 'dead');
+
+-- one row per message and channel, written together with whatever caused it
+CREATE TABLE notify_outbox(
+   id UUID PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   channel VARCHAR(64) NOT NULL,
+   payload JSONB NOT NULL,
+   status outbox_status NOT NULL DEFAULT 'pending',
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   last_error TEXT,
+   delivered_at TIMESTAMP
+);
+
+CREATE INDEX notify_outbox_due_idx ON notify_outbox(status, next_attempt_at);
@@ -0,0 +1,12 @@
+-- admin edits of the notification templates, one row per event. Parts left
+-- NULL fall back to the template directory or the built-in default.
+CREATE TABLE notify_templates(
+   event VARCHAR(64) PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   subject TEXT,
+   html TEXT,
+   text TEXT,
+   telegram TEXT,
+   webhook TEXT
+);
@@ -0,0 +1,2 @@
+ALTER TABLE notes DROP COLUMN published_at;
+DROP TABLE subscribers;
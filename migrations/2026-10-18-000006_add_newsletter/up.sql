@@ -0,0 +1,13 @@
+-- readers who asked for a mail whenever a note goes out, confirmed once they
+-- followed the link of the confirmation mail
+CREATE TABLE subscribers(
+   id UUID PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   email VARCHAR(256) NOT NULL UNIQUE,
+   confirmed_at TIMESTAMP
+);
+
+-- first time the note was public, notes that already are count as announced
+ALTER TABLE notes ADD COLUMN published_at TIMESTAMP;
+UPDATE notes SET published_at = created_at WHERE status = 'public';
@@ -0,0 +1,6 @@
+-- digest periods already mailed to the owner, by the time they ended
+CREATE TABLE notify_digests(
+   period_end TIMESTAMP PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
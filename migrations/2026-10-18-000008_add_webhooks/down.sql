@@ -0,0 +1,4 @@
+DROP INDEX webhook_deliveries_endpoint_idx;
+DROP INDEX webhook_deliveries_due_idx;
+DROP TABLE webhook_deliveries;
+DROP TABLE webhook_endpoints;
@@ -0,0 +1,30 @@
+-- receivers of content events, subscribed to `events` or to all of them when
+-- it is empty
+CREATE TABLE webhook_endpoints(
+   id UUID PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   url VARCHAR(2048) NOT NULL,
+   secret VARCHAR(256) NOT NULL,
+   events TEXT[] NOT NULL DEFAULT '{}',
+   active BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+-- one row per event and endpoint, written together with the change itself
+CREATE TABLE webhook_deliveries(
+   id UUID PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   endpoint_id UUID NOT NULL REFERENCES webhook_endpoints(id) ON DELETE CASCADE,
+   event VARCHAR(64) NOT NULL,
+   payload JSONB NOT NULL,
+   status outbox_status NOT NULL DEFAULT 'pending',
+   attempts INTEGER NOT NULL DEFAULT 0,
+   next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   response_status INTEGER,
+   last_error TEXT,
+   delivered_at TIMESTAMP
+);
+
+CREATE INDEX webhook_deliveries_due_idx ON webhook_deliveries(status, next_attempt_at);
+CREATE INDEX webhook_deliveries_endpoint_idx ON webhook_deliveries(endpoint_id, created_at);
@@ -0,0 +1,3 @@
+DROP INDEX comms_approved_seq_idx;
+ALTER TABLE comms DROP COLUMN approved_seq;
+DROP SEQUENCE comms_approved_seq;
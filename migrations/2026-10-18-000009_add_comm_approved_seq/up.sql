@@ -0,0 +1,13 @@
+-- order in which comms went live, for readers following a comment stream
+CREATE SEQUENCE comms_approved_seq;
+ALTER TABLE comms ADD COLUMN approved_seq BIGINT NULL;
+
+WITH approved AS (
+    SELECT id, row_number() OVER (ORDER BY created_at) AS seq
+    FROM comms
+    WHERE status = 'approved'
+)
+UPDATE comms SET approved_seq = approved.seq FROM approved WHERE comms.id = approved.id;
+SELECT setval('comms_approved_seq', COALESCE((SELECT MAX(approved_seq) FROM comms), 0) + 1, false);
+
+CREATE UNIQUE INDEX comms_approved_seq_idx ON comms(approved_seq);
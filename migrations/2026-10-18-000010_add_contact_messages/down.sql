@@ -0,0 +1,2 @@
+DROP TABLE contact_messages;
+DROP TYPE message_status;
@@ -0,0 +1,15 @@
+CREATE TYPE message_status AS ENUM ('unread', 'read', 'archived');
+
+CREATE TABLE contact_messages (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    name VARCHAR(64) NOT NULL,
+    email VARCHAR(256) NOT NULL,
+    body TEXT NOT NULL,
+    status message_status NOT NULL DEFAULT 'unread',
+    reply TEXT NULL,
+    replied_at TIMESTAMP NULL
+);
+
+CREATE INDEX contact_messages_status_idx ON contact_messages(status, created_at);
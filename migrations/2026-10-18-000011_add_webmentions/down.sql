@@ -0,0 +1,3 @@
+DROP TABLE webmention_sends;
+DROP TABLE webmentions;
+DROP TYPE mention_status;
@@ -0,0 +1,45 @@
+CREATE TYPE mention_status AS ENUM ('pending', 'verified', 'rejected');
+
+-- mentions of our notes and pages by other sites, shown next to the comms
+-- once the source is found to link here
+CREATE TABLE webmentions (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    source VARCHAR(2048) NOT NULL,
+    target VARCHAR(2048) NOT NULL,
+    note_id UUID REFERENCES notes(id) ON DELETE CASCADE,
+    page_id UUID REFERENCES pages(id) ON DELETE CASCADE,
+    status mention_status NOT NULL DEFAULT 'pending',
+    author_name VARCHAR(256),
+    author_url VARCHAR(2048),
+    title VARCHAR(512),
+    content TEXT,
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT,
+    verified_at TIMESTAMP,
+    UNIQUE (source, target)
+);
+
+CREATE INDEX webmentions_due_idx ON webmentions(status, next_attempt_at);
+CREATE INDEX webmentions_note_id_idx ON webmentions(note_id);
+CREATE INDEX webmentions_page_id_idx ON webmentions(page_id);
+
+-- mentions we owe the pages our notes link to
+CREATE TABLE webmention_sends (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    note_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+    target VARCHAR(2048) NOT NULL,
+    status outbox_status NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    endpoint VARCHAR(2048),
+    response_status INT,
+    last_error TEXT,
+    UNIQUE (note_id, target)
+);
+
+CREATE INDEX webmention_sends_due_idx ON webmention_sends(status, next_attempt_at);
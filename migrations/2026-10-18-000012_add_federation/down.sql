@@ -0,0 +1,5 @@
+DROP TABLE ap_deliveries;
+DROP TABLE ap_replies;
+DROP TABLE ap_followers;
+DROP TABLE ap_actors;
+DROP TABLE federation_keys;
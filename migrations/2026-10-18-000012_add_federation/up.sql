@@ -0,0 +1,58 @@
+-- the key the blog's actor signs its requests with, a single row
+CREATE TABLE federation_keys (
+    id SMALLINT PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    private_key TEXT NOT NULL,
+    public_key TEXT NOT NULL
+);
+
+-- Fediverse actors we have heard from, along with the keys their requests
+-- are checked against
+CREATE TABLE ap_actors (
+    id VARCHAR(2048) PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    key_id VARCHAR(2048) NOT NULL UNIQUE,
+    public_key TEXT NOT NULL,
+    inbox VARCHAR(2048) NOT NULL,
+    shared_inbox VARCHAR(2048),
+    username VARCHAR(256),
+    name VARCHAR(256),
+    url VARCHAR(2048)
+);
+
+CREATE TABLE ap_followers (
+    actor_id VARCHAR(2048) PRIMARY KEY REFERENCES ap_actors(id) ON DELETE CASCADE,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    follow_id VARCHAR(2048) NOT NULL
+);
+
+-- replies from the Fediverse and the comms they were stored as
+CREATE TABLE ap_replies (
+    object_id VARCHAR(2048) PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    comm_id UUID NOT NULL REFERENCES comms(id) ON DELETE CASCADE,
+    actor_id VARCHAR(2048) NOT NULL
+);
+
+CREATE INDEX ap_replies_comm_id_idx ON ap_replies(comm_id);
+
+-- one row per activity and inbox
+CREATE TABLE ap_deliveries (
+    id UUID PRIMARY KEY,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    inbox VARCHAR(2048) NOT NULL,
+    activity JSONB NOT NULL,
+    status outbox_status NOT NULL DEFAULT 'pending',
+    attempts INT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    response_status INT,
+    last_error TEXT,
+    delivered_at TIMESTAMP
+);
+
+CREATE INDEX ap_deliveries_due_idx ON ap_deliveries(status, next_attempt_at);
@@ -0,0 +1,12 @@
+-- bearer tokens of publishing clients such as Micropub apps, only a hash of
+-- the token is kept
+CREATE TABLE publish_tokens(
+   id UUID PRIMARY KEY,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   name VARCHAR(256) NOT NULL,
+   token_hash VARCHAR(64) NOT NULL UNIQUE,
+   scopes TEXT[] NOT NULL DEFAULT '{}',
+   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+   last_used_at TIMESTAMP
+);
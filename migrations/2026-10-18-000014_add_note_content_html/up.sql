@@ -0,0 +1,3 @@
+-- content rendered to sanitized HTML whenever a note is saved, notes saved
+-- before are rendered when read
+ALTER TABLE notes ADD COLUMN content_html TEXT;
@@ -0,0 +1,12 @@
+-- every saved version of a note's title, summary and content, the newest one
+-- being the current, along with who saved it
+CREATE TABLE note_revisions(
+   id UUID PRIMARY KEY,
+   note_id UUID NOT NULL REFERENCES notes(id) ON DELETE CASCADE,
+   user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+   created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+   title VARCHAR(256) NOT NULL,
+   summary TEXT NOT NULL,
+   content TEXT NOT NULL
+);
+CREATE INDEX note_revisions_note_id_created_at ON note_revisions(note_id, created_at);
@@ -0,0 +1,4 @@
+DROP INDEX notes_unpublish_at_idx;
+DROP INDEX notes_publish_at_idx;
+ALTER TABLE notes DROP COLUMN unpublish_at;
+ALTER TABLE notes DROP COLUMN publish_at;
@@ -0,0 +1,6 @@
+-- when a draft goes public and when a public note goes back to being a draft,
+-- both cleared once done
+ALTER TABLE notes ADD COLUMN publish_at TIMESTAMP;
+ALTER TABLE notes ADD COLUMN unpublish_at TIMESTAMP;
+CREATE INDEX notes_publish_at_idx ON notes(publish_at) WHERE publish_at IS NOT NULL;
+CREATE INDEX notes_unpublish_at_idx ON notes(unpublish_at) WHERE unpublish_at IS NOT NULL;
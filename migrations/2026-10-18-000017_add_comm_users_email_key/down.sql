@@ -0,0 +1 @@
+DROP INDEX comm_users_email_key;
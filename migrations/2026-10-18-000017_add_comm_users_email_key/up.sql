@@ -0,0 +1,15 @@
+-- commenters are one row per email, so two first comms sent at once cannot
+-- make two of them. Rows made so before are merged into the oldest one.
+WITH ranked AS (
+   SELECT id, FIRST_VALUE(id) OVER (PARTITION BY email ORDER BY created_at, id) AS keep_id
+   FROM comm_users
+)
+UPDATE comms SET comm_user_id = ranked.keep_id
+FROM ranked
+WHERE comms.comm_user_id = ranked.id AND ranked.id <> ranked.keep_id;
+
+DELETE FROM comm_users a
+USING comm_users b
+WHERE a.email = b.email AND (a.created_at, a.id) > (b.created_at, b.id);
+
+CREATE UNIQUE INDEX comm_users_email_key ON comm_users(email);
@@ -0,0 +1,2 @@
+-- what a note was before it went to the recycle bin, to restore it as that
+ALTER TABLE notes ADD COLUMN recycled_from publish_status;
@@ -0,0 +1,2 @@
+DROP INDEX comm_users_email_lower_key;
+CREATE UNIQUE INDEX comm_users_email_key ON comm_users(email);
@@ -0,0 +1,19 @@
+-- commenters are one row per email whatever its case, as notify and
+-- unsubscribe see them. Rows differing only in case are merged into the
+-- oldest one.
+WITH ranked AS (
+   SELECT id, FIRST_VALUE(id) OVER (PARTITION BY lower(email) ORDER BY created_at, id) AS keep_id
+   FROM comm_users
+)
+UPDATE comms SET comm_user_id = ranked.keep_id
+FROM ranked
+WHERE comms.comm_user_id = ranked.id AND ranked.id <> ranked.keep_id;
+
+DELETE FROM comm_users a
+USING comm_users b
+WHERE lower(a.email) = lower(b.email) AND (a.created_at, a.id) > (b.created_at, b.id);
+
+UPDATE comm_users SET email = lower(email) WHERE email <> lower(email);
+
+DROP INDEX comm_users_email_key;
+CREATE UNIQUE INDEX comm_users_email_lower_key ON comm_users(lower(email));
@@ -1,21 +1,66 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+};
+
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
     Json,
 };
-use chrono::{NaiveDateTime};
-use serde::{Deserialize};
+use chrono::NaiveDateTime;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::error;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 use uuid::Uuid;
 
-use crate::{error::BlogError, AppState};
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{
+            comm_users::CommUser,
+            comms::{Comm, CommAuthor, CommStatus},
+            comms_closure::CommsClosure,
+            notes::Status,
+            users::User,
+        },
+        Conn, DbPool,
+    },
+    error::BlogError,
+    service::{
+        antispam::{
+            bayes::{self, Features},
+            Challenge, Submission,
+        },
+        moderation::{self, ModerateAction},
+    },
+    utils::{jwt::Claims, ClientIp, SHUTDOWN},
+    AppState,
+};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct CreateComm {
     pub content: String,
-    pub comm_user_email: String,
-    pub note_id: Option<Uuid>,
-    pub page_id: Option<Uuid>,
+    #[schema(example = "nekonya")]
+    pub nickname: Option<String>,
+    #[schema(example = "neko@example.com")]
+    pub comm_user_email: Option<String>,
+    pub website_url: Option<String>,
+    pub parent_id: Option<Uuid>,
+    /// Solved proof-of-work challenge from `GET /comm/challenge`
+    pub challenge: Option<String>,
+    pub nonce: Option<String>,
+    /// Honeypot, hidden from humans and expected to stay empty
+    #[serde(default)]
+    pub homepage: Option<String>,
 }
 
+#[derive(Serialize, ToSchema)]
 pub struct ReturnComm {
     id: Uuid,
     created_at: NaiveDateTime,
@@ -23,47 +68,768 @@ pub struct ReturnComm {
     nickname: String,
     content: String,
     website_url: Option<String>,
-    note_id: Option<Uuid>,
-    page_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<CommStatus>,
+    replies: Vec<ReturnComm>,
 }
 
+#[derive(Deserialize, ToSchema)]
 pub struct UpdateComm {
     content: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListComms {
+    pub comms: Vec<ReturnComm>,
+    pub total: u64,
+    /// Where the comm stream picks up after these comms
+    pub last_event_id: Option<i64>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct StreamQuery {
+    /// Event ID to resume after, for clients that cannot send `Last-Event-ID`
+    last_event_id: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StreamComm {
+    #[serde(flatten)]
+    comm: ReturnComm,
+    parent_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct QueueQuery {
+    /// Moderation state to list, `pending` when omitted
+    status: Option<CommStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct QueueComm {
+    id: Uuid,
+    created_at: NaiveDateTime,
     nickname: String,
+    email: Option<String>,
     website_url: Option<String>,
+    content: String,
+    status: CommStatus,
+    /// Spam probability given by the classifier when the comm came in
+    spam_score: Option<f64>,
     note_id: Option<Uuid>,
     page_id: Option<Uuid>,
 }
 
-pub struct ListComms {
-    pub comms: Vec<ReturnComm>,
+#[derive(Serialize, ToSchema)]
+pub struct ListQueueComms {
+    pub comms: Vec<QueueComm>,
     pub total: u64,
 }
 
+#[derive(Deserialize, ToSchema)]
+pub struct ModerateComms {
+    ids: Vec<Uuid>,
+    action: ModerateAction,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        get_challenge,
+        create_comm,
+        get_comm,
+        stream_comms,
+        update_comm,
+        delete_comm,
+        list_comm_queue,
+        moderate_comms
+    ),
+    components(schemas(
+        Challenge,
+        CreateComm,
+        ReturnComm,
+        UpdateComm,
+        ListComms,
+        StreamComm,
+        QueueComm,
+        ListQueueComms,
+        ModerateAction,
+        ModerateComms,
+        CommStatus
+    ))
+)]
+pub struct CommDoc;
+
+/// Comms read at once while a stream catches up.
+const STREAM_BATCH: i64 = 50;
+
+#[utoipa::path(
+    get,
+    path = "/comm/challenge",
+    responses(
+        (status = 200, description = "Proof-of-work challenge issued", body = Challenge)
+    )
+)]
+pub async fn get_challenge(state: State<AppState>) -> Json<Challenge> {
+    Json(state.guard.issue_challenge())
+}
+
+#[utoipa::path(
+    post,
+    path = "/comms/{short_id}",
+    params(
+        ("short_id" = String, Path, description = "Note or page short_id")
+    ),
+    request_body = CreateComm,
+    responses(
+        (status = 200, description = "Comm created successfully"),
+        (status = 400, description = "Bad request"),
+        (status = 404, description = "Note or page not found"),
+        (status = 429, description = "Too many requests"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        (),
+        ("jwt_token" = [])
+    )
+)]
 pub async fn create_comm(
     state: State<AppState>,
+    claims: Option<Claims>,
+    ClientIp(ip): ClientIp,
+    Path(short_id): Path<String>,
     Json(new_comm): Json<CreateComm>,
 ) -> Result<String, BlogError> {
-    todo!()
+    // bots filling the honeypot get the usual answer, but nothing is stored
+    if claims.is_none() && new_comm.homepage.as_deref().is_some_and(|h| !h.is_empty()) {
+        return Ok(json!({
+            "ok": "create comm ok!",
+            "id": Uuid::new_v4(),
+            "status": CommStatus::Pending
+        })
+        .to_string());
+    }
+
+    let content = new_comm.content.trim();
+    if content.is_empty() {
+        return Err(BlogError::BadRequest(String::from("content is empty")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let target = Comm::find_target_by_short_id(&short_id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found note or page")))?;
+
+    if !target.comm || target.status != Status::Public {
+        return Err(BlogError::BadRequest(String::from("comments are closed")));
+    }
+
+    if let Some(parent_id) = &new_comm.parent_id {
+        let parent = Comm::find_comm_by_uuid(parent_id, &mut conn)
+            .await?
+            .ok_or(BlogError::BadRequest(String::from("not found parent comm")))?;
+        if parent.note_id.or(parent.page_id) != Some(target.id) {
+            return Err(BlogError::BadRequest(String::from(
+                "parent comm belongs to another note or page",
+            )));
+        }
+    }
+
+    let (author_id, blog_user, status, spam_score) = match &claims {
+        Some(claims) => (
+            Uuid::parse_str(&claims.user_id)?,
+            true,
+            CommStatus::Approved,
+            None,
+        ),
+        None => {
+            let nickname = new_comm
+                .nickname
+                .as_deref()
+                .map(str::trim)
+                .filter(|n| !n.is_empty() && n.len() <= 256)
+                .ok_or(BlogError::BadRequest(String::from("invalid nickname")))?;
+            let email = new_comm
+                .comm_user_email
+                .as_deref()
+                .map(str::trim)
+                .filter(|e| is_valid_email(e))
+                .ok_or(BlogError::BadRequest(String::from("invalid email")))?;
+            let website_url = new_comm
+                .website_url
+                .as_deref()
+                .map(str::trim)
+                .filter(|w| !w.is_empty());
+            if website_url.is_some_and(|w| w.len() > 256) {
+                return Err(BlogError::BadRequest(String::from("website url too long")));
+            }
+
+            state.guard.check(&Submission {
+                ip: &ip,
+                email,
+                content,
+                challenge: new_comm.challenge.as_deref(),
+                nonce: new_comm.nonce.as_deref(),
+            })?;
+
+            let spam_score = if CONFIG.antispam.enabled() {
+                let features = Features {
+                    nickname,
+                    email,
+                    website_url,
+                    content,
+                };
+                bayes::classify(&features, &mut conn).await?
+            } else {
+                None
+            };
+
+            // commenters the admin has approved before skip the queue, unless
+            // the classifier has doubts about this one
+            let status = match spam_score {
+                Some(score) if score >= CONFIG.antispam.spam_threshold() => CommStatus::Spam,
+                Some(score) if score >= CONFIG.antispam.ham_threshold() => CommStatus::Pending,
+                _ if CommUser::has_approved_comm(email, &mut conn).await? => CommStatus::Approved,
+                _ => CommStatus::Pending,
+            };
+
+            let comm_user_id =
+                CommUser::find_or_create(nickname, email, website_url, &mut conn).await?;
+            (comm_user_id, false, status, spam_score)
+        }
+    };
+
+    // the comm and the notifications about it are stored together
+    let notify = state.notify.clone();
+    let content = content.to_string();
+    let parent_id = new_comm.parent_id;
+    let comm_id = conn
+        .transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let author = if blog_user {
+                    CommAuthor::BlogUser(&author_id)
+                } else {
+                    CommAuthor::CommUser(&author_id)
+                };
+                let comm_id = Comm::create_comm(
+                    &content,
+                    author,
+                    &target.id,
+                    target.comm_type,
+                    parent_id.as_ref(),
+                    &status,
+                    spam_score,
+                    conn,
+                )
+                .await?;
+                notify.enqueue_comm(&comm_id, true, conn).await?;
+
+                Ok(comm_id)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    Ok(json!({ "ok": "create comm ok!", "id": comm_id, "status": status }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/comms/{short_id}",
+    params(
+        ("short_id" = String, Path, description = "Note or page short_id")
+    ),
+    responses(
+        (status = 200, description = "Comms retrieved successfully", body = ListComms),
+        (status = 404, description = "Note or page not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        (),
+        ("jwt_token" = [])
+    )
+)]
+pub async fn get_comm(
+    state: State<AppState>,
+    claims: Option<Claims>,
+    Path(short_id): Path<String>,
+) -> Result<Json<ListComms>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let target = Comm::find_target_by_short_id(&short_id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found note or page")))?;
+    if claims.is_none() && target.status != Status::Public {
+        return Err(BlogError::NotFound(String::from("not found note or page")));
+    }
+
+    // the admin also sees comms still waiting in the queue
+    let is_authenticated = claims.is_some();
+    let statuses: &[CommStatus] = if is_authenticated {
+        &[CommStatus::Approved, CommStatus::Pending]
+    } else {
+        &[CommStatus::Approved]
+    };
+    let comms = Comm::get_comms_by_short_id(&short_id, statuses, &mut conn).await?;
+
+    let total = comms.len() as u64;
+    let last_event_id = comms.iter().filter_map(|c| c.approved_seq).max();
+    let comm_ids: Vec<Uuid> = comms.iter().map(|c| c.id).collect();
+    let parents = CommsClosure::get_parents(&comm_ids, &mut conn).await?;
+    let comms = return_comms(comms, is_authenticated, &mut conn).await?;
+
+    Ok(Json(ListComms {
+        comms: build_comm_tree(comms, &parents),
+        total,
+        last_event_id,
+    }))
+}
+
+/// Shapes comms for readers, without their replies attached yet.
+async fn return_comms(
+    comms: Vec<Comm>,
+    is_authenticated: bool,
+    conn: &mut Conn,
+) -> Result<Vec<ReturnComm>, BlogError> {
+    let comm_user_ids: Vec<Uuid> = comms.iter().filter_map(|c| c.comm_user_id).collect();
+    let blog_user_ids: Vec<Uuid> = comms.iter().filter_map(|c| c.blog_user_id).collect();
+
+    let comm_users: HashMap<Uuid, CommUser> =
+        CommUser::find_comm_users_by_ids(&comm_user_ids, conn)
+            .await?
+            .into_iter()
+            .map(|u| (u.id, u))
+            .collect();
+    let blog_users: HashMap<Uuid, String> = User::get_nicknames_by_ids(&blog_user_ids, conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(comms
+        .into_iter()
+        .map(|comm| {
+            let (nickname, website_url) = match (comm.comm_user_id, comm.blog_user_id) {
+                (Some(id), _) => comm_users
+                    .get(&id)
+                    .map(|u| (u.nickname.clone(), u.website_url.clone()))
+                    .unwrap_or_default(),
+                (None, Some(id)) => (blog_users.get(&id).cloned().unwrap_or_default(), None),
+                (None, None) => Default::default(),
+            };
+
+            ReturnComm {
+                id: comm.id,
+                created_at: comm.created_at,
+                updated_at: comm.updated_at,
+                nickname,
+                content: comm.content,
+                website_url,
+                status: if is_authenticated {
+                    Some(comm.status)
+                } else {
+                    None
+                },
+                replies: Vec::new(),
+            }
+        })
+        .collect())
+}
+
+/// A reader following the comms of one note or page.
+struct Follow {
+    pool: DbPool,
+    type_id: Uuid,
+    /// `approved_seq` of the last comm sent
+    cursor: i64,
+    feed: broadcast::Receiver<Uuid>,
+    pending: VecDeque<Event>,
+    behind: bool,
+}
+
+impl Follow {
+    /// Queues comms approved since the cursor. Returns whether there may be
+    /// more than one batch held.
+    async fn catch_up(&mut self) -> Result<bool, BlogError> {
+        let mut conn = self.pool.get_owned().await?;
+        let comms =
+            Comm::get_approved_after(&self.type_id, self.cursor, STREAM_BATCH, &mut conn).await?;
+        let more = comms.len() as i64 == STREAM_BATCH;
+
+        let seqs: Vec<i64> = comms.iter().filter_map(|c| c.approved_seq).collect();
+        let comm_ids: Vec<Uuid> = comms.iter().map(|c| c.id).collect();
+        let parents = CommsClosure::get_parents(&comm_ids, &mut conn).await?;
+        let comms = return_comms(comms, false, &mut conn).await?;
+
+        for (seq, comm) in seqs.into_iter().zip(comms) {
+            let data = StreamComm {
+                parent_id: parents.get(&comm.id).copied(),
+                comm,
+            };
+            let event = Event::default()
+                .id(seq.to_string())
+                .event("comm")
+                .json_data(data)
+                .map_err(anyhow::Error::from)?;
+            self.pending.push_back(event);
+            self.cursor = seq;
+        }
+
+        Ok(more)
+    }
+}
+
+/// Sends every comm approved on the note or page from now on as a `comm`
+/// event with [`StreamComm`] as data and its place in the approval order as
+/// ID. Reconnecting with `Last-Event-ID`, or `last_event_id` from
+/// [`ListComms`], first replays what was missed. The stream ends on shutdown.
+#[utoipa::path(
+    get,
+    path = "/comms/{short_id}/stream",
+    params(
+        ("short_id" = String, Path, description = "Note or page short_id"),
+        StreamQuery
+    ),
+    responses(
+        (status = 200, description = "Server-sent events of approved comms", content_type = "text/event-stream"),
+        (status = 404, description = "Note or page not found"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn stream_comms(
+    state: State<AppState>,
+    headers: HeaderMap,
+    Path(short_id): Path<String>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let target = Comm::find_target_by_short_id(&short_id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found note or page")))?;
+    if target.status != Status::Public {
+        return Err(BlogError::NotFound(String::from("not found note or page")));
+    }
+
+    // subscribed before looking up the cursor, so nothing slips in between
+    let feed = state.comm_feed.subscribe();
+    let last_event_id = headers
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<i64>().ok())
+        .or(query.last_event_id);
+    let cursor = match last_event_id {
+        Some(cursor) => cursor,
+        None => Comm::max_approved_seq(&mut conn).await?,
+    };
+
+    let follow = Follow {
+        pool: state.pool.clone(),
+        type_id: target.id,
+        cursor,
+        feed,
+        pending: VecDeque::new(),
+        behind: last_event_id.is_some(),
+    };
+
+    let events = stream::unfold(follow, |mut follow| async move {
+        loop {
+            if let Some(event) = follow.pending.pop_front() {
+                return Some((Ok(event), follow));
+            }
+
+            if follow.behind {
+                match follow.catch_up().await {
+                    Ok(more) => follow.behind = more,
+                    Err(e) => {
+                        // the reader reconnects and picks up from its last event
+                        error!("comm stream of {} failed: {}", follow.type_id, e);
+                        return None;
+                    }
+                }
+                continue;
+            }
+
+            match follow.feed.recv().await {
+                Ok(type_id) => follow.behind = type_id == follow.type_id,
+                Err(RecvError::Lagged(_)) => follow.behind = true,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .take_until(SHUTDOWN.wait_for_shutdown());
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// The comm, if it exists and `user_id` may manage it.
+async fn find_managed_comm(
+    comm_id: &Uuid,
+    user_id: &Uuid,
+    conn: &mut AsyncPgConnection,
+) -> Result<Comm, BlogError> {
+    let comm = Comm::find_comm_by_uuid(comm_id, conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found comm")))?;
+    if !comm.is_managed_by(user_id, conn).await? {
+        return Err(BlogError::Forbidden(String::from(
+            "only the author or the owner of the page may change a comm",
+        )));
+    }
+
+    Ok(comm)
 }
 
+#[utoipa::path(
+    put,
+    path = "/comm/{comm_id}",
+    params(
+        ("comm_id" = Uuid, Path, description = "Comm id")
+    ),
+    request_body = UpdateComm,
+    responses(
+        (status = 200, description = "Comm update successfully"),
+        (status = 403, description = "Comm is not yours to edit"),
+        (status = 404, description = "Comm not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
 pub async fn update_comm(
     state: State<AppState>,
-    Path(comm_id): Path<String>,
+    claims: Claims,
+    Path(comm_id): Path<Uuid>,
+    Json(u_comm): Json<UpdateComm>,
 ) -> Result<String, BlogError> {
-    todo!()
+    let content = u_comm.content.trim();
+    if content.is_empty() {
+        return Err(BlogError::BadRequest(String::from("content is empty")));
+    }
+
+    let user_id = Uuid::parse_str(&claims.user_id)?;
+    let mut conn = state.pool.get_owned().await?;
+
+    find_managed_comm(&comm_id, &user_id, &mut conn).await?;
+    if !Comm::update_comm_by_uuid(&comm_id, content, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("not found comm")));
+    }
+
+    Ok(json!({ "ok": "update comm ok!"}).to_string())
 }
 
-pub async fn get_comm(
+#[utoipa::path(
+    delete,
+    path = "/comm/{comm_id}",
+    params(
+        ("comm_id" = Uuid, Path, description = "Comm id")
+    ),
+    responses(
+        (status = 200, description = "Comm and its replies deleted successfully"),
+        (status = 403, description = "Comm is not yours to delete"),
+        (status = 404, description = "Comm not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_comm(
     state: State<AppState>,
-    Path(id): Path<String>,
-) -> Result<Json<ListComms>, BlogError> {
-    todo!()
+    claims: Claims,
+    Path(comm_id): Path<Uuid>,
+) -> Result<String, BlogError> {
+    let user_id = Uuid::parse_str(&claims.user_id)?;
+    let mut conn = state.pool.get_owned().await?;
+
+    find_managed_comm(&comm_id, &user_id, &mut conn).await?;
+    if !Comm::delete_comm_by_uuid(&comm_id, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("not found comm")));
+    }
+
+    Ok(json!({ "ok": "delete comm ok!"}).to_string())
 }
 
-pub async fn delete_comm(
+#[utoipa::path(
+    get,
+    path = "/comms/queue/{page}",
+    params(
+        ("page" = u64, Path, description = "List queued comms by page"),
+        QueueQuery
+    ),
+    responses(
+        (status = 200, description = "Queued comms retrieved successfully", body = ListQueueComms),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_comm_queue(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(page): Path<u64>,
+    Query(query): Query<QueueQuery>,
+) -> Result<Json<ListQueueComms>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let status = query.status.unwrap_or(CommStatus::Pending);
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let (comms, total) =
+        Comm::get_comms_by_status(&status, limit as i64, offset as i64, &mut conn).await?;
+
+    let comms = comms
+        .into_iter()
+        .map(|(comm, comm_user)| QueueComm {
+            id: comm.id,
+            created_at: comm.created_at,
+            nickname: comm_user
+                .as_ref()
+                .map(|u| u.nickname.clone())
+                .unwrap_or_default(),
+            email: comm_user.as_ref().map(|u| u.email.clone()),
+            website_url: comm_user.and_then(|u| u.website_url),
+            content: comm.content,
+            status: comm.status,
+            spam_score: comm.spam_score,
+            note_id: comm.note_id,
+            page_id: comm.page_id,
+        })
+        .collect();
+
+    Ok(Json(ListQueueComms { comms, total }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/comms/moderate",
+    request_body = ModerateComms,
+    responses(
+        (status = 200, description = "Comms moderated successfully"),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn moderate_comms(
     state: State<AppState>,
-    Path(comm_id): Path<String>,
+    _claims: Claims,
+    Json(moderate): Json<ModerateComms>,
 ) -> Result<String, BlogError> {
-    todo!()
+    if moderate.ids.is_empty() {
+        return Err(BlogError::BadRequest(String::from("no comms selected")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    let updated =
+        moderation::moderate(&moderate.ids, moderate.action, &state.notify, &mut conn).await?;
+
+    Ok(json!({ "ok": "moderate comms ok!", "updated": updated }).to_string())
+}
+
+fn is_valid_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && email.len() <= 256
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+/// Nests `comms` under their parents. Input order is kept among siblings, and
+/// replies whose parent is not in `comms` are left out.
+fn build_comm_tree(comms: Vec<ReturnComm>, parents: &HashMap<Uuid, Uuid>) -> Vec<ReturnComm> {
+    fn attach(comm: &mut ReturnComm, children: &mut HashMap<Uuid, Vec<ReturnComm>>) {
+        if let Some(mut replies) = children.remove(&comm.id) {
+            for reply in replies.iter_mut() {
+                attach(reply, children);
+            }
+            comm.replies = replies;
+        }
+    }
+
+    let ids: HashSet<Uuid> = comms.iter().map(|c| c.id).collect();
+    let mut children: HashMap<Uuid, Vec<ReturnComm>> = HashMap::new();
+    let mut roots = Vec::new();
+
+    for comm in comms {
+        match parents.get(&comm.id) {
+            Some(parent_id) if ids.contains(parent_id) => {
+                children.entry(*parent_id).or_default().push(comm)
+            }
+            Some(_) => {}
+            None => roots.push(comm),
+        }
+    }
+
+    for root in roots.iter_mut() {
+        attach(root, &mut children);
+    }
+
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comm(id: Uuid) -> ReturnComm {
+        let now = chrono::Utc::now().naive_utc();
+        ReturnComm {
+            id,
+            created_at: now,
+            updated_at: now,
+            nickname: String::from("neko"),
+            content: String::from("nya"),
+            website_url: None,
+            status: None,
+            replies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_build_comm_tree() {
+        let (a, b, c, d, orphan) = (
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+        let parents = HashMap::from([(b, a), (c, b), (d, a), (orphan, Uuid::new_v4())]);
+
+        let tree = build_comm_tree(
+            vec![comm(a), comm(b), comm(c), comm(d), comm(orphan)],
+            &parents,
+        );
+
+        assert_eq!(tree.len(), 1);
+        assert_eq!(tree[0].id, a);
+        let replies: Vec<Uuid> = tree[0].replies.iter().map(|r| r.id).collect();
+        assert_eq!(replies, vec![b, d]);
+        assert_eq!(tree[0].replies[0].replies[0].id, c);
+    }
+
+    #[test]
+    fn test_is_valid_email() {
+        assert!(is_valid_email("neko@example.com"));
+        assert!(!is_valid_email("neko"));
+        assert!(!is_valid_email("@example.com"));
+        assert!(!is_valid_email("neko@example"));
+        assert!(!is_valid_email("ne ko@example.com"));
+    }
 }
@@ -0,0 +1,317 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    db::models::{
+        notify_outbox::OutboxStatus,
+        webhook_deliveries::{WebhookDelivery, EVENTS},
+        webhook_endpoints::{NewWebhookEndpoint, WebhookEndpoint},
+    },
+    error::BlogError,
+    service::hooks::{self, Outcome},
+    utils::{generate_random_string, jwt::Claims},
+    AppState,
+};
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhook {
+    url: String,
+    /// Events to receive, all of them when empty or omitted
+    events: Option<Vec<String>>,
+    /// Signing secret, generated when omitted
+    secret: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnCreatedWebhook {
+    id: Uuid,
+    /// Only shown here, keep it to check signatures
+    secret: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnWebhook {
+    id: Uuid,
+    url: String,
+    events: Vec<String>,
+    active: bool,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnDelivery {
+    id: Uuid,
+    event: String,
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+    status: OutboxStatus,
+    attempts: i32,
+    next_attempt_at: NaiveDateTime,
+    response_status: Option<i32>,
+    last_error: Option<String>,
+    delivered_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListDeliveries {
+    pub deliveries: Vec<ReturnDelivery>,
+    pub total: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnTestDelivery {
+    delivery_id: Uuid,
+    delivered: bool,
+    response_status: Option<i32>,
+    error: Option<String>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        create_webhook,
+        list_webhooks,
+        delete_webhook,
+        test_webhook,
+        list_deliveries
+    ),
+    components(schemas(
+        CreateWebhook,
+        ReturnCreatedWebhook,
+        ReturnWebhook,
+        ReturnDelivery,
+        ListDeliveries,
+        ReturnTestDelivery
+    ))
+)]
+pub struct HooksDoc;
+
+/// Registers an endpoint for content events. Each request carries
+/// `X-Blog-Signature: sha256=<hex>`, the HMAC-SHA256 of
+/// `"{X-Blog-Timestamp}.{body}"` with the secret.
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 200, description = "Webhook registered", body = ReturnCreatedWebhook),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn create_webhook(
+    state: State<AppState>,
+    _claims: Claims,
+    Json(webhook): Json<CreateWebhook>,
+) -> Result<Json<ReturnCreatedWebhook>, BlogError> {
+    match reqwest::Url::parse(&webhook.url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        _ => return Err(BlogError::BadRequest(String::from("invalid url"))),
+    }
+
+    let events = webhook.events.unwrap_or_default();
+    if let Some(event) = events.iter().find(|e| !EVENTS.contains(&e.as_str())) {
+        return Err(BlogError::BadRequest(format!("unknown event {}", event)));
+    }
+
+    let secret = match webhook.secret {
+        Some(secret) if secret.is_empty() => {
+            return Err(BlogError::BadRequest(String::from("empty secret")))
+        }
+        Some(secret) => secret,
+        None => generate_random_string(32),
+    };
+
+    let mut conn = state.pool.get_owned().await?;
+    let id = Uuid::new_v4();
+    WebhookEndpoint::create(
+        &NewWebhookEndpoint {
+            id,
+            url: &webhook.url,
+            secret: &secret,
+            events: &events,
+        },
+        &mut conn,
+    )
+    .await?;
+
+    Ok(Json(ReturnCreatedWebhook { id, secret }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    responses(
+        (status = 200, description = "Webhooks retrieved successfully", body = Vec<ReturnWebhook>),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_webhooks(
+    state: State<AppState>,
+    _claims: Claims,
+) -> Result<Json<Vec<ReturnWebhook>>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let webhooks = WebhookEndpoint::get_all(&mut conn)
+        .await?
+        .into_iter()
+        .map(|endpoint| ReturnWebhook {
+            id: endpoint.id,
+            url: endpoint.url,
+            events: endpoint.events,
+            active: endpoint.active,
+            created_at: endpoint.created_at,
+        })
+        .collect();
+
+    Ok(Json(webhooks))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhook/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Webhook ID")
+    ),
+    responses(
+        (status = 200, description = "Webhook deleted"),
+        (status = 404, description = "Webhook not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_webhook(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+) -> Result<String, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    if !WebhookEndpoint::delete_by_uuid(&id, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("webhook not found")));
+    }
+
+    Ok(json!({ "ok": "delete webhook ok!" }).to_string())
+}
+
+/// Sends a `ping` event right away and tells how the endpoint answered.
+#[utoipa::path(
+    post,
+    path = "/webhook/{id}/test",
+    params(
+        ("id" = Uuid, Path, description = "Webhook ID")
+    ),
+    responses(
+        (status = 200, description = "Test event sent", body = ReturnTestDelivery),
+        (status = 404, description = "Webhook not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn test_webhook(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ReturnTestDelivery>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let Some(endpoint) = WebhookEndpoint::find_by_uuid(&id, &mut conn).await? else {
+        return Err(BlogError::NotFound(String::from("webhook not found")));
+    };
+
+    let payload = WebhookDelivery::payload("ping", json!({ "webhook_id": endpoint.id }));
+    let delivery_id = WebhookDelivery::create(&endpoint.id, "ping", &payload, &mut conn).await?;
+
+    let client = hooks::client()?;
+    let result = match hooks::send(&client, &endpoint, &delivery_id, "ping", &payload).await {
+        Outcome::Delivered(status) => {
+            WebhookDelivery::mark_delivered(&delivery_id, status, &mut conn).await?;
+            ReturnTestDelivery {
+                delivery_id,
+                delivered: true,
+                response_status: Some(status),
+                error: None,
+            }
+        }
+        Outcome::Failed(status, e) => {
+            WebhookDelivery::mark_failed(&delivery_id, status, &e, None, &mut conn).await?;
+            ReturnTestDelivery {
+                delivery_id,
+                delivered: false,
+                response_status: status,
+                error: Some(e),
+            }
+        }
+    };
+
+    Ok(Json(result))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}/deliveries/{page}",
+    params(
+        ("id" = Uuid, Path, description = "Webhook ID"),
+        ("page" = u64, Path, description = "List deliveries by page")
+    ),
+    responses(
+        (status = 200, description = "Deliveries retrieved successfully", body = ListDeliveries),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_deliveries(
+    state: State<AppState>,
+    _claims: Claims,
+    Path((id, page)): Path<(Uuid, u64)>,
+) -> Result<Json<ListDeliveries>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let (deliveries, total) =
+        WebhookDelivery::get_by_endpoint(&id, limit as i64, offset as i64, &mut conn).await?;
+
+    let deliveries = deliveries
+        .into_iter()
+        .map(|delivery| ReturnDelivery {
+            id: delivery.id,
+            event: delivery.event,
+            payload: delivery.payload,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            next_attempt_at: delivery.next_attempt_at,
+            response_status: delivery.response_status,
+            last_error: delivery.last_error,
+            delivered_at: delivery.delivered_at,
+            created_at: delivery.created_at,
+        })
+        .collect();
+
+    Ok(Json(ListDeliveries { deliveries, total }))
+}
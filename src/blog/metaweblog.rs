@@ -0,0 +1,633 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::State,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{
+            info::Info,
+            note_sorts::NoteSort,
+            note_tags::NoteTag,
+            notes::{Note, Status},
+            publish_tokens::PublishToken,
+            sorts::Sort,
+        },
+        Conn,
+    },
+    error::BlogError,
+    service::{
+        media,
+        micropub::hash_token,
+        publish,
+        xmlrpc::{self, Call, Value},
+    },
+    utils::summary_of,
+    AppState,
+};
+
+/// Most posts `getRecentPosts` hands out at once.
+const RECENT_POSTS_LIMIT: i32 = 100;
+
+/// Takes MetaWeblog and Blogger API calls from desktop editors. The password
+/// is a publishing token, the user name is not looked at. Errors are sent as
+/// XML-RPC faults.
+pub async fn xmlrpc(state: State<AppState>, body: String) -> Response {
+    let result = match xmlrpc::parse_call(&body) {
+        Ok(call) => dispatch(&state, call).await,
+        Err(e) => Err(BlogError::BadRequest(e)),
+    };
+
+    let body = match result {
+        Ok(value) => xmlrpc::response(&value),
+        Err(e) => {
+            let (code, message) = match e {
+                BlogError::Unauthorized(s) => (403, s),
+                BlogError::Forbidden(s) => (401, s),
+                BlogError::BadRequest(s) => (400, s),
+                BlogError::NotFound(s) => (404, s),
+                BlogError::Conflict(s) => (409, s),
+                BlogError::TooManyRequests(s) => (429, s),
+                e => {
+                    error!("xmlrpc call failed: {}", e);
+                    (500, String::from("internal server error"))
+                }
+            };
+            xmlrpc::fault(code, &message)
+        }
+    };
+
+    ([(CONTENT_TYPE, "text/xml; charset=utf-8")], body).into_response()
+}
+
+async fn dispatch(state: &AppState, call: Call) -> Result<Value, BlogError> {
+    let params = &call.params;
+    let mut conn = state.pool.get_owned().await?;
+
+    match call.method.as_str() {
+        "blogger.getUsersBlogs" | "metaWeblog.getUsersBlogs" => {
+            authorize(params, 2, &mut conn).await?;
+            get_users_blogs(&mut conn).await
+        }
+        "metaWeblog.getCategories" => {
+            authorize(params, 2, &mut conn).await?;
+            get_categories(&mut conn).await
+        }
+        "metaWeblog.newPost" => {
+            let token = authorize(params, 2, &mut conn).await?;
+            new_post(
+                state,
+                &token,
+                param(params, 3)?,
+                publish(params, 4),
+                &mut conn,
+            )
+            .await
+        }
+        "metaWeblog.editPost" => {
+            let token = authorize(params, 2, &mut conn).await?;
+            require(&token, &["update"])?;
+            let note_id = post_id(params, 0)?;
+            edit_post(
+                state,
+                &token,
+                &note_id,
+                param(params, 3)?,
+                publish(params, 4),
+                &mut conn,
+            )
+            .await
+        }
+        "metaWeblog.getPost" => {
+            authorize(params, 2, &mut conn).await?;
+            get_post(&post_id(params, 0)?, &mut conn).await
+        }
+        "metaWeblog.getRecentPosts" => {
+            authorize(params, 2, &mut conn).await?;
+            let limit = param(params, 3)?
+                .as_i32()
+                .ok_or(BlogError::BadRequest(String::from("invalid numberOfPosts")))?;
+            get_recent_posts(limit.clamp(1, RECENT_POSTS_LIMIT), &mut conn).await
+        }
+        "metaWeblog.newMediaObject" => {
+            let token = authorize(params, 2, &mut conn).await?;
+            require(&token, &["media", "create"])?;
+            new_media_object(param(params, 3)?).await
+        }
+        "blogger.deletePost" | "metaWeblog.deletePost" => {
+            let token = authorize(params, 3, &mut conn).await?;
+            require(&token, &["delete"])?;
+            delete_post(state, &post_id(params, 1)?, &mut conn).await
+        }
+        method => Err(BlogError::NotFound(format!("unknown method {}", method))),
+    }
+}
+
+fn param(params: &[Value], index: usize) -> Result<&Value, BlogError> {
+    params
+        .get(index)
+        .ok_or_else(|| BlogError::BadRequest(format!("missing parameter {}", index + 1)))
+}
+
+fn string_param(params: &[Value], index: usize) -> Result<&str, BlogError> {
+    param(params, index)?
+        .as_str()
+        .ok_or_else(|| BlogError::BadRequest(format!("parameter {} is not a string", index + 1)))
+}
+
+fn post_id(params: &[Value], index: usize) -> Result<Uuid, BlogError> {
+    Uuid::parse_str(string_param(params, index)?.trim())
+        .map_err(|_| BlogError::NotFound(String::from("not found post")))
+}
+
+/// The `publish` flag, which editors leave out for drafts now and then.
+fn publish(params: &[Value], index: usize) -> bool {
+    params.get(index).and_then(Value::as_bool).unwrap_or(false)
+}
+
+async fn authorize(
+    params: &[Value],
+    password: usize,
+    conn: &mut Conn,
+) -> Result<PublishToken, BlogError> {
+    let token = string_param(params, password)?;
+
+    PublishToken::use_by_hash(&hash_token(token), conn)
+        .await?
+        .ok_or(BlogError::Unauthorized(String::from(
+            "invalid password or username",
+        )))
+}
+
+fn require(token: &PublishToken, scopes: &[&str]) -> Result<(), BlogError> {
+    if token.allows(scopes) {
+        Ok(())
+    } else {
+        Err(BlogError::Forbidden(String::from(
+            "the token is not allowed to do this",
+        )))
+    }
+}
+
+/// Status a post is saved with. Only tokens that may create posts publish
+/// them, for others `publish` keeps a public note public and leaves the rest
+/// drafts.
+fn post_status(token: &PublishToken, publish: bool, current: Option<&Status>) -> Status {
+    match (publish, current) {
+        (false, _) => Status::Draft,
+        (true, _) if token.allows(&["create"]) => Status::Public,
+        (true, Some(Status::Public)) => Status::Public,
+        (true, _) => Status::Draft,
+    }
+}
+
+fn member<'a>(post: &'a Value, key: &str) -> Option<&'a str> {
+    post.get(key).and_then(Value::as_str)
+}
+
+/// Tag contents out of `mt_keywords`, a comma separated list.
+fn keywords(post: &Value) -> Option<Vec<String>> {
+    let keywords = member(post, "mt_keywords")?;
+    let mut tags: Vec<String> = Vec::new();
+    for tag in keywords.split(',').map(str::trim) {
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+
+    Some(tags)
+}
+
+fn slug(post: &Value) -> Option<String> {
+    member(post, "wp_slug")
+        .or_else(|| member(post, "mt_basename"))
+        .map(str::trim)
+        .filter(|slug| !slug.is_empty())
+        .map(str::to_string)
+}
+
+fn allow_comments(post: &Value) -> Option<bool> {
+    match post.get("mt_allow_comments")? {
+        Value::String(s) => Some(s == "open" || s == "1"),
+        value => value.as_bool(),
+    }
+}
+
+/// IDs of the sorts `categories` names, by content or by name.
+async fn sort_ids(post: &Value, conn: &mut Conn) -> Result<Option<Vec<Uuid>>, BlogError> {
+    let Some(categories) = post.get("categories").and_then(Value::as_array) else {
+        return Ok(None);
+    };
+
+    let sorts = Sort::get_sorts(conn).await?;
+    let mut ids = Vec::with_capacity(categories.len());
+    for category in categories.iter().filter_map(Value::as_str) {
+        let sort = sorts
+            .iter()
+            .find(|sort| sort.content == category || sort.name == category)
+            .ok_or_else(|| BlogError::BadRequest(format!("unknown category {}", category)))?;
+        if !ids.contains(&sort.id) {
+            ids.push(sort.id);
+        }
+    }
+
+    Ok(Some(ids))
+}
+
+async fn check_subname(subname: &str, conn: &mut Conn) -> Result<(), BlogError> {
+    if Note::find_note_by_short_id(subname, conn).await?.is_some() {
+        return Err(BlogError::Conflict("subname already exists".to_string()));
+    }
+
+    Ok(())
+}
+
+async fn get_users_blogs(conn: &mut Conn) -> Result<Value, BlogError> {
+    let site_url = CONFIG.site_url();
+    let name = match Info::get_info(conn).await? {
+        Some(info) => info.title,
+        None => site_url.clone(),
+    };
+
+    Ok(Value::Array(vec![Value::object([
+        ("blogid", Value::from("1")),
+        ("blogName", Value::from(name)),
+        ("url", Value::from(format!("{}/", site_url))),
+        ("xmlrpc", Value::from(format!("{}/api/xmlrpc", site_url))),
+        ("isAdmin", Value::Bool(true)),
+    ])]))
+}
+
+async fn get_categories(conn: &mut Conn) -> Result<Value, BlogError> {
+    let categories = Sort::get_sorts(conn)
+        .await?
+        .into_iter()
+        .map(|sort| {
+            let parent_id = sort
+                .parent_id
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| String::from("0"));
+            Value::object([
+                ("categoryId", Value::from(sort.id.to_string())),
+                ("parentId", Value::from(parent_id)),
+                ("categoryName", Value::from(sort.content.as_str())),
+                ("title", Value::from(sort.content.as_str())),
+                ("description", Value::from(sort.content)),
+                ("htmlUrl", Value::from("")),
+                ("rssUrl", Value::from("")),
+            ])
+        })
+        .collect();
+
+    Ok(Value::Array(categories))
+}
+
+async fn new_post(
+    state: &AppState,
+    token: &PublishToken,
+    post: &Value,
+    publish: bool,
+    conn: &mut Conn,
+) -> Result<Value, BlogError> {
+    require(token, &["create", "draft"])?;
+
+    let title = member(post, "title").unwrap_or_default().trim();
+    if title.is_empty() {
+        return Err(BlogError::BadRequest(String::from("title is required")));
+    }
+    let content = member(post, "description").unwrap_or_default();
+    let status = post_status(token, publish, None);
+    let subname = slug(post);
+    if let Some(subname) = &subname {
+        check_subname(subname, conn).await?;
+    }
+    let sort_ids = sort_ids(post, conn).await?;
+
+    let summary = summary_of(member(post, "mt_excerpt"), content, None);
+    let comm = allow_comments(post).unwrap_or(true);
+    let tags = keywords(post);
+    let (subname, summary) = (&subname, &summary);
+    let notify = state.notify.clone();
+    let note_id = conn
+        .transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let note_id = Note::insert_note(
+                    subname.as_deref(),
+                    &status,
+                    title,
+                    summary,
+                    content,
+                    comm,
+                    &token.user_id,
+                    None,
+                    conn,
+                )
+                .await?;
+                if let Some(sort_ids) = sort_ids {
+                    NoteSort::set_note_sorts(&note_id, &sort_ids, conn).await?;
+                }
+                if let Some(tags) = tags {
+                    NoteTag::set_note_tag_contents(&note_id, &tags, conn).await?;
+                }
+                publish::published(&note_id, &notify, conn).await?;
+
+                Ok(note_id)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    Ok(Value::from(note_id.to_string()))
+}
+
+async fn edit_post(
+    state: &AppState,
+    token: &PublishToken,
+    note_id: &Uuid,
+    post: &Value,
+    publish: bool,
+    conn: &mut Conn,
+) -> Result<Value, BlogError> {
+    let note = Note::find_note_by_uuid(note_id, conn)
+        .await?
+        .filter(|note| note.status != Status::Recycle)
+        .ok_or(BlogError::NotFound(String::from("not found post")))?;
+
+    let title = match member(post, "title").map(str::trim) {
+        Some("") => return Err(BlogError::BadRequest(String::from("title is required"))),
+        Some(title) => title,
+        None => &note.title,
+    };
+    let content = member(post, "description").unwrap_or(&note.content);
+    let status = post_status(token, publish, Some(&note.status));
+    let current = Note::find_subname(&note.id, conn).await?;
+    let subname = slug(post).or(current.clone());
+    if let Some(subname) = &subname {
+        if Some(subname) != current.as_ref() {
+            check_subname(subname, conn).await?;
+        }
+    }
+    let sort_ids = sort_ids(post, conn).await?;
+
+    let summary = summary_of(
+        member(post, "mt_excerpt"),
+        content,
+        Some((&note.summary, &note.content)),
+    );
+    let comm = allow_comments(post).unwrap_or(note.comm);
+    let tags = keywords(post);
+    let (note, subname, summary) = (&note, &subname, &summary);
+    let notify = state.notify.clone();
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            Note::edit_note(
+                &note.id,
+                title,
+                subname.as_deref(),
+                summary,
+                content,
+                &status,
+                comm,
+                note.fancy_img.as_deref(),
+                &token.user_id,
+                conn,
+            )
+            .await?;
+            if let Some(sort_ids) = sort_ids {
+                NoteSort::set_note_sorts(&note.id, &sort_ids, conn).await?;
+            }
+            if let Some(tags) = tags {
+                NoteTag::set_note_tag_contents(&note.id, &tags, conn).await?;
+            }
+            publish::published(&note.id, &notify, conn).await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(Value::Bool(true))
+}
+
+/// A note as a MetaWeblog post.
+fn post(
+    note: Note,
+    short_name: &str,
+    subname: Option<String>,
+    sorts: Vec<String>,
+    tags: Vec<String>,
+) -> Value {
+    let link = format!("{}/{}", CONFIG.site_url(), short_name);
+    let status = match note.status {
+        Status::Public => "publish",
+        Status::Draft | Status::Recycle => "draft",
+    };
+
+    Value::object([
+        ("postid", Value::from(note.id.to_string())),
+        ("userid", Value::from(note.user_id.to_string())),
+        ("title", Value::from(note.title)),
+        ("description", Value::from(note.content)),
+        ("dateCreated", Value::DateTime(note.created_at)),
+        ("date_modified", Value::DateTime(note.updated_at)),
+        (
+            "categories",
+            Value::Array(sorts.into_iter().map(Value::from).collect()),
+        ),
+        ("mt_keywords", Value::from(tags.join(", "))),
+        ("mt_excerpt", Value::from(note.summary)),
+        ("mt_allow_comments", Value::Int(i32::from(note.comm))),
+        ("wp_slug", Value::from(subname.unwrap_or_default())),
+        ("post_status", Value::from(status)),
+        ("link", Value::from(link.as_str())),
+        ("permaLink", Value::from(link)),
+    ])
+}
+
+async fn get_post(note_id: &Uuid, conn: &mut Conn) -> Result<Value, BlogError> {
+    let (note, short_name) = Note::find_note_with_short_name(note_id, conn)
+        .await?
+        .filter(|(note, _)| note.status != Status::Recycle)
+        .ok_or(BlogError::NotFound(String::from("not found post")))?;
+
+    let subname = Note::find_subname(&note.id, conn).await?;
+    let sorts = NoteSort::get_sorts_by_note_id(&note.id, conn)
+        .await?
+        .into_iter()
+        .map(|sort| sort.content)
+        .collect();
+    let tags = NoteTag::get_tags_by_note_id(&note.id, conn)
+        .await?
+        .into_iter()
+        .map(|tag| tag.content)
+        .collect();
+
+    Ok(post(note, &short_name, subname, sorts, tags))
+}
+
+async fn get_recent_posts(limit: i32, conn: &mut Conn) -> Result<Value, BlogError> {
+    let (notes, short_names, _) = Note::get_notes_short_total(limit as i64, 0, conn).await?;
+
+    let mut tags: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for (note_tag, tag) in NoteTag::get_note_tags_and_tags_by_notes(&notes, conn).await? {
+        tags.entry(note_tag.note_id).or_default().push(tag.content);
+    }
+    let mut sorts: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for (note_sort, sort) in NoteSort::get_note_sorts_and_tags_by_notes(&notes, conn).await? {
+        sorts
+            .entry(note_sort.note_id)
+            .or_default()
+            .push(sort.content);
+    }
+
+    let posts = notes
+        .into_iter()
+        .zip(short_names)
+        .filter(|(note, _)| note.status != Status::Recycle)
+        .map(|(note, (short_name, subname))| {
+            let link_name = subname.clone().unwrap_or(short_name);
+            let note_tags = tags.remove(&note.id).unwrap_or_default();
+            let note_sorts = sorts.remove(&note.id).unwrap_or_default();
+            post(note, &link_name, subname, note_sorts, note_tags)
+        })
+        .collect();
+
+    Ok(Value::Array(posts))
+}
+
+async fn new_media_object(file: &Value) -> Result<Value, BlogError> {
+    let data = file
+        .get("bits")
+        .and_then(Value::as_bytes)
+        .ok_or(BlogError::BadRequest(String::from("bits is required")))?;
+    let content_type = member(file, "type");
+
+    let url = media::store(data, content_type, member(file, "name")).await?;
+    let name = url.rsplit('/').next().unwrap_or_default().to_string();
+
+    Ok(Value::object([
+        ("file", Value::from(name)),
+        ("url", Value::from(url)),
+        ("type", Value::from(content_type.unwrap_or_default())),
+    ]))
+}
+
+async fn delete_post(
+    state: &AppState,
+    note_id: &Uuid,
+    conn: &mut Conn,
+) -> Result<Value, BlogError> {
+    let note = Note::find_note_by_uuid(note_id, conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found post")))?;
+
+    let notify = state.notify.clone();
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            Note::recycle(&note.id, conn).await?;
+            publish::published(&note.id, &notify, conn).await?;
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(Value::Bool(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    #[test]
+    fn test_post_members() {
+        let post = Value::object([
+            ("mt_keywords", Value::from(" cat, life ,, cat ")),
+            ("mt_basename", Value::from(" hello ")),
+            ("mt_allow_comments", Value::from("closed")),
+        ]);
+
+        assert_eq!(
+            keywords(&post),
+            Some(vec![String::from("cat"), String::from("life")])
+        );
+        assert_eq!(slug(&post).as_deref(), Some("hello"));
+        assert_eq!(allow_comments(&post), Some(false));
+
+        let post = Value::object([
+            ("wp_slug", Value::from("nya")),
+            ("mt_basename", Value::from("hello")),
+            ("mt_allow_comments", Value::Int(1)),
+        ]);
+        assert_eq!(keywords(&post), None);
+        assert_eq!(slug(&post).as_deref(), Some("nya"));
+        assert_eq!(allow_comments(&post), Some(true));
+
+        let post = Value::object([("wp_slug", Value::from(" "))]);
+        assert_eq!(slug(&post), None);
+        assert_eq!(allow_comments(&post), None);
+    }
+
+    #[test]
+    fn test_post_status() {
+        let token = |scopes: &[&str]| PublishToken {
+            id: Uuid::nil(),
+            name: String::from("editor"),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            user_id: Uuid::nil(),
+            created_at: NaiveDateTime::default(),
+            last_used_at: None,
+        };
+
+        let create = token(&["create", "update"]);
+        assert_eq!(post_status(&create, true, None), Status::Public);
+        assert_eq!(
+            post_status(&create, true, Some(&Status::Draft)),
+            Status::Public
+        );
+        assert_eq!(
+            post_status(&create, false, Some(&Status::Public)),
+            Status::Draft
+        );
+
+        // without create, publishing cannot take a draft public
+        let draft = token(&["draft"]);
+        assert_eq!(post_status(&draft, true, None), Status::Draft);
+        let update = token(&["update"]);
+        assert_eq!(
+            post_status(&update, true, Some(&Status::Draft)),
+            Status::Draft
+        );
+        assert_eq!(
+            post_status(&update, true, Some(&Status::Public)),
+            Status::Public
+        );
+        assert_eq!(
+            post_status(&update, false, Some(&Status::Public)),
+            Status::Draft
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let params = vec![Value::from("1"), Value::Int(2), Value::Bool(true)];
+
+        assert_eq!(string_param(&params, 0).unwrap(), "1");
+        assert!(string_param(&params, 1).is_err());
+        assert!(param(&params, 3).is_err());
+        assert!(publish(&params, 2));
+        assert!(!publish(&params, 3));
+        assert!(post_id(&params, 0).is_err());
+    }
+}
@@ -0,0 +1,635 @@
+use axum::{
+    extract::{FromRequest, Multipart, Path, Query, Request, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION, X_CONTENT_TYPE_OPTIONS},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use chrono::NaiveDateTime;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{
+            note_tags::NoteTag,
+            notes::{Note, Status},
+            publish_tokens::{NewPublishToken, PublishToken},
+            tags::Tag,
+        },
+        Conn,
+    },
+    error::BlogError,
+    service::{
+        media,
+        micropub::{self, bearer, hash_token, Entry, Properties, Request as Micropub, SCOPES},
+        publish, webmention,
+    },
+    utils::{generate_random_string, jwt::Claims, summary_of},
+    AppState,
+};
+
+/// Room left in request bodies for form fields next to an upload of the
+/// largest size.
+pub const FORM_OVERHEAD: usize = 64 * 1024;
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateToken {
+    #[schema(example = "phone")]
+    name: String,
+    /// Scopes granted, all of them when omitted
+    scopes: Option<Vec<String>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnCreatedToken {
+    id: Uuid,
+    /// Only shown here, the blog keeps a hash of it
+    token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnToken {
+    id: Uuid,
+    name: String,
+    scopes: Vec<String>,
+    created_at: NaiveDateTime,
+    last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(create_token, list_tokens, delete_token),
+    components(schemas(CreateToken, ReturnCreatedToken, ReturnToken))
+)]
+pub struct MicropubDoc;
+
+/// Issues a bearer token for Micropub clients at `/api/micropub`.
+#[utoipa::path(
+    post,
+    path = "/micropub/tokens",
+    request_body = CreateToken,
+    responses(
+        (status = 200, description = "Token created", body = ReturnCreatedToken),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn create_token(
+    state: State<AppState>,
+    claims: Claims,
+    Json(new_token): Json<CreateToken>,
+) -> Result<Json<ReturnCreatedToken>, BlogError> {
+    let user_id = Uuid::parse_str(&claims.user_id)?;
+
+    let name = new_token.name.trim();
+    if name.is_empty() {
+        return Err(BlogError::BadRequest(String::from("empty name")));
+    }
+    let scopes = match new_token.scopes {
+        Some(scopes) if scopes.is_empty() => {
+            return Err(BlogError::BadRequest(String::from("no scopes")))
+        }
+        Some(scopes) => scopes,
+        None => SCOPES.iter().map(|scope| scope.to_string()).collect(),
+    };
+    if let Some(scope) = scopes.iter().find(|s| !SCOPES.contains(&s.as_str())) {
+        return Err(BlogError::BadRequest(format!("unknown scope {}", scope)));
+    }
+
+    let token = generate_random_string(40);
+    let id = Uuid::new_v4();
+    let mut conn = state.pool.get_owned().await?;
+    PublishToken::create(
+        &NewPublishToken {
+            id,
+            name,
+            token_hash: &hash_token(&token),
+            scopes: &scopes,
+            user_id: &user_id,
+        },
+        &mut conn,
+    )
+    .await?;
+
+    Ok(Json(ReturnCreatedToken { id, token }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/micropub/tokens",
+    responses(
+        (status = 200, description = "Tokens retrieved successfully", body = Vec<ReturnToken>),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_tokens(
+    state: State<AppState>,
+    _claims: Claims,
+) -> Result<Json<Vec<ReturnToken>>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let tokens = PublishToken::get_all(&mut conn)
+        .await?
+        .into_iter()
+        .map(|token| ReturnToken {
+            id: token.id,
+            name: token.name,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        })
+        .collect();
+
+    Ok(Json(tokens))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/micropub/token/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Token ID")
+    ),
+    responses(
+        (status = 200, description = "Token revoked"),
+        (status = 404, description = "Token not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_token(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+) -> Result<String, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    if !PublishToken::delete_by_uuid(&id, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("not found token")));
+    }
+
+    Ok(json!({ "ok": "delete token ok!"}).to_string())
+}
+
+/// The publishing token of a request, from its `Authorization` header or
+/// else from an `access_token` field.
+async fn authorize(
+    headers: &HeaderMap,
+    access_token: Option<&str>,
+    conn: &mut AsyncPgConnection,
+) -> Result<PublishToken, BlogError> {
+    let token = bearer(headers)
+        .or(access_token)
+        .ok_or(BlogError::Unauthorized(String::from("unauthorized")))?;
+
+    PublishToken::use_by_hash(&hash_token(token), conn)
+        .await?
+        .ok_or(BlogError::InvalidToken)
+}
+
+fn require(token: &PublishToken, scopes: &[&str]) -> Result<(), BlogError> {
+    if token.allows(scopes) {
+        Ok(())
+    } else {
+        Err(BlogError::Forbidden(String::from("insufficient_scope")))
+    }
+}
+
+/// Status an update saves. Only tokens that may create posts take a note
+/// public, for others it stays what it was unless it already is.
+fn update_status(token: &PublishToken, wanted: Status, current: &Status) -> Status {
+    match current {
+        _ if wanted != Status::Public || token.allows(&["create"]) => wanted,
+        Status::Public => Status::Public,
+        Status::Draft => Status::Draft,
+        Status::Recycle => Status::Recycle,
+    }
+}
+
+/// The note a post URL of ours points at.
+async fn find_note(url: &str, conn: &mut Conn) -> Result<Note, BlogError> {
+    let not_found = || BlogError::NotFound(String::from("not found note"));
+
+    let url = Url::parse(url).map_err(|_| BlogError::BadRequest(String::from("invalid url")))?;
+    let site = Url::parse(&CONFIG.site_url())
+        .map_err(|_| BlogError::BadRequest(String::from("invalid site url")))?;
+    let short_name = webmention::short_name(&url, &site).ok_or_else(not_found)?;
+
+    Note::find_note_by_short_id(&short_name, conn)
+        .await?
+        .ok_or_else(not_found)
+}
+
+fn location(url: String) -> Response {
+    (StatusCode::CREATED, [(LOCATION, url)]).into_response()
+}
+
+/// Micropub queries: `config`, `source`, `category` and `syndicate-to`.
+pub async fn query(
+    state: State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Result<Json<Value>, BlogError> {
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let mut conn = state.pool.get_owned().await?;
+    authorize(&headers, param("access_token"), &mut conn).await?;
+
+    match param("q") {
+        Some("config") => Ok(Json(json!({
+            "media-endpoint": format!("{}/api/micropub/media", CONFIG.site_url()),
+            "syndicate-to": [],
+            "post-types": [
+                {"type": "article", "name": "Article"},
+                {"type": "note", "name": "Note"},
+                {"type": "photo", "name": "Photo"}
+            ],
+            "q": ["config", "source", "category", "syndicate-to"]
+        }))),
+        Some("syndicate-to") => Ok(Json(json!({ "syndicate-to": [] }))),
+        Some("category") => {
+            let filter = param("filter").unwrap_or_default().to_lowercase();
+            let categories: Vec<String> = Tag::get_tags(i64::MAX, 0, &mut conn)
+                .await?
+                .into_iter()
+                .map(|tag| tag.content)
+                .filter(|tag| tag.to_lowercase().contains(&filter))
+                .collect();
+
+            Ok(Json(json!({ "categories": categories })))
+        }
+        Some("source") => {
+            let url = param("url").ok_or(BlogError::BadRequest(String::from("url is required")))?;
+            let names: Vec<String> = params
+                .iter()
+                .filter(|(name, _)| name == "properties[]" || name == "properties")
+                .map(|(_, value)| value.clone())
+                .collect();
+
+            let properties = note_properties(&find_note(url, &mut conn).await?, &mut conn).await?;
+
+            Ok(Json(micropub::source(properties, &names)))
+        }
+        Some(q) => Err(BlogError::BadRequest(format!("unknown query {}", q))),
+        None => Err(BlogError::BadRequest(String::from("q is required"))),
+    }
+}
+
+async fn note_properties(note: &Note, conn: &mut Conn) -> Result<Properties, BlogError> {
+    let tags = NoteTag::get_tags_by_note_id(&note.id, conn)
+        .await?
+        .into_iter()
+        .map(|tag| tag.content)
+        .collect();
+    let subname = Note::find_subname(&note.id, conn).await?;
+
+    Ok(Entry::properties(note, tags, subname))
+}
+
+/// Creates, updates and deletes notes. Takes form-encoded and multipart
+/// requests, files in the latter are stored as media, and JSON ones.
+pub async fn micropub(
+    state: State<AppState>,
+    headers: HeaderMap,
+    request: Request,
+) -> Result<Response, BlogError> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let bad_request = |e: String| BlogError::BadRequest(e);
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let (request, token) = if content_type.starts_with("application/json") {
+        let Json(body) = Json::<Value>::from_request(request, &())
+            .await
+            .map_err(|_| BlogError::BadRequest(String::from("invalid json")))?;
+        let request = micropub::parse_json(&body).map_err(bad_request)?;
+        let token = authorize(&headers, None, &mut conn).await?;
+        (request, token)
+    } else if content_type.starts_with("multipart/form-data") {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|_| BlogError::BadRequest(String::from("invalid form")))?;
+        let (pairs, files) = read_multipart(multipart).await?;
+        let (mut request, access_token) = micropub::parse_form(pairs).map_err(bad_request)?;
+        let token = authorize(&headers, access_token.as_deref(), &mut conn).await?;
+
+        if let Micropub::Create(properties) = &mut request {
+            if !files.is_empty() {
+                require(&token, &["media", "create"])?;
+            }
+            for file in files {
+                let url = media::store(
+                    &file.data,
+                    file.content_type.as_deref(),
+                    file.file_name.as_deref(),
+                )
+                .await?;
+                properties
+                    .entry(file.name)
+                    .or_default()
+                    .push(Value::String(url));
+            }
+        }
+        (request, token)
+    } else {
+        let Form(pairs) = Form::<Vec<(String, String)>>::from_request(request, &())
+            .await
+            .map_err(|_| BlogError::BadRequest(String::from("invalid form")))?;
+        let (request, access_token) = micropub::parse_form(pairs).map_err(bad_request)?;
+        let token = authorize(&headers, access_token.as_deref(), &mut conn).await?;
+        (request, token)
+    };
+
+    match request {
+        Micropub::Create(properties) => {
+            require(&token, &["create", "draft"])?;
+            let mut entry = Entry::from_properties(&properties).map_err(bad_request)?;
+            if !token.allows(&["create"]) {
+                entry.status = Status::Draft;
+            }
+
+            if let Some(slug) = &entry.slug {
+                if Note::find_note_by_short_id(slug, &mut conn)
+                    .await?
+                    .is_some()
+                {
+                    return Err(BlogError::Conflict("subname already exists".to_string()));
+                }
+            }
+
+            let summary = summary_of(entry.summary.as_deref(), &entry.content, None);
+            let (entry, summary, token) = (&entry, &summary, &token);
+            let notify = state.notify.clone();
+            let note_id = conn
+                .transaction::<_, BlogError, _>(|conn| {
+                    async move {
+                        let note_id = Note::insert_note(
+                            entry.slug.as_deref(),
+                            &entry.status,
+                            &entry.title,
+                            summary,
+                            &entry.content,
+                            true,
+                            &token.user_id,
+                            entry.featured.as_deref(),
+                            conn,
+                        )
+                        .await?;
+                        NoteTag::set_note_tag_contents(&note_id, &entry.categories, conn).await?;
+                        publish::published(&note_id, &notify, conn).await?;
+
+                        Ok(note_id)
+                    }
+                    .scope_boxed()
+                })
+                .await?;
+
+            let (_, short_name) = Note::find_note_with_short_name(&note_id, &mut conn)
+                .await?
+                .ok_or(BlogError::InternalServerError)?;
+            Ok(location(format!("{}/{}", CONFIG.site_url(), short_name)))
+        }
+        Micropub::Update { url, update } => {
+            require(&token, &["update"])?;
+            let note = find_note(&url, &mut conn).await?;
+            if note.status == Status::Recycle {
+                return Err(BlogError::BadRequest(String::from("note is deleted")));
+            }
+
+            let mut properties = note_properties(&note, &mut conn).await?;
+            update.apply(&mut properties);
+            let mut entry = Entry::from_properties(&properties).map_err(bad_request)?;
+            entry.status = update_status(&token, entry.status, &note.status);
+
+            let subname = Note::find_subname(&note.id, &mut conn).await?;
+            if entry.slug.is_some() && entry.slug != subname {
+                let slug = entry.slug.as_deref().unwrap_or_default();
+                if Note::find_note_by_short_id(slug, &mut conn)
+                    .await?
+                    .is_some()
+                {
+                    return Err(BlogError::Conflict("subname already exists".to_string()));
+                }
+            }
+
+            // the properties carry the summary written by hand, if any
+            let summary = summary_of(entry.summary.as_deref(), &entry.content, None);
+            let (note, entry, summary, token) = (&note, &entry, &summary, &token);
+            let tags = update.touches("category");
+            let notify = state.notify.clone();
+            conn.transaction::<_, BlogError, _>(|conn| {
+                async move {
+                    Note::edit_note(
+                        &note.id,
+                        &entry.title,
+                        entry.slug.as_deref(),
+                        summary,
+                        &entry.content,
+                        &entry.status,
+                        note.comm,
+                        entry.featured.as_deref(),
+                        &token.user_id,
+                        conn,
+                    )
+                    .await?;
+                    if tags {
+                        NoteTag::set_note_tag_contents(&note.id, &entry.categories, conn).await?;
+                    }
+                    publish::published(&note.id, &notify, conn).await?;
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+            if entry.slug != subname {
+                let (_, short_name) = Note::find_note_with_short_name(&note.id, &mut conn)
+                    .await?
+                    .ok_or(BlogError::InternalServerError)?;
+                return Ok(location(format!("{}/{}", CONFIG.site_url(), short_name)));
+            }
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Micropub::Delete(url) => {
+            require(&token, &["delete"])?;
+            let note = find_note(&url, &mut conn).await?;
+            let notify = state.notify.clone();
+            conn.transaction::<_, BlogError, _>(|conn| {
+                async move {
+                    Note::recycle(&note.id, conn).await?;
+                    publish::published(&note.id, &notify, conn).await?;
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+        Micropub::Undelete(url) => {
+            require(&token, &["delete"])?;
+            let note = find_note(&url, &mut conn).await?;
+            let notify = state.notify.clone();
+            conn.transaction::<_, BlogError, _>(|conn| {
+                async move {
+                    if Note::restore(&note.id, conn).await? {
+                        publish::published(&note.id, &notify, conn).await?;
+                    }
+
+                    Ok(())
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+            Ok(StatusCode::NO_CONTENT.into_response())
+        }
+    }
+}
+
+struct Upload {
+    name: String,
+    content_type: Option<String>,
+    file_name: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Text fields of a multipart form and its files, read whole so the token
+/// is checked before anything is stored.
+async fn read_multipart(
+    mut multipart: Multipart,
+) -> Result<(Vec<(String, String)>, Vec<Upload>), BlogError> {
+    let invalid = |_| BlogError::BadRequest(String::from("invalid form"));
+
+    let mut pairs = Vec::new();
+    let mut files = Vec::new();
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        let name = field.name().unwrap_or_default();
+        let name = name.strip_suffix("[]").unwrap_or(name).to_string();
+
+        if field.file_name().is_some() {
+            files.push(Upload {
+                name,
+                content_type: field.content_type().map(str::to_string),
+                file_name: field.file_name().map(str::to_string),
+                data: field.bytes().await.map_err(invalid)?.to_vec(),
+            });
+        } else {
+            pairs.push((name, field.text().await.map_err(invalid)?));
+        }
+    }
+
+    Ok((pairs, files))
+}
+
+/// The Micropub media endpoint, takes one `file` and answers with where it
+/// is served.
+pub async fn upload(
+    state: State<AppState>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, BlogError> {
+    let (pairs, files) = read_multipart(multipart).await?;
+    let access_token = pairs
+        .iter()
+        .find(|(name, _)| name == "access_token")
+        .map(|(_, value)| value.as_str());
+
+    let mut conn = state.pool.get_owned().await?;
+    let token = authorize(&headers, access_token, &mut conn).await?;
+    require(&token, &["media", "create"])?;
+
+    let file = files
+        .into_iter()
+        .find(|file| file.name == "file")
+        .ok_or(BlogError::BadRequest(String::from("file is required")))?;
+    let url = media::store(
+        &file.data,
+        file.content_type.as_deref(),
+        file.file_name.as_deref(),
+    )
+    .await?;
+
+    Ok(location(url))
+}
+
+/// Serves an uploaded file.
+pub async fn get_media(Path(name): Path<String>) -> Result<Response, BlogError> {
+    let (content_type, data) = media::load(&name)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found media")))?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, content_type),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+            (CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_status() {
+        let token = |scopes: &[&str]| PublishToken {
+            id: Uuid::nil(),
+            name: String::from("editor"),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            user_id: Uuid::nil(),
+            created_at: NaiveDateTime::default(),
+            last_used_at: None,
+        };
+
+        // post-status: published from a token that may only update
+        let update = token(&["update"]);
+        assert_eq!(
+            update_status(&update, Status::Public, &Status::Draft),
+            Status::Draft
+        );
+        assert_eq!(
+            update_status(&update, Status::Public, &Status::Public),
+            Status::Public
+        );
+        assert_eq!(
+            update_status(&update, Status::Draft, &Status::Public),
+            Status::Draft
+        );
+
+        let create = token(&["create", "update"]);
+        assert_eq!(
+            update_status(&create, Status::Public, &Status::Draft),
+            Status::Public
+        );
+    }
+}
@@ -1,21 +1,42 @@
 pub mod auth;
 pub mod comm;
+pub mod hooks;
 pub mod info;
+pub mod metaweblog;
+pub mod micropub;
+pub mod newsletter;
 pub mod note;
+pub mod notify;
 pub mod online;
 pub mod page;
+pub mod revision;
 pub mod sort;
 pub mod tag;
 pub mod user;
+pub mod webmention;
 
 use auth::login;
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{delete, get, post, put},
     Router,
 };
+use comm::{
+    create_comm, delete_comm, get_challenge, get_comm, list_comm_queue, moderate_comms,
+    stream_comms, update_comm,
+};
+use hooks::{create_webhook, delete_webhook, list_deliveries, list_webhooks, test_webhook};
 use info::{create_info, get_info, update_info};
-use note::{create_note, delete_note, get_note, list_notes, update_note};
+use micropub::{create_token, delete_token, get_media, list_tokens, query, upload, FORM_OVERHEAD};
+use newsletter::{confirm, list_subscribers, subscribe};
+use note::{create_note, delete_note, get_note, list_notes, preview_note, update_note};
+use notify::{
+    list_outbox, list_templates, preview_template, replay_outbox, reset_template, unsubscribe,
+    update_template,
+};
+use online::{online, online_count};
 use page::{create_page, delete_page, get_page, update_page};
+use revision::{diff_revisions, get_revision, list_revisions, restore_revision};
 use sort::{create_sort, get_sorts, update_sort};
 use tag::{attach_tag, create_tag, delete_tag, detach_tag, get_tags, update_tag};
 use user::{create_user, update_user_info};
@@ -23,18 +44,24 @@ use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
 };
+use webmention::{delete_mention, list_mentions, receive_mention};
 
-use crate::AppState;
+use crate::{config::CONFIG, AppState};
 
 pub fn router(state: AppState) -> Router {
     Router::new()
         .route("/user", post(create_user).put(update_user_info))
         .route("/login", post(login))
         .route("/note", post(create_note))
+        .route("/note/preview", post(preview_note))
         .route(
             "/note/:id",
             get(get_note).put(update_note).delete(delete_note),
         )
+        .route("/note/:id/revisions/:page", get(list_revisions))
+        .route("/note/:id/revision/:rev", get(get_revision))
+        .route("/note/:id/revision/:rev/restore", post(restore_revision))
+        .route("/note/:id/diff", get(diff_revisions))
         .route("/notes/:page", get(list_notes))
         .route("/page", post(create_page))
         .route(
@@ -49,6 +76,60 @@ pub fn router(state: AppState) -> Router {
         .route("/sort/:id", put(update_sort))
         .route("/sorts", get(get_sorts))
         .route("/info", post(create_info).get(get_info).put(update_info))
+        .route("/comms/:short_id", get(get_comm).post(create_comm))
+        .route("/comms/:short_id/stream", get(stream_comms))
+        .route("/webmention", post(receive_mention))
+        .route("/webmention/:id", delete(delete_mention))
+        .route("/webmentions/:short_id", get(list_mentions))
+        .route("/comm/challenge", get(get_challenge))
+        .route("/comm/:id", put(update_comm).delete(delete_comm))
+        .route("/comms/queue/:page", get(list_comm_queue))
+        .route("/comms/moderate", post(moderate_comms))
+        .route("/unsubscribe", get(unsubscribe).post(unsubscribe))
+        .route("/newsletter/subscribe", post(subscribe))
+        .route("/newsletter/confirm", get(confirm))
+        .route(
+            "/newsletter/unsubscribe",
+            get(newsletter::unsubscribe).post(newsletter::unsubscribe),
+        )
+        .route("/newsletter/subscribers/:page", get(list_subscribers))
+        .route("/online", get(online))
+        .route("/online/count", get(online_count))
+        .route("/outbox/:page", get(list_outbox))
+        .route("/outbox/replay", post(replay_outbox))
+        .route("/webhooks", post(create_webhook).get(list_webhooks))
+        .route("/webhook/:id", delete(delete_webhook))
+        .route("/webhook/:id/test", post(test_webhook))
+        .route("/webhook/:id/deliveries/:page", get(list_deliveries))
+        .route("/micropub/tokens", post(create_token).get(list_tokens))
+        .route("/micropub/token/:id", delete(delete_token))
+        .route(
+            "/micropub",
+            get(query)
+                .post(micropub::micropub)
+                .layer(DefaultBodyLimit::max(
+                    CONFIG.media.max_size() + FORM_OVERHEAD,
+                )),
+        )
+        .route(
+            "/micropub/media",
+            post(upload).layer(DefaultBodyLimit::max(
+                CONFIG.media.max_size() + FORM_OVERHEAD,
+            )),
+        )
+        .route("/media/:name", get(get_media))
+        .route(
+            "/xmlrpc",
+            post(metaweblog::xmlrpc).layer(DefaultBodyLimit::max(
+                CONFIG.media.max_size() / 3 * 4 + FORM_OVERHEAD,
+            )),
+        )
+        .route("/templates", get(list_templates))
+        .route("/template/preview", post(preview_template))
+        .route(
+            "/template/:event",
+            put(update_template).delete(reset_template),
+        )
         .with_state(state)
 }
 
@@ -60,8 +141,17 @@ pub fn router(state: AppState) -> Router {
             (path = "/api", api = user::UserDoc),
             (path = "/api", api = auth::AuthDoc),
             (path = "/api", api = note::NoteDoc),
+            (path = "/api", api = revision::RevisionDoc),
             (path = "/api", api = tag::TagDoc),
             (path = "/api", api = page::PageDoc),
+            (path = "/api", api = comm::CommDoc),
+            (path = "/api", api = notify::NotifyDoc),
+            (path = "/api", api = newsletter::NewsletterDoc),
+            (path = "/api", api = hooks::HooksDoc),
+            (path = "/api", api = online::OnlineDoc),
+            (path = "/api", api = webmention::WebmentionDoc),
+            (path = "/api", api = micropub::MicropubDoc),
+            (path = "/inbox", api = crate::inbox::contact::ContactDoc),
         ),
         tags(
             (name = "tsuiio's blog", description = "tsuiio's blog API")
@@ -0,0 +1,192 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    db::models::subscribers::Subscriber,
+    error::BlogError,
+    service::newsletter::{self, verify_confirm_token, verify_unsubscribe_token},
+    utils::{jwt::Claims, ClientIp},
+    AppState,
+};
+
+#[derive(Deserialize, ToSchema)]
+pub struct Subscribe {
+    email: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct ConfirmQuery {
+    email: String,
+    /// Unix time the link stops working
+    expires: i64,
+    /// Token from the confirmation mail
+    token: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct NewsletterUnsubscribeQuery {
+    email: String,
+    /// Token from the unsubscribe link of the mail
+    token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnSubscriber {
+    id: Uuid,
+    email: String,
+    created_at: NaiveDateTime,
+    confirmed_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListSubscribers {
+    pub subscribers: Vec<ReturnSubscriber>,
+    pub total: u64,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(subscribe, confirm, unsubscribe, list_subscribers),
+    components(schemas(Subscribe, ReturnSubscriber, ListSubscribers))
+)]
+pub struct NewsletterDoc;
+
+/// Mails a confirmation link, the subscription starts once it is followed.
+#[utoipa::path(
+    post,
+    path = "/newsletter/subscribe",
+    request_body = Subscribe,
+    responses(
+        (status = 200, description = "Confirmation mail on its way"),
+        (status = 400, description = "Bad Request"),
+        (status = 429, description = "Too many requests"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn subscribe(
+    state: State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(subscribe): Json<Subscribe>,
+) -> Result<String, BlogError> {
+    if !state.notify.can_mail() {
+        return Err(BlogError::BadRequest(String::from(
+            "newsletter is not available",
+        )));
+    }
+
+    let email = subscribe.email.trim();
+    if email.parse::<lettre::Address>().is_err() {
+        return Err(BlogError::BadRequest(String::from("invalid email")));
+    }
+    state.guard.throttle(&ip, email)?;
+
+    let mut conn = state.pool.get_owned().await?;
+    newsletter::subscribe(email, &state.notify, &mut conn).await?;
+
+    Ok(json!({ "ok": "subscribe ok!" }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/newsletter/confirm",
+    params(ConfirmQuery),
+    responses(
+        (status = 200, description = "Subscription confirmed"),
+        (status = 400, description = "Invalid or expired confirmation link")
+    )
+)]
+pub async fn confirm(
+    state: State<AppState>,
+    Query(query): Query<ConfirmQuery>,
+) -> Result<String, BlogError> {
+    let email = query.email.trim();
+    if !verify_confirm_token(email, query.expires, &query.token) {
+        return Err(BlogError::BadRequest(String::from(
+            "invalid or expired confirmation link",
+        )));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    Subscriber::confirm(email, &mut conn).await?;
+
+    Ok(json!({ "ok": "confirm subscription ok!" }).to_string())
+}
+
+/// Also accepts `POST` with the same query, for one-click unsubscribe from
+/// mail clients.
+#[utoipa::path(
+    get,
+    path = "/newsletter/unsubscribe",
+    params(NewsletterUnsubscribeQuery),
+    responses(
+        (status = 200, description = "No more newsletter mails to this address"),
+        (status = 400, description = "Invalid unsubscribe link")
+    )
+)]
+pub async fn unsubscribe(
+    state: State<AppState>,
+    Query(query): Query<NewsletterUnsubscribeQuery>,
+) -> Result<String, BlogError> {
+    let email = query.email.trim();
+    if !verify_unsubscribe_token(email, &query.token) {
+        return Err(BlogError::BadRequest(String::from(
+            "invalid unsubscribe link",
+        )));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    Subscriber::delete_by_email(email, &mut conn).await?;
+
+    Ok(json!({ "ok": "unsubscribe ok!" }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/newsletter/subscribers/{page}",
+    params(
+        ("page" = u64, Path, description = "List subscribers by page")
+    ),
+    responses(
+        (status = 200, description = "Subscribers retrieved successfully", body = ListSubscribers),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_subscribers(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(page): Path<u64>,
+) -> Result<Json<ListSubscribers>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let (subscribers, total) =
+        Subscriber::get_subscribers(limit as i64, offset as i64, &mut conn).await?;
+
+    let subscribers = subscribers
+        .into_iter()
+        .map(|subscriber| ReturnSubscriber {
+            id: subscriber.id,
+            email: subscriber.email,
+            created_at: subscriber.created_at,
+            confirmed_at: subscriber.confirmed_at,
+        })
+        .collect();
+
+    Ok(Json(ListSubscribers { subscribers, total }))
+}
@@ -4,7 +4,8 @@ use axum::{
     extract::{Path, State},
     Json,
 };
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
 use futures_util::future::try_join;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -19,7 +20,8 @@ use crate::{
         notes::{Note, Status},
     },
     error::BlogError,
-    utils::{extract_summary, jwt::Claims},
+    service::{federation, publish},
+    utils::{jwt::Claims, markdown, summary_of},
     AppState,
 };
 
@@ -28,9 +30,16 @@ pub struct CreateNote {
     pub title: String,
     pub subname: Option<String>,
     pub status: Status,
+    /// Taken from `content` when left out, up to a `<!--more-->` line if
+    /// there is one
+    pub summary: Option<String>,
     pub content: String,
     pub comm: bool,
     pub fancy_img: Option<String>,
+    /// UTC time to go public at, the note is kept a draft until then
+    pub publish_at: Option<NaiveDateTime>,
+    /// UTC time to go back to being a draft at
+    pub unpublish_at: Option<NaiveDateTime>,
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -38,9 +47,14 @@ pub struct UpdateNote {
     pub title: String,
     pub subname: Option<String>,
     pub status: Status,
+    /// Taken from `content` again when left out
+    pub summary: Option<String>,
     pub content: String,
     pub comm: bool,
     pub fancy_img: Option<String>,
+    /// Replaces the schedule, left out times are cleared
+    pub publish_at: Option<NaiveDateTime>,
+    pub unpublish_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -53,11 +67,28 @@ pub struct ReturnNote {
     #[serde(skip_serializing_if = "Option::is_none")]
     status: Option<Status>,
     summary: String,
+    /// Markdown source
     content: String,
+    /// `content` rendered to sanitized HTML
+    content_html: String,
     comm: bool,
     tags: Vec<String>,
     sorts: Vec<ListNoteSorts>,
     fancy_img: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    publish_at: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unpublish_at: Option<NaiveDateTime>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PreviewNote {
+    pub content: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RenderedNote {
+    pub content_html: String,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -74,6 +105,10 @@ pub struct ListNoteInner {
     pub tags: Vec<String>,
     pub sorts: Vec<ListNoteSorts>,
     pub fancy_img: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<NaiveDateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unpublish_at: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -90,10 +125,19 @@ pub struct ListNotes {
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(create_note, update_note, get_note, list_notes, delete_note),
+    paths(
+        create_note,
+        update_note,
+        get_note,
+        list_notes,
+        delete_note,
+        preview_note
+    ),
     components(schemas(
         CreateNote,
         UpdateNote,
+        PreviewNote,
+        RenderedNote,
         ListNoteInner,
         ListNoteSorts,
         ListNotes,
@@ -103,6 +147,29 @@ pub struct ListNotes {
 )]
 pub struct NoteDoc;
 
+/// What a note is saved as for the schedule given: times already past are
+/// dropped, and a note to go public later is kept a draft until then.
+fn schedule_of(
+    status: Status,
+    publish_at: Option<NaiveDateTime>,
+    unpublish_at: Option<NaiveDateTime>,
+) -> Result<(Status, Option<NaiveDateTime>, Option<NaiveDateTime>), BlogError> {
+    let now = Utc::now().naive_utc();
+    let publish_at = publish_at.filter(|at| *at > now);
+    if unpublish_at.is_some_and(|at| at <= publish_at.unwrap_or(now)) {
+        return Err(BlogError::BadRequest(String::from(
+            "unpublish_at must be later than publish_at and now",
+        )));
+    }
+
+    let status = match (status, publish_at) {
+        (Status::Public, Some(_)) => Status::Draft,
+        (status, _) => status,
+    };
+
+    Ok((status, publish_at, unpublish_at))
+}
+
 #[utoipa::path(
     post,
     path = "/note",
@@ -123,7 +190,9 @@ pub async fn create_note(
     let pool = &state.pool;
 
     let user_id = Uuid::parse_str(&claims.user_id)?;
-    let summary = extract_summary(&new_note.content, 40);
+    let summary = summary_of(new_note.summary.as_deref(), &new_note.content, None);
+    let (status, publish_at, unpublish_at) =
+        schedule_of(new_note.status, new_note.publish_at, new_note.unpublish_at)?;
 
     if let Some(subname) = &new_note.subname {
         let mut conn = pool.get_owned().await?;
@@ -139,9 +208,9 @@ pub async fn create_note(
         }
     }
 
-    Note::create_note(
+    let note_id = Note::create_note(
         new_note.subname.as_deref(),
-        &new_note.status,
+        &status,
         &new_note.title,
         &summary,
         &new_note.content,
@@ -156,6 +225,10 @@ pub async fn create_note(
         BlogError::InternalServerError
     })?;
 
+    let mut conn = pool.get_owned().await?;
+    Note::set_schedule(&note_id, publish_at, unpublish_at, &mut conn).await?;
+    publish::published(&note_id, &state.notify, &mut conn).await?;
+
     Ok(json!({ "ok": "create note ok!"}).to_string())
 }
 
@@ -176,13 +249,26 @@ pub async fn create_note(
 )]
 pub async fn update_note(
     state: State<AppState>,
-    _claims: Claims,
+    claims: Claims,
     Path(note_id): Path<Uuid>,
     Json(u_note): Json<UpdateNote>,
 ) -> Result<String, BlogError> {
     let pool = &state.pool;
+    let user_id = Uuid::parse_str(&claims.user_id)?;
 
-    let summary = extract_summary(&u_note.content, 40);
+    let stored = {
+        let mut conn = pool.get_owned().await?;
+        Note::find_note_by_uuid(&note_id, &mut conn)
+            .await?
+            .ok_or(BlogError::NotFound(String::from("not found note")))?
+    };
+    let summary = summary_of(
+        u_note.summary.as_deref(),
+        &u_note.content,
+        Some((&stored.summary, &stored.content)),
+    );
+    let (status, publish_at, unpublish_at) =
+        schedule_of(u_note.status, u_note.publish_at, u_note.unpublish_at)?;
 
     Note::update_note_by_uuid(
         &note_id,
@@ -190,9 +276,10 @@ pub async fn update_note(
         u_note.subname.as_deref(),
         &summary,
         &u_note.content,
-        &u_note.status,
+        &status,
         u_note.comm,
         u_note.fancy_img.as_deref(),
+        &user_id,
         pool.clone(),
     )
     .await
@@ -201,6 +288,10 @@ pub async fn update_note(
         BlogError::InternalServerError
     })?;
 
+    let mut conn = pool.get_owned().await?;
+    Note::set_schedule(&note_id, publish_at, unpublish_at, &mut conn).await?;
+    publish::published(&note_id, &state.notify, &mut conn).await?;
+
     Ok(json!({ "ok": "update note ok!"}).to_string())
 }
 
@@ -300,6 +391,8 @@ pub async fn list_notes(
             tags: tags_map.remove(&note.id).unwrap_or_default(),
             sorts: sorts_map.remove(&note.id).unwrap_or_default(),
             fancy_img: note.fancy_img,
+            publish_at: note.publish_at.filter(|_| is_authenticated),
+            unpublish_at: note.unpublish_at.filter(|_| is_authenticated),
         })
         .collect();
 
@@ -328,14 +421,29 @@ pub async fn delete_note(
     _claims: Claims,
     Path(note_id): Path<Uuid>,
 ) -> Result<String, BlogError> {
-    let pool = &state.pool;
+    let mut conn = state.pool.get_owned().await?;
+    let note_id = &note_id;
 
-    Note::delete_note_by_uuid(&note_id, pool.clone())
-        .await
-        .map_err(|e| {
-            error!("delete note {} error: {}", note_id, e);
-            BlogError::InternalServerError
-        })?;
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            let note = Note::find_note_by_uuid(note_id, conn).await?;
+
+            Note::delete_note_by_uuid(note_id, conn).await?;
+
+            // queued with the delete, so followers hear of it only if it sticks
+            if let Some(note) = note {
+                federation::enqueue_delete(&note, conn).await?;
+            }
+
+            Ok(())
+        }
+        .scope_boxed()
+    })
+    .await
+    .map_err(|e| {
+        error!("delete note {} error: {}", note_id, e);
+        BlogError::InternalServerError
+    })?;
 
     Ok(json!({ "ok": "delete note ok!"}).to_string())
 }
@@ -388,6 +496,7 @@ pub async fn get_note(
     })?;
 
     let is_authenticated = claims.is_some();
+    let content_html = note.html();
 
     Ok(Json(ReturnNote {
         id: if is_authenticated {
@@ -405,6 +514,7 @@ pub async fn get_note(
         },
         summary: note.summary,
         content: note.content,
+        content_html,
         comm: note.comm,
         tags: tags.into_iter().map(|t| t.content).collect(),
         sorts: sorts
@@ -415,5 +525,55 @@ pub async fn get_note(
             })
             .collect(),
         fancy_img: note.fancy_img,
+        publish_at: note.publish_at.filter(|_| is_authenticated),
+        unpublish_at: note.unpublish_at.filter(|_| is_authenticated),
     }))
 }
+
+/// Renders content the way it would be saved, without saving anything.
+#[utoipa::path(
+    post,
+    path = "/note/preview",
+    request_body = PreviewNote,
+    responses(
+        (status = 200, description = "Content rendered", body = RenderedNote),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn preview_note(
+    _claims: Claims,
+    Json(preview): Json<PreviewNote>,
+) -> Result<Json<RenderedNote>, BlogError> {
+    Ok(Json(RenderedNote {
+        content_html: markdown::render(&preview.content),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::*;
+
+    #[test]
+    fn test_schedule_of() {
+        let now = Utc::now().naive_utc();
+        let hour = TimeDelta::hours(1);
+
+        let (status, publish_at, unpublish_at) =
+            schedule_of(Status::Public, Some(now + hour), Some(now + hour * 2)).unwrap();
+        assert_eq!(status, Status::Draft);
+        assert_eq!(publish_at, Some(now + hour));
+        assert_eq!(unpublish_at, Some(now + hour * 2));
+
+        let (status, publish_at, _) = schedule_of(Status::Public, Some(now - hour), None).unwrap();
+        assert_eq!(status, Status::Public);
+        assert_eq!(publish_at, None);
+
+        assert!(schedule_of(Status::Public, Some(now + hour * 2), Some(now + hour)).is_err());
+        assert!(schedule_of(Status::Public, None, Some(now - hour)).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    db::models::{
+        mail_unsubscribes::MailUnsubscribe,
+        notify_outbox::{OutboxMessage, OutboxStatus},
+        notify_templates::NotifyTemplate,
+    },
+    error::BlogError,
+    service::notify::{
+        template::{self, Rendered, Templates},
+        verify_unsubscribe_token,
+    },
+    utils::jwt::Claims,
+    AppState,
+};
+
+#[derive(Deserialize, IntoParams)]
+pub struct UnsubscribeQuery {
+    /// Recipient the mail was sent to
+    email: String,
+    /// Token from the unsubscribe link of the mail
+    token: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct OutboxQuery {
+    /// Delivery state to list, `dead` when omitted
+    status: Option<OutboxStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnOutboxMessage {
+    id: Uuid,
+    created_at: NaiveDateTime,
+    channel: String,
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+    status: OutboxStatus,
+    attempts: i32,
+    next_attempt_at: NaiveDateTime,
+    last_error: Option<String>,
+    delivered_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListOutboxMessages {
+    pub messages: Vec<ReturnOutboxMessage>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReplayOutboxMessages {
+    ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnTemplate {
+    event: String,
+    /// The templates in effect
+    templates: Templates,
+    /// Whether the admin has edited them
+    custom: bool,
+    /// Sample of the variables the templates get
+    #[schema(value_type = Object)]
+    vars: serde_json::Value,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PreviewTemplate {
+    event: String,
+    /// Parts to try out, the ones in effect are used for the rest
+    #[serde(default)]
+    templates: Templates,
+    /// Overrides of the sample variables
+    #[schema(value_type = Option<Object>)]
+    vars: Option<serde_json::Value>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        unsubscribe,
+        list_outbox,
+        replay_outbox,
+        list_templates,
+        update_template,
+        reset_template,
+        preview_template
+    ),
+    components(schemas(
+        ReturnOutboxMessage,
+        ListOutboxMessages,
+        ReplayOutboxMessages,
+        OutboxStatus,
+        ReturnTemplate,
+        PreviewTemplate,
+        Templates,
+        Rendered
+    ))
+)]
+pub struct NotifyDoc;
+
+/// Also accepts `POST` with the same query, for one-click unsubscribe from
+/// mail clients.
+#[utoipa::path(
+    get,
+    path = "/unsubscribe",
+    params(UnsubscribeQuery),
+    responses(
+        (status = 200, description = "No more notification mails to this address"),
+        (status = 400, description = "Invalid unsubscribe link")
+    )
+)]
+pub async fn unsubscribe(
+    state: State<AppState>,
+    Query(query): Query<UnsubscribeQuery>,
+) -> Result<String, BlogError> {
+    let email = query.email.trim();
+    if !verify_unsubscribe_token(email, &query.token) {
+        return Err(BlogError::BadRequest(String::from(
+            "invalid unsubscribe link",
+        )));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    MailUnsubscribe::create(email, &mut conn).await?;
+
+    Ok(json!({ "ok": "unsubscribe ok!" }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/outbox/{page}",
+    params(
+        ("page" = u64, Path, description = "List outbox messages by page"),
+        OutboxQuery
+    ),
+    responses(
+        (status = 200, description = "Outbox messages retrieved successfully", body = ListOutboxMessages),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_outbox(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(page): Path<u64>,
+    Query(query): Query<OutboxQuery>,
+) -> Result<Json<ListOutboxMessages>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let status = query.status.unwrap_or(OutboxStatus::Dead);
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let (messages, total) =
+        OutboxMessage::get_messages_by_status(&status, limit as i64, offset as i64, &mut conn)
+            .await?;
+
+    let messages = messages
+        .into_iter()
+        .map(|message| ReturnOutboxMessage {
+            id: message.id,
+            created_at: message.created_at,
+            channel: message.channel,
+            payload: message.payload,
+            status: message.status,
+            attempts: message.attempts,
+            next_attempt_at: message.next_attempt_at,
+            last_error: message.last_error,
+            delivered_at: message.delivered_at,
+        })
+        .collect();
+
+    Ok(Json(ListOutboxMessages { messages, total }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/outbox/replay",
+    request_body = ReplayOutboxMessages,
+    responses(
+        (status = 200, description = "Messages queued for delivery again"),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn replay_outbox(
+    state: State<AppState>,
+    _claims: Claims,
+    Json(replay): Json<ReplayOutboxMessages>,
+) -> Result<String, BlogError> {
+    if replay.ids.is_empty() {
+        return Err(BlogError::BadRequest(String::from("no messages selected")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    let replayed = OutboxMessage::replay_by_uuids(&replay.ids, &mut conn).await?;
+
+    Ok(json!({ "ok": "replay outbox ok!", "replayed": replayed }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/templates",
+    responses(
+        (status = 200, description = "Notification templates retrieved successfully", body = Vec<ReturnTemplate>),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_templates(
+    state: State<AppState>,
+    _claims: Claims,
+) -> Result<Json<Vec<ReturnTemplate>>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let stored = NotifyTemplate::get_all(&mut conn).await?;
+    let mut templates = Vec::new();
+    for event in template::EVENTS {
+        templates.push(ReturnTemplate {
+            event: event.to_string(),
+            templates: Templates::load(event, &mut conn).await?.unwrap_or_default(),
+            custom: stored.iter().any(|t| t.event == *event),
+            vars: template::sample(event).unwrap_or_default(),
+        });
+    }
+
+    Ok(Json(templates))
+}
+
+/// Parts left out fall back to the template directory or the built-in
+/// templates. Templates that do not render against the sample variables are
+/// refused.
+#[utoipa::path(
+    put,
+    path = "/template/{event}",
+    params(
+        ("event" = String, Path, description = "Event the templates are for")
+    ),
+    request_body = Templates,
+    responses(
+        (status = 200, description = "Templates saved"),
+        (status = 400, description = "Templates do not render"),
+        (status = 404, description = "Unknown event"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn update_template(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(event): Path<String>,
+    Json(templates): Json<Templates>,
+) -> Result<String, BlogError> {
+    let (builtin, vars) = Templates::builtin(&event)
+        .zip(template::sample(&event))
+        .ok_or(BlogError::NotFound(String::from("unknown event")))?;
+
+    let templates = templates.normalize();
+    templates
+        .clone()
+        .or(builtin)
+        .render(&event, &vars)
+        .map_err(|e| BlogError::BadRequest(e.to_string()))?;
+
+    let mut conn = state.pool.get_owned().await?;
+    NotifyTemplate {
+        event,
+        subject: templates.subject,
+        html: templates.html,
+        text: templates.text,
+        telegram: templates.telegram,
+        webhook: templates.webhook,
+    }
+    .save(&mut conn)
+    .await?;
+
+    Ok(json!({ "ok": "update template ok!" }).to_string())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/template/{event}",
+    params(
+        ("event" = String, Path, description = "Event the templates are for")
+    ),
+    responses(
+        (status = 200, description = "Back to the default templates"),
+        (status = 404, description = "Unknown event"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn reset_template(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(event): Path<String>,
+) -> Result<String, BlogError> {
+    if Templates::builtin(&event).is_none() {
+        return Err(BlogError::NotFound(String::from("unknown event")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    NotifyTemplate::delete_by_event(&event, &mut conn).await?;
+
+    Ok(json!({ "ok": "reset template ok!" }).to_string())
+}
+
+/// Renders templates against sample variables without saving anything.
+#[utoipa::path(
+    post,
+    path = "/template/preview",
+    request_body = PreviewTemplate,
+    responses(
+        (status = 200, description = "Templates rendered", body = Rendered),
+        (status = 400, description = "Templates do not render"),
+        (status = 404, description = "Unknown event"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn preview_template(
+    state: State<AppState>,
+    _claims: Claims,
+    Json(preview): Json<PreviewTemplate>,
+) -> Result<Json<Rendered>, BlogError> {
+    let mut vars = template::sample(&preview.event)
+        .ok_or(BlogError::NotFound(String::from("unknown event")))?;
+    if let (Some(vars), Some(overrides)) = (
+        vars.as_object_mut(),
+        preview.vars.as_ref().and_then(|v| v.as_object()),
+    ) {
+        vars.extend(overrides.clone());
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    let current = Templates::load(&preview.event, &mut conn)
+        .await?
+        .unwrap_or_default();
+
+    let rendered = preview
+        .templates
+        .normalize()
+        .or(current)
+        .render(&preview.event, &vars)
+        .map_err(|e| BlogError::BadRequest(e.to_string()))?;
+
+    Ok(Json(rendered))
+}
@@ -1,5 +1,135 @@
-use crate::error::BlogError;
+use axum::{
+    extract::{Query, State},
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+use utoipa::{IntoParams, OpenApi, ToSchema};
 
-pub async fn online() -> Result<(), BlogError> {
-    Ok(())
+use crate::{
+    error::BlogError,
+    service::online::{Counts, Presence, HEARTBEAT},
+    utils::SHUTDOWN,
+    AppState,
+};
+
+/// Longest short_id counted separately.
+const MAX_SHORT_ID: usize = 64;
+
+#[derive(Deserialize, IntoParams)]
+pub struct OnlineQuery {
+    /// Note or page being read, to count its readers too
+    short_id: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnOnline {
+    /// Readers anywhere on the site
+    site: usize,
+    /// Readers of `short_id`, when asked for
+    page: Option<usize>,
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(online, online_count), components(schemas(ReturnOnline)))]
+pub struct OnlineDoc;
+
+fn short_id(query: OnlineQuery) -> Result<Option<String>, BlogError> {
+    match query.short_id {
+        Some(short_id) if short_id.is_empty() || short_id.len() > MAX_SHORT_ID => {
+            Err(BlogError::BadRequest(String::from("invalid short_id")))
+        }
+        short_id => Ok(short_id),
+    }
+}
+
+struct Watch {
+    presence: Presence,
+    short_id: Option<String>,
+    changed: watch::Receiver<()>,
+    last: Option<Counts>,
+}
+
+/// Counts this reader for as long as the stream is open, sending an `online`
+/// event with [`ReturnOnline`] as data every time the counts change. The
+/// stream ends on shutdown.
+#[utoipa::path(
+    get,
+    path = "/online",
+    params(OnlineQuery),
+    responses(
+        (status = 200, description = "Server-sent events of online counts", content_type = "text/event-stream"),
+        (status = 400, description = "Bad Request"),
+        (status = 429, description = "Too many readers online")
+    )
+)]
+pub async fn online(
+    state: State<AppState>,
+    Query(query): Query<OnlineQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, BlogError> {
+    let short_id = short_id(query)?;
+    let watch = Watch {
+        presence: state.online.join(short_id.clone())?,
+        short_id,
+        changed: state.online.subscribe(),
+        last: None,
+    };
+    let online = state.online.clone();
+
+    let events = stream::unfold(watch, move |mut watch| {
+        let online = online.clone();
+        async move {
+            loop {
+                if !watch.presence.heartbeat() {
+                    return None;
+                }
+
+                let counts = online.counts(watch.short_id.as_deref());
+                if watch.last != Some(counts) {
+                    watch.last = Some(counts);
+                    let data = ReturnOnline {
+                        site: counts.site,
+                        page: counts.page,
+                    };
+                    let event = Event::default().event("online").json_data(data);
+                    return Some((event, watch));
+                }
+
+                tokio::select! {
+                    changed = watch.changed.changed() => if changed.is_err() {
+                        return None;
+                    },
+                    _ = tokio::time::sleep(HEARTBEAT) => {}
+                }
+            }
+        }
+    })
+    .take_until(SHUTDOWN.wait_for_shutdown());
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// The same counts once, for clients that only poll.
+#[utoipa::path(
+    get,
+    path = "/online/count",
+    params(OnlineQuery),
+    responses(
+        (status = 200, description = "Online counts", body = ReturnOnline),
+        (status = 400, description = "Bad Request")
+    )
+)]
+pub async fn online_count(
+    state: State<AppState>,
+    Query(query): Query<OnlineQuery>,
+) -> Result<Json<ReturnOnline>, BlogError> {
+    let short_id = short_id(query)?;
+    let counts = state.online.counts(short_id.as_deref());
+
+    Ok(Json(ReturnOnline {
+        site: counts.site,
+        page: counts.page,
+    }))
 }
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    db::models::{
+        note_revisions::NoteRevision,
+        notes::{Note, Status},
+        users::User,
+    },
+    error::BlogError,
+    service::{
+        publish,
+        revisions::{self, Change, DiffMode, Op},
+    },
+    utils::jwt::Claims,
+    AppState,
+};
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnRevision {
+    id: Uuid,
+    created_at: NaiveDateTime,
+    /// Who saved it, unknown once their account is gone
+    user_id: Option<Uuid>,
+    nickname: Option<String>,
+    /// Whether the note is at this version now
+    current: bool,
+    title: String,
+    summary: String,
+    /// Only given for a single revision
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListRevisions {
+    pub revisions: Vec<ReturnRevision>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct DiffQuery {
+    /// Revision to compare from
+    from: Uuid,
+    /// Revision to compare to, the current one when omitted
+    to: Option<Uuid>,
+    /// `unified` when omitted
+    mode: Option<DiffMode>,
+}
+
+/// How one field changed, as a unified diff or as word changes depending on
+/// the mode.
+#[derive(Serialize, ToSchema)]
+pub struct FieldDiff {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unified: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changes: Option<Vec<Change>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnDiff {
+    from: Uuid,
+    to: Uuid,
+    title: FieldDiff,
+    summary: FieldDiff,
+    content: FieldDiff,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list_revisions, get_revision, diff_revisions, restore_revision),
+    components(schemas(
+        ReturnRevision,
+        ListRevisions,
+        DiffMode,
+        Op,
+        Change,
+        FieldDiff,
+        ReturnDiff
+    ))
+)]
+pub struct RevisionDoc;
+
+/// Saved versions of a note, newest first.
+#[utoipa::path(
+    get,
+    path = "/note/{id}/revisions/{page}",
+    params(
+        ("id" = Uuid, Path, description = "Note id"),
+        ("page" = u64, Path, description = "List revisions by page")
+    ),
+    responses(
+        (status = 200, description = "Revisions retrieved successfully", body = ListRevisions),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_revisions(
+    state: State<AppState>,
+    _claims: Claims,
+    Path((id, page)): Path<(Uuid, u64)>,
+) -> Result<Json<ListRevisions>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let (revisions, total) =
+        NoteRevision::get_by_note(&id, limit as i64, offset as i64, &mut conn).await?;
+
+    let user_ids: Vec<Uuid> = revisions.iter().filter_map(|r| r.user_id).collect();
+    let nicknames: HashMap<Uuid, String> = User::get_nicknames_by_ids(&user_ids, &mut conn)
+        .await?
+        .into_iter()
+        .collect();
+
+    let revisions = revisions
+        .into_iter()
+        .enumerate()
+        .map(|(i, revision)| ReturnRevision {
+            id: revision.id,
+            created_at: revision.created_at,
+            user_id: revision.user_id,
+            nickname: revision.user_id.and_then(|id| nicknames.get(&id).cloned()),
+            current: page == 1 && i == 0,
+            title: revision.title,
+            summary: revision.summary,
+            content: None,
+        })
+        .collect();
+
+    Ok(Json(ListRevisions { revisions, total }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/note/{id}/revision/{rev}",
+    params(
+        ("id" = Uuid, Path, description = "Note id"),
+        ("rev" = Uuid, Path, description = "Revision id")
+    ),
+    responses(
+        (status = 200, description = "Revision retrieved successfully", body = ReturnRevision),
+        (status = 404, description = "Revision not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn get_revision(
+    state: State<AppState>,
+    _claims: Claims,
+    Path((id, rev)): Path<(Uuid, Uuid)>,
+) -> Result<Json<ReturnRevision>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let revision = NoteRevision::find(&id, &rev, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found revision")))?;
+    let current = NoteRevision::latest(&id, &mut conn)
+        .await?
+        .is_some_and(|latest| latest.id == revision.id);
+    let nickname = match revision.user_id {
+        Some(user_id) => User::get_nicknames_by_ids(&[user_id], &mut conn)
+            .await?
+            .pop()
+            .map(|(_, nickname)| nickname),
+        None => None,
+    };
+
+    Ok(Json(ReturnRevision {
+        id: revision.id,
+        created_at: revision.created_at,
+        user_id: revision.user_id,
+        nickname,
+        current,
+        title: revision.title,
+        summary: revision.summary,
+        content: Some(revision.content),
+    }))
+}
+
+/// Compares two revisions of a note.
+#[utoipa::path(
+    get,
+    path = "/note/{id}/diff",
+    params(
+        ("id" = Uuid, Path, description = "Note id"),
+        DiffQuery
+    ),
+    responses(
+        (status = 200, description = "Revisions compared successfully", body = ReturnDiff),
+        (status = 404, description = "Revision not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn diff_revisions(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+    Query(query): Query<DiffQuery>,
+) -> Result<Json<ReturnDiff>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let not_found = || BlogError::NotFound(String::from("not found revision"));
+    let from = NoteRevision::find(&id, &query.from, &mut conn)
+        .await?
+        .ok_or_else(not_found)?;
+    let to = match query.to {
+        Some(to) => NoteRevision::find(&id, &to, &mut conn).await?,
+        None => NoteRevision::latest(&id, &mut conn).await?,
+    }
+    .ok_or_else(not_found)?;
+
+    let mode = query.mode.unwrap_or_default();
+    let diff = |field: &str, old: &str, new: &str| match mode {
+        DiffMode::Unified => FieldDiff {
+            unified: Some(revisions::unified(
+                old,
+                new,
+                &format!("{} {}", field, from.created_at),
+                &format!("{} {}", field, to.created_at),
+            )),
+            changes: None,
+        },
+        DiffMode::Words => FieldDiff {
+            unified: None,
+            changes: Some(revisions::words(old, new)),
+        },
+    };
+
+    Ok(Json(ReturnDiff {
+        from: from.id,
+        to: to.id,
+        title: diff("title", &from.title, &to.title),
+        summary: diff("summary", &from.summary, &to.summary),
+        content: diff("content", &from.content, &to.content),
+    }))
+}
+
+/// Brings the title, summary and content of a revision back as a new
+/// version. Status, comments and the rest stay as they are.
+#[utoipa::path(
+    post,
+    path = "/note/{id}/revision/{rev}/restore",
+    params(
+        ("id" = Uuid, Path, description = "Note id"),
+        ("rev" = Uuid, Path, description = "Revision id")
+    ),
+    responses(
+        (status = 200, description = "Revision restored successfully"),
+        (status = 400, description = "Note is deleted"),
+        (status = 404, description = "Revision not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn restore_revision(
+    state: State<AppState>,
+    claims: Claims,
+    Path((id, rev)): Path<(Uuid, Uuid)>,
+) -> Result<String, BlogError> {
+    let user_id = Uuid::parse_str(&claims.user_id)?;
+    let mut conn = state.pool.get_owned().await?;
+
+    let note = Note::find_note_by_uuid(&id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found note")))?;
+    if note.status == Status::Recycle {
+        return Err(BlogError::BadRequest(String::from("note is deleted")));
+    }
+    let revision = NoteRevision::find(&id, &rev, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found revision")))?;
+    let subname = Note::find_subname(&id, &mut conn).await?;
+
+    Note::update_note_by_uuid(
+        &id,
+        &revision.title,
+        subname.as_deref(),
+        &revision.summary,
+        &revision.content,
+        &note.status,
+        note.comm,
+        note.fancy_img.as_deref(),
+        &user_id,
+        state.pool.clone(),
+    )
+    .await?;
+    publish::published(&id, &state.notify, &mut conn).await?;
+
+    Ok(json!({ "ok": "restore revision ok!"}).to_string())
+}
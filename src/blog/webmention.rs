@@ -0,0 +1,253 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Form, Json,
+};
+use chrono::NaiveDateTime;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::models::{comms::Comm, notes::Status, webmentions::Webmention},
+    error::BlogError,
+    service::webmention::short_name,
+    utils::{jwt::Claims, ClientIp},
+    AppState,
+};
+
+/// Longest source or target taken.
+const MAX_URL_LEN: usize = 2048;
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReceiveMention {
+    /// Page that mentions ours
+    #[schema(example = "https://example.org/reply")]
+    source: String,
+    /// Our page it mentions
+    #[schema(example = "https://example.com/hello-world")]
+    target: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnMention {
+    id: Uuid,
+    source: String,
+    author_name: Option<String>,
+    author_url: Option<String>,
+    title: Option<String>,
+    /// Excerpt of the source's content
+    content: Option<String>,
+    verified_at: Option<NaiveDateTime>,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(receive_mention, list_mentions, delete_mention),
+    components(schemas(ReceiveMention, ReturnMention))
+)]
+pub struct WebmentionDoc;
+
+/// Checks a received mention, returning its source and target along with the
+/// short name the target is known by.
+fn check_mention(
+    source: &str,
+    target: &str,
+    site: &Url,
+) -> Result<(Url, Url, String), &'static str> {
+    if source.len() > MAX_URL_LEN || target.len() > MAX_URL_LEN {
+        return Err("url too long");
+    }
+    let source = Url::parse(source.trim()).map_err(|_| "invalid source")?;
+    let target = Url::parse(target.trim()).map_err(|_| "invalid target")?;
+    if !matches!(source.scheme(), "http" | "https") {
+        return Err("invalid source");
+    }
+    if source == target {
+        return Err("source and target are the same");
+    }
+    let name = short_name(&target, site).ok_or("target is not on this site")?;
+
+    Ok((source, target, name))
+}
+
+/// Receives a [Webmention](https://www.w3.org/TR/webmention/). The source
+/// is fetched later, the mention shows up once it is found to link here.
+#[utoipa::path(
+    post,
+    path = "/webmention",
+    request_body(content = ReceiveMention, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 202, description = "Mention queued for verification"),
+        (status = 400, description = "Bad Request"),
+        (status = 429, description = "Too many requests"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn receive_mention(
+    state: State<AppState>,
+    ClientIp(ip): ClientIp,
+    Form(mention): Form<ReceiveMention>,
+) -> Result<(StatusCode, String), BlogError> {
+    let site = Url::parse(&CONFIG.site_url()).map_err(|e| anyhow::anyhow!(e))?;
+    let (source, target, name) = check_mention(&mention.source, &mention.target, &site)
+        .map_err(|e| BlogError::BadRequest(String::from(e)))?;
+    state
+        .guard
+        .throttle(&ip, source.host_str().unwrap_or_default())?;
+
+    let mut conn = state.pool.get_owned().await?;
+    let comm_target = Comm::find_target_by_short_id(&name, &mut conn)
+        .await?
+        .filter(|t| t.status == Status::Public)
+        .ok_or(BlogError::BadRequest(String::from(
+            "target is not a note or page",
+        )))?;
+
+    Webmention::upsert(
+        source.as_str(),
+        target.as_str(),
+        &comm_target.id,
+        comm_target.comm_type,
+        &mut conn,
+    )
+    .await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        json!({ "ok": "receive webmention ok!" }).to_string(),
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webmentions/{short_id}",
+    params(
+        ("short_id" = String, Path, description = "Note or page short_id")
+    ),
+    responses(
+        (status = 200, description = "Mentions retrieved successfully", body = Vec<ReturnMention>),
+        (status = 404, description = "Note or page not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        (),
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_mentions(
+    state: State<AppState>,
+    claims: Option<Claims>,
+    Path(short_id): Path<String>,
+) -> Result<Json<Vec<ReturnMention>>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let target = Comm::find_target_by_short_id(&short_id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("not found note or page")))?;
+    if claims.is_none() && target.status != Status::Public {
+        return Err(BlogError::NotFound(String::from("not found note or page")));
+    }
+
+    let mentions = Webmention::get_verified_by_type_id(&target.id, &mut conn)
+        .await?
+        .into_iter()
+        .map(|mention| ReturnMention {
+            id: mention.id,
+            source: mention.source,
+            author_name: mention.author_name,
+            author_url: mention.author_url,
+            title: mention.title,
+            content: mention.content,
+            verified_at: mention.verified_at,
+        })
+        .collect();
+
+    Ok(Json(mentions))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webmention/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Mention ID")
+    ),
+    responses(
+        (status = 200, description = "Mention deleted"),
+        (status = 404, description = "Mention not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn delete_mention(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+) -> Result<String, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    if !Webmention::delete_by_uuid(&id, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("mention not found")));
+    }
+
+    Ok(json!({ "ok": "delete webmention ok!" }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_mention() {
+        let site = Url::parse("https://example.com/blog").unwrap();
+        let check = |source, target| check_mention(source, target, &site);
+
+        let (source, target, name) = check(
+            "https://example.org/reply",
+            "https://example.com/blog/hello#top",
+        )
+        .unwrap();
+        assert_eq!(source.as_str(), "https://example.org/reply");
+        assert_eq!(target.as_str(), "https://example.com/blog/hello#top");
+        assert_eq!(name, "hello");
+
+        assert_eq!(
+            check(
+                "https://example.org/reply",
+                "https://example.com/blog/%E3%81%AD%E3%81%93/"
+            )
+            .unwrap()
+            .2,
+            "ねこ"
+        );
+        assert_eq!(
+            check("ftp://example.org/reply", "https://example.com/blog/hello").unwrap_err(),
+            "invalid source"
+        );
+        assert_eq!(
+            check(
+                "https://example.com/blog/hello",
+                "https://example.com/blog/hello"
+            )
+            .unwrap_err(),
+            "source and target are the same"
+        );
+        for target in [
+            "https://example.org/blog/hello",
+            "http://example.com/blog/hello",
+            "https://example.com/hello",
+            "https://example.com/blog/",
+            "https://example.com/blog/hello/world",
+        ] {
+            assert_eq!(
+                check("https://example.org/reply", target).unwrap_err(),
+                "target is not on this site"
+            );
+        }
+    }
+}
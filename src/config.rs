@@ -33,6 +33,22 @@ pub struct Config {
     pub db: Db,
     #[clap(flatten)]
     pub log: LogLevel,
+    #[clap(flatten)]
+    pub antispam: AntiSpam,
+    #[clap(flatten)]
+    pub smtp: Smtp,
+    #[clap(flatten)]
+    pub notify: Notify,
+    #[clap(flatten)]
+    pub inbox: Inbox,
+    #[clap(flatten)]
+    pub federation: Federation,
+    #[clap(flatten)]
+    pub media: Media,
+    #[clap(flatten)]
+    pub revisions: Revisions,
+    #[clap(flatten)]
+    pub schedule: Schedule,
 }
 
 #[derive(Debug, Args, Serialize, Deserialize)]
@@ -53,6 +69,11 @@ pub struct Web {
     #[serde(rename = "jwtexp")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwt_exp: Option<u64>,
+    /// Public address of the blog, used for links in outgoing mails
+    #[clap(long = "web-site-url")]
+    #[serde(rename = "siteurl")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site_url: Option<String>,
 }
 
 #[derive(Debug, Args, Serialize, Deserialize)]
@@ -94,6 +115,531 @@ pub struct LogLevel {
     pub level: Option<Log>,
 }
 
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct AntiSpam {
+    #[clap(long = "antispam-enabled")]
+    #[serde(rename = "enabled")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub antispam_enabled: Option<bool>,
+    /// Leading zero bits the proof-of-work hash needs, 0 turns it off
+    #[clap(long = "antispam-pow-difficulty")]
+    #[serde(rename = "powdifficulty")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pow_difficulty: Option<u32>,
+    /// Seconds an issued challenge stays valid
+    #[clap(long = "antispam-challenge-ttl")]
+    #[serde(rename = "challengettl")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge_ttl: Option<u64>,
+    /// Comms allowed per IP within one window
+    #[clap(long = "antispam-ip-limit")]
+    #[serde(rename = "iplimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_limit: Option<usize>,
+    /// Comms allowed per email within one window
+    #[clap(long = "antispam-email-limit")]
+    #[serde(rename = "emaillimit")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_limit: Option<usize>,
+    /// Sliding window length in seconds
+    #[clap(long = "antispam-window")]
+    #[serde(rename = "window")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window: Option<u64>,
+    #[clap(long = "antispam-max-links")]
+    #[serde(rename = "maxlinks")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_links: Option<usize>,
+    /// Take the client IP from `X-Forwarded-For` / `X-Real-IP`
+    #[clap(long = "antispam-trust-proxy")]
+    #[serde(rename = "trustproxy")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_proxy: Option<bool>,
+    /// Classifier score from which an anonymous comm goes straight to spam
+    #[clap(long = "antispam-spam-threshold")]
+    #[serde(rename = "spamthreshold")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spam_threshold: Option<f64>,
+    /// Classifier score from which an anonymous comm always waits in the queue
+    #[clap(long = "antispam-ham-threshold")]
+    #[serde(rename = "hamthreshold")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ham_threshold: Option<f64>,
+    /// Comms each class needs to be trained on before the classifier is used
+    #[clap(long = "antispam-min-samples")]
+    #[serde(rename = "minsamples")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_samples: Option<i64>,
+}
+
+impl AntiSpam {
+    pub fn enabled(&self) -> bool {
+        self.antispam_enabled.unwrap_or(true)
+    }
+
+    pub fn pow_difficulty(&self) -> u32 {
+        self.pow_difficulty.unwrap_or(16)
+    }
+
+    pub fn challenge_ttl(&self) -> u64 {
+        self.challenge_ttl.unwrap_or(60 * 10)
+    }
+
+    pub fn ip_limit(&self) -> usize {
+        self.ip_limit.unwrap_or(5)
+    }
+
+    pub fn email_limit(&self) -> usize {
+        self.email_limit.unwrap_or(3)
+    }
+
+    pub fn window(&self) -> u64 {
+        self.window.unwrap_or(60 * 10)
+    }
+
+    pub fn max_links(&self) -> usize {
+        self.max_links.unwrap_or(3)
+    }
+
+    pub fn trust_proxy(&self) -> bool {
+        self.trust_proxy.unwrap_or(false)
+    }
+
+    pub fn spam_threshold(&self) -> f64 {
+        self.spam_threshold.unwrap_or(0.9)
+    }
+
+    pub fn ham_threshold(&self) -> f64 {
+        self.ham_threshold.unwrap_or(0.4)
+    }
+
+    pub fn min_samples(&self) -> i64 {
+        self.min_samples.unwrap_or(10)
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Smtp {
+    /// Mail relay, no mails are sent while it is unset
+    #[clap(long = "smtp-host")]
+    #[serde(rename = "host")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_host: Option<String>,
+    #[clap(long = "smtp-port")]
+    #[serde(rename = "port")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_port: Option<u16>,
+    #[clap(long = "smtp-tls")]
+    #[serde(rename = "tls")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_tls: Option<SmtpTls>,
+    #[clap(long = "smtp-user")]
+    #[serde(rename = "user")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_user: Option<String>,
+    #[clap(long = "smtp-passwd")]
+    #[serde(rename = "passwd")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_passwd: Option<String>,
+    /// Sender mailbox, e.g. `Blog <blog@example.com>`
+    #[clap(long = "smtp-from")]
+    #[serde(rename = "from")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smtp_from: Option<String>,
+}
+
+impl Smtp {
+    pub fn tls(&self) -> SmtpTls {
+        self.smtp_tls.unwrap_or(SmtpTls::Starttls)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.smtp_port.unwrap_or(match self.tls() {
+            SmtpTls::None => 25,
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+        })
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    None,
+    Starttls,
+    Tls,
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Notify {
+    /// Where owner notifications go, `smtp` alone by default
+    #[clap(long = "notify-channels", value_delimiter = ',')]
+    #[serde(rename = "channels")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_channels: Option<Vec<NotifyChannel>>,
+    #[clap(long = "notify-telegram-token")]
+    #[serde(rename = "telegramtoken")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_token: Option<String>,
+    #[clap(long = "notify-telegram-chat-id")]
+    #[serde(rename = "telegramchatid")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_chat_id: Option<String>,
+    /// Bot API base URL, for self-hosted Bot API servers
+    #[clap(long = "notify-telegram-api")]
+    #[serde(rename = "telegramapi")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_api: Option<String>,
+    #[clap(long = "notify-webhook-url")]
+    #[serde(rename = "webhookurl")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Payload format for `webhookurl`, `json` by default
+    #[clap(long = "notify-webhook-format")]
+    #[serde(rename = "webhookformat")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_format: Option<WebhookFormat>,
+    /// More webhook targets, each with its own format, as
+    /// `[[notify.webhooks]]` tables in the config file
+    #[clap(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhooks: Option<Vec<WebhookTarget>>,
+    /// Failed deliveries are retried this often before they are dead-lettered
+    #[clap(long = "notify-max-attempts")]
+    #[serde(rename = "maxattempts")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<i32>,
+    /// Seconds before the first retry, doubled on each further one
+    #[clap(long = "notify-retry-base")]
+    #[serde(rename = "retrybase")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_base: Option<u64>,
+    /// Seconds between two looks into the outbox
+    #[clap(long = "notify-poll-interval")]
+    #[serde(rename = "pollinterval")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+    /// Directory of template files, `<event>/<part>`, overriding the built-in
+    /// ones where admins have not edited them
+    #[clap(long = "notify-template-dir")]
+    #[serde(rename = "templatedir")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_dir: Option<String>,
+    /// Mail the owner a daily or weekly digest instead of a mail per event,
+    /// `off` by default
+    #[clap(long = "notify-digest")]
+    #[serde(rename = "digest")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<DigestPeriod>,
+    /// Hour of the day, in UTC, the digest goes out. Weekly ones go out on
+    /// Mondays.
+    #[clap(long = "notify-digest-hour")]
+    #[serde(rename = "digesthour")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest_hour: Option<u32>,
+}
+
+impl Notify {
+    pub fn channels(&self) -> Vec<NotifyChannel> {
+        self.notify_channels
+            .clone()
+            .unwrap_or_else(|| vec![NotifyChannel::Smtp])
+    }
+
+    pub fn telegram_api(&self) -> &str {
+        self.telegram_api
+            .as_deref()
+            .unwrap_or("https://api.telegram.org")
+    }
+
+    pub fn max_attempts(&self) -> i32 {
+        self.max_attempts.unwrap_or(8)
+    }
+
+    pub fn retry_base(&self) -> u64 {
+        self.retry_base.unwrap_or(30)
+    }
+
+    pub fn poll_interval(&self) -> u64 {
+        self.poll_interval.unwrap_or(5)
+    }
+
+    /// `webhookurl` as the target named `webhook`, followed by `webhooks`.
+    pub fn webhook_targets(&self) -> Vec<WebhookTarget> {
+        let first = self.webhook_url.as_ref().map(|url| WebhookTarget {
+            name: Some(String::from("webhook")),
+            url: url.clone(),
+            format: self.webhook_format,
+            room: None,
+            token: None,
+        });
+        let rest = self
+            .webhooks
+            .iter()
+            .flatten()
+            .enumerate()
+            .map(|(i, target)| {
+                let mut target = target.clone();
+                target
+                    .name
+                    .get_or_insert_with(|| format!("webhook-{}", i + 1));
+                target
+            });
+
+        first.into_iter().chain(rest).collect()
+    }
+
+    pub fn digest(&self) -> DigestPeriod {
+        self.digest.unwrap_or(DigestPeriod::Off)
+    }
+
+    pub fn digest_hour(&self) -> u32 {
+        self.digest_hour.unwrap_or(8).min(23)
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyChannel {
+    Smtp,
+    Telegram,
+    Webhook,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookTarget {
+    /// Outbox channel of the target, `webhook-<n>` by default. Renaming a
+    /// target strands the messages still queued for it.
+    pub name: Option<String>,
+    /// Where payloads go. For `matrix` it is the homeserver, e.g.
+    /// `https://matrix.org`.
+    pub url: String,
+    pub format: Option<WebhookFormat>,
+    /// Room ID to post to, `matrix` only
+    pub room: Option<String>,
+    /// Access token of the posting user, `matrix` only
+    pub token: Option<String>,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    /// The rendered webhook template, as is
+    Json,
+    Discord,
+    Slack,
+    Matrix,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestPeriod {
+    Off,
+    Daily,
+    Weekly,
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Inbox {
+    /// How the Telegram bot gets its updates, `off` by default. It uses the
+    /// token and API of the telegram notify channel.
+    #[clap(long = "inbox-telegram-mode")]
+    #[serde(rename = "telegrammode")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_mode: Option<TelegramMode>,
+    /// Chats the bot takes orders from, the notify chat when unset
+    #[clap(long = "inbox-telegram-allowed-chats", value_delimiter = ',')]
+    #[serde(rename = "telegramallowedchats")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_allowed_chats: Option<Vec<i64>>,
+    /// Secret Telegram sends along with webhook updates, required for webhook
+    #[clap(long = "inbox-telegram-secret")]
+    #[serde(rename = "telegramsecret")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telegram_secret: Option<String>,
+    /// Address to take mail on, e.g. `127.0.0.1:2525`, no mail is taken
+    /// while it is unset
+    #[clap(long = "inbox-mail-listen")]
+    #[serde(rename = "maillisten")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mail_listen: Option<String>,
+    /// Whether mail comes in over `smtp`, the default, or `lmtp` from a local
+    /// mail server
+    #[clap(long = "inbox-mail-protocol")]
+    #[serde(rename = "mailprotocol")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mail_protocol: Option<MailProtocol>,
+    /// Secret recipient mails turn into drafts at, required for the listener.
+    /// Sender addresses are easily forged, so keep this one to yourself
+    #[clap(long = "inbox-mail-address")]
+    #[serde(rename = "mailaddress")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mail_address: Option<String>,
+    /// Addresses drafts are taken from, the blog users' when unset. Both the
+    /// envelope sender (`MAIL FROM`) and the `From` header must be one of them
+    #[clap(long = "inbox-mail-allowed-senders", value_delimiter = ',')]
+    #[serde(rename = "mailallowedsenders")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mail_allowed_senders: Option<Vec<String>>,
+    /// Largest mail taken, in bytes, 25 MiB by default
+    #[clap(long = "inbox-mail-max-size")]
+    #[serde(rename = "mailmaxsize")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mail_max_size: Option<usize>,
+}
+
+impl Inbox {
+    pub fn telegram_mode(&self) -> TelegramMode {
+        self.telegram_mode.unwrap_or(TelegramMode::Off)
+    }
+
+    pub fn telegram_allowed_chats(&self, notify: &Notify) -> Vec<i64> {
+        match &self.telegram_allowed_chats {
+            Some(chats) => chats.clone(),
+            None => notify
+                .telegram_chat_id
+                .iter()
+                .filter_map(|id| id.parse().ok())
+                .collect(),
+        }
+    }
+
+    pub fn mail_protocol(&self) -> MailProtocol {
+        self.mail_protocol.unwrap_or(MailProtocol::Smtp)
+    }
+
+    pub fn mail_max_size(&self) -> usize {
+        self.mail_max_size.unwrap_or(25 * 1024 * 1024)
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TelegramMode {
+    Off,
+    Webhook,
+    Polling,
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailProtocol {
+    Smtp,
+    Lmtp,
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Federation {
+    /// Let Fediverse users follow the blog over ActivityPub, off by default
+    #[clap(long = "federation-enabled")]
+    #[serde(rename = "enabled")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub federation_enabled: Option<bool>,
+    /// Name of the blog's actor, as in `@blog@example.com`
+    #[clap(long = "federation-username")]
+    #[serde(rename = "username")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Public address `/.well-known` and `/ap` are reached at, the site URL
+    /// when unset. Changing it makes a new actor of the blog.
+    #[clap(long = "federation-base-url")]
+    #[serde(rename = "baseurl")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// What notes go out as, `article` by default
+    #[clap(long = "federation-object-type")]
+    #[serde(rename = "objecttype")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_type: Option<ObjectType>,
+}
+
+impl Federation {
+    pub fn enabled(&self) -> bool {
+        self.federation_enabled.unwrap_or(false)
+    }
+
+    pub fn username(&self) -> &str {
+        self.username.as_deref().unwrap_or("blog")
+    }
+
+    pub fn object_type(&self) -> ObjectType {
+        self.object_type.unwrap_or(ObjectType::Article)
+    }
+}
+
+#[derive(Debug, ValueEnum, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ObjectType {
+    /// Title, summary and full content, shown by Mastodon as a link with a
+    /// summary
+    Article,
+    /// A short status with the title, summary and link
+    Note,
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Media {
+    /// Directory uploaded files are kept in, `media` by default
+    #[clap(long = "media-dir")]
+    #[serde(rename = "dir")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_dir: Option<String>,
+    /// Largest upload taken, in bytes, 10 MiB by default
+    #[clap(long = "media-max-size")]
+    #[serde(rename = "maxsize")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<usize>,
+}
+
+impl Media {
+    pub fn dir(&self) -> &str {
+        self.media_dir.as_deref().unwrap_or("media")
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size.unwrap_or(10 * 1024 * 1024)
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Revisions {
+    /// Versions kept per note, 50 by default, 0 keeps them all
+    #[clap(long = "revisions-keep")]
+    #[serde(rename = "keep")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+    /// Days a version is kept once replaced, forever when unset
+    #[clap(long = "revisions-max-age")]
+    #[serde(rename = "maxage")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<u64>,
+}
+
+impl Revisions {
+    pub fn keep(&self) -> Option<usize> {
+        match self.keep.unwrap_or(50) {
+            0 => None,
+            keep => Some(keep),
+        }
+    }
+}
+
+#[derive(Debug, Args, Serialize, Deserialize)]
+pub struct Schedule {
+    /// Seconds between two looks for notes due to be published or taken
+    /// down, 30 by default
+    #[clap(long = "schedule-poll-interval")]
+    #[serde(rename = "pollinterval")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_interval: Option<u64>,
+}
+
+impl Schedule {
+    pub fn poll_interval(&self) -> u64 {
+        self.poll_interval.unwrap_or(30)
+    }
+}
+
 #[derive(Debug, ValueEnum, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Log {
@@ -124,7 +670,8 @@ impl Config {
             .merge(Toml::file(path))
             .merge(Env::prefixed("TSUIIO_BLOG_").split('_'))
             .merge(Serialized::defaults(conf))
-            .extract()?;
+            .extract()
+            .map_err(Box::new)?;
 
         Ok(config)
     }
@@ -137,6 +684,22 @@ impl Config {
         )
     }
 
+    /// Base URL for links in outgoing mails, without a trailing slash.
+    pub fn site_url(&self) -> String {
+        match &self.web.site_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}", self.listener_host()),
+        }
+    }
+
+    /// Base URL of the ActivityPub endpoints, without a trailing slash.
+    pub fn federation_url(&self) -> String {
+        match &self.federation.base_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => self.site_url(),
+        }
+    }
+
     pub fn db_url(&self) -> String {
         let ssl_param = if self.db.db_ssl.unwrap() {
             "?sslmode=require"
@@ -73,7 +73,7 @@ impl AsMut<PooledConnection<'static, AsyncPgConnection>> for DbConn {
     }
 }
 
-fn establish_connection(config: &str) -> BoxFuture<ConnectionResult<AsyncPgConnection>> {
+fn establish_connection(config: &str) -> BoxFuture<'_, ConnectionResult<AsyncPgConnection>> {
     let fut = async {
         let rustls_config = rustls::ClientConfig::builder()
             .with_root_certificates(root_certs())
@@ -1,11 +1,30 @@
+pub mod ap_actors;
+pub mod ap_deliveries;
+pub mod ap_followers;
+pub mod ap_replies;
+pub mod comm_users;
 pub mod comms;
 pub mod comms_closure;
+pub mod contact_messages;
+pub mod federation_keys;
 pub mod info;
+pub mod mail_unsubscribes;
+pub mod note_revisions;
 pub mod note_sorts;
 pub mod note_tags;
 pub mod notes;
+pub mod notify_digests;
+pub mod notify_outbox;
+pub mod notify_templates;
 pub mod page_sorts;
 pub mod pages;
+pub mod publish_tokens;
 pub mod sorts;
+pub mod spam_tokens;
+pub mod subscribers;
 pub mod tags;
 pub mod users;
+pub mod webhook_deliveries;
+pub mod webhook_endpoints;
+pub mod webmention_sends;
+pub mod webmentions;
@@ -0,0 +1,109 @@
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use reqwest::Url;
+
+use crate::{db::schema::ap_actors, error::BlogError};
+
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = ap_actors)]
+#[diesel(treat_none_as_null = true)]
+pub struct ApActor {
+    pub id: String,
+    pub key_id: String,
+    /// PEM, as the actor publishes it
+    pub public_key: String,
+    pub inbox: String,
+    pub shared_inbox: Option<String>,
+    /// `preferredUsername`
+    pub username: Option<String>,
+    pub name: Option<String>,
+    /// Profile page
+    pub url: Option<String>,
+}
+
+impl ApActor {
+    /// Whether the key lives on the actor's own origin, the only one that
+    /// may speak for it.
+    pub fn owns_key(&self) -> bool {
+        match (Url::parse(&self.id), Url::parse(&self.key_id)) {
+            (Ok(id), Ok(key_id)) => id.origin() == key_id.origin(),
+            _ => false,
+        }
+    }
+
+    /// Stores the actor as last fetched. A key from another origin is
+    /// refused, so no server can swap in its own key for a known actor.
+    pub async fn upsert(&self, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::ap_actors;
+
+        if !self.owns_key() {
+            return Err(BlogError::BadRequest(format!(
+                "key {} does not belong to {}",
+                self.key_id, self.id
+            )));
+        }
+
+        // a key moving to another actor takes its old row along
+        diesel::delete(
+            ap_actors::table
+                .filter(ap_actors::key_id.eq(&self.key_id))
+                .filter(ap_actors::id.ne(&self.id)),
+        )
+        .execute(conn)
+        .await?;
+
+        diesel::insert_into(ap_actors::table)
+            .values(self)
+            .on_conflict(ap_actors::id)
+            .do_update()
+            .set((self, ap_actors::updated_at.eq(Utc::now().naive_utc())))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_id(
+        id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::ap_actors;
+
+        let actor = ap_actors::table
+            .find(id_)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(actor)
+    }
+
+    pub async fn find_by_key_id(
+        key_id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::ap_actors;
+
+        let actor = ap_actors::table
+            .filter(ap_actors::key_id.eq(key_id_))
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(actor)
+    }
+
+    /// Forgets the actor along with its following. Replies it left stay.
+    pub async fn delete_by_id(id_: &str, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::ap_actors;
+
+        diesel::delete(ap_actors::table.find(id_))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{db::schema::ap_deliveries, error::BlogError};
+
+use super::notify_outbox::OutboxStatus;
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = ap_deliveries)]
+pub struct ApDelivery {
+    pub id: Uuid,
+    pub inbox: String,
+    pub activity: Value,
+    pub attempts: i32,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = ap_deliveries)]
+struct NewApDelivery<'a> {
+    id: Uuid,
+    inbox: &'a str,
+    activity: &'a Value,
+}
+
+impl ApDelivery {
+    /// Queues `activity` for each of `inboxes`.
+    pub async fn enqueue(
+        inboxes: &[String],
+        activity_: &Value,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::ap_deliveries;
+
+        if inboxes.is_empty() {
+            return Ok(());
+        }
+
+        let deliveries: Vec<_> = inboxes
+            .iter()
+            .map(|inbox_| NewApDelivery {
+                id: Uuid::new_v4(),
+                inbox: inbox_,
+                activity: activity_,
+            })
+            .collect();
+
+        diesel::insert_into(ap_deliveries::table)
+            .values(&deliveries)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Takes up to `limit` due deliveries and pushes their next attempt back
+    /// by `lease_secs`, so other workers leave them alone while they are sent.
+    pub async fn claim_due(
+        limit: i64,
+        lease_secs: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::ap_deliveries;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let now = Utc::now().naive_utc();
+                let deliveries = ap_deliveries::table
+                    .filter(ap_deliveries::status.eq(OutboxStatus::Pending))
+                    .filter(ap_deliveries::next_attempt_at.le(now))
+                    .order(ap_deliveries::next_attempt_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(Self::as_select())
+                    .load::<Self>(conn)
+                    .await?;
+
+                let ids: Vec<Uuid> = deliveries.iter().map(|d| d.id).collect();
+                diesel::update(ap_deliveries::table.filter(ap_deliveries::id.eq_any(&ids)))
+                    .set(
+                        ap_deliveries::next_attempt_at
+                            .eq(now + chrono::Duration::seconds(lease_secs)),
+                    )
+                    .execute(conn)
+                    .await?;
+
+                Ok(deliveries)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn mark_delivered(
+        id: &Uuid,
+        response_status_: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::ap_deliveries;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(ap_deliveries::table.find(id))
+            .set((
+                ap_deliveries::status.eq(OutboxStatus::Delivered),
+                ap_deliveries::attempts.eq(ap_deliveries::attempts + 1),
+                ap_deliveries::response_status.eq(response_status_),
+                ap_deliveries::last_error.eq(None::<String>),
+                ap_deliveries::delivered_at.eq(now),
+                ap_deliveries::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. Without a `retry_at` the delivery is given up
+    /// on.
+    pub async fn mark_failed(
+        id: &Uuid,
+        response_status_: Option<i32>,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::ap_deliveries;
+
+        let now = Utc::now().naive_utc();
+        let status_ = if retry_at.is_some() {
+            OutboxStatus::Pending
+        } else {
+            OutboxStatus::Dead
+        };
+
+        diesel::update(ap_deliveries::table.find(id))
+            .set((
+                ap_deliveries::status.eq(status_),
+                ap_deliveries::attempts.eq(ap_deliveries::attempts + 1),
+                ap_deliveries::next_attempt_at.eq(retry_at.unwrap_or(now)),
+                ap_deliveries::response_status.eq(response_status_),
+                ap_deliveries::last_error.eq(error),
+                ap_deliveries::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::error::BlogError;
+
+pub struct ApFollower;
+
+impl ApFollower {
+    /// Records that `actor_id` follows the blog, through the `Follow` activity
+    /// `follow_id`.
+    pub async fn follow(
+        actor_id_: &str,
+        follow_id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::ap_followers;
+
+        diesel::insert_into(ap_followers::table)
+            .values((
+                ap_followers::actor_id.eq(actor_id_),
+                ap_followers::follow_id.eq(follow_id_),
+            ))
+            .on_conflict(ap_followers::actor_id)
+            .do_update()
+            .set((
+                ap_followers::follow_id.eq(follow_id_),
+                ap_followers::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns whether `actor_id` was following.
+    pub async fn unfollow(
+        actor_id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::ap_followers;
+
+        let deleted = diesel::delete(ap_followers::table.find(actor_id_))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    pub async fn count(conn: &mut AsyncPgConnection) -> Result<i64, BlogError> {
+        use crate::db::schema::ap_followers;
+
+        let total = ap_followers::table.count().get_result::<i64>(conn).await?;
+
+        Ok(total)
+    }
+
+    /// Inboxes reaching every follower, shared ones where the server has one.
+    pub async fn get_inboxes(conn: &mut AsyncPgConnection) -> Result<Vec<String>, BlogError> {
+        use crate::db::schema::{ap_actors, ap_followers};
+
+        let inboxes = ap_followers::table
+            .inner_join(ap_actors::table)
+            .select((ap_actors::inbox, ap_actors::shared_inbox))
+            .load::<(String, Option<String>)>(conn)
+            .await?;
+
+        let mut seen = HashSet::new();
+        Ok(inboxes
+            .into_iter()
+            .map(|(inbox, shared_inbox)| shared_inbox.unwrap_or(inbox))
+            .filter(|inbox| seen.insert(inbox.clone()))
+            .collect())
+    }
+}
@@ -0,0 +1,50 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{db::schema::ap_replies, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = ap_replies)]
+pub struct ApReply {
+    pub comm_id: Uuid,
+    pub actor_id: String,
+}
+
+impl ApReply {
+    pub async fn create(
+        object_id_: &str,
+        comm_id_: &Uuid,
+        actor_id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::ap_replies;
+
+        diesel::insert_into(ap_replies::table)
+            .values((
+                ap_replies::object_id.eq(object_id_),
+                ap_replies::comm_id.eq(comm_id_),
+                ap_replies::actor_id.eq(actor_id_),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_object_id(
+        object_id_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::ap_replies;
+
+        let reply = ap_replies::table
+            .find(object_id_)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(reply)
+    }
+}
@@ -0,0 +1,106 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{
+    db::{schema::comm_users, Conn},
+    error::BlogError,
+};
+
+use super::comms::CommStatus;
+
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, Identifiable)]
+#[diesel(table_name = comm_users)]
+pub struct CommUser {
+    pub id: Uuid,
+    pub nickname: String,
+    pub email: String,
+    pub website_url: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = comm_users)]
+pub struct NewCommUser<'a> {
+    id: Uuid,
+    nickname: &'a str,
+    email: &'a str,
+    website_url: Option<&'a str>,
+}
+
+impl CommUser {
+    /// Commenters are identified by email, so a returning commenter reuses
+    /// their row. Nobody proves an email is theirs, so the nickname and
+    /// website it was first seen with stay as they are.
+    pub async fn find_or_create(
+        nickname: &str,
+        email: &str,
+        website_url: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        use crate::db::schema::comm_users;
+
+        let email = email.to_lowercase();
+        let new_user = NewCommUser {
+            id: Uuid::new_v4(),
+            nickname,
+            email: &email,
+            website_url,
+        };
+
+        let created = diesel::insert_into(comm_users::table)
+            .values(&new_user)
+            .on_conflict_do_nothing()
+            .returning(comm_users::id)
+            .get_result::<Uuid>(conn)
+            .await
+            .optional()?;
+        if let Some(id) = created {
+            return Ok(id);
+        }
+
+        let id = comm_users::table
+            .filter(lower(comm_users::email).eq(&email))
+            .select(comm_users::id)
+            .first::<Uuid>(conn)
+            .await?;
+
+        Ok(id)
+    }
+
+    /// Whether any comm written under this email, whatever its case, has
+    /// been approved before.
+    pub async fn has_approved_comm(email: &str, conn: &mut Conn) -> Result<bool, BlogError> {
+        use crate::db::schema::{comm_users, comms};
+
+        let approved = diesel::select(diesel::dsl::exists(
+            comms::table
+                .inner_join(comm_users::table)
+                .filter(lower(comm_users::email).eq(email.to_lowercase()))
+                .filter(comms::status.eq(CommStatus::Approved)),
+        ))
+        .get_result::<bool>(conn)
+        .await?;
+
+        Ok(approved)
+    }
+
+    pub async fn find_comm_users_by_ids(
+        ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::comm_users;
+
+        let users = comm_users::table
+            .filter(comm_users::id.eq_any(ids))
+            .select(CommUser::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(users)
+    }
+}
@@ -1,13 +1,28 @@
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
-    db::{schema::comms, Conn},
+    config::CONFIG,
+    db::{
+        models::webhook_deliveries::WebhookDelivery,
+        schema::{comms, sql_types::CommStatus as CommStatusType},
+        Conn,
+    },
     error::BlogError,
 };
 
+use super::{comm_users::CommUser, comms_closure::CommsClosure, notes::Status};
+
+/// Advisory lock key serializing [`Comm::mark_approved`], "commseq" in ASCII.
+const APPROVED_SEQ_LOCK: i64 = 0x636f_6d6d_7365_71;
+
 #[derive(Debug, Queryable, Selectable, Insertable)]
 #[diesel(table_name = comms)]
 #[diesel(belongs_to(Note))]
@@ -18,6 +33,11 @@ pub struct Comm {
     pub blog_user_id: Option<Uuid>,
     pub note_id: Option<Uuid>,
     pub page_id: Option<Uuid>,
+    pub status: CommStatus,
+    pub spam_score: Option<f64>,
+    pub spam_trained: Option<bool>,
+    /// Place in the order comms went live, set each time one is approved
+    pub approved_seq: Option<i64>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
@@ -27,62 +47,295 @@ pub struct Comm {
 pub struct NewComm<'a> {
     id: Uuid,
     content: &'a str,
-    comm_user_id: &'a Uuid,
+    comm_user_id: Option<&'a Uuid>,
+    blog_user_id: Option<&'a Uuid>,
     note_id: Option<&'a Uuid>,
     page_id: Option<&'a Uuid>,
+    status: &'a CommStatus,
+    spam_score: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize, utoipa::ToSchema)]
+#[ExistingTypePath = "CommStatusType"]
+#[serde(rename_all = "lowercase")]
+pub enum CommStatus {
+    Pending,
+    Approved,
+    Spam,
+    Trash,
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CommType {
     Note,
     Page,
 }
 
+/// The note or page a short_id points at, as far as comments care.
+pub struct CommTarget {
+    pub comm_type: CommType,
+    pub id: Uuid,
+    pub status: Status,
+    pub comm: bool,
+}
+
+/// The note or page a comm was left on, as far as notifications care.
+pub struct CommPlace {
+    pub title: String,
+    pub short_name: String,
+    pub owner_id: Uuid,
+}
+
+/// Who wrote a comm: an anonymous commenter from `comm_users`, or a blog user.
+pub enum CommAuthor<'a> {
+    CommUser(&'a Uuid),
+    BlogUser(&'a Uuid),
+}
+
 impl Comm {
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_comm(
         content_: &str,
-        comm_user_id_: &Uuid,
-        type_id_: Option<&Uuid>,
+        author: CommAuthor<'_>,
+        type_id_: &Uuid,
         comm_type_: CommType,
-        conn: &mut Conn,
-    ) -> Result<(), BlogError> {
+        parent_id: Option<&Uuid>,
+        status_: &CommStatus,
+        spam_score_: Option<f64>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
         use crate::db::schema::comms::dsl::*;
 
         let id_ = Uuid::new_v4();
         let new_comm = NewComm {
             id: id_,
             content: content_,
-            comm_user_id: comm_user_id_,
+            comm_user_id: match author {
+                CommAuthor::CommUser(user_id) => Some(user_id),
+                CommAuthor::BlogUser(_) => None,
+            },
+            blog_user_id: match author {
+                CommAuthor::BlogUser(user_id) => Some(user_id),
+                CommAuthor::CommUser(_) => None,
+            },
             note_id: if comm_type_ == CommType::Note {
-                type_id_
+                Some(type_id_)
             } else {
                 None
             },
             page_id: if comm_type_ == CommType::Page {
-                type_id_
+                Some(type_id_)
             } else {
                 None
             },
+            status: status_,
+            spam_score: spam_score_,
         };
 
-        diesel::insert_into(comms)
-            .values(&new_comm)
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                diesel::insert_into(comms)
+                    .values(&new_comm)
+                    .execute(conn)
+                    .await?;
+
+                CommsClosure::insert_comm(&id_, parent_id, conn).await?;
+
+                if *status_ == CommStatus::Approved {
+                    Self::mark_approved(&[id_], conn).await?;
+                    Self::emit("comment.approved", &id_, conn).await?;
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(id_)
+    }
+
+    pub async fn find_comm_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::comms;
+
+        let comm = comms::table
+            .find(id)
+            .select(Comm::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(comm)
+    }
+
+    /// Whether `user_id` may edit or delete the comm: blog users manage the
+    /// comms they wrote and every comm under their own notes and pages.
+    pub async fn is_managed_by(
+        &self,
+        user_id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::{notes, pages};
+
+        if self.blog_user_id.as_ref() == Some(user_id) {
+            return Ok(true);
+        }
+
+        let owner = match (self.note_id, self.page_id) {
+            (Some(note_id), _) => notes::table
+                .find(note_id)
+                .select(notes::user_id)
+                .first::<Uuid>(conn)
+                .await
+                .optional()?,
+            (None, Some(page_id)) => pages::table
+                .find(page_id)
+                .select(pages::user_id)
+                .first::<Uuid>(conn)
+                .await
+                .optional()?,
+            (None, None) => None,
+        };
+
+        Ok(owner.as_ref() == Some(user_id))
+    }
+
+    /// Returns whether there was such a comm.
+    pub async fn update_comm_by_uuid(
+        id: &Uuid,
+        content: &str,
+        conn: &mut Conn,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::comms;
+
+        let now = Utc::now().naive_utc();
+        let updated = diesel::update(comms::table.find(id))
+            .set((comms::content.eq(content), comms::updated_at.eq(now)))
             .execute(conn)
             .await?;
 
-        Ok(())
+        Ok(updated > 0)
     }
 
-    pub async fn delete_comm_by_uuid(id: &Uuid, conn: &mut Conn) -> Result<(), BlogError> {
+    /// Deletes a comm together with every reply beneath it. Returns whether
+    /// there was such a comm.
+    pub async fn delete_comm_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
         use crate::db::schema::comms;
 
-        diesel::delete(comms::table.find(id)).execute(conn).await?;
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let subtree = CommsClosure::get_subtree(id, conn).await?;
 
-        Ok(())
+                CommsClosure::delete_by_comm_ids(&subtree, conn).await?;
+
+                let deleted = diesel::delete(comms::table.filter(comms::id.eq_any(&subtree)))
+                    .execute(conn)
+                    .await?;
+
+                Ok(deleted > 0)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn find_target_by_short_id(
+        short_name: &str,
+        conn: &mut Conn,
+    ) -> Result<Option<CommTarget>, BlogError> {
+        use crate::db::schema::{notes, pages, short_ids};
+
+        let note = notes::table
+            .inner_join(short_ids::table)
+            .filter(
+                short_ids::short_name
+                    .eq(short_name)
+                    .or(short_ids::subname.eq(short_name)),
+            )
+            .select((notes::id, notes::status, notes::comm))
+            .first::<(Uuid, Status, bool)>(conn)
+            .await
+            .optional()?;
+
+        if let Some((id, status, comm)) = note {
+            return Ok(Some(CommTarget {
+                comm_type: CommType::Note,
+                id,
+                status,
+                comm,
+            }));
+        }
+
+        let page = pages::table
+            .inner_join(short_ids::table)
+            .filter(
+                short_ids::short_name
+                    .eq(short_name)
+                    .or(short_ids::subname.eq(short_name)),
+            )
+            .select((pages::id, pages::status, pages::comm))
+            .first::<(Uuid, Status, bool)>(conn)
+            .await
+            .optional()?;
+
+        Ok(page.map(|(id, status, comm)| CommTarget {
+            comm_type: CommType::Page,
+            id,
+            status,
+            comm,
+        }))
+    }
+
+    pub async fn find_place(
+        comm: &Comm,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<CommPlace>, BlogError> {
+        use crate::db::schema::{notes, pages, short_ids};
+
+        if let Some(note_id) = &comm.note_id {
+            let place = notes::table
+                .inner_join(short_ids::table)
+                .filter(notes::id.eq(note_id))
+                .select((notes::title, short_ids::short_name, notes::user_id))
+                .first::<(String, String, Uuid)>(conn)
+                .await
+                .optional()?;
+
+            return Ok(place.map(|(title, short_name, owner_id)| CommPlace {
+                title,
+                short_name,
+                owner_id,
+            }));
+        }
+
+        let Some(page_id) = &comm.page_id else {
+            return Ok(None);
+        };
+
+        let place = pages::table
+            .inner_join(short_ids::table)
+            .filter(pages::id.eq(page_id))
+            .select((short_ids::subname, short_ids::short_name, pages::user_id))
+            .first::<(Option<String>, String, Uuid)>(conn)
+            .await
+            .optional()?;
+
+        Ok(place.map(|(subname, short_name, owner_id)| CommPlace {
+            title: subname.unwrap_or_else(|| short_name.clone()),
+            short_name,
+            owner_id,
+        }))
     }
 
     pub async fn get_comms_by_short_id(
         short_name: &str,
+        statuses: &[CommStatus],
         conn: &mut Conn,
     ) -> Result<Vec<Self>, BlogError> {
         use crate::db::schema::{comms, notes, pages, short_ids};
@@ -95,7 +348,13 @@ impl Comm {
                     .eq(short_ids::id)
                     .or(pages::short_id.eq(short_ids::id))),
             )
-            .filter(short_ids::short_name.eq(short_name))
+            .filter(
+                short_ids::short_name
+                    .eq(short_name)
+                    .or(short_ids::subname.eq(short_name)),
+            )
+            .filter(comms::status.eq_any(statuses))
+            .order(comms::created_at.asc())
             .select(Self::as_select())
             .load::<Self>(conn)
             .await?;
@@ -103,26 +362,285 @@ impl Comm {
         Ok(results)
     }
 
-    pub async fn delete_comms_by_type_id(
-        type_id: &Uuid,
-        comm_type: CommType,
+    /// Lists comms in one moderation state, newest first, together with the
+    /// commenter who wrote them.
+    pub async fn get_comms_by_status(
+        status: &CommStatus,
+        limit: i64,
+        offset: i64,
         conn: &mut Conn,
+    ) -> Result<(Vec<(Self, Option<CommUser>)>, u64), BlogError> {
+        use crate::db::schema::{comm_users, comms};
+
+        let results = comms::table
+            .left_join(comm_users::table)
+            .filter(comms::status.eq(status))
+            .order(comms::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .select((Self::as_select(), Option::<CommUser>::as_select()))
+            .load::<(Self, Option<CommUser>)>(conn)
+            .await?;
+
+        let total = comms::table
+            .filter(comms::status.eq(status))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((results, total as u64))
+    }
+
+    /// Comms written between `start` and `end` in any state, newest first,
+    /// together with the commenter who wrote them.
+    pub async fn get_comms_created_between(
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<(Self, Option<CommUser>)>, u64), BlogError> {
+        use crate::db::schema::{comm_users, comms};
+
+        let results = comms::table
+            .left_join(comm_users::table)
+            .filter(comms::created_at.ge(start))
+            .filter(comms::created_at.lt(end))
+            .order(comms::created_at.desc())
+            .limit(limit)
+            .select((Self::as_select(), Option::<CommUser>::as_select()))
+            .load::<(Self, Option<CommUser>)>(conn)
+            .await?;
+
+        let total = comms::table
+            .filter(comms::created_at.ge(start))
+            .filter(comms::created_at.lt(end))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((results, total as u64))
+    }
+
+    pub async fn find_comms_with_users_by_ids(
+        ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(Self, Option<CommUser>)>, BlogError> {
+        use crate::db::schema::{comm_users, comms};
+
+        let results = comms::table
+            .left_join(comm_users::table)
+            .filter(comms::id.eq_any(ids))
+            .select((Self::as_select(), Option::<CommUser>::as_select()))
+            .load::<(Self, Option<CommUser>)>(conn)
+            .await?;
+
+        Ok(results)
+    }
+
+    pub async fn set_spam_trained(
+        id: &Uuid,
+        spam_trained: Option<bool>,
+        conn: &mut AsyncPgConnection,
     ) -> Result<(), BlogError> {
         use crate::db::schema::comms;
 
-        match comm_type {
-            CommType::Note => {
-                diesel::delete(comms::table.filter(comms::note_id.eq(type_id)))
-                    .execute(conn)
+        diesel::update(comms::table.find(id))
+            .set(comms::spam_trained.eq(spam_trained))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Returns how many of the comms there are and which of them were
+    /// approved just now.
+    pub async fn update_status_by_uuids(
+        ids: &[Uuid],
+        status: &CommStatus,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(usize, Vec<Uuid>), BlogError> {
+        use crate::db::schema::comms;
+
+        let status = *status;
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                // only comms that change come back. A verdict on the same
+                // comms at the same time waits for the row locks, then finds
+                // them changed already.
+                let now = Utc::now().naive_utc();
+                let changed = diesel::update(
+                    comms::table
+                        .filter(comms::id.eq_any(ids))
+                        .filter(comms::status.ne(status)),
+                )
+                .set((comms::status.eq(status), comms::updated_at.eq(now)))
+                .returning(comms::id)
+                .get_results::<Uuid>(conn)
+                .await?;
+                let updated = comms::table
+                    .filter(comms::id.eq_any(ids))
+                    .count()
+                    .get_result::<i64>(conn)
                     .await?;
+
+                let newly_approved = if status == CommStatus::Approved {
+                    changed
+                } else {
+                    Vec::new()
+                };
+                Self::mark_approved(&newly_approved, conn).await?;
+                for id in &newly_approved {
+                    Self::emit("comment.approved", id, conn).await?;
+                }
+
+                Ok((updated as usize, newly_approved))
             }
-            CommType::Page => {
-                diesel::delete(comms::table.filter(comms::page_id.eq(type_id)))
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn delete_comms_by_type_id(
+        type_id: &Uuid,
+        comm_type: CommType,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::comms;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let ids = match comm_type {
+                    CommType::Note => {
+                        comms::table
+                            .filter(comms::note_id.eq(type_id))
+                            .select(comms::id)
+                            .load::<Uuid>(conn)
+                            .await?
+                    }
+                    CommType::Page => {
+                        comms::table
+                            .filter(comms::page_id.eq(type_id))
+                            .select(comms::id)
+                            .load::<Uuid>(conn)
+                            .await?
+                    }
+                };
+
+                CommsClosure::delete_by_comm_ids(&ids, conn).await?;
+
+                diesel::delete(comms::table.filter(comms::id.eq_any(&ids)))
                     .execute(conn)
                     .await?;
+
+                Ok(())
             }
+            .scope_boxed()
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    /// Highest `approved_seq` handed out so far, 0 before any.
+    pub async fn max_approved_seq(conn: &mut AsyncPgConnection) -> Result<i64, BlogError> {
+        use crate::db::schema::comms;
+
+        let seq = comms::table
+            .select(diesel::dsl::max(comms::approved_seq))
+            .first::<Option<i64>>(conn)
+            .await?;
+
+        Ok(seq.unwrap_or(0))
+    }
+
+    /// Comms approved after `after` in approval order, as `(approved_seq,
+    /// note or page id)`.
+    pub async fn get_approved_since(
+        after: i64,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(i64, Uuid)>, BlogError> {
+        use crate::db::schema::comms;
+
+        let landed = comms::table
+            .filter(comms::status.eq(CommStatus::Approved))
+            .filter(comms::approved_seq.gt(after))
+            .order(comms::approved_seq.asc())
+            .limit(limit)
+            .select((comms::approved_seq, comms::note_id, comms::page_id))
+            .load::<(Option<i64>, Option<Uuid>, Option<Uuid>)>(conn)
+            .await?;
+
+        Ok(landed
+            .into_iter()
+            .filter_map(|(seq, note_id, page_id)| Some((seq?, note_id.or(page_id)?)))
+            .collect())
+    }
+
+    /// Comms on one note or page approved after `after`, in approval order.
+    pub async fn get_approved_after(
+        type_id_: &Uuid,
+        after: i64,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::comms;
+
+        let results = comms::table
+            .filter(comms::note_id.eq(type_id_).or(comms::page_id.eq(type_id_)))
+            .filter(comms::status.eq(CommStatus::Approved))
+            .filter(comms::approved_seq.gt(after))
+            .order(comms::approved_seq.asc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(results)
+    }
+
+    /// Puts freshly approved comms at the end of the approval order. Meant to
+    /// run inside the transaction approving them, which then holds the
+    /// approval lock until it commits: readers resume after the highest seq
+    /// they saw, so a lower one must never become visible after a higher one.
+    async fn mark_approved(ids: &[Uuid], conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::comms;
+
+        if ids.is_empty() {
+            return Ok(());
         }
 
+        diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+            .bind::<diesel::sql_types::BigInt, _>(APPROVED_SEQ_LOCK)
+            .execute(conn)
+            .await?;
+        diesel::update(comms::table.filter(comms::id.eq_any(ids)))
+            .set(comms::approved_seq.eq(diesel::dsl::sql::<
+                diesel::sql_types::Nullable<diesel::sql_types::BigInt>,
+            >("nextval('comms_approved_seq')")))
+            .execute(conn)
+            .await?;
+
         Ok(())
     }
+
+    /// Queues content webhooks about the comm, as it is now.
+    async fn emit(event: &str, id: &Uuid, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        let Some(comm) = Self::find_comm_by_uuid(id, conn).await? else {
+            return Ok(());
+        };
+        let Some(place) = Self::find_place(&comm, conn).await? else {
+            return Ok(());
+        };
+
+        let data = json!({
+            "id": comm.id,
+            "note_id": comm.note_id,
+            "page_id": comm.page_id,
+            "short_id": place.short_name,
+            "url": format!("{}/{}#comm-{}", CONFIG.site_url(), place.short_name, comm.id),
+        });
+
+        WebhookDelivery::enqueue(event, data, conn).await
+    }
 }
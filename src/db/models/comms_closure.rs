@@ -1,8 +1,11 @@
+use std::collections::HashMap;
+
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use uuid::Uuid;
 
-use crate::db::schema::comms_closure;
+use crate::{db::schema::comms_closure, error::BlogError};
 
 #[derive(Queryable, Selectable, Insertable)]
 #[diesel(table_name = comms_closure)]
@@ -21,3 +24,104 @@ pub struct NewCommsClosure<'a> {
     descendant_id: &'a Uuid,
     distance: i64,
 }
+
+impl CommsClosure {
+    /// Links a freshly inserted comm into the tree: one self row at distance 0,
+    /// plus one row per ancestor of `parent_id` with the distance bumped by one.
+    pub async fn insert_comm(
+        comm_id: &Uuid,
+        parent_id: Option<&Uuid>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::comms_closure;
+
+        let self_row = NewCommsClosure {
+            ancestor_id: comm_id,
+            descendant_id: comm_id,
+            distance: 0,
+        };
+
+        diesel::insert_into(comms_closure::table)
+            .values(&self_row)
+            .execute(conn)
+            .await?;
+
+        if let Some(parent_id) = parent_id {
+            let ancestors = comms_closure::table
+                .filter(comms_closure::descendant_id.eq(parent_id))
+                .select((
+                    comms_closure::ancestor_id,
+                    comm_id.into_sql::<diesel::sql_types::Uuid>(),
+                    comms_closure::distance + 1,
+                ));
+
+            diesel::insert_into(comms_closure::table)
+                .values(ancestors)
+                .into_columns((
+                    comms_closure::ancestor_id,
+                    comms_closure::descendant_id,
+                    comms_closure::distance,
+                ))
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps every comm in `comm_ids` that is a reply to its direct parent.
+    pub async fn get_parents(
+        comm_ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<HashMap<Uuid, Uuid>, BlogError> {
+        use crate::db::schema::comms_closure;
+
+        let parents = comms_closure::table
+            .filter(comms_closure::descendant_id.eq_any(comm_ids))
+            .filter(comms_closure::distance.eq(1))
+            .select((comms_closure::descendant_id, comms_closure::ancestor_id))
+            .load::<(Uuid, Uuid)>(conn)
+            .await?;
+
+        Ok(parents.into_iter().collect())
+    }
+
+    /// Returns the comm itself followed by all of its replies, at any depth.
+    pub async fn get_subtree(
+        comm_id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Uuid>, BlogError> {
+        use crate::db::schema::comms_closure;
+
+        let ids = comms_closure::table
+            .filter(comms_closure::ancestor_id.eq(comm_id))
+            .order(comms_closure::distance.asc())
+            .select(comms_closure::descendant_id)
+            .load::<Uuid>(conn)
+            .await?;
+
+        Ok(ids)
+    }
+
+    /// Removes every closure row touching the given comms. Since a subtree
+    /// never spans two notes or pages, clearing rows by descendant is enough
+    /// to unlink whole threads.
+    pub async fn delete_by_comm_ids(
+        comm_ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::comms_closure;
+
+        diesel::delete(
+            comms_closure::table.filter(
+                comms_closure::descendant_id
+                    .eq_any(comm_ids)
+                    .or(comms_closure::ancestor_id.eq_any(comm_ids)),
+            ),
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
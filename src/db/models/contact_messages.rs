@@ -0,0 +1,151 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::schema::{contact_messages, sql_types::MessageStatus as MessageStatusType},
+    error::BlogError,
+};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = contact_messages)]
+pub struct ContactMessage {
+    pub id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub body: String,
+    pub status: MessageStatus,
+    pub reply: Option<String>,
+    pub replied_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = contact_messages)]
+pub struct NewContactMessage<'a> {
+    pub id: Uuid,
+    pub name: &'a str,
+    pub email: &'a str,
+    pub body: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize, utoipa::ToSchema)]
+#[ExistingTypePath = "MessageStatusType"]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStatus {
+    Unread,
+    Read,
+    Archived,
+}
+
+impl ContactMessage {
+    pub async fn create(
+        new_message: &NewContactMessage<'_>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::contact_messages;
+
+        diesel::insert_into(contact_messages::table)
+            .values(new_message)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::contact_messages;
+
+        let message = contact_messages::table
+            .find(id)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(message)
+    }
+
+    /// Lists messages in one state, newest first.
+    pub async fn get_by_status(
+        status_: &MessageStatus,
+        limit: i64,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        use crate::db::schema::contact_messages;
+
+        let list = contact_messages::table
+            .filter(contact_messages::status.eq(status_))
+            .order(contact_messages::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        let total = contact_messages::table
+            .filter(contact_messages::status.eq(status_))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((list, total as u64))
+    }
+
+    /// Returns whether the message exists.
+    pub async fn update_status(
+        id: &Uuid,
+        status_: &MessageStatus,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::contact_messages;
+
+        let now = Utc::now().naive_utc();
+        let updated = diesel::update(contact_messages::table.find(id))
+            .set((
+                contact_messages::status.eq(status_),
+                contact_messages::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(updated > 0)
+    }
+
+    /// Keeps the latest reply. An unread message counts as read from then on.
+    pub async fn set_reply(
+        id: &Uuid,
+        reply_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::contact_messages;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(contact_messages::table.find(id))
+            .set((
+                contact_messages::reply.eq(reply_),
+                contact_messages::replied_at.eq(now),
+                contact_messages::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        diesel::update(
+            contact_messages::table
+                .find(id)
+                .filter(contact_messages::status.eq(MessageStatus::Unread)),
+        )
+        .set(contact_messages::status.eq(MessageStatus::Read))
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{db::schema::federation_keys, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = federation_keys)]
+pub struct FederationKey {
+    /// PKCS#8 PEM
+    pub private_key: String,
+    /// SPKI PEM, as published on the actor
+    pub public_key: String,
+}
+
+impl FederationKey {
+    pub async fn find(conn: &mut AsyncPgConnection) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::federation_keys;
+
+        let key = federation_keys::table
+            .find(1)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(key)
+    }
+
+    /// Stores the key unless there is one already, and returns the one that
+    /// is kept.
+    pub async fn create_or_get(
+        private_key_: &str,
+        public_key_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Self, BlogError> {
+        use crate::db::schema::federation_keys;
+
+        diesel::insert_into(federation_keys::table)
+            .values((
+                federation_keys::id.eq(1),
+                federation_keys::private_key.eq(private_key_),
+                federation_keys::public_key.eq(public_key_),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        let key = federation_keys::table
+            .find(1)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await?;
+
+        Ok(key)
+    }
+}
@@ -0,0 +1,37 @@
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{db::Conn, error::BlogError};
+
+pub struct MailUnsubscribe;
+
+impl MailUnsubscribe {
+    /// Stops all further notification mails to `email`. Unsubscribing twice is
+    /// not an error.
+    pub async fn create(email: &str, conn: &mut Conn) -> Result<(), BlogError> {
+        use crate::db::schema::mail_unsubscribes;
+
+        diesel::insert_into(mail_unsubscribes::table)
+            .values(mail_unsubscribes::email.eq(email.to_lowercase()))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn is_unsubscribed(
+        email: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::mail_unsubscribes;
+
+        let unsubscribed = diesel::select(diesel::dsl::exists(
+            mail_unsubscribes::table.filter(mail_unsubscribes::email.eq(email.to_lowercase())),
+        ))
+        .get_result::<bool>(conn)
+        .await?;
+
+        Ok(unsubscribed)
+    }
+}
@@ -0,0 +1,170 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{db::schema::note_revisions, error::BlogError};
+
+/// A saved version of a note. The newest one of a note is what it is now.
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = note_revisions)]
+pub struct NoteRevision {
+    pub id: Uuid,
+    pub note_id: Uuid,
+    /// Who saved it, gone along with their account
+    pub user_id: Option<Uuid>,
+    pub created_at: NaiveDateTime,
+    pub title: String,
+    pub summary: String,
+    pub content: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = note_revisions)]
+struct NewNoteRevision<'a> {
+    id: Uuid,
+    note_id: &'a Uuid,
+    user_id: Option<&'a Uuid>,
+    created_at: NaiveDateTime,
+    title: &'a str,
+    summary: &'a str,
+    content: &'a str,
+}
+
+impl NoteRevision {
+    /// Records a version of the note saved by `user_id_` at `at`. Meant to
+    /// run inside the transaction saving it.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record(
+        note_id_: &Uuid,
+        user_id_: &Uuid,
+        at: NaiveDateTime,
+        title_: &str,
+        summary_: &str,
+        content_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        let id_ = Uuid::new_v4();
+        diesel::insert_into(note_revisions::table)
+            .values(NewNoteRevision {
+                id: id_,
+                note_id: note_id_,
+                user_id: Some(user_id_),
+                created_at: at,
+                title: title_,
+                summary: summary_,
+                content: content_,
+            })
+            .execute(conn)
+            .await?;
+
+        Ok(id_)
+    }
+
+    /// Whether any version of the note was recorded. Notes saved before
+    /// revisions were kept have none.
+    pub async fn exists(note_id_: &Uuid, conn: &mut AsyncPgConnection) -> Result<bool, BlogError> {
+        let exists = diesel::select(diesel::dsl::exists(
+            note_revisions::table.filter(note_revisions::note_id.eq(note_id_)),
+        ))
+        .get_result::<bool>(conn)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Versions of one note, newest first.
+    pub async fn get_by_note(
+        note_id_: &Uuid,
+        limit: i64,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        let revisions = note_revisions::table
+            .filter(note_revisions::note_id.eq(note_id_))
+            .order(note_revisions::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        let total = note_revisions::table
+            .filter(note_revisions::note_id.eq(note_id_))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((revisions, total as u64))
+    }
+
+    pub async fn find(
+        note_id_: &Uuid,
+        id_: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        let revision = note_revisions::table
+            .filter(note_revisions::note_id.eq(note_id_))
+            .filter(note_revisions::id.eq(id_))
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(revision)
+    }
+
+    /// The version the note is at now.
+    pub async fn latest(
+        note_id_: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        let revision = note_revisions::table
+            .filter(note_revisions::note_id.eq(note_id_))
+            .order(note_revisions::created_at.desc())
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(revision)
+    }
+
+    /// Deletes the versions of a note past the newest `keep`, and those
+    /// replaced more than `max_age_days` ago. The newest one always stays.
+    pub async fn prune(
+        note_id_: &Uuid,
+        keep: Option<usize>,
+        max_age_days: Option<u64>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        let revisions = note_revisions::table
+            .filter(note_revisions::note_id.eq(note_id_))
+            .order(note_revisions::created_at.desc())
+            .select((note_revisions::id, note_revisions::created_at))
+            .load::<(Uuid, NaiveDateTime)>(conn)
+            .await?;
+
+        let oldest = max_age_days.map(|days| Utc::now().naive_utc() - Duration::days(days as i64));
+        let stale: Vec<Uuid> = revisions
+            .windows(2)
+            .enumerate()
+            .filter(|(i, pair)| {
+                // a version is replaced when the next newer one was saved
+                let replaced_at = pair[0].1;
+                keep.is_some_and(|keep| i + 1 >= keep)
+                    || oldest.is_some_and(|oldest| replaced_at < oldest)
+            })
+            .map(|(_, pair)| pair[1].0)
+            .collect();
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        diesel::delete(note_revisions::table.filter(note_revisions::id.eq_any(&stale)))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
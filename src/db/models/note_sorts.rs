@@ -1,6 +1,8 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use uuid::Uuid;
 
 use crate::{
@@ -64,6 +66,49 @@ impl NoteSort {
         Ok(())
     }
 
+    /// Leaves the note in exactly these sorts.
+    pub async fn set_note_sorts(
+        note_id_: &Uuid,
+        sort_ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::note_sorts;
+
+        let note_id_ = *note_id_;
+        let sort_ids = sort_ids.to_vec();
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                diesel::delete(
+                    note_sorts::table.filter(
+                        note_sorts::note_id
+                            .eq(note_id_)
+                            .and(note_sorts::sort_id.ne_all(&sort_ids)),
+                    ),
+                )
+                .execute(conn)
+                .await?;
+
+                let new_note_sorts: Vec<NewNoteSort> = sort_ids
+                    .iter()
+                    .map(|sort_id| NewNoteSort {
+                        note_id: &note_id_,
+                        sort_id,
+                    })
+                    .collect();
+                diesel::insert_into(note_sorts::table)
+                    .values(&new_note_sorts)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
     pub async fn get_sorts_by_note_id(
         note_id_: &Uuid,
         conn: &mut Conn,
@@ -1,6 +1,8 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use uuid::Uuid;
 
 use crate::{
@@ -67,6 +69,64 @@ impl NoteTag {
         Ok(())
     }
 
+    /// Leaves the note with exactly these tags.
+    pub async fn set_note_tags(
+        note_id: &Uuid,
+        tag_ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::note_tags;
+
+        let note_id = *note_id;
+        let tag_ids = tag_ids.to_vec();
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                diesel::delete(
+                    note_tags::table.filter(
+                        note_tags::note_id
+                            .eq(note_id)
+                            .and(note_tags::tag_id.ne_all(&tag_ids)),
+                    ),
+                )
+                .execute(conn)
+                .await?;
+
+                let new_note_tags: Vec<NewNoteTag> = tag_ids
+                    .iter()
+                    .map(|tag_id| NewNoteTag {
+                        note_id: &note_id,
+                        tag_id,
+                    })
+                    .collect();
+                diesel::insert_into(note_tags::table)
+                    .values(&new_note_tags)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Leaves the note with exactly the tags of these contents, creating the
+    /// ones that do not exist yet.
+    pub async fn set_note_tag_contents(
+        note_id: &Uuid,
+        contents: &[String],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        let mut tag_ids = Vec::with_capacity(contents.len());
+        for content in contents {
+            tag_ids.push(Tag::find_or_create(content, conn).await?);
+        }
+
+        Self::set_note_tags(note_id, &tag_ids, conn).await
+    }
+
     pub async fn get_tags_by_note_id(
         note_id: &Uuid,
         conn: &mut Conn,
@@ -1,17 +1,22 @@
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
 use crate::{
+    config::CONFIG,
     db::{
+        models::{note_revisions::NoteRevision, webhook_deliveries::WebhookDelivery},
         schema::{notes, sql_types::PublishStatus},
         Conn, DbConn, DbPool,
     },
     error::BlogError,
-    utils::generate_random_string,
+    utils::{generate_random_string, markdown},
 };
 
 #[derive(Debug, Queryable, Selectable, Insertable, PartialEq, Identifiable)]
@@ -29,6 +34,16 @@ pub struct Note {
     pub fancy_img: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// When the note was first found public
+    pub published_at: Option<NaiveDateTime>,
+    /// `content` rendered and sanitized, see [`Note::html`]
+    pub content_html: Option<String>,
+    /// When the draft goes public, see [`Note::apply_due_schedules`]
+    pub publish_at: Option<NaiveDateTime>,
+    /// When the public note goes back to being a draft
+    pub unpublish_at: Option<NaiveDateTime>,
+    /// What the note was before it went to the recycle bin
+    pub recycled_from: Option<Status>,
 }
 
 #[derive(Insertable)]
@@ -44,6 +59,7 @@ pub struct NewNote<'a> {
     user_id: &'a Uuid,
     short_id: Uuid,
     fancy_img: Option<&'a str>,
+    content_html: Option<&'a str>,
 }
 
 #[derive(Debug, PartialEq, DbEnum, Deserialize, Serialize, utoipa::ToSchema)]
@@ -56,6 +72,14 @@ pub enum Status {
 }
 
 impl Note {
+    /// `content` as HTML. Notes saved before it was kept are rendered now.
+    pub fn html(&self) -> String {
+        self.content_html
+            .clone()
+            .unwrap_or_else(|| markdown::render(&self.content))
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_note(
         subname: Option<&str>,
         status: &Status,
@@ -66,49 +90,76 @@ impl Note {
         user_id: &Uuid,
         fancy_img: Option<&str>,
         pool: DbPool,
-    ) -> Result<(), BlogError> {
-        use crate::db::schema::{notes, short_ids};
-
+    ) -> Result<Uuid, BlogError> {
         let mut conn = pool.get().await?;
-        conn.transaction::<_, diesel::result::Error, _>(|conn| {
-            async move {
-                let short_id = Uuid::new_v4();
-                let short_name = generate_random_string(16);
-
-                diesel::insert_into(short_ids::table)
-                    .values((
-                        short_ids::id.eq(short_id),
-                        short_ids::short_name.eq(short_name),
-                        short_ids::subname.eq(subname),
-                    ))
-                    .execute(conn)
-                    .await?;
-
-                let new_note = NewNote {
-                    id: Uuid::new_v4(),
-                    title,
-                    status,
-                    summary,
-                    content,
-                    views: 0,
-                    comm,
-                    user_id,
-                    short_id,
-                    fancy_img,
-                };
+        Self::insert_note(
+            subname, status, title, summary, content, comm, user_id, fancy_img, &mut conn,
+        )
+        .await
+    }
 
-                diesel::insert_into(notes::table)
-                    .values(&new_note)
-                    .execute(conn)
-                    .await?;
+    /// [`Self::create_note`] on a connection at hand.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_note(
+        subname: Option<&str>,
+        status: &Status,
+        title: &str,
+        summary: &str,
+        content: &str,
+        comm: bool,
+        user_id: &Uuid,
+        fancy_img: Option<&str>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        use crate::db::schema::{notes, short_ids};
 
-                Ok(())
-            }
-            .scope_boxed()
-        })
-        .await?;
+        let content_html = markdown::render(content);
+        let id = conn
+            .transaction::<_, BlogError, _>(|conn| {
+                async move {
+                    let short_id = Uuid::new_v4();
+                    let short_name = generate_random_string(16);
+
+                    diesel::insert_into(short_ids::table)
+                        .values((
+                            short_ids::id.eq(short_id),
+                            short_ids::short_name.eq(short_name),
+                            short_ids::subname.eq(subname),
+                        ))
+                        .execute(conn)
+                        .await?;
+
+                    let id = Uuid::new_v4();
+                    let new_note = NewNote {
+                        id,
+                        title,
+                        status,
+                        summary,
+                        content,
+                        views: 0,
+                        comm,
+                        user_id,
+                        short_id,
+                        fancy_img,
+                        content_html: Some(&content_html),
+                    };
+
+                    diesel::insert_into(notes::table)
+                        .values(&new_note)
+                        .execute(conn)
+                        .await?;
+
+                    let now = Utc::now().naive_utc();
+                    NoteRevision::record(&id, user_id, now, title, summary, content, conn).await?;
+                    Self::emit("note.created", &id, conn).await?;
+
+                    Ok(id)
+                }
+                .scope_boxed()
+            })
+            .await?;
 
-        Ok(())
+        Ok(id)
     }
 
     pub async fn find_note_by_uuid(id: &Uuid, conn: &mut Conn) -> Result<Option<Self>, BlogError> {
@@ -214,6 +265,9 @@ impl Note {
         Ok(notes)
     }
 
+    /// Saves a new version of the note, made by `editor`, and keeps the one
+    /// it replaces in its revisions.
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_note_by_uuid(
         id: &Uuid,
         title: &str,
@@ -223,18 +277,63 @@ impl Note {
         status: &Status,
         comm: bool,
         fancy_img: Option<&str>,
+        editor: &Uuid,
         pool: DbPool,
     ) -> Result<(), BlogError> {
-        use crate::db::schema::{notes, short_ids};
-
         let mut conn = pool.get_owned().await?;
+        Self::edit_note(
+            id, title, subname, summary, content, status, comm, fancy_img, editor, &mut conn,
+        )
+        .await
+    }
 
-        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+    /// [`Self::update_note_by_uuid`] on a connection at hand.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn edit_note(
+        id: &Uuid,
+        title: &str,
+        subname: Option<&str>,
+        summary: &str,
+        content: &str,
+        status: &Status,
+        comm: bool,
+        fancy_img: Option<&str>,
+        editor: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::{notes, short_ids};
+
+        let content_html = markdown::render(content);
+        conn.transaction::<_, BlogError, _>(|conn| {
             async move {
+                // notes saved before revisions were kept get what they were
+                // as their first one
+                if !NoteRevision::exists(id, conn).await? {
+                    let note = notes::table
+                        .find(id)
+                        .select(Note::as_select())
+                        .first::<Self>(conn)
+                        .await
+                        .optional()?;
+                    if let Some(note) = note {
+                        NoteRevision::record(
+                            id,
+                            &note.user_id,
+                            note.updated_at,
+                            &note.title,
+                            &note.summary,
+                            &note.content,
+                            conn,
+                        )
+                        .await?;
+                    }
+                }
+
                 let now = Utc::now().naive_utc();
                 diesel::update(notes::table.find(id))
                     .set((
                         notes::content.eq(content),
+                        notes::content_html.eq(&content_html),
                         notes::summary.eq(summary),
                         notes::title.eq(title),
                         notes::status.eq(status),
@@ -260,6 +359,12 @@ impl Note {
                     .execute(conn)
                     .await?;
 
+                NoteRevision::record(id, editor, now, title, summary, content, conn).await?;
+                NoteRevision::prune(id, CONFIG.revisions.keep(), CONFIG.revisions.max_age, conn)
+                    .await?;
+
+                Self::emit("note.updated", id, conn).await?;
+
                 Ok(())
             }
             .scope_boxed()
@@ -269,19 +374,32 @@ impl Note {
         Ok(())
     }
 
-    pub async fn delete_note_by_uuid(id: &Uuid, pool: DbPool) -> Result<(), BlogError> {
-        use crate::db::schema::{comms, note_sorts, note_tags, notes, short_ids};
-
-        let mut conn = pool.get_owned().await?;
+    pub async fn delete_note_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::{comms, comms_closure, note_sorts, note_tags, notes, short_ids};
 
-        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        conn.transaction::<_, BlogError, _>(|conn| {
             async move {
+                // described while it still exists
+                Self::emit("note.deleted", id, conn).await?;
+
                 let short_id: Uuid = notes::table
                     .filter(notes::id.eq(id))
                     .select(notes::short_id)
                     .first::<Uuid>(conn)
                     .await?;
 
+                diesel::delete(
+                    comms_closure::table.filter(
+                        comms_closure::descendant_id
+                            .eq_any(comms::table.filter(comms::note_id.eq(id)).select(comms::id)),
+                    ),
+                )
+                .execute(conn)
+                .await?;
+
                 diesel::delete(comms::table.filter(comms::note_id.eq(id)))
                     .execute(conn)
                     .await?;
@@ -308,4 +426,415 @@ impl Note {
 
         Ok(())
     }
+
+    pub async fn update_status_by_uuid(
+        id: &Uuid,
+        status: &Status,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::notes;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                diesel::update(notes::table.find(id))
+                    .set((
+                        notes::status.eq(status),
+                        notes::updated_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+                    .await?;
+
+                Self::emit("note.updated", id, conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Moves the note to the recycle bin, keeping what it was for
+    /// [`Self::restore`].
+    pub async fn recycle(id: &Uuid, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::notes;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let recycled = diesel::update(
+                    notes::table
+                        .find(id)
+                        .filter(notes::status.ne(Status::Recycle)),
+                )
+                .set((
+                    notes::recycled_from.eq(notes::status.nullable()),
+                    notes::status.eq(Status::Recycle),
+                    notes::updated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(conn)
+                .await?;
+
+                if recycled > 0 {
+                    Self::emit("note.updated", id, conn).await?;
+                }
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Takes the note out of the recycle bin as what it was before, or as a
+    /// draft for notes recycled before that was kept. Returns whether it was
+    /// in there.
+    pub async fn restore(id: &Uuid, conn: &mut AsyncPgConnection) -> Result<bool, BlogError> {
+        use crate::db::schema::notes;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let recycled_from = notes::table
+                    .find(id)
+                    .filter(notes::status.eq(Status::Recycle))
+                    .select(notes::recycled_from)
+                    .for_update()
+                    .first::<Option<Status>>(conn)
+                    .await
+                    .optional()?;
+                let Some(recycled_from) = recycled_from else {
+                    return Ok(false);
+                };
+
+                diesel::update(notes::table.find(id))
+                    .set((
+                        notes::status.eq(recycled_from.unwrap_or(Status::Draft)),
+                        notes::recycled_from.eq(None::<Status>),
+                        notes::updated_at.eq(Utc::now().naive_utc()),
+                    ))
+                    .execute(conn)
+                    .await?;
+                Self::emit("note.updated", id, conn).await?;
+
+                Ok(true)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Sets when the note goes public and when it goes back to being a
+    /// draft, clearing either when `None`.
+    pub async fn set_schedule(
+        id: &Uuid,
+        publish_at: Option<NaiveDateTime>,
+        unpublish_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::notes;
+
+        diesel::update(notes::table.find(id))
+            .set((
+                notes::publish_at.eq(publish_at),
+                notes::unpublish_at.eq(unpublish_at),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Makes drafts due by `now` public and takes public notes due by `now`
+    /// back to drafts, up to `limit` of each, clearing the times they were
+    /// due at. Times left over on notes that were moved by hand are cleared
+    /// too. Returns the IDs of the notes moved, which still need
+    /// [`publish::published`](crate::service::publish::published) within the
+    /// same transaction.
+    pub async fn apply_due_schedules(
+        now: NaiveDateTime,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Uuid>, BlogError> {
+        use crate::db::schema::notes;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let to_publish = notes::table
+                    .filter(notes::status.eq(Status::Draft))
+                    .filter(notes::publish_at.le(now))
+                    .order(notes::publish_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(notes::id)
+                    .load::<Uuid>(conn)
+                    .await?;
+                diesel::update(notes::table.filter(notes::id.eq_any(&to_publish)))
+                    .set((
+                        notes::status.eq(Status::Public),
+                        notes::publish_at.eq(None::<NaiveDateTime>),
+                        notes::updated_at.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                diesel::update(
+                    notes::table
+                        .filter(notes::status.ne(Status::Draft))
+                        .filter(notes::publish_at.le(now)),
+                )
+                .set(notes::publish_at.eq(None::<NaiveDateTime>))
+                .execute(conn)
+                .await?;
+
+                let to_unpublish = notes::table
+                    .filter(notes::status.eq(Status::Public))
+                    .filter(notes::unpublish_at.le(now))
+                    .order(notes::unpublish_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(notes::id)
+                    .load::<Uuid>(conn)
+                    .await?;
+                diesel::update(notes::table.filter(notes::id.eq_any(&to_unpublish)))
+                    .set((
+                        notes::status.eq(Status::Draft),
+                        notes::unpublish_at.eq(None::<NaiveDateTime>),
+                        notes::updated_at.eq(now),
+                    ))
+                    .execute(conn)
+                    .await?;
+                // a note not public and not due to be either is never taken
+                // down
+                diesel::update(
+                    notes::table
+                        .filter(notes::status.ne(Status::Public))
+                        .filter(notes::publish_at.is_null())
+                        .filter(notes::unpublish_at.le(now)),
+                )
+                .set(notes::unpublish_at.eq(None::<NaiveDateTime>))
+                .execute(conn)
+                .await?;
+
+                let mut moved = to_publish;
+                for id in to_unpublish {
+                    if !moved.contains(&id) {
+                        moved.push(id);
+                    }
+                }
+                for id in &moved {
+                    Self::emit("note.updated", id, conn).await?;
+                }
+
+                Ok(moved)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Latest notes in `status` along with their short names.
+    pub async fn get_notes_by_status(
+        status: &Status,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(Self, String)>, BlogError> {
+        use crate::db::schema::{notes, short_ids};
+
+        let notes = notes::table
+            .inner_join(short_ids::table)
+            .filter(notes::status.eq(status))
+            .select((Note::as_select(), short_ids::short_name))
+            .order(notes::updated_at.desc())
+            .limit(limit)
+            .load::<(Self, String)>(conn)
+            .await?;
+
+        Ok(notes)
+    }
+
+    pub async fn count_by_status(
+        status: &Status,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<i64, BlogError> {
+        use crate::db::schema::notes;
+
+        let total = notes::table
+            .filter(notes::status.eq(status))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok(total)
+    }
+
+    /// Stamps `published_at` if the note is public for the first time, and
+    /// returns whether it was.
+    pub async fn mark_published(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::notes;
+
+        let marked = diesel::update(
+            notes::table
+                .find(id)
+                .filter(notes::status.eq(Status::Public))
+                .filter(notes::published_at.is_null()),
+        )
+        .set(notes::published_at.eq(Utc::now().naive_utc()))
+        .execute(conn)
+        .await?;
+
+        if marked > 0 {
+            Self::emit("note.published", id, conn).await?;
+        }
+
+        Ok(marked > 0)
+    }
+
+    pub async fn find_subname(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<String>, BlogError> {
+        use crate::db::schema::{notes, short_ids};
+
+        let subname = notes::table
+            .inner_join(short_ids::table)
+            .filter(notes::id.eq(id))
+            .select(short_ids::subname)
+            .first::<Option<String>>(conn)
+            .await
+            .optional()?;
+
+        Ok(subname.flatten())
+    }
+
+    /// The note along with the name it is linked by, its subname if it has
+    /// one.
+    pub async fn find_note_with_short_name(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<(Self, String)>, BlogError> {
+        use crate::db::schema::{notes, short_ids};
+
+        let note = notes::table
+            .inner_join(short_ids::table)
+            .filter(notes::id.eq(id))
+            .select((
+                Note::as_select(),
+                (short_ids::short_name, short_ids::subname),
+            ))
+            .first::<(Self, (String, Option<String>))>(conn)
+            .await
+            .optional()?;
+
+        Ok(note.map(|(note, (short_name, subname))| (note, subname.unwrap_or(short_name))))
+    }
+
+    /// Public notes with the most views along with the names they are linked
+    /// by.
+    pub async fn get_most_viewed(
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<(Self, String)>, BlogError> {
+        use crate::db::schema::{notes, short_ids};
+
+        let notes = notes::table
+            .inner_join(short_ids::table)
+            .filter(notes::status.eq(Status::Public))
+            .select((
+                Note::as_select(),
+                (short_ids::short_name, short_ids::subname),
+            ))
+            .order(notes::views.desc())
+            .limit(limit)
+            .load::<(Self, (String, Option<String>))>(conn)
+            .await?;
+
+        Ok(notes
+            .into_iter()
+            .map(|(note, (short_name, subname))| (note, subname.unwrap_or(short_name)))
+            .collect())
+    }
+
+    /// Queues content webhooks about the note, as it is now.
+    async fn emit(event: &str, id: &Uuid, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        let Some((note, short_name)) = Self::find_note_with_short_name(id, conn).await? else {
+            return Ok(());
+        };
+
+        let data = json!({
+            "id": note.id,
+            "title": note.title,
+            "status": note.status,
+            "short_id": short_name,
+            "url": format!("{}/{}", CONFIG.site_url(), short_name),
+        });
+
+        WebhookDelivery::enqueue(event, data, conn).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::models::webhook_endpoints::{NewWebhookEndpoint, WebhookEndpoint};
+
+    /// Needs a migrated database at `TEST_DATABASE_URL` and is skipped
+    /// without one. Nothing it writes outlives the test transaction.
+    #[tokio::test]
+    async fn test_create_note_emits_created() {
+        let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+            return;
+        };
+        let mut conn = AsyncPgConnection::establish(&url).await.unwrap();
+        conn.begin_test_transaction().await.unwrap();
+
+        let user_id = Uuid::new_v4();
+        {
+            use crate::db::schema::users;
+
+            diesel::insert_into(users::table)
+                .values((
+                    users::id.eq(user_id),
+                    users::username.eq("neko"),
+                    users::nickname.eq("Neko"),
+                    users::email.eq("neko@example.com"),
+                    users::password_hash.eq(""),
+                ))
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        let endpoint_id = Uuid::new_v4();
+        WebhookEndpoint::create(
+            &NewWebhookEndpoint {
+                id: endpoint_id,
+                url: "https://example.com/hook",
+                secret: "nekonya",
+                events: &[String::from("note.created")],
+            },
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let note_id = Note::insert_note(
+            None,
+            &Status::Draft,
+            "Hello",
+            "",
+            "nya",
+            true,
+            &user_id,
+            None,
+            &mut conn,
+        )
+        .await
+        .unwrap();
+
+        let (deliveries, total) = WebhookDelivery::get_by_endpoint(&endpoint_id, 10, 0, &mut conn)
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(deliveries[0].event, "note.created");
+        assert_eq!(deliveries[0].payload["data"]["id"], json!(note_id));
+    }
 }
@@ -0,0 +1,26 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::error::BlogError;
+
+pub struct NotifyDigest;
+
+impl NotifyDigest {
+    /// Claims the digest of the period ending at `period_end`. Returns whether
+    /// it was still to be sent.
+    pub async fn claim(
+        period_end: NaiveDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::notify_digests;
+
+        let claimed = diesel::insert_into(notify_digests::table)
+            .values(notify_digests::period_end.eq(period_end))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        Ok(claimed > 0)
+    }
+}
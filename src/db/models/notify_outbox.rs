@@ -0,0 +1,257 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::schema::{notify_outbox, sql_types::OutboxStatus as OutboxStatusType},
+    error::BlogError,
+};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = notify_outbox)]
+pub struct OutboxMessage {
+    pub id: Uuid,
+    pub channel: String,
+    pub payload: serde_json::Value,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = notify_outbox)]
+pub struct NewOutboxMessage<'a> {
+    id: Uuid,
+    channel: &'a str,
+    payload: &'a serde_json::Value,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize, utoipa::ToSchema)]
+#[ExistingTypePath = "OutboxStatusType"]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    Pending,
+    Delivered,
+    Dead,
+}
+
+impl OutboxMessage {
+    /// Queues `payload` for `channel`. Meant to run inside the transaction of
+    /// the write that caused it, so neither exists without the other.
+    pub async fn enqueue(
+        channel: &str,
+        payload: &serde_json::Value,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        diesel::insert_into(notify_outbox::table)
+            .values(NewOutboxMessage {
+                id: Uuid::new_v4(),
+                channel,
+                payload,
+            })
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Queues many payloads for `channel` with a single insert.
+    pub async fn enqueue_many(
+        channel: &str,
+        payloads: &[serde_json::Value],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let messages: Vec<_> = payloads
+            .iter()
+            .map(|payload| NewOutboxMessage {
+                id: Uuid::new_v4(),
+                channel,
+                payload,
+            })
+            .collect();
+
+        diesel::insert_into(notify_outbox::table)
+            .values(&messages)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Takes up to `limit` due messages and pushes their next attempt back by
+    /// `lease_secs`, so other workers leave them alone while they are sent.
+    pub async fn claim_due(
+        limit: i64,
+        lease_secs: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let now = Utc::now().naive_utc();
+                let messages = notify_outbox::table
+                    .filter(notify_outbox::status.eq(OutboxStatus::Pending))
+                    .filter(notify_outbox::next_attempt_at.le(now))
+                    .order(notify_outbox::next_attempt_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(Self::as_select())
+                    .load::<Self>(conn)
+                    .await?;
+
+                let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+                diesel::update(notify_outbox::table.filter(notify_outbox::id.eq_any(&ids)))
+                    .set(
+                        notify_outbox::next_attempt_at
+                            .eq(now + chrono::Duration::seconds(lease_secs)),
+                    )
+                    .execute(conn)
+                    .await?;
+
+                Ok(messages)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn mark_delivered(id: &Uuid, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(notify_outbox::table.find(id))
+            .set((
+                notify_outbox::status.eq(OutboxStatus::Delivered),
+                notify_outbox::attempts.eq(notify_outbox::attempts + 1),
+                notify_outbox::delivered_at.eq(now),
+                notify_outbox::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. Without a `retry_at` the message is given up
+    /// on and left for an admin to replay.
+    pub async fn mark_failed(
+        id: &Uuid,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let now = Utc::now().naive_utc();
+        let status = if retry_at.is_some() {
+            OutboxStatus::Pending
+        } else {
+            OutboxStatus::Dead
+        };
+
+        diesel::update(notify_outbox::table.find(id))
+            .set((
+                notify_outbox::status.eq(status),
+                notify_outbox::attempts.eq(notify_outbox::attempts + 1),
+                notify_outbox::next_attempt_at.eq(retry_at.unwrap_or(now)),
+                notify_outbox::last_error.eq(error),
+                notify_outbox::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_messages_by_status(
+        status: &OutboxStatus,
+        limit: i64,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let messages = notify_outbox::table
+            .filter(notify_outbox::status.eq(status))
+            .order(notify_outbox::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        let total = notify_outbox::table
+            .filter(notify_outbox::status.eq(status))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((messages, total as u64))
+    }
+
+    /// Sends the given messages again from scratch, whatever became of them.
+    pub async fn replay_by_uuids(
+        ids: &[Uuid],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<usize, BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let now = Utc::now().naive_utc();
+        let replayed = diesel::update(notify_outbox::table.filter(notify_outbox::id.eq_any(ids)))
+            .set((
+                notify_outbox::status.eq(OutboxStatus::Pending),
+                notify_outbox::attempts.eq(0),
+                notify_outbox::next_attempt_at.eq(now),
+                notify_outbox::delivered_at.eq(None::<NaiveDateTime>),
+                notify_outbox::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(replayed)
+    }
+
+    /// Messages given up on between `start` and `end`, latest first.
+    pub async fn get_dead_between(
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        limit: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        use crate::db::schema::notify_outbox;
+
+        let messages = notify_outbox::table
+            .filter(notify_outbox::status.eq(OutboxStatus::Dead))
+            .filter(notify_outbox::updated_at.ge(start))
+            .filter(notify_outbox::updated_at.lt(end))
+            .order(notify_outbox::updated_at.desc())
+            .limit(limit)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        let total = notify_outbox::table
+            .filter(notify_outbox::status.eq(OutboxStatus::Dead))
+            .filter(notify_outbox::updated_at.ge(start))
+            .filter(notify_outbox::updated_at.lt(end))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((messages, total as u64))
+    }
+}
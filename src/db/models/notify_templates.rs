@@ -0,0 +1,79 @@
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{db::schema::notify_templates, error::BlogError};
+
+/// The admin's version of the templates for one event.
+#[derive(Debug, Clone, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = notify_templates)]
+#[diesel(treat_none_as_null = true)]
+pub struct NotifyTemplate {
+    pub event: String,
+    pub subject: Option<String>,
+    pub html: Option<String>,
+    pub text: Option<String>,
+    pub telegram: Option<String>,
+    pub webhook: Option<String>,
+}
+
+impl NotifyTemplate {
+    pub async fn find_by_event(
+        event_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::notify_templates;
+
+        let template = notify_templates::table
+            .find(event_)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(template)
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::notify_templates;
+
+        let templates = notify_templates::table
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(templates)
+    }
+
+    /// Replaces whatever was stored for the event, parts left out included.
+    pub async fn save(&self, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+        use crate::db::schema::notify_templates;
+
+        diesel::insert_into(notify_templates::table)
+            .values(self)
+            .on_conflict(notify_templates::event)
+            .do_update()
+            .set((
+                self,
+                notify_templates::updated_at.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Goes back to the defaults. Returns whether anything was stored.
+    pub async fn delete_by_event(
+        event_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::notify_templates;
+
+        let deleted = diesel::delete(notify_templates::table.find(event_))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -3,14 +3,18 @@ pub mod about;
 use about::AboutPage;
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use uuid::Uuid;
 
 use super::notes::Status;
 use crate::{
-    db::{schema::sql_types::PageType, DbPool},
+    config::CONFIG,
+    db::{models::webhook_deliveries::WebhookDelivery, schema::sql_types::PageType, DbPool},
     error::BlogError,
 };
 
@@ -84,7 +88,7 @@ pub trait Page {
     }
 
     async fn delete_page(id: &Uuid, pool: DbPool) -> Result<(), BlogError> {
-        use crate::db::schema::{comms, page_about, page_sorts, pages, short_ids};
+        use crate::db::schema::{comms, comms_closure, page_about, page_sorts, pages, short_ids};
         let mut conn = pool.get_owned().await?;
 
         conn.transaction::<_, diesel::result::Error, _>(|conn| {
@@ -96,7 +100,16 @@ pub trait Page {
                     .first::<(Uuid, PageTy)>(conn)
                     .await?;
 
-                diesel::delete(comms::table.filter(comms::note_id.eq(id)))
+                diesel::delete(
+                    comms_closure::table.filter(
+                        comms_closure::descendant_id
+                            .eq_any(comms::table.filter(comms::page_id.eq(id)).select(comms::id)),
+                    ),
+                )
+                .execute(conn)
+                .await?;
+
+                diesel::delete(comms::table.filter(comms::page_id.eq(id)))
                     .execute(conn)
                     .await?;
 
@@ -128,6 +141,38 @@ pub trait Page {
     }
 }
 
+/// Queues content webhooks about the page, as it is now.
+async fn emit(event: &str, id: &Uuid, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+    use crate::db::schema::{pages, short_ids};
+
+    let page = pages::table
+        .inner_join(short_ids::table)
+        .filter(pages::id.eq(id))
+        .select((
+            pages::page_type,
+            pages::status,
+            short_ids::short_name,
+            short_ids::subname,
+        ))
+        .first::<(PageTy, Status, String, Option<String>)>(conn)
+        .await
+        .optional()?;
+    let Some((page_type, status, short_name, subname)) = page else {
+        return Ok(());
+    };
+
+    let short_name = subname.unwrap_or(short_name);
+    let data = json!({
+        "id": id,
+        "type": page_type,
+        "status": status,
+        "short_id": short_name,
+        "url": format!("{}/{}", CONFIG.site_url(), short_name),
+    });
+
+    WebhookDelivery::enqueue(event, data, conn).await
+}
+
 #[derive(Debug, PartialEq, DbEnum, Serialize, Deserialize)]
 #[ExistingTypePath = "PageType"]
 pub enum PageTy {
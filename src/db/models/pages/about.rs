@@ -13,7 +13,7 @@ use crate::{
     utils::generate_random_string,
 };
 
-use super::Page;
+use super::{emit, Page};
 
 #[derive(Debug, Queryable, Selectable, Insertable, Identifiable)]
 #[diesel(table_name = page_about)]
@@ -95,7 +95,7 @@ impl Page for AboutPage {
         use crate::db::schema::{page_about, pages, short_ids};
 
         let mut conn = pool.get().await?;
-        conn.transaction::<_, diesel::result::Error, _>(|conn| {
+        conn.transaction::<_, BlogError, _>(|conn| {
             async move {
                 let now = Utc::now().naive_utc();
                 diesel::update(pages::table.find(id))
@@ -129,7 +129,7 @@ impl Page for AboutPage {
                     .execute(conn)
                     .await?;
 
-                Ok(())
+                emit("page.updated", id, conn).await
             }
             .scope_boxed()
         })
@@ -0,0 +1,95 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{db::schema::publish_tokens, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = publish_tokens)]
+pub struct PublishToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub user_id: Uuid,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = publish_tokens)]
+pub struct NewPublishToken<'a> {
+    pub id: Uuid,
+    pub name: &'a str,
+    pub token_hash: &'a str,
+    pub scopes: &'a [String],
+    pub user_id: &'a Uuid,
+}
+
+impl PublishToken {
+    /// Whether the token was granted any of these scopes.
+    pub fn allows(&self, scopes: &[&str]) -> bool {
+        self.scopes
+            .iter()
+            .any(|scope| scopes.contains(&scope.as_str()))
+    }
+
+    pub async fn create(
+        new_token: &NewPublishToken<'_>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::publish_tokens;
+
+        diesel::insert_into(publish_tokens::table)
+            .values(new_token)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// The token with this hash, stamped as used.
+    pub async fn use_by_hash(
+        token_hash_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::publish_tokens;
+
+        let token = diesel::update(
+            publish_tokens::table.filter(publish_tokens::token_hash.eq(token_hash_)),
+        )
+        .set(publish_tokens::last_used_at.eq(Utc::now().naive_utc()))
+        .returning(Self::as_returning())
+        .get_result::<Self>(conn)
+        .await
+        .optional()?;
+
+        Ok(token)
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::publish_tokens;
+
+        let tokens = publish_tokens::table
+            .select(Self::as_select())
+            .order(publish_tokens::created_at.asc())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revokes the token. Returns whether it existed.
+    pub async fn delete_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::publish_tokens;
+
+        let deleted = diesel::delete(publish_tokens::table.find(id))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+
+use crate::{db::schema::spam_tokens, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = spam_tokens)]
+pub struct SpamToken {
+    pub token: String,
+    pub spam_count: i64,
+    pub ham_count: i64,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = spam_tokens)]
+pub struct NewSpamToken<'a> {
+    token: &'a str,
+    spam_count: i64,
+    ham_count: i64,
+}
+
+impl SpamToken {
+    /// Looks up the spam/ham counts of the given tokens; unseen ones are absent.
+    pub async fn get_counts(
+        tokens: &[String],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<HashMap<String, (i64, i64)>, BlogError> {
+        use crate::db::schema::spam_tokens;
+
+        let counts = spam_tokens::table
+            .filter(spam_tokens::token.eq_any(tokens))
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?
+            .into_iter()
+            .map(|t| (t.token, (t.spam_count, t.ham_count)))
+            .collect();
+
+        Ok(counts)
+    }
+
+    /// Returns how many comms were trained as spam and as ham.
+    pub async fn get_corpus(conn: &mut AsyncPgConnection) -> Result<(i64, i64), BlogError> {
+        use crate::db::schema::spam_corpus;
+
+        let corpus = spam_corpus::table
+            .find(1)
+            .select((spam_corpus::spam_docs, spam_corpus::ham_docs))
+            .first::<(i64, i64)>(conn)
+            .await
+            .optional()?
+            .unwrap_or_default();
+
+        Ok(corpus)
+    }
+
+    /// Adds one document worth of `tokens` to the statistics. Negative deltas
+    /// take a previously learned document back out.
+    pub async fn train(
+        tokens: &[String],
+        spam_delta: i64,
+        ham_delta: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::{spam_corpus, spam_tokens};
+
+        let now = Utc::now().naive_utc();
+
+        if !tokens.is_empty() {
+            let rows: Vec<NewSpamToken> = tokens
+                .iter()
+                .map(|token| NewSpamToken {
+                    token,
+                    spam_count: spam_delta.max(0),
+                    ham_count: ham_delta.max(0),
+                })
+                .collect();
+
+            diesel::insert_into(spam_tokens::table)
+                .values(&rows)
+                .on_conflict(spam_tokens::token)
+                .do_update()
+                .set((
+                    spam_tokens::spam_count.eq(spam_tokens::spam_count + spam_delta),
+                    spam_tokens::ham_count.eq(spam_tokens::ham_count + ham_delta),
+                    spam_tokens::updated_at.eq(now),
+                ))
+                .execute(conn)
+                .await?;
+
+            // untraining can leave tokens nobody has counts for anymore
+            diesel::delete(
+                spam_tokens::table
+                    .filter(spam_tokens::token.eq_any(tokens))
+                    .filter(spam_tokens::spam_count.le(0))
+                    .filter(spam_tokens::ham_count.le(0)),
+            )
+            .execute(conn)
+            .await?;
+        }
+
+        diesel::insert_into(spam_corpus::table)
+            .values((
+                spam_corpus::id.eq(1),
+                spam_corpus::spam_docs.eq(spam_delta.max(0)),
+                spam_corpus::ham_docs.eq(ham_delta.max(0)),
+            ))
+            .on_conflict(spam_corpus::id)
+            .do_update()
+            .set((
+                spam_corpus::spam_docs.eq(spam_corpus::spam_docs + spam_delta),
+                spam_corpus::ham_docs.eq(spam_corpus::ham_docs + ham_delta),
+                spam_corpus::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{db::schema::subscribers, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = subscribers)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub created_at: NaiveDateTime,
+    pub confirmed_at: Option<NaiveDateTime>,
+}
+
+impl Subscriber {
+    /// Signs `email` up, unconfirmed. Signing up again returns the subscriber
+    /// as it is.
+    pub async fn create(email: &str, conn: &mut AsyncPgConnection) -> Result<Self, BlogError> {
+        use crate::db::schema::subscribers;
+
+        let email = email.to_lowercase();
+        diesel::insert_into(subscribers::table)
+            .values((
+                subscribers::id.eq(Uuid::new_v4()),
+                subscribers::email.eq(&email),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)
+            .await?;
+
+        let subscriber = subscribers::table
+            .filter(subscribers::email.eq(&email))
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await?;
+
+        Ok(subscriber)
+    }
+
+    /// Returns whether `email` was waiting for confirmation.
+    pub async fn confirm(email: &str, conn: &mut AsyncPgConnection) -> Result<bool, BlogError> {
+        use crate::db::schema::subscribers;
+
+        let now = Utc::now().naive_utc();
+        let confirmed = diesel::update(
+            subscribers::table
+                .filter(subscribers::email.eq(email.to_lowercase()))
+                .filter(subscribers::confirmed_at.is_null()),
+        )
+        .set((
+            subscribers::confirmed_at.eq(now),
+            subscribers::updated_at.eq(now),
+        ))
+        .execute(conn)
+        .await?;
+
+        Ok(confirmed > 0)
+    }
+
+    /// Unsubscribing twice is not an error.
+    pub async fn delete_by_email(
+        email: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::subscribers;
+
+        diesel::delete(subscribers::table.filter(subscribers::email.eq(email.to_lowercase())))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_confirmed_emails(
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<String>, BlogError> {
+        use crate::db::schema::subscribers;
+
+        let emails = subscribers::table
+            .filter(subscribers::confirmed_at.is_not_null())
+            .select(subscribers::email)
+            .order(subscribers::created_at.asc())
+            .load::<String>(conn)
+            .await?;
+
+        Ok(emails)
+    }
+
+    pub async fn get_subscribers(
+        limit: i64,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        use crate::db::schema::subscribers;
+
+        let list = subscribers::table
+            .select(Self::as_select())
+            .order(subscribers::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .load::<Self>(conn)
+            .await?;
+
+        let total = subscribers::table.count().get_result::<i64>(conn).await?;
+
+        Ok((list, total as u64))
+    }
+
+    /// Subscribers confirmed between `start` and `end`, and all of them.
+    pub async fn count_confirmed(
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(u64, u64), BlogError> {
+        use crate::db::schema::subscribers;
+
+        let new = subscribers::table
+            .filter(subscribers::confirmed_at.ge(start))
+            .filter(subscribers::confirmed_at.lt(end))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        let total = subscribers::table
+            .filter(subscribers::confirmed_at.is_not_null())
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((new as u64, total as u64))
+    }
+}
@@ -1,6 +1,8 @@
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
-use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, RunQueryDsl};
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
 use uuid::Uuid;
 
 use crate::{
@@ -42,6 +44,35 @@ impl Tag {
         Ok(())
     }
 
+    /// ID of the tag with this content, created when there is none.
+    pub async fn find_or_create(
+        content_: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        use crate::db::schema::tags;
+
+        let id = tags::table
+            .filter(tags::content.eq(content_))
+            .select(tags::id)
+            .first::<Uuid>(conn)
+            .await
+            .optional()?;
+        if let Some(id) = id {
+            return Ok(id);
+        }
+
+        let new_tag = NewTag {
+            id: Uuid::new_v4(),
+            content: content_,
+        };
+        diesel::insert_into(tags::table)
+            .values(&new_tag)
+            .execute(conn)
+            .await?;
+
+        Ok(new_tag.id)
+    }
+
     pub async fn update_tag_by_uuid(
         id: &Uuid,
         content: &str,
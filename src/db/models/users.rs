@@ -1,6 +1,6 @@
 use chrono::NaiveDateTime;
 use diesel::prelude::*;
-use diesel_async::RunQueryDsl;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
 use uuid::Uuid;
 
 use crate::{
@@ -73,6 +73,22 @@ impl User {
         Ok(user)
     }
 
+    pub async fn find_user_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::users;
+
+        let user = users::table
+            .find(id)
+            .select(User::as_select())
+            .first(conn)
+            .await
+            .optional()?;
+
+        Ok(user)
+    }
+
     pub async fn find_user_by_email(
         email: &str,
         conn: &mut Conn,
@@ -119,4 +135,30 @@ impl User {
 
         Ok(())
     }
+
+    pub async fn get_nicknames_by_ids(
+        ids: &[Uuid],
+        conn: &mut Conn,
+    ) -> Result<Vec<(Uuid, String)>, BlogError> {
+        use crate::db::schema::users;
+
+        let nicknames = users::table
+            .filter(users::id.eq_any(ids))
+            .select((users::id, users::nickname))
+            .load::<(Uuid, String)>(conn)
+            .await?;
+
+        Ok(nicknames)
+    }
+
+    pub async fn get_emails(conn: &mut AsyncPgConnection) -> Result<Vec<String>, BlogError> {
+        use crate::db::schema::users;
+
+        let emails = users::table
+            .select(users::email)
+            .load::<String>(conn)
+            .await?;
+
+        Ok(emails)
+    }
 }
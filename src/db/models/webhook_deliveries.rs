@@ -0,0 +1,249 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{db::schema::webhook_deliveries, error::BlogError};
+
+use super::notify_outbox::OutboxStatus;
+
+/// Content events endpoints can subscribe to.
+pub const EVENTS: &[&str] = &[
+    "note.created",
+    "note.published",
+    "note.updated",
+    "note.deleted",
+    "page.updated",
+    "comment.approved",
+];
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub endpoint_id: Uuid,
+    pub event: String,
+    pub payload: Value,
+    pub status: OutboxStatus,
+    pub attempts: i32,
+    pub next_attempt_at: NaiveDateTime,
+    pub response_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+struct NewWebhookDelivery<'a> {
+    id: Uuid,
+    endpoint_id: Uuid,
+    event: &'a str,
+    payload: &'a Value,
+}
+
+impl WebhookDelivery {
+    /// Queues `event` for every active endpoint subscribed to it. Meant to run
+    /// inside the transaction of the change it is about.
+    pub async fn enqueue(
+        event_: &str,
+        data: Value,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::{webhook_deliveries, webhook_endpoints};
+
+        let endpoints = webhook_endpoints::table
+            .filter(webhook_endpoints::active.eq(true))
+            .select((webhook_endpoints::id, webhook_endpoints::events))
+            .load::<(Uuid, Vec<String>)>(conn)
+            .await?;
+
+        let endpoint_ids: Vec<Uuid> = endpoints
+            .into_iter()
+            .filter(|(_, events)| events.is_empty() || events.iter().any(|e| e == event_))
+            .map(|(id, _)| id)
+            .collect();
+        if endpoint_ids.is_empty() {
+            return Ok(());
+        }
+
+        let payload = Self::payload(event_, data);
+        let deliveries: Vec<_> = endpoint_ids
+            .into_iter()
+            .map(|endpoint_id| NewWebhookDelivery {
+                id: Uuid::new_v4(),
+                endpoint_id,
+                event: event_,
+                payload: &payload,
+            })
+            .collect();
+
+        diesel::insert_into(webhook_deliveries::table)
+            .values(&deliveries)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// What receivers get: the event with its own ID, which stays the same
+    /// across endpoints and retries.
+    pub fn payload(event_: &str, data: Value) -> Value {
+        json!({
+            "id": Uuid::new_v4(),
+            "event": event_,
+            "created_at": Utc::now().timestamp(),
+            "data": data,
+        })
+    }
+
+    /// Records a delivery made right away, as for test events. Returns its ID.
+    pub async fn create(
+        endpoint_id_: &Uuid,
+        event_: &str,
+        payload_: &Value,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        use crate::db::schema::webhook_deliveries;
+
+        let id_ = Uuid::new_v4();
+        diesel::insert_into(webhook_deliveries::table)
+            .values(NewWebhookDelivery {
+                id: id_,
+                endpoint_id: *endpoint_id_,
+                event: event_,
+                payload: payload_,
+            })
+            .execute(conn)
+            .await?;
+
+        Ok(id_)
+    }
+
+    /// Takes up to `limit` due deliveries and pushes their next attempt back
+    /// by `lease_secs`, so other workers leave them alone while they are sent.
+    pub async fn claim_due(
+        limit: i64,
+        lease_secs: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::webhook_deliveries;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let now = Utc::now().naive_utc();
+                let deliveries = webhook_deliveries::table
+                    .filter(webhook_deliveries::status.eq(OutboxStatus::Pending))
+                    .filter(webhook_deliveries::next_attempt_at.le(now))
+                    .order(webhook_deliveries::next_attempt_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(Self::as_select())
+                    .load::<Self>(conn)
+                    .await?;
+
+                let ids: Vec<Uuid> = deliveries.iter().map(|d| d.id).collect();
+                diesel::update(
+                    webhook_deliveries::table.filter(webhook_deliveries::id.eq_any(&ids)),
+                )
+                .set(
+                    webhook_deliveries::next_attempt_at
+                        .eq(now + chrono::Duration::seconds(lease_secs)),
+                )
+                .execute(conn)
+                .await?;
+
+                Ok(deliveries)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn mark_delivered(
+        id: &Uuid,
+        response_status_: i32,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webhook_deliveries;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(OutboxStatus::Delivered),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::response_status.eq(response_status_),
+                webhook_deliveries::last_error.eq(None::<String>),
+                webhook_deliveries::delivered_at.eq(now),
+                webhook_deliveries::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. Without a `retry_at` the delivery is given up
+    /// on.
+    pub async fn mark_failed(
+        id: &Uuid,
+        response_status_: Option<i32>,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webhook_deliveries;
+
+        let now = Utc::now().naive_utc();
+        let status_ = if retry_at.is_some() {
+            OutboxStatus::Pending
+        } else {
+            OutboxStatus::Dead
+        };
+
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::status.eq(status_),
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::next_attempt_at.eq(retry_at.unwrap_or(now)),
+                webhook_deliveries::response_status.eq(response_status_),
+                webhook_deliveries::last_error.eq(error),
+                webhook_deliveries::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deliveries to one endpoint, latest first.
+    pub async fn get_by_endpoint(
+        endpoint_id_: &Uuid,
+        limit: i64,
+        offset: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(Vec<Self>, u64), BlogError> {
+        use crate::db::schema::webhook_deliveries;
+
+        let deliveries = webhook_deliveries::table
+            .filter(webhook_deliveries::endpoint_id.eq(endpoint_id_))
+            .order(webhook_deliveries::created_at.desc())
+            .limit(limit)
+            .offset(offset)
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        let total = webhook_deliveries::table
+            .filter(webhook_deliveries::endpoint_id.eq(endpoint_id_))
+            .count()
+            .get_result::<i64>(conn)
+            .await?;
+
+        Ok((deliveries, total as u64))
+    }
+}
@@ -0,0 +1,85 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use diesel_async::{AsyncPgConnection, RunQueryDsl};
+use uuid::Uuid;
+
+use crate::{db::schema::webhook_endpoints, error::BlogError};
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = webhook_endpoints)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub active: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_endpoints)]
+pub struct NewWebhookEndpoint<'a> {
+    pub id: Uuid,
+    pub url: &'a str,
+    pub secret: &'a str,
+    pub events: &'a [String],
+}
+
+impl WebhookEndpoint {
+    pub async fn create(
+        new_endpoint: &NewWebhookEndpoint<'_>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webhook_endpoints;
+
+        diesel::insert_into(webhook_endpoints::table)
+            .values(new_endpoint)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        use crate::db::schema::webhook_endpoints;
+
+        let endpoint = webhook_endpoints::table
+            .find(id)
+            .select(Self::as_select())
+            .first::<Self>(conn)
+            .await
+            .optional()?;
+
+        Ok(endpoint)
+    }
+
+    pub async fn get_all(conn: &mut AsyncPgConnection) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::webhook_endpoints;
+
+        let endpoints = webhook_endpoints::table
+            .select(Self::as_select())
+            .order(webhook_endpoints::created_at.asc())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(endpoints)
+    }
+
+    /// Removes the endpoint along with its deliveries. Returns whether it
+    /// existed.
+    pub async fn delete_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::webhook_endpoints;
+
+        let deleted = diesel::delete(webhook_endpoints::table.find(id))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
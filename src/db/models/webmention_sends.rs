@@ -0,0 +1,177 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use uuid::Uuid;
+
+use crate::{db::schema::webmention_sends, error::BlogError};
+
+use super::notify_outbox::OutboxStatus;
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = webmention_sends)]
+pub struct WebmentionSend {
+    pub id: Uuid,
+    pub note_id: Uuid,
+    pub target: String,
+    pub attempts: i32,
+}
+
+impl WebmentionSend {
+    /// Queues a mention of every target by the note. Targets mentioned before
+    /// are queued again, so they can have a look at what changed.
+    pub async fn enqueue(
+        note_id_: &Uuid,
+        targets: &[String],
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webmention_sends;
+
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let now = Utc::now().naive_utc();
+        let rows: Vec<_> = targets
+            .iter()
+            .map(|target_| {
+                (
+                    webmention_sends::id.eq(Uuid::new_v4()),
+                    webmention_sends::note_id.eq(*note_id_),
+                    webmention_sends::target.eq(target_),
+                )
+            })
+            .collect();
+
+        diesel::insert_into(webmention_sends::table)
+            .values(&rows)
+            .on_conflict((webmention_sends::note_id, webmention_sends::target))
+            .do_update()
+            .set((
+                webmention_sends::status.eq(OutboxStatus::Pending),
+                webmention_sends::attempts.eq(0),
+                webmention_sends::next_attempt_at.eq(now),
+                webmention_sends::last_error.eq(None::<String>),
+                webmention_sends::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Everything the note was ever found to link to.
+    pub async fn get_targets(
+        note_id_: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<String>, BlogError> {
+        use crate::db::schema::webmention_sends;
+
+        let targets = webmention_sends::table
+            .filter(webmention_sends::note_id.eq(note_id_))
+            .select(webmention_sends::target)
+            .load::<String>(conn)
+            .await?;
+
+        Ok(targets)
+    }
+
+    /// Takes up to `limit` due mentions and pushes their next attempt back by
+    /// `lease_secs`, so other workers leave them alone while they are sent.
+    pub async fn claim_due(
+        limit: i64,
+        lease_secs: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::webmention_sends;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let now = Utc::now().naive_utc();
+                let sends = webmention_sends::table
+                    .filter(webmention_sends::status.eq(OutboxStatus::Pending))
+                    .filter(webmention_sends::next_attempt_at.le(now))
+                    .order(webmention_sends::next_attempt_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(Self::as_select())
+                    .load::<Self>(conn)
+                    .await?;
+
+                let ids: Vec<Uuid> = sends.iter().map(|s| s.id).collect();
+                diesel::update(webmention_sends::table.filter(webmention_sends::id.eq_any(&ids)))
+                    .set(
+                        webmention_sends::next_attempt_at
+                            .eq(now + chrono::Duration::seconds(lease_secs)),
+                    )
+                    .execute(conn)
+                    .await?;
+
+                Ok(sends)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    /// Records a mention that is done with, sent or not: targets without an
+    /// endpoint do not take mentions.
+    pub async fn mark_sent(
+        id: &Uuid,
+        endpoint_: Option<&str>,
+        response_status_: Option<i32>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webmention_sends;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(webmention_sends::table.find(id))
+            .set((
+                webmention_sends::status.eq(OutboxStatus::Delivered),
+                webmention_sends::attempts.eq(webmention_sends::attempts + 1),
+                webmention_sends::endpoint.eq(endpoint_),
+                webmention_sends::response_status.eq(response_status_),
+                webmention_sends::last_error.eq(None::<String>),
+                webmention_sends::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. Without a `retry_at` the mention is given up
+    /// on.
+    pub async fn mark_failed(
+        id: &Uuid,
+        response_status_: Option<i32>,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webmention_sends;
+
+        let now = Utc::now().naive_utc();
+        let status_ = if retry_at.is_some() {
+            OutboxStatus::Pending
+        } else {
+            OutboxStatus::Dead
+        };
+
+        diesel::update(webmention_sends::table.find(id))
+            .set((
+                webmention_sends::status.eq(status_),
+                webmention_sends::attempts.eq(webmention_sends::attempts + 1),
+                webmention_sends::next_attempt_at.eq(retry_at.unwrap_or(now)),
+                webmention_sends::response_status.eq(response_status_),
+                webmention_sends::last_error.eq(error),
+                webmention_sends::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
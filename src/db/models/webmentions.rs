@@ -0,0 +1,224 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel_async::{
+    scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection, RunQueryDsl,
+};
+use diesel_derive_enum::DbEnum;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    db::schema::{sql_types::MentionStatus as MentionStatusType, webmentions},
+    error::BlogError,
+};
+
+use super::comms::CommType;
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = webmentions)]
+pub struct Webmention {
+    pub id: Uuid,
+    pub source: String,
+    pub target: String,
+    pub author_name: Option<String>,
+    pub author_url: Option<String>,
+    pub title: Option<String>,
+    pub content: Option<String>,
+    pub attempts: i32,
+    pub verified_at: Option<NaiveDateTime>,
+}
+
+/// What the source of a mention says about itself, as far as it could be
+/// told.
+#[derive(Debug, Default, PartialEq, AsChangeset)]
+#[diesel(table_name = webmentions)]
+#[diesel(treat_none_as_null = true)]
+pub struct MentionDetails {
+    pub author_name: Option<String>,
+    pub author_url: Option<String>,
+    pub title: Option<String>,
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, DbEnum, Deserialize, Serialize, utoipa::ToSchema)]
+#[ExistingTypePath = "MentionStatusType"]
+#[serde(rename_all = "lowercase")]
+pub enum MentionStatus {
+    Pending,
+    Verified,
+    Rejected,
+}
+
+impl Webmention {
+    /// Queues the mention of the note or page at `target` for verification.
+    /// A mention sent again is verified again, as its source may have changed.
+    pub async fn upsert(
+        source_: &str,
+        target_: &str,
+        type_id: &Uuid,
+        comm_type: CommType,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Uuid, BlogError> {
+        use crate::db::schema::webmentions;
+
+        let (note_id_, page_id_) = match comm_type {
+            CommType::Note => (Some(*type_id), None),
+            CommType::Page => (None, Some(*type_id)),
+        };
+        let now = Utc::now().naive_utc();
+
+        let id_ = diesel::insert_into(webmentions::table)
+            .values((
+                webmentions::id.eq(Uuid::new_v4()),
+                webmentions::source.eq(source_),
+                webmentions::target.eq(target_),
+                webmentions::note_id.eq(note_id_),
+                webmentions::page_id.eq(page_id_),
+            ))
+            .on_conflict((webmentions::source, webmentions::target))
+            .do_update()
+            .set((
+                webmentions::note_id.eq(note_id_),
+                webmentions::page_id.eq(page_id_),
+                webmentions::status.eq(MentionStatus::Pending),
+                webmentions::attempts.eq(0),
+                webmentions::next_attempt_at.eq(now),
+                webmentions::last_error.eq(None::<String>),
+                webmentions::updated_at.eq(now),
+            ))
+            .returning(webmentions::id)
+            .get_result::<Uuid>(conn)
+            .await?;
+
+        Ok(id_)
+    }
+
+    /// Takes up to `limit` mentions due for verification and pushes their next
+    /// attempt back by `lease_secs`, so other workers leave them alone.
+    pub async fn claim_due(
+        limit: i64,
+        lease_secs: i64,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::webmentions;
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let now = Utc::now().naive_utc();
+                let mentions = webmentions::table
+                    .filter(webmentions::status.eq(MentionStatus::Pending))
+                    .filter(webmentions::next_attempt_at.le(now))
+                    .order(webmentions::next_attempt_at.asc())
+                    .limit(limit)
+                    .for_update()
+                    .skip_locked()
+                    .select(Self::as_select())
+                    .load::<Self>(conn)
+                    .await?;
+
+                let ids: Vec<Uuid> = mentions.iter().map(|m| m.id).collect();
+                diesel::update(webmentions::table.filter(webmentions::id.eq_any(&ids)))
+                    .set(
+                        webmentions::next_attempt_at
+                            .eq(now + chrono::Duration::seconds(lease_secs)),
+                    )
+                    .execute(conn)
+                    .await?;
+
+                Ok(mentions)
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    pub async fn mark_verified(
+        id: &Uuid,
+        details: &MentionDetails,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webmentions;
+
+        let now = Utc::now().naive_utc();
+        diesel::update(webmentions::table.find(id))
+            .set((
+                details,
+                webmentions::status.eq(MentionStatus::Verified),
+                webmentions::attempts.eq(webmentions::attempts + 1),
+                webmentions::last_error.eq(None::<String>),
+                webmentions::verified_at.eq(now),
+                webmentions::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed verification. Without a `retry_at` the mention is
+    /// rejected, and no longer shown should it have been verified before.
+    pub async fn mark_failed(
+        id: &Uuid,
+        error: &str,
+        retry_at: Option<NaiveDateTime>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        use crate::db::schema::webmentions;
+
+        let now = Utc::now().naive_utc();
+        let status_ = if retry_at.is_some() {
+            MentionStatus::Pending
+        } else {
+            MentionStatus::Rejected
+        };
+
+        diesel::update(webmentions::table.find(id))
+            .set((
+                webmentions::status.eq(status_),
+                webmentions::attempts.eq(webmentions::attempts + 1),
+                webmentions::next_attempt_at.eq(retry_at.unwrap_or(now)),
+                webmentions::last_error.eq(error),
+                webmentions::updated_at.eq(now),
+            ))
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Verified mentions of a note or page, oldest first.
+    pub async fn get_verified_by_type_id(
+        type_id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Vec<Self>, BlogError> {
+        use crate::db::schema::webmentions;
+
+        let mentions = webmentions::table
+            .filter(
+                webmentions::note_id
+                    .eq(type_id)
+                    .or(webmentions::page_id.eq(type_id)),
+            )
+            .filter(webmentions::status.eq(MentionStatus::Verified))
+            .order(webmentions::verified_at.asc())
+            .select(Self::as_select())
+            .load::<Self>(conn)
+            .await?;
+
+        Ok(mentions)
+    }
+
+    /// Returns whether the mention existed.
+    pub async fn delete_by_uuid(
+        id: &Uuid,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<bool, BlogError> {
+        use crate::db::schema::webmentions;
+
+        let deleted = diesel::delete(webmentions::table.find(id))
+            .execute(conn)
+            .await?;
+
+        Ok(deleted > 0)
+    }
+}
@@ -1,6 +1,22 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "comm_status"))]
+    pub struct CommStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "mention_status"))]
+    pub struct MentionStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "message_status"))]
+    pub struct MessageStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "outbox_status"))]
+    pub struct OutboxStatus;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "page_type"))]
     pub struct PageType;
@@ -14,6 +30,71 @@ pub mod sql_types {
     pub struct SortType;
 }
 
+diesel::table! {
+    ap_actors (id) {
+        #[max_length = 2048]
+        id -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 2048]
+        key_id -> Varchar,
+        public_key -> Text,
+        #[max_length = 2048]
+        inbox -> Varchar,
+        #[max_length = 2048]
+        shared_inbox -> Nullable<Varchar>,
+        #[max_length = 256]
+        username -> Nullable<Varchar>,
+        #[max_length = 256]
+        name -> Nullable<Varchar>,
+        #[max_length = 2048]
+        url -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OutboxStatus;
+
+    ap_deliveries (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 2048]
+        inbox -> Varchar,
+        activity -> Jsonb,
+        status -> OutboxStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    ap_followers (actor_id) {
+        #[max_length = 2048]
+        actor_id -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 2048]
+        follow_id -> Varchar,
+    }
+}
+
+diesel::table! {
+    ap_replies (object_id) {
+        #[max_length = 2048]
+        object_id -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        comm_id -> Uuid,
+        #[max_length = 2048]
+        actor_id -> Varchar,
+    }
+}
+
 diesel::table! {
     comm_users (id) {
         id -> Uuid,
@@ -29,6 +110,9 @@ diesel::table! {
 }
 
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::CommStatus;
+
     comms (id) {
         id -> Uuid,
         created_at -> Timestamp,
@@ -38,6 +122,10 @@ diesel::table! {
         blog_user_id -> Nullable<Uuid>,
         note_id -> Nullable<Uuid>,
         page_id -> Nullable<Uuid>,
+        status -> CommStatus,
+        spam_score -> Nullable<Float8>,
+        spam_trained -> Nullable<Bool>,
+        approved_seq -> Nullable<Int8>,
     }
 }
 
@@ -51,6 +139,35 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MessageStatus;
+
+    contact_messages (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 64]
+        name -> Varchar,
+        #[max_length = 256]
+        email -> Varchar,
+        body -> Text,
+        status -> MessageStatus,
+        reply -> Nullable<Text>,
+        replied_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    federation_keys (id) {
+        id -> Int2,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        private_key -> Text,
+        public_key -> Text,
+    }
+}
+
 diesel::table! {
     info (id) {
         id -> Uuid,
@@ -62,6 +179,28 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    mail_unsubscribes (email) {
+        #[max_length = 256]
+        email -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    note_revisions (id) {
+        id -> Uuid,
+        note_id -> Uuid,
+        user_id -> Nullable<Uuid>,
+        created_at -> Timestamp,
+        #[max_length = 256]
+        title -> Varchar,
+        summary -> Text,
+        content -> Text,
+    }
+}
+
 diesel::table! {
     note_sorts (note_id, sort_id) {
         created_at -> Timestamp,
@@ -99,6 +238,52 @@ diesel::table! {
         short_id -> Uuid,
         #[max_length = 2048]
         fancy_img -> Nullable<Varchar>,
+        published_at -> Nullable<Timestamp>,
+        content_html -> Nullable<Text>,
+        publish_at -> Nullable<Timestamp>,
+        unpublish_at -> Nullable<Timestamp>,
+        recycled_from -> Nullable<PublishStatus>,
+    }
+}
+
+diesel::table! {
+    notify_digests (period_end) {
+        period_end -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OutboxStatus;
+
+    notify_outbox (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 64]
+        channel -> Varchar,
+        payload -> Jsonb,
+        status -> OutboxStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    notify_templates (event) {
+        #[max_length = 64]
+        event -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        subject -> Nullable<Text>,
+        html -> Nullable<Text>,
+        text -> Nullable<Text>,
+        telegram -> Nullable<Text>,
+        webhook -> Nullable<Text>,
     }
 }
 
@@ -138,6 +323,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    publish_tokens (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 256]
+        name -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        scopes -> Array<Text>,
+        user_id -> Uuid,
+        last_used_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     short_ids (id) {
         id -> Uuid,
@@ -168,6 +368,38 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    spam_corpus (id) {
+        id -> Int2,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        spam_docs -> Int8,
+        ham_docs -> Int8,
+    }
+}
+
+diesel::table! {
+    spam_tokens (token) {
+        #[max_length = 64]
+        token -> Varchar,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        spam_count -> Int8,
+        ham_count -> Int8,
+    }
+}
+
+diesel::table! {
+    subscribers (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 256]
+        email -> Varchar,
+        confirmed_at -> Nullable<Timestamp>,
+    }
+}
+
 diesel::table! {
     tags (id) {
         id -> Uuid,
@@ -194,10 +426,99 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OutboxStatus;
+
+    webhook_deliveries (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        endpoint_id -> Uuid,
+        #[max_length = 64]
+        event -> Varchar,
+        payload -> Jsonb,
+        status -> OutboxStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    webhook_endpoints (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 2048]
+        url -> Varchar,
+        #[max_length = 256]
+        secret -> Varchar,
+        events -> Array<Text>,
+        active -> Bool,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::OutboxStatus;
+
+    webmention_sends (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        note_id -> Uuid,
+        #[max_length = 2048]
+        target -> Varchar,
+        status -> OutboxStatus,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        #[max_length = 2048]
+        endpoint -> Nullable<Varchar>,
+        response_status -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MentionStatus;
+
+    webmentions (id) {
+        id -> Uuid,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+        #[max_length = 2048]
+        source -> Varchar,
+        #[max_length = 2048]
+        target -> Varchar,
+        note_id -> Nullable<Uuid>,
+        page_id -> Nullable<Uuid>,
+        status -> MentionStatus,
+        #[max_length = 256]
+        author_name -> Nullable<Varchar>,
+        #[max_length = 2048]
+        author_url -> Nullable<Varchar>,
+        #[max_length = 512]
+        title -> Nullable<Varchar>,
+        content -> Nullable<Text>,
+        attempts -> Int4,
+        next_attempt_at -> Timestamp,
+        last_error -> Nullable<Text>,
+        verified_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::joinable!(ap_followers -> ap_actors (actor_id));
+diesel::joinable!(ap_replies -> comms (comm_id));
 diesel::joinable!(comms -> comm_users (comm_user_id));
 diesel::joinable!(comms -> notes (note_id));
 diesel::joinable!(comms -> pages (page_id));
 diesel::joinable!(comms -> users (blog_user_id));
+diesel::joinable!(note_revisions -> notes (note_id));
+diesel::joinable!(note_revisions -> users (user_id));
 diesel::joinable!(note_sorts -> notes (note_id));
 diesel::joinable!(note_sorts -> sorts (sort_id));
 diesel::joinable!(note_tags -> notes (note_id));
@@ -209,20 +530,44 @@ diesel::joinable!(page_sorts -> pages (page_id));
 diesel::joinable!(page_sorts -> sorts (sort_id));
 diesel::joinable!(pages -> short_ids (short_id));
 diesel::joinable!(pages -> users (user_id));
+diesel::joinable!(publish_tokens -> users (user_id));
+diesel::joinable!(webhook_deliveries -> webhook_endpoints (endpoint_id));
+diesel::joinable!(webmention_sends -> notes (note_id));
+diesel::joinable!(webmentions -> notes (note_id));
+diesel::joinable!(webmentions -> pages (page_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    ap_actors,
+    ap_deliveries,
+    ap_followers,
+    ap_replies,
     comm_users,
     comms,
     comms_closure,
+    contact_messages,
+    federation_keys,
     info,
+    mail_unsubscribes,
+    note_revisions,
     note_sorts,
     note_tags,
     notes,
+    notify_digests,
+    notify_outbox,
+    notify_templates,
     page_about,
     page_sorts,
     pages,
+    publish_tokens,
     short_ids,
     sorts,
+    spam_corpus,
+    spam_tokens,
+    subscribers,
     tags,
     users,
+    webhook_deliveries,
+    webhook_endpoints,
+    webmention_sends,
+    webmentions,
 );
@@ -38,6 +38,18 @@ pub enum BlogError {
     #[error("letter error: {0}")]
     LettreError(#[from] lettre::error::Error),
 
+    #[error("smtp error: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+
+    #[error("mail address error: {0}")]
+    MailAddressError(#[from] lettre::address::AddressError),
+
+    #[error("template error: {0}")]
+    TemplateError(#[from] minijinja::Error),
+
+    #[error("http client error: {0}")]
+    ReqwestError(#[from] reqwest::Error),
+
     #[error("diesel connection error: {0}")]
     DiseleError(#[from] diesel::ConnectionError),
 
@@ -48,7 +60,7 @@ pub enum BlogError {
     SubscriberError(#[from] tracing::subscriber::SetGlobalDefaultError),
 
     #[error("figment error: {0}")]
-    FigmentError(#[from] figment::Error),
+    FigmentError(#[from] Box<figment::Error>),
 
     #[error("dotenvy error: {0}")]
     DotenvyErrro(#[from] dotenvy::Error),
@@ -80,11 +92,17 @@ pub enum BlogError {
     #[error("{0}")]
     Unauthorized(String),
 
+    #[error("{0}")]
+    Forbidden(String),
+
     #[error("{0}")]
     NotFound(String),
 
     #[error("{0}")]
     Conflict(String),
+
+    #[error("{0}")]
+    TooManyRequests(String),
 }
 
 impl IntoResponse for BlogError {
@@ -93,8 +111,10 @@ impl IntoResponse for BlogError {
             BlogError::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             BlogError::BadRequest(s) => (StatusCode::BAD_REQUEST, s),
             BlogError::Unauthorized(s) => (StatusCode::UNAUTHORIZED, s),
+            BlogError::Forbidden(s) => (StatusCode::FORBIDDEN, s),
             BlogError::NotFound(s) => (StatusCode::NOT_FOUND, s),
             BlogError::Conflict(s) => (StatusCode::CONFLICT, s),
+            BlogError::TooManyRequests(s) => (StatusCode::TOO_MANY_REQUESTS, s),
             BlogError::WrongCredentials => (StatusCode::UNAUTHORIZED, self.to_string()),
             BlogError::MissingCredentials => (StatusCode::BAD_REQUEST, self.to_string()),
             BlogError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
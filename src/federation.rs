@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{header::CONTENT_TYPE, HeaderMap, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    db::models::{
+        ap_actors::ApActor,
+        ap_followers::ApFollower,
+        info::Info,
+        notes::{Note, Status},
+    },
+    error::BlogError,
+    service::federation::{
+        activity::{self, id_of},
+        Federation, MAX_DOCUMENT_BYTES,
+    },
+};
+
+/// Latest notes listed in the outbox.
+const OUTBOX_LIMIT: i64 = 20;
+
+#[derive(Deserialize)]
+struct WebfingerQuery {
+    resource: String,
+}
+
+/// The blog's ActivityPub actor, to be mounted at the root: WebFinger under
+/// `/.well-known`, the rest under `/ap`.
+pub fn router(federation: Arc<Federation>) -> Router {
+    Router::new()
+        .route("/.well-known/webfinger", get(webfinger))
+        .route("/ap/actor", get(actor))
+        .route("/ap/inbox", post(inbox))
+        .route("/ap/outbox", get(outbox))
+        .route("/ap/followers", get(followers))
+        .route("/ap/notes/:id", get(object))
+        .with_state(federation)
+}
+
+/// JSON served as ActivityStreams.
+struct Activity(Value);
+
+impl IntoResponse for Activity {
+    fn into_response(self) -> Response {
+        (
+            [(CONTENT_TYPE, "application/activity+json; charset=utf-8")],
+            Json(self.0),
+        )
+            .into_response()
+    }
+}
+
+async fn webfinger(
+    State(federation): State<Arc<Federation>>,
+    Query(query): Query<WebfingerQuery>,
+) -> Result<Response, BlogError> {
+    let jrd = federation
+        .site()
+        .webfinger(query.resource.trim())
+        .ok_or(BlogError::NotFound(String::from("unknown resource")))?;
+
+    Ok(([(CONTENT_TYPE, "application/jrd+json")], Json(jrd)).into_response())
+}
+
+async fn actor(State(federation): State<Arc<Federation>>) -> Result<Activity, BlogError> {
+    let mut conn = federation.pool().get_owned().await?;
+    let site = federation.site();
+    let (name, summary) = match Info::get_info(&mut conn).await? {
+        Some(info) => (info.title, info.bio),
+        None => (site.username.clone(), String::new()),
+    };
+
+    Ok(Activity(site.actor(
+        &name,
+        &summary,
+        federation.public_key(),
+    )))
+}
+
+async fn inbox(
+    State(federation): State<Arc<Federation>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<StatusCode, BlogError> {
+    if body.len() > MAX_DOCUMENT_BYTES {
+        return Err(BlogError::BadRequest(String::from("activity too large")));
+    }
+    let activity: Value = serde_json::from_slice(&body)
+        .map_err(|_| BlogError::BadRequest(String::from("invalid activity")))?;
+
+    // deleted accounts tell every server they knew, their keys can no longer
+    // be fetched and there is nothing to forget about the ones we never met
+    if activity["type"] == "Delete" && id_of(&activity["object"]) == id_of(&activity["actor"]) {
+        let mut conn = federation.pool().get().await?;
+        let known = match id_of(&activity["actor"]) {
+            Some(actor_id) => ApActor::find_by_id(actor_id, &mut conn).await?.is_some(),
+            None => false,
+        };
+        if !known {
+            return Ok(StatusCode::ACCEPTED);
+        }
+    }
+
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let actor = federation
+        .verify(&method, path, &headers, &body)
+        .await
+        .inspect_err(|e| warn!("rejected inbox post: {}", e))?;
+    federation.receive(&actor, &activity).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn outbox(State(federation): State<Arc<Federation>>) -> Result<Activity, BlogError> {
+    let mut conn = federation.pool().get().await?;
+    let site = federation.site();
+
+    let total = Note::count_by_status(&Status::Public, &mut conn).await?;
+    let items: Vec<Value> = Note::get_notes_by_status(&Status::Public, OUTBOX_LIMIT, &mut conn)
+        .await?
+        .into_iter()
+        .map(|(note, short_name)| site.wrap("Create", site.object(&note, &short_name)))
+        .collect();
+
+    Ok(Activity(json!({
+        "@context": activity::CONTEXT,
+        "id": site.outbox(),
+        "type": "OrderedCollection",
+        "totalItems": total,
+        "orderedItems": items,
+    })))
+}
+
+/// Only how many there are, followers are nobody else's business.
+async fn followers(State(federation): State<Arc<Federation>>) -> Result<Activity, BlogError> {
+    let mut conn = federation.pool().get().await?;
+    let total = ApFollower::count(&mut conn).await?;
+
+    Ok(Activity(json!({
+        "@context": activity::CONTEXT,
+        "id": federation.site().followers(),
+        "type": "OrderedCollection",
+        "totalItems": total,
+    })))
+}
+
+async fn object(
+    State(federation): State<Arc<Federation>>,
+    Path(id): Path<Uuid>,
+) -> Result<Activity, BlogError> {
+    let mut conn = federation.pool().get().await?;
+    let (note, short_name) = Note::find_note_with_short_name(&id, &mut conn)
+        .await?
+        .filter(|(note, _)| note.status == Status::Public)
+        .ok_or(BlogError::NotFound(String::from("not found note")))?;
+
+    let mut object = federation.site().object(&note, &short_name);
+    object["@context"] = json!(activity::CONTEXT);
+    Ok(Activity(object))
+}
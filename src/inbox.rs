@@ -0,0 +1,48 @@
+pub mod contact;
+pub mod mail;
+pub mod telegram;
+
+use std::sync::Arc;
+
+use axum::Router;
+use tokio::{net::TcpListener, task::JoinHandle};
+use tracing::info;
+
+use crate::{
+    config::{TelegramMode, CONFIG},
+    error::BlogError,
+    AppState,
+};
+
+use mail::MailInbox;
+use telegram::Bot;
+
+/// Sets up whatever takes messages from outside the blog: the routes to mount
+/// under `/inbox`, the contact form among them, and background tasks which
+/// stop on `SHUTDOWN`, such as the mail listener.
+pub async fn start(state: &AppState) -> Result<(Router, Vec<JoinHandle<()>>), BlogError> {
+    let mut router = contact::router(state.clone());
+    let mut tasks = Vec::new();
+
+    match CONFIG.inbox.telegram_mode() {
+        TelegramMode::Off => {}
+        TelegramMode::Webhook => {
+            let bot = Arc::new(Bot::from_config(state)?);
+            bot.set_webhook().await?;
+            router = router.merge(telegram::router(bot));
+        }
+        TelegramMode::Polling => {
+            let bot = Arc::new(Bot::from_config(state)?);
+            tasks.push(tokio::spawn(bot.poll()));
+        }
+    }
+
+    if let Some(listen) = &CONFIG.inbox.mail_listen {
+        let inbox = Arc::new(MailInbox::from_config(state)?);
+        let listener = TcpListener::bind(listen).await?;
+        info!("taking mail on {}", listener.local_addr()?);
+        tasks.push(tokio::spawn(inbox.listen(listener)));
+    }
+
+    Ok((router, tasks))
+}
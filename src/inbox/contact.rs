@@ -0,0 +1,403 @@
+use axum::{
+    extract::{Path, Query, State},
+    routing::{get, post},
+    Json, Router,
+};
+use chrono::NaiveDateTime;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    db::models::{
+        contact_messages::{ContactMessage, MessageStatus, NewContactMessage},
+        users::User,
+    },
+    error::BlogError,
+    service::notify::{
+        template::{excerpt, EXCERPT_CHARS},
+        Notification, Recipient,
+    },
+    utils::{jwt::Claims, ClientIp},
+    AppState,
+};
+
+const MAX_NAME_CHARS: usize = 64;
+const MAX_BODY_CHARS: usize = 5000;
+
+#[derive(Deserialize, ToSchema)]
+pub struct SendMessage {
+    #[schema(example = "nekonya")]
+    name: String,
+    #[schema(example = "neko@example.com")]
+    email: String,
+    body: String,
+    /// Honeypot, hidden from humans and expected to stay empty
+    #[serde(default)]
+    homepage: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct MessagesQuery {
+    /// State to list, `unread` when omitted
+    status: Option<MessageStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReturnMessage {
+    id: Uuid,
+    created_at: NaiveDateTime,
+    name: String,
+    email: String,
+    body: String,
+    status: MessageStatus,
+    /// Latest reply sent
+    reply: Option<String>,
+    replied_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ListMessages {
+    pub messages: Vec<ReturnMessage>,
+    pub total: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateMessage {
+    status: MessageStatus,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ReplyMessage {
+    body: String,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        send_message,
+        list_messages,
+        get_message,
+        update_message,
+        reply_message
+    ),
+    components(schemas(
+        SendMessage,
+        ReturnMessage,
+        ListMessages,
+        UpdateMessage,
+        ReplyMessage,
+        MessageStatus
+    ))
+)]
+pub struct ContactDoc;
+
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/contact", post(send_message))
+        .route("/messages/:page", get(list_messages))
+        .route("/message/:id", get(get_message).put(update_message))
+        .route("/message/:id/reply", post(reply_message))
+        .with_state(state)
+}
+
+impl From<ContactMessage> for ReturnMessage {
+    fn from(message: ContactMessage) -> Self {
+        Self {
+            id: message.id,
+            created_at: message.created_at,
+            name: message.name,
+            email: message.email,
+            body: message.body,
+            status: message.status,
+            reply: message.reply,
+            replied_at: message.replied_at,
+        }
+    }
+}
+
+/// What is wrong with a message from the contact form, if anything.
+fn check_message(name: &str, email: &str, body: &str) -> Option<&'static str> {
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Some("invalid name");
+    }
+    if email.parse::<lettre::Address>().is_err() {
+        return Some("invalid email");
+    }
+    if body.is_empty() || body.chars().count() > MAX_BODY_CHARS {
+        return Some("invalid message");
+    }
+
+    None
+}
+
+/// Leaves a private message for the blog owner, who is notified of it.
+#[utoipa::path(
+    post,
+    path = "/contact",
+    request_body = SendMessage,
+    responses(
+        (status = 200, description = "Message sent"),
+        (status = 400, description = "Bad Request"),
+        (status = 429, description = "Too many requests"),
+        (status = 500, description = "Internal server error")
+    )
+)]
+pub async fn send_message(
+    state: State<AppState>,
+    ClientIp(ip): ClientIp,
+    Json(message): Json<SendMessage>,
+) -> Result<String, BlogError> {
+    let name = message.name.trim();
+    let email = message.email.trim();
+    let body = message.body.trim();
+    if let Some(problem) = check_message(name, email, body) {
+        return Err(BlogError::BadRequest(String::from(problem)));
+    }
+    state.guard.throttle(&ip, email)?;
+
+    // bots get the same answer, but nothing is kept
+    if message.homepage.is_some_and(|h| !h.is_empty()) {
+        return Ok(json!({ "ok": "send message ok!" }).to_string());
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    let notify = state.notify.clone();
+    let (name, email, body) = (name.to_string(), email.to_string(), body.to_string());
+
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            ContactMessage::create(
+                &NewContactMessage {
+                    id: Uuid::new_v4(),
+                    name: &name,
+                    email: &email,
+                    body: &body,
+                },
+                conn,
+            )
+            .await?;
+
+            let mail_to = match User::get_emails(conn).await?.into_iter().next() {
+                Some(owner) => Recipient::subscribed(owner, conn).await?,
+                None => None,
+            };
+            let notification = Notification {
+                event: String::from("contact.received"),
+                vars: json!({
+                    "name": name,
+                    "email": email,
+                    "message": body,
+                    "excerpt": excerpt(&body, EXCERPT_CHARS),
+                }),
+                mail_to,
+                comm_id: None,
+            };
+            notify.enqueue(&notification, conn).await
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(json!({ "ok": "send message ok!" }).to_string())
+}
+
+#[utoipa::path(
+    get,
+    path = "/messages/{page}",
+    params(
+        ("page" = u64, Path, description = "List messages by page"),
+        MessagesQuery
+    ),
+    responses(
+        (status = 200, description = "Messages retrieved successfully", body = ListMessages),
+        (status = 400, description = "Bad Request"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn list_messages(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(page): Path<u64>,
+    Query(query): Query<MessagesQuery>,
+) -> Result<Json<ListMessages>, BlogError> {
+    if page == 0 {
+        return Err(BlogError::BadRequest(String::from("not 0!")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+
+    let limit = 20;
+    let offset = (page - 1) * limit;
+    let status = query.status.unwrap_or(MessageStatus::Unread);
+    let (messages, total) =
+        ContactMessage::get_by_status(&status, limit as i64, offset as i64, &mut conn).await?;
+
+    Ok(Json(ListMessages {
+        messages: messages.into_iter().map(ReturnMessage::from).collect(),
+        total,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/message/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Message ID")
+    ),
+    responses(
+        (status = 200, description = "Message retrieved successfully", body = ReturnMessage),
+        (status = 404, description = "Message not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn get_message(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ReturnMessage>, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    let message = ContactMessage::find_by_uuid(&id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("message not found")))?;
+
+    Ok(Json(message.into()))
+}
+
+/// Marks a message as read or unread, or archives it.
+#[utoipa::path(
+    put,
+    path = "/message/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Message ID")
+    ),
+    request_body = UpdateMessage,
+    responses(
+        (status = 200, description = "Message updated"),
+        (status = 404, description = "Message not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn update_message(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+    Json(update): Json<UpdateMessage>,
+) -> Result<String, BlogError> {
+    let mut conn = state.pool.get_owned().await?;
+
+    if !ContactMessage::update_status(&id, &update.status, &mut conn).await? {
+        return Err(BlogError::NotFound(String::from("message not found")));
+    }
+
+    Ok(json!({ "ok": "update message ok!" }).to_string())
+}
+
+/// Mails a reply to the sender of the message.
+#[utoipa::path(
+    post,
+    path = "/message/{id}/reply",
+    params(
+        ("id" = Uuid, Path, description = "Message ID")
+    ),
+    request_body = ReplyMessage,
+    responses(
+        (status = 200, description = "Reply on its way"),
+        (status = 400, description = "Bad Request"),
+        (status = 404, description = "Message not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("jwt_token" = [])
+    )
+)]
+pub async fn reply_message(
+    state: State<AppState>,
+    _claims: Claims,
+    Path(id): Path<Uuid>,
+    Json(reply): Json<ReplyMessage>,
+) -> Result<String, BlogError> {
+    if !state.notify.can_mail() {
+        return Err(BlogError::BadRequest(String::from(
+            "replies need smtp to be set up",
+        )));
+    }
+    let body = reply.body.trim().to_string();
+    if body.is_empty() {
+        return Err(BlogError::BadRequest(String::from("empty reply")));
+    }
+
+    let mut conn = state.pool.get_owned().await?;
+    let message = ContactMessage::find_by_uuid(&id, &mut conn)
+        .await?
+        .ok_or(BlogError::NotFound(String::from("message not found")))?;
+    let recipient = Recipient::subscribed(message.email.clone(), &mut conn)
+        .await?
+        .ok_or(BlogError::BadRequest(String::from(
+            "the sender has unsubscribed from mails",
+        )))?;
+
+    let notify = state.notify.clone();
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            ContactMessage::set_reply(&message.id, &body, conn).await?;
+
+            let vars = json!({
+                "name": message.name,
+                "email": message.email,
+                "message": message.body,
+                "excerpt": excerpt(&message.body, EXCERPT_CHARS),
+                "reply": body,
+            });
+            notify
+                .enqueue_mail("contact.reply", &vars, recipient, conn)
+                .await
+        }
+        .scope_boxed()
+    })
+    .await?;
+
+    Ok(json!({ "ok": "reply message ok!" }).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_message() {
+        assert_eq!(check_message("neko", "neko@example.com", "nya"), None);
+        assert_eq!(
+            check_message("", "neko@example.com", "nya"),
+            Some("invalid name")
+        );
+        assert_eq!(
+            check_message(&"n".repeat(65), "neko@example.com", "nya"),
+            Some("invalid name")
+        );
+        assert_eq!(
+            check_message("neko", "not an email", "nya"),
+            Some("invalid email")
+        );
+        assert_eq!(
+            check_message("neko", "neko@example.com", ""),
+            Some("invalid message")
+        );
+        assert_eq!(
+            check_message("ねこ", "neko@example.com", &"に".repeat(5000)),
+            None
+        );
+    }
+}
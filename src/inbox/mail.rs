@@ -0,0 +1,630 @@
+use std::{io, sync::Arc, time::Duration};
+
+use mail_parser::{MessageParser, MimeHeaders};
+use serde_json::json;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+
+use crate::{
+    config::{MailProtocol, CONFIG},
+    db::{
+        models::{
+            notes::{Note, Status},
+            users::User,
+        },
+        Conn, DbPool,
+    },
+    error::BlogError,
+    service::{
+        media,
+        notify::{Notify, Recipient},
+    },
+    utils::{summary_of, SHUTDOWN},
+    AppState,
+};
+
+/// How long a client may keep us waiting for its next line.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Bytes of a line read at once, longer ones are read in pieces.
+const MAX_LINE: u64 = 8192;
+
+/// What a command asks the connection to do next.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Reply(String),
+    /// Take the message, after telling the client to go ahead
+    Data,
+    Quit,
+}
+
+/// The envelope of the mail being sent, as far as the client got.
+#[derive(Debug, Default)]
+struct Session {
+    greeted: bool,
+    from: Option<String>,
+    /// Accepted recipients, each answered on its own after DATA over LMTP
+    recipients: usize,
+}
+
+impl Session {
+    fn reset(&mut self) {
+        self.from = None;
+        self.recipients = 0;
+    }
+
+    fn command(&mut self, line: &str, inbox: &MailInbox) -> Step {
+        let (verb, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let reply = |text: &str| Step::Reply(text.to_string());
+
+        match (verb.to_ascii_uppercase().as_str(), inbox.protocol) {
+            ("HELO", MailProtocol::Smtp) => {
+                self.greeted = true;
+                self.reset();
+                Step::Reply(format!("250 {}", inbox.domain()))
+            }
+            ("EHLO", MailProtocol::Smtp) | ("LHLO", MailProtocol::Lmtp) => {
+                self.greeted = true;
+                self.reset();
+                Step::Reply(format!(
+                    "250-{}\r\n250-8BITMIME\r\n250-PIPELINING\r\n250 SIZE {}",
+                    inbox.domain(),
+                    inbox.max_size
+                ))
+            }
+            ("HELO" | "EHLO", MailProtocol::Lmtp) => reply("500 5.5.1 use LHLO"),
+            ("LHLO", MailProtocol::Smtp) => reply("500 5.5.1 use EHLO"),
+            ("MAIL", _) => {
+                if !self.greeted {
+                    return reply("503 5.5.1 say hello first");
+                }
+                if self.from.is_some() {
+                    return reply("503 5.5.1 sender already given");
+                }
+                let Some((from, params)) = path(arg, "FROM:") else {
+                    return reply("501 5.5.4 syntax: MAIL FROM:<address>");
+                };
+                let size = params
+                    .split_whitespace()
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("SIZE"))
+                    .and_then(|(_, size)| size.parse::<usize>().ok());
+                if size.is_some_and(|size| size > inbox.max_size) {
+                    return reply("552 5.3.4 message too big");
+                }
+
+                self.from = Some(from.to_string());
+                reply("250 2.1.0 ok")
+            }
+            ("RCPT", _) => {
+                if self.from.is_none() {
+                    return reply("503 5.5.1 sender first");
+                }
+                let Some((to, _)) = path(arg, "TO:") else {
+                    return reply("501 5.5.4 syntax: RCPT TO:<address>");
+                };
+                if !to.eq_ignore_ascii_case(&inbox.address) {
+                    return reply("550 5.1.1 no such mailbox");
+                }
+
+                self.recipients += 1;
+                reply("250 2.1.5 ok")
+            }
+            ("DATA", _) => {
+                if self.from.is_none() || self.recipients == 0 {
+                    return reply("503 5.5.1 recipient first");
+                }
+                Step::Data
+            }
+            ("RSET", _) => {
+                self.reset();
+                reply("250 2.0.0 ok")
+            }
+            ("NOOP", _) => reply("250 2.0.0 ok"),
+            ("VRFY", _) => reply("252 2.1.5 cannot verify"),
+            ("QUIT", _) => Step::Quit,
+            _ => reply("502 5.5.2 command not implemented"),
+        }
+    }
+}
+
+/// The address in `FROM:<a@b> SIZE=1` and the parameters after it.
+fn path<'a>(arg: &'a str, keyword: &str) -> Option<(&'a str, &'a str)> {
+    let arg = arg.trim_start();
+    if !arg
+        .get(..keyword.len())
+        .is_some_and(|k| k.eq_ignore_ascii_case(keyword))
+    {
+        return None;
+    }
+
+    let rest = arg[keyword.len()..].trim_start().strip_prefix('<')?;
+    let (address, params) = rest.split_once('>')?;
+    Some((address.trim(), params.trim()))
+}
+
+/// Reads a message up to the lone `.` line, undoing dot-stuffing. `None`
+/// when it is bigger than `max_size`, the rest is read and dropped then.
+async fn read_data<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    max_size: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+    let mut chunk = Vec::new();
+    let mut line_start = true;
+    let mut too_big = false;
+
+    loop {
+        chunk.clear();
+        let read = tokio::time::timeout(
+            COMMAND_TIMEOUT,
+            (&mut *reader).take(MAX_LINE).read_until(b'\n', &mut chunk),
+        )
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+
+        let mut piece = &chunk[..];
+        if line_start {
+            if piece == b".\r\n" || piece == b".\n" {
+                break;
+            }
+            piece = piece.strip_prefix(b".").unwrap_or(piece);
+        }
+        line_start = chunk.ends_with(b"\n");
+
+        if data.len() + piece.len() > max_size {
+            too_big = true;
+            data.clear();
+        }
+        if !too_big {
+            data.extend_from_slice(piece);
+        }
+    }
+
+    Ok((!too_big).then_some(data))
+}
+
+async fn write_reply<W: AsyncWrite + Unpin>(write: &mut W, reply: &str) -> io::Result<()> {
+    write.write_all(reply.as_bytes()).await?;
+    write.write_all(b"\r\n").await
+}
+
+#[derive(Debug, PartialEq)]
+struct Attachment {
+    name: Option<String>,
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+/// A mail as the note it turns into.
+#[derive(Debug, PartialEq)]
+struct Draft {
+    /// Address in the `From` header
+    from: String,
+    title: String,
+    content: String,
+    attachments: Vec<Attachment>,
+}
+
+/// Reads a mail into a draft: the subject is the title and the plain text
+/// body, without its signature, the content. HTML only mails are turned
+/// into text.
+fn parse_mail(raw: &[u8]) -> Result<Draft, &'static str> {
+    let message = MessageParser::default().parse(raw).ok_or("not a mail")?;
+
+    let from = message
+        .from()
+        .and_then(|from| from.first())
+        .and_then(|from| from.address())
+        .ok_or("no From address")?;
+    let title = message.subject().unwrap_or_default().trim();
+    if title.is_empty() {
+        return Err("the subject is the title and may not be empty");
+    }
+    let content = message
+        .body_text(0)
+        .map(|body| strip_signature(&body).trim().to_string())
+        .unwrap_or_default();
+
+    let attachments = message
+        .attachments()
+        .map(|part| Attachment {
+            name: part.attachment_name().map(str::to_string),
+            content_type: part.content_type().map(|ct| match ct.subtype() {
+                Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                None => ct.ctype().to_string(),
+            }),
+            data: part.contents().to_vec(),
+        })
+        .collect::<Vec<_>>();
+    if content.is_empty() && attachments.is_empty() {
+        return Err("empty mail");
+    }
+
+    Ok(Draft {
+        from: from.to_string(),
+        title: title.to_string(),
+        content,
+        attachments,
+    })
+}
+
+/// `body` without what follows the `-- ` signature separator.
+fn strip_signature(body: &str) -> &str {
+    let body_start = body.starts_with("-- \n") || body.starts_with("-- \r\n");
+    if body_start {
+        return "";
+    }
+
+    match body.find("\n-- \n").or_else(|| body.find("\n-- \r\n")) {
+        Some(at) => &body[..at],
+        None => body,
+    }
+}
+
+/// Whether both the envelope sender and the `From` header are in `allowed`.
+/// Either one is easily forged, so this only narrows down who can post as
+/// long as the inbox address itself stays secret.
+fn senders_allowed(allowed: &[String], envelope_from: &str, from: &str) -> bool {
+    let is_allowed = |sender: &str| {
+        allowed
+            .iter()
+            .any(|allowed| allowed.trim().eq_ignore_ascii_case(sender))
+    };
+
+    is_allowed(envelope_from) && is_allowed(from)
+}
+
+/// Takes mail sent to a secret address over SMTP or LMTP and keeps it as a
+/// draft, for writing notes from a mail client. Only mails whose envelope
+/// sender and `From` are both allowed are kept, and they hear back once it
+/// is done.
+pub struct MailInbox {
+    protocol: MailProtocol,
+    address: String,
+    allowed_senders: Option<Vec<String>>,
+    max_size: usize,
+    pool: DbPool,
+    notify: Arc<Notify>,
+}
+
+impl MailInbox {
+    pub fn from_config(state: &AppState) -> Result<Self, BlogError> {
+        let address = CONFIG
+            .inbox
+            .mail_address
+            .clone()
+            .ok_or(anyhow::anyhow!(
+                "inbox.mailaddress is required for the mail listener"
+            ))?;
+
+        let allowed_senders = CONFIG.inbox.mail_allowed_senders.clone();
+        if allowed_senders.as_ref().is_some_and(Vec::is_empty) {
+            warn!("mail listener has no allowed senders and will refuse everyone");
+        }
+
+        Ok(Self {
+            protocol: CONFIG.inbox.mail_protocol(),
+            address,
+            allowed_senders,
+            max_size: CONFIG.inbox.mail_max_size(),
+            pool: state.pool.clone(),
+            notify: state.notify.clone(),
+        })
+    }
+
+    /// What the server calls itself, the domain of the secret address.
+    fn domain(&self) -> &str {
+        self.address
+            .rsplit_once('@')
+            .map(|(_, domain)| domain)
+            .unwrap_or(&self.address)
+    }
+
+    /// Accepts connections until `SHUTDOWN` fires. Sessions still running
+    /// then are left to finish on their own.
+    pub async fn listen(self: Arc<Self>, listener: TcpListener) {
+        let shutdown = SHUTDOWN.wait_for_shutdown();
+        tokio::pin!(shutdown);
+
+        loop {
+            let accepted = tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => accepted,
+            };
+
+            match accepted {
+                Ok((stream, peer)) => {
+                    let inbox = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = inbox.serve(stream).await {
+                            warn!("mail session with {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => warn!("mail listener accept failed: {}", e),
+            }
+        }
+
+        info!("mail listener stopped");
+    }
+
+    async fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let mut session = Session::default();
+
+        let protocol = match self.protocol {
+            MailProtocol::Smtp => "ESMTP",
+            MailProtocol::Lmtp => "LMTP",
+        };
+        write_reply(&mut write, &format!("220 {} {} ready", self.domain(), protocol)).await?;
+
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = tokio::time::timeout(
+                COMMAND_TIMEOUT,
+                (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line),
+            )
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+            if read == 0 {
+                return Ok(());
+            }
+
+            let command = String::from_utf8_lossy(&line);
+            match session.command(command.trim_end(), self) {
+                Step::Reply(reply) => write_reply(&mut write, &reply).await?,
+                Step::Quit => {
+                    write_reply(&mut write, "221 2.0.0 bye").await?;
+                    return Ok(());
+                }
+                Step::Data => {
+                    write_reply(&mut write, "354 end data with <CR><LF>.<CR><LF>").await?;
+                    let reply = match read_data(&mut reader, self.max_size).await? {
+                        Some(raw) => {
+                            let envelope_from = session.from.as_deref().unwrap_or_default();
+                            self.receive(envelope_from, &raw).await.unwrap_or_else(|e| {
+                                warn!("mail not kept: {}", e);
+                                String::from("451 4.3.0 try again later")
+                            })
+                        }
+                        None => String::from("552 5.3.4 message too big"),
+                    };
+
+                    // LMTP answers for every recipient, SMTP for the mail
+                    let replies = match self.protocol {
+                        MailProtocol::Smtp => 1,
+                        MailProtocol::Lmtp => session.recipients,
+                    };
+                    for _ in 0..replies {
+                        write_reply(&mut write, &reply).await?;
+                    }
+                    session.reset();
+                }
+            }
+        }
+    }
+
+    async fn allowed(
+        &self,
+        envelope_from: &str,
+        from: &str,
+        conn: &mut Conn,
+    ) -> Result<bool, BlogError> {
+        let allowed = match &self.allowed_senders {
+            Some(senders) => senders.clone(),
+            None => User::get_emails(conn).await?,
+        };
+
+        Ok(senders_allowed(&allowed, envelope_from, from))
+    }
+
+    /// Keeps a mail as a draft and returns the reply for it. Mails that are
+    /// refused get a permanent failure, so the sender's server bounces them.
+    async fn receive(&self, envelope_from: &str, raw: &[u8]) -> Result<String, BlogError> {
+        let draft = match parse_mail(raw) {
+            Ok(draft) => draft,
+            Err(problem) => return Ok(format!("550 5.6.0 {}", problem)),
+        };
+
+        let mut conn = self.pool.get_owned().await?;
+        if !self.allowed(envelope_from, &draft.from, &mut conn).await? {
+            warn!(
+                "mail from {} (envelope {:?}) refused, not an allowed sender",
+                draft.from, envelope_from
+            );
+            return Ok(String::from("550 5.7.1 sender not allowed"));
+        }
+
+        // the sender's own account when there is one, the owner's otherwise
+        let author = match User::find_user_by_email(&draft.from, &mut conn).await? {
+            Some(user) => Some(user),
+            None => match User::get_emails(&mut conn).await?.first() {
+                Some(owner) => User::find_user_by_email(owner, &mut conn).await?,
+                None => None,
+            },
+        }
+        .ok_or(anyhow::anyhow!("there is no user to write the draft as"))?;
+
+        let mut content = draft.content;
+        let mut kept = Vec::new();
+        let mut skipped = Vec::new();
+        for attachment in &draft.attachments {
+            let name = attachment.name.as_deref();
+            let url = match media::store(
+                &attachment.data,
+                attachment.content_type.as_deref(),
+                name,
+            )
+            .await
+            {
+                Ok(url) => url,
+                Err(BlogError::BadRequest(problem)) => {
+                    warn!("attachment {:?} left out: {}", name, problem);
+                    skipped.push(name.unwrap_or("unnamed").to_string());
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let image = attachment
+                .content_type
+                .as_deref()
+                .is_some_and(|ct| ct.starts_with("image/"));
+            let link = format!(
+                "{}[{}]({})",
+                if image { "!" } else { "" },
+                name.unwrap_or_default(),
+                url
+            );
+            if !content.is_empty() {
+                content.push_str("\n\n");
+            }
+            content.push_str(&link);
+            kept.push(url);
+        }
+
+        let summary = summary_of(None, &content, None);
+        let note_id = Note::create_note(
+            None,
+            &Status::Draft,
+            &draft.title,
+            &summary,
+            &content,
+            true,
+            &author.id,
+            None,
+            self.pool.clone(),
+        )
+        .await?;
+        info!("mail from {} kept as draft {}", draft.from, note_id);
+
+        if let Some(recipient) = Recipient::subscribed(draft.from, &mut conn).await? {
+            let short_name = Note::find_note_with_short_name(&note_id, &mut conn)
+                .await?
+                .map(|(_, short_name)| short_name)
+                .unwrap_or_default();
+            let vars = json!({
+                "note_title": draft.title,
+                "summary": summary,
+                "short_name": short_name,
+                "link": format!("{}/{}", CONFIG.site_url(), short_name),
+                "attachments": kept,
+                "skipped": skipped,
+            });
+            self.notify
+                .enqueue_mail("inbox.draft", &vars, recipient, &mut conn)
+                .await?;
+        }
+
+        Ok(String::from("250 2.0.0 draft saved"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIL: &str = "From: Neko <neko@example.com>\r
+To: drafts-s3cret@example.com\r
+Subject: Hello, world!\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"b\"\r
+\r
+--b\r
+Content-Type: text/plain; charset=utf-8\r
+\r
+The first note, sent by mail.\r
+\r
+-- \r
+Neko\r
+--b\r
+Content-Type: image/png\r
+Content-Disposition: attachment; filename=\"cat.png\"\r
+Content-Transfer-Encoding: base64\r
+\r
+iVBORw0KGgo=\r
+--b--\r
+";
+
+    #[test]
+    fn test_path() {
+        assert_eq!(
+            path("FROM:<neko@example.com> SIZE=1024", "FROM:"),
+            Some(("neko@example.com", "SIZE=1024"))
+        );
+        assert_eq!(path("to: <a@b>", "TO:"), Some(("a@b", "")));
+        assert_eq!(path("FROM:<>", "FROM:"), Some(("", "")));
+        assert_eq!(path("FROM:neko@example.com", "FROM:"), None);
+        assert_eq!(path("TO:<a@b>", "FROM:"), None);
+    }
+
+    #[test]
+    fn test_parse_mail() {
+        let draft = parse_mail(MAIL.as_bytes()).unwrap();
+        assert_eq!(draft.from, "neko@example.com");
+        assert_eq!(draft.title, "Hello, world!");
+        assert_eq!(draft.content, "The first note, sent by mail.");
+        assert_eq!(
+            draft.attachments,
+            vec![Attachment {
+                name: Some(String::from("cat.png")),
+                content_type: Some(String::from("image/png")),
+                data: b"\x89PNG\r\n\x1a\n".to_vec(),
+            }]
+        );
+
+        let untitled = MAIL.replace("Subject: Hello, world!\r\n", "");
+        assert_eq!(
+            parse_mail(untitled.as_bytes()),
+            Err("the subject is the title and may not be empty")
+        );
+        let anonymous = MAIL.replace("From: Neko <neko@example.com>\r\n", "");
+        assert_eq!(parse_mail(anonymous.as_bytes()), Err("no From address"));
+    }
+
+    #[test]
+    fn test_senders_allowed() {
+        let senders = [
+            String::from("Neko@example.com "),
+            String::from("inu@example.com"),
+        ];
+        let allowed = |envelope_from, from| senders_allowed(&senders, envelope_from, from);
+
+        assert!(allowed("neko@example.com", "NEKO@example.com"));
+        assert!(allowed("inu@example.com", "neko@example.com"));
+        assert!(!allowed("spoofer@example.net", "neko@example.com"));
+        assert!(!allowed("neko@example.com", "spoofer@example.net"));
+        assert!(!allowed("", "neko@example.com"));
+        assert!(!senders_allowed(&[], "inu@example.com", "inu@example.com"));
+    }
+
+    #[test]
+    fn test_strip_signature() {
+        assert_eq!(strip_signature("nya\n-- \nneko"), "nya");
+        assert_eq!(strip_signature("nya\n--\nneko"), "nya\n--\nneko");
+        assert_eq!(strip_signature("-- \nneko"), "");
+    }
+
+    #[tokio::test]
+    async fn test_read_data() {
+        let mut input: &[u8] = b"Subject: a\r\n\r\n..dots\r\n.\r\nQUIT\r\n";
+        let data = read_data(&mut input, 1024).await.unwrap();
+        assert_eq!(data.as_deref(), Some(&b"Subject: a\r\n\r\n.dots\r\n"[..]));
+        assert_eq!(input, &b"QUIT\r\n"[..]);
+
+        let mut input: &[u8] = b"Subject: a\r\n\r\nmuch too long\r\n.\r\n";
+        assert_eq!(read_data(&mut input, 16).await.unwrap(), None);
+        assert!(input.is_empty());
+
+        let mut input: &[u8] = b"Subject: a\r\n";
+        assert!(read_data(&mut input, 1024).await.is_err());
+    }
+}
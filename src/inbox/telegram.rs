@@ -0,0 +1,577 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::{TelegramMode, CONFIG},
+    db::{
+        models::{
+            comms::{Comm, CommAuthor, CommStatus, CommType},
+            notes::{Note, Status},
+        },
+        DbPool,
+    },
+    error::BlogError,
+    service::{
+        moderation::{self, ModerateAction},
+        notify::{comm_keyboard, template::escape_markdown_v2, BotApi, Notify},
+        publish,
+    },
+    utils::SHUTDOWN,
+    AppState,
+};
+
+/// Seconds a `getUpdates` call waits for something to happen.
+const POLL_TIMEOUT_SECS: u64 = 30;
+/// Wait after a failed `getUpdates` call.
+const POLL_RETRY: Duration = Duration::from_secs(5);
+/// Drafts listed by `/drafts`.
+const DRAFTS_LIMIT: i64 = 20;
+const ALLOWED_UPDATES: [&str; 2] = ["message", "callback_query"];
+const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+const HELP: &str = "Tap the buttons on a comment notification to approve or delete it, \
+or reply to the notification to answer the comment.
+
+/drafts - list drafts
+/publish <short name> - publish a draft";
+
+#[derive(Debug, Deserialize)]
+pub struct Update {
+    update_id: i64,
+    message: Option<TgMessage>,
+    callback_query: Option<CallbackQuery>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TgMessage {
+    message_id: i64,
+    chat: Chat,
+    text: Option<String>,
+    reply_to_message: Option<Box<TgMessage>>,
+    reply_markup: Option<Keyboard>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackQuery {
+    id: String,
+    message: Option<TgMessage>,
+    data: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Keyboard {
+    inline_keyboard: Vec<Vec<Button>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Button {
+    callback_data: Option<String>,
+}
+
+/// What a button on a notification asks for, see [`comm_keyboard`].
+#[derive(Debug, PartialEq)]
+enum Action {
+    Approve(Uuid),
+    Delete(Uuid),
+}
+
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Publish(&'a str),
+    Drafts,
+    Help,
+}
+
+fn parse_action(data: &str) -> Option<Action> {
+    let (action, id) = data.split_once(':')?;
+    let id = Uuid::parse_str(id).ok()?;
+
+    match action {
+        "approve" => Some(Action::Approve(id)),
+        "delete" => Some(Action::Delete(id)),
+        _ => None,
+    }
+}
+
+/// Commands may carry the bot's name, as in `/publish@blogbot`.
+fn parse_command(text: &str) -> Option<Command<'_>> {
+    let text = text.trim();
+    if !text.starts_with('/') {
+        return None;
+    }
+
+    let (command, arg) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let command = command.split('@').next().unwrap_or(command);
+    match command {
+        "/publish" => Some(Command::Publish(arg.trim())),
+        "/drafts" => Some(Command::Drafts),
+        _ => Some(Command::Help),
+    }
+}
+
+/// The comm a message replies to, known from the buttons of the notification.
+fn replied_comm(message: &TgMessage) -> Option<Uuid> {
+    message
+        .reply_to_message
+        .as_ref()?
+        .reply_markup
+        .as_ref()?
+        .inline_keyboard
+        .iter()
+        .flatten()
+        .filter_map(|button| button.callback_data.as_deref().and_then(parse_action))
+        .map(|action| match action {
+            Action::Approve(id) | Action::Delete(id) => id,
+        })
+        .next()
+}
+
+/// Lets the blog owner moderate and answer comments and publish drafts from
+/// Telegram. Only chats on the allowlist are listened to.
+pub struct Bot {
+    api: BotApi,
+    allowed_chats: Vec<i64>,
+    secret: Option<String>,
+    pool: DbPool,
+    notify: Arc<Notify>,
+}
+
+impl Bot {
+    pub fn new(
+        api: BotApi,
+        allowed_chats: Vec<i64>,
+        secret: Option<String>,
+        pool: DbPool,
+        notify: Arc<Notify>,
+    ) -> Self {
+        Self {
+            api,
+            allowed_chats,
+            secret,
+            pool,
+            notify,
+        }
+    }
+
+    pub fn from_config(state: &AppState) -> Result<Self, BlogError> {
+        // long enough for a getUpdates call to wait out its timeout
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+            .build()?;
+        let api = BotApi::from_config(&CONFIG.notify, client)?;
+
+        let allowed_chats = CONFIG.inbox.telegram_allowed_chats(&CONFIG.notify);
+        if allowed_chats.is_empty() {
+            warn!("telegram bot has no allowed chats and will ignore everyone");
+        }
+
+        let secret = CONFIG.inbox.telegram_secret.clone();
+        if CONFIG.inbox.telegram_mode() == TelegramMode::Webhook && secret.is_none() {
+            return Err(anyhow::anyhow!(
+                "inbox.telegramsecret is required for the telegram webhook"
+            )
+            .into());
+        }
+
+        Ok(Self::new(
+            api,
+            allowed_chats,
+            secret,
+            state.pool.clone(),
+            state.notify.clone(),
+        ))
+    }
+
+    fn allowed(&self, chat_id: i64) -> bool {
+        self.allowed_chats.contains(&chat_id)
+    }
+
+    /// Points Telegram at `/inbox/telegram`.
+    pub async fn set_webhook(&self) -> Result<(), BlogError> {
+        self.api
+            .call::<Value>(
+                "setWebhook",
+                &json!({
+                    "url": format!("{}/inbox/telegram", CONFIG.site_url()),
+                    "secret_token": self.secret,
+                    "allowed_updates": ALLOWED_UPDATES,
+                }),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Long polls for updates until `SHUTDOWN` fires.
+    pub async fn poll(self: Arc<Self>) {
+        // getUpdates refuses to work while a webhook is set
+        if let Err(e) = self.api.call::<Value>("deleteWebhook", &json!({})).await {
+            warn!("telegram deleteWebhook failed: {}", e);
+        }
+
+        let shutdown = SHUTDOWN.wait_for_shutdown();
+        tokio::pin!(shutdown);
+
+        let mut offset = 0;
+        loop {
+            let params = json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT_SECS,
+                "allowed_updates": ALLOWED_UPDATES,
+            });
+            let updates = tokio::select! {
+                _ = &mut shutdown => break,
+                updates = self.api.call::<Vec<Update>>("getUpdates", &params) => updates,
+            };
+
+            match updates {
+                Ok(updates) => {
+                    for update in updates {
+                        offset = offset.max(update.update_id + 1);
+                        self.handle(update).await;
+                    }
+                }
+                Err(e) => {
+                    warn!("telegram getUpdates failed: {}", e);
+                    tokio::select! {
+                        _ = &mut shutdown => break,
+                        _ = tokio::time::sleep(POLL_RETRY) => {}
+                    }
+                }
+            }
+        }
+
+        info!("telegram bot stopped");
+    }
+
+    /// Acts on one update. Failures are logged, Telegram would only send the
+    /// same update again.
+    pub async fn handle(&self, update: Update) {
+        let result = if let Some(query) = update.callback_query {
+            self.on_callback(query).await
+        } else if let Some(message) = update.message {
+            self.on_message(message).await
+        } else {
+            Ok(())
+        };
+
+        if let Err(e) = result {
+            warn!("telegram update {} failed: {}", update.update_id, e);
+        }
+    }
+
+    async fn say(&self, chat_id: i64, text: &str) -> Result<(), BlogError> {
+        self.api
+            .send_message(&json!(chat_id), &escape_markdown_v2(text), json!({}))
+            .await
+    }
+
+    async fn on_callback(&self, query: CallbackQuery) -> Result<(), BlogError> {
+        let chat_id = query.message.as_ref().map(|m| m.chat.id);
+        let action = query.data.as_deref().and_then(parse_action);
+
+        let (text, keyboard) = match (chat_id, action) {
+            (Some(chat_id), _) if !self.allowed(chat_id) => {
+                warn!("telegram button from chat {} ignored", chat_id);
+                ("Not allowed", None)
+            }
+            (Some(_), Some(Action::Approve(id))) => {
+                let mut conn = self.pool.get_owned().await?;
+                match Comm::find_comm_by_uuid(&id, &mut conn).await? {
+                    None => (
+                        "The comment is gone",
+                        Some(json!({ "inline_keyboard": [] })),
+                    ),
+                    Some(comm) if comm.status == CommStatus::Approved => {
+                        ("Already approved", Some(comm_keyboard(&id, false)))
+                    }
+                    Some(_) => {
+                        moderation::moderate(
+                            &[id],
+                            ModerateAction::Approve,
+                            &self.notify,
+                            &mut conn,
+                        )
+                        .await?;
+                        ("Approved", Some(comm_keyboard(&id, false)))
+                    }
+                }
+            }
+            (Some(_), Some(Action::Delete(id))) => {
+                let mut conn = self.pool.get_owned().await?;
+                Comm::delete_comm_by_uuid(&id, &mut conn).await?;
+                ("Deleted", Some(json!({ "inline_keyboard": [] })))
+            }
+            _ => ("Unknown button", None),
+        };
+
+        self.api
+            .call::<Value>(
+                "answerCallbackQuery",
+                &json!({ "callback_query_id": query.id, "text": text }),
+            )
+            .await?;
+
+        // only buttons that still make sense are left on the notification
+        if let (Some(message), Some(keyboard)) = (&query.message, keyboard) {
+            let edited = self
+                .api
+                .call::<Value>(
+                    "editMessageReplyMarkup",
+                    &json!({
+                        "chat_id": message.chat.id,
+                        "message_id": message.message_id,
+                        "reply_markup": keyboard,
+                    }),
+                )
+                .await;
+            if let Err(e) = edited {
+                warn!("telegram buttons not updated: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn on_message(&self, message: TgMessage) -> Result<(), BlogError> {
+        let chat_id = message.chat.id;
+        if !self.allowed(chat_id) {
+            warn!("telegram message from chat {} ignored", chat_id);
+            return Ok(());
+        }
+        let Some(text) = message.text.as_deref() else {
+            return Ok(());
+        };
+
+        if let Some(command) = parse_command(text) {
+            return match command {
+                Command::Publish(name) => self.publish(chat_id, name).await,
+                Command::Drafts => self.drafts(chat_id).await,
+                Command::Help => self.say(chat_id, HELP).await,
+            };
+        }
+
+        match replied_comm(&message) {
+            Some(comm_id) => self.reply_to_comm(chat_id, &comm_id, text).await,
+            None => self.say(chat_id, HELP).await,
+        }
+    }
+
+    /// Answers a comm as the owner of its note or page.
+    async fn reply_to_comm(
+        &self,
+        chat_id: i64,
+        comm_id: &Uuid,
+        content: &str,
+    ) -> Result<(), BlogError> {
+        let mut conn = self.pool.get_owned().await?;
+
+        let Some(parent) = Comm::find_comm_by_uuid(comm_id, &mut conn).await? else {
+            return self.say(chat_id, "That comment is gone.").await;
+        };
+        if parent.status != CommStatus::Approved {
+            return self
+                .say(chat_id, "Approve the comment before replying to it.")
+                .await;
+        }
+        let (comm_type, type_id) = match (parent.note_id, parent.page_id) {
+            (Some(note_id), _) => (CommType::Note, note_id),
+            (None, Some(page_id)) => (CommType::Page, page_id),
+            (None, None) => return Ok(()),
+        };
+        let Some(place) = Comm::find_place(&parent, &mut conn).await? else {
+            return self.say(chat_id, "That comment is gone.").await;
+        };
+
+        let notify = self.notify.clone();
+        let content = content.trim().to_string();
+        let owner_id = place.owner_id;
+        let comm_id = conn
+            .transaction::<_, BlogError, _>(|conn| {
+                async move {
+                    let comm_id = Comm::create_comm(
+                        &content,
+                        CommAuthor::BlogUser(&owner_id),
+                        &type_id,
+                        comm_type,
+                        Some(&parent.id),
+                        &CommStatus::Approved,
+                        None,
+                        conn,
+                    )
+                    .await?;
+                    notify.enqueue_comm(&comm_id, true, conn).await?;
+
+                    Ok(comm_id)
+                }
+                .scope_boxed()
+            })
+            .await?;
+
+        self.say(
+            chat_id,
+            &format!(
+                "Reply posted: {}/{}#comm-{}",
+                CONFIG.site_url(),
+                place.short_name,
+                comm_id
+            ),
+        )
+        .await
+    }
+
+    async fn publish(&self, chat_id: i64, name: &str) -> Result<(), BlogError> {
+        if name.is_empty() {
+            return self.say(chat_id, "Usage: /publish <short name>").await;
+        }
+
+        let mut conn = self.pool.get_owned().await?;
+        let Some(note) = Note::find_note_by_short_id(name, &mut conn).await? else {
+            return self
+                .say(chat_id, &format!("No note called {}.", name))
+                .await;
+        };
+        if note.status != Status::Draft {
+            return self
+                .say(chat_id, &format!("{} is not a draft.", note.title))
+                .await;
+        }
+
+        // published along with the status, or not at all
+        let notify = self.notify.clone();
+        let note_id = note.id;
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                Note::update_status_by_uuid(&note_id, &Status::Public, conn).await?;
+                publish::published(&note_id, &notify, conn).await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        self.say(
+            chat_id,
+            &format!("Published {}: {}/{}", note.title, CONFIG.site_url(), name),
+        )
+        .await
+    }
+
+    async fn drafts(&self, chat_id: i64) -> Result<(), BlogError> {
+        let mut conn = self.pool.get_owned().await?;
+        let drafts = Note::get_notes_by_status(&Status::Draft, DRAFTS_LIMIT, &mut conn).await?;
+        if drafts.is_empty() {
+            return self.say(chat_id, "No drafts.").await;
+        }
+
+        let list = drafts
+            .iter()
+            .map(|(note, short_name)| format!("{}\n/publish {}", note.title, short_name))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        self.say(chat_id, &list).await
+    }
+}
+
+/// The webhook Telegram posts updates to, mounted under `/inbox`.
+pub fn router(bot: Arc<Bot>) -> Router {
+    Router::new()
+        .route("/telegram", post(webhook))
+        .with_state(bot)
+}
+
+async fn webhook(
+    State(bot): State<Arc<Bot>>,
+    headers: HeaderMap,
+    Json(update): Json<Update>,
+) -> StatusCode {
+    let secret = headers.get(SECRET_HEADER).and_then(|v| v.to_str().ok());
+    if bot.secret.is_none() || secret != bot.secret.as_deref() {
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    bot.handle(update).await;
+
+    StatusCode::OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_action() {
+        let id = Uuid::new_v4();
+        assert_eq!(
+            parse_action(&format!("approve:{}", id)),
+            Some(Action::Approve(id))
+        );
+        assert_eq!(
+            parse_action(&format!("delete:{}", id)),
+            Some(Action::Delete(id))
+        );
+        assert_eq!(parse_action(&format!("spam:{}", id)), None);
+        assert_eq!(parse_action("approve:nya"), None);
+        assert_eq!(parse_action("approve"), None);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("/publish hello-world"),
+            Some(Command::Publish("hello-world"))
+        );
+        assert_eq!(
+            parse_command("/publish@blogbot  hello-world "),
+            Some(Command::Publish("hello-world"))
+        );
+        assert_eq!(parse_command("/publish"), Some(Command::Publish("")));
+        assert_eq!(parse_command("/drafts"), Some(Command::Drafts));
+        assert_eq!(parse_command("/start"), Some(Command::Help));
+        assert_eq!(parse_command("thanks for reading!"), None);
+    }
+
+    #[test]
+    fn test_replied_comm() {
+        let id = Uuid::new_v4();
+        let update: Update = serde_json::from_value(json!({
+            "update_id": 7,
+            "message": {
+                "message_id": 2,
+                "chat": { "id": 42, "type": "private" },
+                "text": "Thank you!",
+                "reply_to_message": {
+                    "message_id": 1,
+                    "chat": { "id": 42, "type": "private" },
+                    "text": "New comment on nya",
+                    "reply_markup": comm_keyboard(&id, true),
+                }
+            }
+        }))
+        .unwrap();
+
+        let message = update.message.unwrap();
+        assert_eq!(replied_comm(&message), Some(id));
+        assert_eq!(
+            replied_comm(message.reply_to_message.as_ref().unwrap()),
+            None
+        );
+    }
+}
@@ -3,24 +3,39 @@ mod cli;
 mod config;
 mod db;
 mod error;
+mod federation;
+mod inbox;
 mod service;
 mod utils;
 
-use std::process;
+use std::{net::SocketAddr, process, sync::Arc};
 
 use axum::Router;
 use blog::ApiDoc;
 use db::{migrations::run_migrations, DbPool};
 use error::BlogError;
+use futures_util::future::OptionFuture;
+use service::{
+    antispam::SpamGuard, comm_feed::CommFeed, federation::Federation, notify::Notify,
+    online::Online,
+};
 use tracing::{error, info};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
-use crate::{config::CONFIG, db::create_pool, utils::SHUTDOWN};
+use crate::{
+    config::{DigestPeriod, CONFIG},
+    db::create_pool,
+    utils::SHUTDOWN,
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pool: DbPool,
+    guard: Arc<SpamGuard>,
+    notify: Arc<Notify>,
+    online: Arc<Online>,
+    comm_feed: Arc<CommFeed>,
 }
 
 #[tokio::main]
@@ -38,18 +53,100 @@ async fn main() -> Result<(), BlogError> {
 
     info!("starting Blog...");
     let pool = create_pool().await?;
-    let appstate = AppState { pool };
+    let appstate = AppState {
+        pool,
+        guard: Arc::new(SpamGuard::new()),
+        notify: Arc::new(Notify::new()?),
+        online: Arc::new(Online::new()),
+        comm_feed: Arc::new(CommFeed::new()),
+    };
 
-    let app = Router::new()
+    let outbox = tokio::spawn(service::outbox::run(
+        appstate.pool.clone(),
+        appstate.notify.clone(),
+    ));
+
+    let online = tokio::spawn(service::online::run(appstate.online.clone()));
+
+    let comm_feed = tokio::spawn(service::comm_feed::run(
+        appstate.pool.clone(),
+        appstate.comm_feed.clone(),
+    ));
+
+    let webmention = tokio::spawn(service::webmention::run(appstate.pool.clone()));
+
+    let hooks = tokio::spawn(service::hooks::run(appstate.pool.clone()));
+
+    let schedule = tokio::spawn(service::schedule::run(
+        appstate.pool.clone(),
+        appstate.notify.clone(),
+    ));
+
+    let digest = (CONFIG.notify.digest() != DigestPeriod::Off).then(|| {
+        tokio::spawn(service::digest::run(
+            appstate.pool.clone(),
+            appstate.notify.clone(),
+        ))
+    });
+
+    let (inbox, inbox_tasks) = inbox::start(&appstate).await?;
+
+    let federation = if CONFIG.federation.enabled() {
+        Some(Arc::new(Federation::from_config(&appstate).await?))
+    } else {
+        None
+    };
+    let federation_worker = federation
+        .clone()
+        .map(|federation| tokio::spawn(service::federation::run(federation)));
+
+    let mut app = Router::new()
         .nest("/api", blog::router(appstate))
+        .nest("/inbox", inbox)
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()));
+    if let Some(federation) = federation {
+        app = app.merge(federation::router(federation));
+    }
 
     let listener = tokio::net::TcpListener::bind(&CONFIG.listener_host()).await?;
 
     info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app)
-        .with_graceful_shutdown(SHUTDOWN.wait_for_shutdown())
-        .await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(SHUTDOWN.wait_for_shutdown())
+    .await?;
+
+    if let Err(e) = outbox.await {
+        error!("outbox worker panicked: {}", e);
+    }
+    if let Err(e) = online.await {
+        error!("online sweeper panicked: {}", e);
+    }
+    if let Err(e) = comm_feed.await {
+        error!("comm feed panicked: {}", e);
+    }
+    if let Err(e) = webmention.await {
+        error!("webmention worker panicked: {}", e);
+    }
+    if let Err(e) = hooks.await {
+        error!("webhook worker panicked: {}", e);
+    }
+    if let Err(e) = schedule.await {
+        error!("note scheduler panicked: {}", e);
+    }
+    if let Some(Err(e)) = OptionFuture::from(digest).await {
+        error!("digest worker panicked: {}", e);
+    }
+    if let Some(Err(e)) = OptionFuture::from(federation_worker).await {
+        error!("federation worker panicked: {}", e);
+    }
+    for task in inbox_tasks {
+        if let Err(e) = task.await {
+            error!("inbox task panicked: {}", e);
+        }
+    }
 
     Ok(())
 }
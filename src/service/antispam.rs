@@ -0,0 +1,314 @@
+pub mod bayes;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+use crate::{
+    config::CONFIG,
+    error::BlogError,
+    utils::{generate_random_string, sign},
+};
+
+/// A proof-of-work puzzle handed to the frontend before it may post a comm.
+///
+/// The client has to find a `nonce` such that `sha256("{challenge}:{nonce}")`
+/// starts with at least `difficulty` zero bits.
+#[derive(Serialize, ToSchema)]
+pub struct Challenge {
+    pub challenge: String,
+    pub difficulty: u32,
+    pub expires_at: i64,
+}
+
+/// What a visitor submits, as far as spam checks are concerned.
+pub struct Submission<'a> {
+    pub ip: &'a str,
+    pub email: &'a str,
+    pub content: &'a str,
+    pub challenge: Option<&'a str>,
+    pub nonce: Option<&'a str>,
+}
+
+/// Self-contained defence for anonymous writes: signed proof-of-work
+/// challenges, a link cap and sliding-window limits per IP and per email.
+pub struct SpamGuard {
+    limiter: Mutex<SlidingWindow>,
+    /// Challenges already redeemed, kept until they expire anyway.
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+impl Default for SpamGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpamGuard {
+    pub fn new() -> Self {
+        Self {
+            limiter: Mutex::new(SlidingWindow::default()),
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn issue_challenge(&self) -> Challenge {
+        let difficulty = CONFIG.antispam.pow_difficulty();
+        let expires_at = Utc::now().timestamp() + CONFIG.antispam.challenge_ttl() as i64;
+        let payload = challenge_payload(expires_at, difficulty, &generate_random_string(16));
+
+        Challenge {
+            challenge: format!("{}.{}", payload, sign::sign(&payload)),
+            difficulty,
+            expires_at,
+        }
+    }
+
+    pub fn check(&self, submission: &Submission) -> Result<(), BlogError> {
+        let antispam = &CONFIG.antispam;
+        if !antispam.enabled() {
+            return Ok(());
+        }
+
+        if count_links(submission.content) > antispam.max_links() {
+            return Err(BlogError::BadRequest(String::from("too many links")));
+        }
+
+        self.throttle(submission.ip, submission.email)?;
+
+        // last, so a challenge is only spent on a submission that gets through
+        if antispam.pow_difficulty() > 0 {
+            self.redeem_challenge(submission.challenge, submission.nonce)?;
+        }
+
+        Ok(())
+    }
+
+    /// The per IP and per email limits alone, for anonymous writes that need
+    /// no challenge.
+    pub fn throttle(&self, ip: &str, email: &str) -> Result<(), BlogError> {
+        let antispam = &CONFIG.antispam;
+        if !antispam.enabled() {
+            return Ok(());
+        }
+
+        let window = Duration::from_secs(antispam.window());
+        let ip_key = format!("ip:{}", ip);
+        let email_key = format!("email:{}", email.to_lowercase());
+        let allowed = self.limiter.lock().unwrap().hit(
+            &[
+                (ip_key.as_str(), antispam.ip_limit()),
+                (email_key.as_str(), antispam.email_limit()),
+            ],
+            window,
+            Instant::now(),
+        );
+
+        if allowed {
+            Ok(())
+        } else {
+            Err(BlogError::TooManyRequests(String::from(
+                "too many requests, slow down",
+            )))
+        }
+    }
+
+    fn redeem_challenge(
+        &self,
+        challenge: Option<&str>,
+        nonce: Option<&str>,
+    ) -> Result<(), BlogError> {
+        let (Some(challenge), Some(nonce)) = (challenge, nonce) else {
+            return Err(BlogError::BadRequest(String::from(
+                "missing proof-of-work challenge",
+            )));
+        };
+
+        let now = Utc::now().timestamp();
+        let (expires_at, difficulty) = open_challenge(challenge, now, sign::verify)
+            .ok_or(BlogError::BadRequest(String::from("invalid challenge")))?;
+
+        if !verify_pow(challenge, nonce, difficulty) {
+            return Err(BlogError::BadRequest(String::from("invalid proof-of-work")));
+        }
+
+        let mut spent = self.spent.lock().unwrap();
+        spent.retain(|_, expires| *expires > now);
+        if spent.insert(challenge.to_string(), expires_at).is_some() {
+            return Err(BlogError::BadRequest(String::from(
+                "challenge already used",
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct SlidingWindow {
+    hits: HashMap<String, VecDeque<Instant>>,
+}
+
+impl SlidingWindow {
+    /// Records one hit against every key, unless any of them is already at
+    /// its limit within `window`, in which case nothing is recorded.
+    fn hit(&mut self, keys: &[(&str, usize)], window: Duration, now: Instant) -> bool {
+        self.hits.retain(|_, hits| {
+            while hits
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= window)
+            {
+                hits.pop_front();
+            }
+            !hits.is_empty()
+        });
+
+        let allowed = keys
+            .iter()
+            .all(|(key, limit)| self.hits.get(*key).map_or(0, VecDeque::len) < *limit);
+
+        if allowed {
+            for (key, _) in keys {
+                self.hits.entry(key.to_string()).or_default().push_back(now);
+            }
+        }
+
+        allowed
+    }
+}
+
+fn challenge_payload(expires_at: i64, difficulty: u32, salt: &str) -> String {
+    format!("{}.{}.{}", expires_at, difficulty, salt)
+}
+
+/// Checks the signature and expiry of a challenge, returning its expiry and
+/// difficulty.
+fn open_challenge(
+    challenge: &str,
+    now: i64,
+    verify: impl Fn(&str, &str) -> bool,
+) -> Option<(i64, u32)> {
+    let (payload, signature) = challenge.rsplit_once('.')?;
+    if !verify(payload, signature) {
+        return None;
+    }
+
+    let mut parts = payload.splitn(3, '.');
+    let expires_at: i64 = parts.next()?.parse().ok()?;
+    let difficulty: u32 = parts.next()?.parse().ok()?;
+
+    (expires_at > now).then_some((expires_at, difficulty))
+}
+
+fn verify_pow(challenge: &str, nonce: &str, difficulty: u32) -> bool {
+    let hash = Sha256::digest(format!("{}:{}", challenge, nonce).as_bytes());
+    leading_zero_bits(&hash) >= difficulty
+}
+
+fn leading_zero_bits(bytes: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in bytes {
+        if *byte == 0 {
+            bits += 8;
+        } else {
+            bits += byte.leading_zeros();
+            break;
+        }
+    }
+    bits
+}
+
+/// Counts bare and Markdown/HTML links alike, since they all end up clickable.
+pub fn count_links(content: &str) -> usize {
+    let content = content.to_lowercase();
+    let schemes = content.matches("http://").count() + content.matches("https://").count();
+    let bare_www = content
+        .match_indices("www.")
+        .filter(|(i, _)| !content[..*i].ends_with("://"))
+        .count();
+
+    schemes + bare_www
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sign::{sign_with, verify_with};
+
+    const KEY: &[u8] = b"nekonya";
+
+    fn make_challenge(expires_at: i64, difficulty: u32) -> String {
+        let payload = challenge_payload(expires_at, difficulty, "salt");
+        format!("{}.{}", payload, sign_with(KEY, &payload))
+    }
+
+    fn solve(challenge: &str, difficulty: u32) -> String {
+        (0u64..)
+            .map(|n| n.to_string())
+            .find(|nonce| verify_pow(challenge, nonce, difficulty))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_open_challenge() {
+        let verify = |data: &str, sig: &str| verify_with(KEY, data, sig);
+        let challenge = make_challenge(1_000, 8);
+
+        assert_eq!(open_challenge(&challenge, 999, verify), Some((1_000, 8)));
+        assert_eq!(open_challenge(&challenge, 1_000, verify), None);
+
+        let tampered = challenge.replacen(".8.", ".0.", 1);
+        assert_eq!(open_challenge(&tampered, 999, verify), None);
+        assert_eq!(open_challenge("garbage", 999, verify), None);
+    }
+
+    #[test]
+    fn test_verify_pow() {
+        let challenge = make_challenge(1_000, 8);
+        let nonce = solve(&challenge, 8);
+
+        assert!(verify_pow(&challenge, &nonce, 8));
+        assert!(verify_pow(&challenge, &nonce, 0));
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        assert_eq!(leading_zero_bits(&[0, 0, 0xff]), 16);
+        assert_eq!(leading_zero_bits(&[0, 0x10]), 11);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+    }
+
+    #[test]
+    fn test_sliding_window() {
+        let mut window = SlidingWindow::default();
+        let span = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(window.hit(&[("ip:a", 2), ("email:a", 5)], span, start));
+        assert!(window.hit(&[("ip:a", 2), ("email:b", 5)], span, start));
+        assert!(!window.hit(&[("ip:a", 2), ("email:c", 5)], span, start));
+        // a refused hit is not recorded against the other keys
+        assert!(!window.hits.contains_key("email:c"));
+
+        let later = start + Duration::from_secs(61);
+        assert!(window.hit(&[("ip:a", 2), ("email:c", 5)], span, later));
+    }
+
+    #[test]
+    fn test_count_links() {
+        assert_eq!(count_links("nya"), 0);
+        assert_eq!(
+            count_links("see https://example.com and www.example.org"),
+            2
+        );
+        assert_eq!(count_links("[a](HTTP://www.example.com)"), 1);
+        assert_eq!(count_links("<a href=\"http://a\">http://b</a>"), 2);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::{BTreeSet, HashMap};
+
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::models::{comms::Comm, spam_tokens::SpamToken},
+    error::BlogError,
+};
+
+/// Robinson's strength of the background belief, in documents.
+const S: f64 = 1.0;
+/// Robinson's background probability for tokens seen only a few times.
+const X: f64 = 0.5;
+/// Tokens closer to neutral than this are ignored.
+const MIN_STRENGTH: f64 = 0.1;
+/// Only the most decisive tokens take part in a score.
+const MAX_DISCRIMINATORS: usize = 15;
+/// Longest token stored, matching `spam_tokens.token`.
+const MAX_TOKEN_CHARS: usize = 64;
+
+/// The parts of an anonymous comm the classifier looks at.
+pub struct Features<'a> {
+    pub nickname: &'a str,
+    pub email: &'a str,
+    pub website_url: Option<&'a str>,
+    pub content: &'a str,
+}
+
+/// Spam probability of a comm between 0 and 1, or `None` while either class
+/// has seen fewer than `antispam.minsamples` comms.
+pub async fn classify(
+    features: &Features<'_>,
+    conn: &mut AsyncPgConnection,
+) -> Result<Option<f64>, BlogError> {
+    let (spam_docs, ham_docs) = SpamToken::get_corpus(conn).await?;
+    let min_samples = CONFIG.antispam.min_samples();
+    if spam_docs < min_samples || ham_docs < min_samples {
+        return Ok(None);
+    }
+
+    let tokens = tokenize(features);
+    let counts = SpamToken::get_counts(&tokens, conn).await?;
+
+    Ok(Some(score(&tokens, &counts, spam_docs, ham_docs)))
+}
+
+/// Learns the given comms as spam or ham. Comms already learned the other way
+/// are untrained first, comms already learned this way are skipped, and comms
+/// by blog users are ignored.
+pub async fn learn(
+    ids: &[Uuid],
+    spam: bool,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), BlogError> {
+    for (comm, comm_user) in Comm::find_comms_with_users_by_ids(ids, conn).await? {
+        let Some(comm_user) = comm_user else {
+            continue;
+        };
+        if comm.spam_trained == Some(spam) {
+            continue;
+        }
+
+        let tokens = tokenize(&Features {
+            nickname: &comm_user.nickname,
+            email: &comm_user.email,
+            website_url: comm_user.website_url.as_deref(),
+            content: &comm.content,
+        });
+
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                if let Some(was_spam) = comm.spam_trained {
+                    let (spam_delta, ham_delta) = deltas(was_spam);
+                    SpamToken::train(&tokens, -spam_delta, -ham_delta, conn).await?;
+                }
+
+                let (spam_delta, ham_delta) = deltas(spam);
+                SpamToken::train(&tokens, spam_delta, ham_delta, conn).await?;
+                Comm::set_spam_trained(&comm.id, Some(spam), conn).await?;
+
+                Ok(())
+            }
+            .scope_boxed()
+        })
+        .await?;
+    }
+
+    Ok(())
+}
+
+fn deltas(spam: bool) -> (i64, i64) {
+    if spam {
+        (1, 0)
+    } else {
+        (0, 1)
+    }
+}
+
+/// Splits a comm into unique tokens: lowercased words, CJK bigrams, linked
+/// hosts and prefixed tokens for the commenter's details.
+pub fn tokenize(features: &Features) -> Vec<String> {
+    let mut tokens = BTreeSet::new();
+    let content = features.content.to_lowercase();
+
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+    for c in content.chars().chain(std::iter::once(' ')) {
+        if is_cjk(c) {
+            push_word(&mut tokens, &mut word);
+            cjk.push(c);
+            continue;
+        }
+
+        push_cjk(&mut tokens, &mut cjk);
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            push_word(&mut tokens, &mut word);
+        }
+    }
+
+    for piece in content.split_whitespace() {
+        if let Some(host) = url_host(piece) {
+            insert(&mut tokens, format!("url:{}", host));
+        }
+    }
+
+    let nickname = features.nickname.trim().to_lowercase();
+    if !nickname.is_empty() {
+        insert(&mut tokens, format!("nick:{}", nickname));
+    }
+    if let Some((_, domain)) = features.email.rsplit_once('@') {
+        insert(&mut tokens, format!("email:{}", domain.to_lowercase()));
+    }
+    match features.website_url.and_then(url_host) {
+        Some(host) => insert(&mut tokens, format!("site:{}", host)),
+        None => insert(&mut tokens, String::from("site:")),
+    };
+
+    tokens.into_iter().collect()
+}
+
+/// Adds a token cut to what `spam_tokens` stores, so tokens only told apart
+/// past that length become one.
+fn insert(tokens: &mut BTreeSet<String>, token: String) {
+    tokens.insert(token.chars().take(MAX_TOKEN_CHARS).collect());
+}
+
+fn push_word(tokens: &mut BTreeSet<String>, word: &mut String) {
+    let len = word.chars().count();
+    if (2..=40).contains(&len) {
+        insert(tokens, word.clone());
+    }
+    word.clear();
+}
+
+/// CJK text has no spaces, so overlapping bigrams stand in for words.
+fn push_cjk(tokens: &mut BTreeSet<String>, run: &mut Vec<char>) {
+    match run.len() {
+        0 => {}
+        1 => insert(tokens, run[0].to_string()),
+        _ => {
+            for pair in run.windows(2) {
+                insert(tokens, pair.iter().collect());
+            }
+        }
+    }
+    run.clear();
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+fn url_host(url: &str) -> Option<String> {
+    let lower = url.to_lowercase();
+    let start = lower.find("://")? + 3;
+    let host = lower[start..]
+        .split(['/', '?', '#', ':', ')', '"', '\'', '>', ']'])
+        .next()?
+        .trim_start_matches("www.");
+
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+/// Combines the most decisive token probabilities with Fisher's method, the
+/// way SpamBayes does: 0 is ham, 1 is spam and 0.5 means no idea.
+pub fn score(
+    tokens: &[String],
+    counts: &HashMap<String, (i64, i64)>,
+    spam_docs: i64,
+    ham_docs: i64,
+) -> f64 {
+    let mut probs: Vec<f64> = tokens
+        .iter()
+        .filter_map(|token| counts.get(token))
+        .map(|&(spam, ham)| token_prob(spam, ham, spam_docs, ham_docs))
+        .filter(|p| (p - 0.5).abs() > MIN_STRENGTH)
+        .collect();
+
+    if probs.is_empty() {
+        return 0.5;
+    }
+
+    probs.sort_by(|a, b| (b - 0.5).abs().total_cmp(&(a - 0.5).abs()));
+    probs.truncate(MAX_DISCRIMINATORS);
+
+    let freedom = 2 * probs.len();
+    let ln_spam: f64 = probs.iter().map(|p| p.ln()).sum();
+    let ln_ham: f64 = probs.iter().map(|p| (1.0 - p).ln()).sum();
+
+    let spamminess = 1.0 - chi2q(-2.0 * ln_ham, freedom);
+    let hamminess = 1.0 - chi2q(-2.0 * ln_spam, freedom);
+
+    (spamminess - hamminess + 1.0) / 2.0
+}
+
+/// Robinson's f(w): the token's spam ratio pulled towards `X` while it has
+/// only been seen a few times.
+fn token_prob(spam: i64, ham: i64, spam_docs: i64, ham_docs: i64) -> f64 {
+    let (spam, ham) = (spam.max(0) as f64, ham.max(0) as f64);
+    let spam_ratio = spam / spam_docs.max(1) as f64;
+    let ham_ratio = ham / ham_docs.max(1) as f64;
+
+    let p = if spam_ratio + ham_ratio > 0.0 {
+        spam_ratio / (spam_ratio + ham_ratio)
+    } else {
+        X
+    };
+    let n = spam + ham;
+
+    (S * X + n * p) / (S + n)
+}
+
+/// Probability that a chi-square with `freedom` (even) degrees of freedom is
+/// at least `x2`.
+fn chi2q(x2: f64, freedom: usize) -> f64 {
+    let m = x2 / 2.0;
+    let mut term = (-m).exp();
+    let mut sum = term;
+    for i in 1..freedom / 2 {
+        term *= m / i as f64;
+        sum += term;
+    }
+
+    sum.min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(content: &str) -> Features<'_> {
+        Features {
+            nickname: "Neko",
+            email: "neko@Example.com",
+            website_url: Some("https://www.example.org/about"),
+            content,
+        }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize(&features(
+            "Buy CHEAP pills a https://spam.example/x 猫が好き",
+        ));
+
+        for token in [
+            "buy",
+            "cheap",
+            "pills",
+            "https",
+            "url:spam.example",
+            "猫が",
+            "が好",
+            "好き",
+            "nick:neko",
+            "email:example.com",
+            "site:example.org",
+        ] {
+            assert!(tokens.contains(&token.to_string()), "missing {}", token);
+        }
+        // single letters say nothing
+        assert!(!tokens.contains(&"a".to_string()));
+
+        let tokens = tokenize(&Features {
+            website_url: None,
+            ..features("猫")
+        });
+        assert!(tokens.contains(&"猫".to_string()));
+        assert!(tokens.contains(&"site:".to_string()));
+    }
+
+    #[test]
+    fn test_tokenize_long_tokens() {
+        let host = "a".repeat(MAX_TOKEN_CHARS);
+        let tokens = tokenize(&features(&format!(
+            "https://{}.one.example https://{}.two.example",
+            host, host
+        )));
+
+        let urls: Vec<&String> = tokens.iter().filter(|t| t.starts_with("url:")).collect();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].chars().count(), MAX_TOKEN_CHARS);
+        // what `SpamToken::train` inserts in one statement must be unique
+        let unique: BTreeSet<&String> = tokens.iter().collect();
+        assert_eq!(unique.len(), tokens.len());
+    }
+
+    #[test]
+    fn test_token_prob() {
+        assert_eq!(token_prob(0, 0, 10, 10), 0.5);
+        assert!(token_prob(10, 0, 10, 10) > 0.9);
+        assert!(token_prob(0, 10, 10, 10) < 0.1);
+        // one sighting is not much evidence yet
+        assert!(token_prob(1, 0, 10, 10) < 0.8);
+    }
+
+    #[test]
+    fn test_chi2q() {
+        assert!((chi2q(0.0, 2) - 1.0).abs() < 1e-9);
+        // P(chi2 with 2 dof >= 2) = e^-1
+        assert!((chi2q(2.0, 2) - (-1.0f64).exp()).abs() < 1e-9);
+        assert!(chi2q(100.0, 4) < 1e-9);
+    }
+
+    #[test]
+    fn test_score() {
+        let counts: HashMap<String, (i64, i64)> = [
+            ("viagra", (20, 0)),
+            ("casino", (15, 1)),
+            ("nice", (1, 20)),
+            ("post", (2, 18)),
+            ("the", (10, 10)),
+        ]
+        .into_iter()
+        .map(|(t, c)| (t.to_string(), c))
+        .collect();
+        let tokens =
+            |words: &[&str]| -> Vec<String> { words.iter().map(|w| w.to_string()).collect() };
+
+        assert!(score(&tokens(&["viagra", "casino", "the"]), &counts, 20, 20) > 0.9);
+        assert!(score(&tokens(&["nice", "post", "the"]), &counts, 20, 20) < 0.1);
+        assert_eq!(score(&tokens(&["the", "unknown"]), &counts, 20, 20), 0.5);
+
+        let mixed = score(&tokens(&["viagra", "nice"]), &counts, 20, 20);
+        assert!(mixed > 0.3 && mixed < 0.7);
+    }
+}
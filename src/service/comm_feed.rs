@@ -0,0 +1,93 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::broadcast;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    db::{models::comms::Comm, DbPool},
+    error::BlogError,
+    utils::SHUTDOWN,
+};
+
+/// How often newly approved comms are looked for.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Approved comms taken per look.
+const BATCH_SIZE: i64 = 100;
+/// Announcements a slow stream may fall behind by before it has to catch up
+/// from the database.
+const CAPACITY: usize = 256;
+
+/// Tells comment streams which note or page just got a comm approved. Only
+/// the ID goes out, the streams read the comms themselves from where they
+/// left off.
+pub struct CommFeed {
+    sender: broadcast::Sender<Uuid>,
+}
+
+impl Default for CommFeed {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommFeed {
+    pub fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Uuid> {
+        self.sender.subscribe()
+    }
+}
+
+/// Watches for approved comms until `SHUTDOWN` fires. Reading them back from
+/// the database catches approvals from anywhere: the API, the bot, or another
+/// instance of the blog.
+pub async fn run(pool: DbPool, feed: Arc<CommFeed>) {
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+    let mut cursor = None;
+
+    loop {
+        match announce(&pool, &feed, cursor).await {
+            Ok(seq) => cursor = Some(seq),
+            Err(e) => error!("comm feed round failed: {}", e),
+        }
+
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+
+    info!("comm feed stopped");
+}
+
+/// Announces what was approved after `cursor`, or nothing on the first round
+/// which only finds where to start. Returns the new cursor.
+async fn announce(pool: &DbPool, feed: &CommFeed, cursor: Option<i64>) -> Result<i64, BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let Some(mut cursor) = cursor else {
+        return Comm::max_approved_seq(&mut conn).await;
+    };
+
+    loop {
+        let landed = Comm::get_approved_since(cursor, BATCH_SIZE, &mut conn).await?;
+        let Some(&(last, _)) = landed.last() else {
+            return Ok(cursor);
+        };
+
+        for (_, type_id) in &landed {
+            // nobody listening is fine
+            let _ = feed.sender.send(*type_id);
+        }
+        cursor = last;
+
+        if (landed.len() as i64) < BATCH_SIZE {
+            return Ok(cursor);
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::{Datelike, NaiveDateTime, TimeDelta, Utc};
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::{
+    config::{DigestPeriod, CONFIG},
+    db::{
+        models::{
+            comms::Comm, notes::Note, notify_digests::NotifyDigest, notify_outbox::OutboxMessage,
+            subscribers::Subscriber, users::User,
+        },
+        DbPool,
+    },
+    error::BlogError,
+    service::notify::{
+        template::{excerpt, EXCERPT_CHARS},
+        Notify, Recipient,
+    },
+    utils::SHUTDOWN,
+};
+
+/// Comments and failed messages listed in full, the rest are only counted.
+const LIST_LIMIT: i64 = 20;
+/// Notes listed as most viewed.
+const TOP_NOTES: i64 = 5;
+/// Wait before trying again after a digest could not be put together.
+const RETRY: Duration = Duration::from_secs(5 * 60);
+
+fn length(period: DigestPeriod) -> TimeDelta {
+    match period {
+        DigestPeriod::Weekly => TimeDelta::days(7),
+        _ => TimeDelta::days(1),
+    }
+}
+
+/// End of the latest period that is over at `now`: the last `hour` o'clock,
+/// and for weekly digests the last Monday at that hour.
+fn last_boundary(now: NaiveDateTime, period: DigestPeriod, hour: u32) -> NaiveDateTime {
+    let today = now
+        .date()
+        .and_hms_opt(hour, 0, 0)
+        .expect("digest hour is below 24");
+    let daily = if today <= now {
+        today
+    } else {
+        today - TimeDelta::days(1)
+    };
+
+    match period {
+        DigestPeriod::Weekly => {
+            daily - TimeDelta::days(daily.weekday().num_days_from_monday() as i64)
+        }
+        _ => daily,
+    }
+}
+
+/// Mails the owner a digest at the end of every period until `SHUTDOWN`
+/// fires. The period that ended last is sent on start too, unless it was
+/// already, so a restart neither skips nor repeats one.
+pub async fn run(pool: DbPool, notify: Arc<Notify>) {
+    let period = CONFIG.notify.digest();
+    let hour = CONFIG.notify.digest_hour();
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        let now = Utc::now().naive_utc();
+        let end = last_boundary(now, period, hour);
+        let next = end + length(period);
+
+        let wait = match send(&pool, &notify, period, end).await {
+            Ok(()) => (next - now).to_std().unwrap_or_default(),
+            Err(e) => {
+                error!("digest until {} failed: {}", end, e);
+                (next - now).to_std().unwrap_or_default().min(RETRY)
+            }
+        };
+
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(wait) => {}
+        }
+    }
+
+    info!("digest worker stopped");
+}
+
+/// Queues the digest of the period ending at `end` for every blog user.
+/// Quiet periods, without comments, subscribers or failures, are skipped.
+async fn send(
+    pool: &DbPool,
+    notify: &Notify,
+    period: DigestPeriod,
+    end: NaiveDateTime,
+) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let start = end - length(period);
+
+    let (comms, comments_total) =
+        Comm::get_comms_created_between(start, end, LIST_LIMIT, &mut conn).await?;
+    let mut comments = Vec::new();
+    for (comm, user) in comms {
+        let Some(place) = Comm::find_place(&comm, &mut conn).await? else {
+            continue;
+        };
+        let nickname = match (user, &comm.blog_user_id) {
+            (Some(user), _) => user.nickname,
+            (None, Some(_)) => String::from("you"),
+            (None, None) => String::from("someone"),
+        };
+        comments.push(json!({
+            "nickname": nickname,
+            "note_title": place.title,
+            "excerpt": excerpt(&comm.content, EXCERPT_CHARS),
+            "status": comm.status,
+            "link": format!("{}/{}#comm-{}", CONFIG.site_url(), place.short_name, comm.id),
+        }));
+    }
+
+    let top_notes: Vec<_> = Note::get_most_viewed(TOP_NOTES, &mut conn)
+        .await?
+        .into_iter()
+        .map(|(note, short_name)| {
+            json!({
+                "title": note.title,
+                "views": note.views,
+                "link": format!("{}/{}", CONFIG.site_url(), short_name),
+            })
+        })
+        .collect();
+
+    let (subscribers_new, subscribers_total) =
+        Subscriber::count_confirmed(start, end, &mut conn).await?;
+
+    let (dead, failed_total) =
+        OutboxMessage::get_dead_between(start, end, LIST_LIMIT, &mut conn).await?;
+    let failed: Vec<_> = dead
+        .into_iter()
+        .map(|message| {
+            json!({
+                "channel": message.channel,
+                "attempts": message.attempts,
+                "error": message.last_error.unwrap_or_default(),
+            })
+        })
+        .collect();
+
+    let quiet = comments_total == 0 && subscribers_new == 0 && failed_total == 0;
+    let vars = json!({
+        "period": period,
+        "start": start.format("%Y-%m-%d %H:%M UTC").to_string(),
+        "end": end.format("%Y-%m-%d %H:%M UTC").to_string(),
+        "comments": comments,
+        "comments_total": comments_total,
+        "top_notes": top_notes,
+        "subscribers_new": subscribers_new,
+        "subscribers_total": subscribers_total,
+        "failed": failed,
+        "failed_total": failed_total,
+    });
+
+    let mut recipients = Vec::new();
+    for email in User::get_emails(&mut conn).await? {
+        if let Some(recipient) = Recipient::subscribed(email, &mut conn).await? {
+            recipients.push(recipient);
+        }
+    }
+
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            if !NotifyDigest::claim(end, conn).await? || quiet {
+                return Ok(());
+            }
+
+            notify
+                .enqueue_mails("digest", &vars, recipients, conn)
+                .await
+        }
+        .scope_boxed()
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, min: u32) -> NaiveDateTime {
+        // 2026-10-19 is a Monday
+        NaiveDate::from_ymd_opt(2026, 10, day)
+            .unwrap()
+            .and_hms_opt(hour, min, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_last_boundary() {
+        assert_eq!(
+            last_boundary(at(21, 9, 30), DigestPeriod::Daily, 8),
+            at(21, 8, 0)
+        );
+        assert_eq!(
+            last_boundary(at(21, 8, 0), DigestPeriod::Daily, 8),
+            at(21, 8, 0)
+        );
+        assert_eq!(
+            last_boundary(at(21, 7, 59), DigestPeriod::Daily, 8),
+            at(20, 8, 0)
+        );
+
+        assert_eq!(
+            last_boundary(at(21, 9, 30), DigestPeriod::Weekly, 8),
+            at(19, 8, 0)
+        );
+        assert_eq!(
+            last_boundary(at(19, 7, 0), DigestPeriod::Weekly, 8),
+            at(12, 8, 0)
+        );
+        assert_eq!(
+            last_boundary(at(25, 23, 0), DigestPeriod::Weekly, 0),
+            at(19, 0, 0)
+        );
+    }
+}
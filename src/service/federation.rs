@@ -0,0 +1,605 @@
+pub mod activity;
+pub mod signature;
+
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use axum::http::{HeaderMap, Method};
+use chrono::Utc;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use futures_util::future::join_all;
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client, Url,
+};
+use rsa::RsaPrivateKey;
+use serde_json::Value;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{
+            ap_actors::ApActor,
+            ap_deliveries::ApDelivery,
+            ap_followers::ApFollower,
+            ap_replies::ApReply,
+            comm_users::CommUser,
+            comms::{Comm, CommAuthor, CommStatus, CommType},
+            federation_keys::FederationKey,
+            notes::{Note, Status},
+        },
+        DbPool,
+    },
+    error::BlogError,
+    service::{hooks::Outcome, notify::Notify, outbox::backoff},
+    utils::{public_net, SHUTDOWN},
+    AppState,
+};
+
+use activity::{id_of, parse_actor, reply_text, same_origin, Site};
+use signature::{parse_public_key, SignatureHeader};
+
+/// Deliveries taken per round.
+const BATCH_SIZE: i64 = 50;
+/// How long a claimed delivery stays hidden from other workers.
+const LEASE_SECS: i64 = 5 * 60;
+/// Response bodies kept as the error of a failed delivery, in chars.
+const ERROR_CHARS: usize = 512;
+/// Size of the actor's key.
+const KEY_BITS: usize = 2048;
+/// Largest document taken from other servers, inbox posts included.
+pub const MAX_DOCUMENT_BYTES: usize = 1 << 20;
+/// Longest reply stored as a comm, in chars.
+const MAX_REPLY_CHARS: usize = 5000;
+/// Headers a signature must cover for an inbox post to count.
+const SIGNED_HEADERS: [&str; 4] = ["(request-target)", "host", "date", "digest"];
+
+pub fn client() -> Result<Client, BlogError> {
+    Ok(public_net::public_only(Client::builder())
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!("tsuiiblog/", env!("CARGO_PKG_VERSION")))
+        .build()?)
+}
+
+/// The blog's actor: its key, and what it needs to talk to other servers.
+pub struct Federation {
+    site: Site,
+    key: RsaPrivateKey,
+    public_key: String,
+    client: Client,
+    pool: DbPool,
+    notify: Arc<Notify>,
+}
+
+impl Federation {
+    /// Loads the actor's key, making one the first time.
+    pub async fn from_config(state: &AppState) -> Result<Self, BlogError> {
+        let mut conn = state.pool.get().await?;
+        let key = match FederationKey::find(&mut conn).await? {
+            Some(key) => key,
+            None => {
+                info!("generating the federation key");
+                let (private_key, public_key) =
+                    tokio::task::spawn_blocking(|| signature::generate_key(KEY_BITS))
+                        .await
+                        .map_err(|e| anyhow::anyhow!(e))??;
+                FederationKey::create_or_get(&private_key, &public_key, &mut conn).await?
+            }
+        };
+
+        Self::new(
+            Site::from_config(),
+            &key,
+            client()?,
+            state.pool.clone(),
+            state.notify.clone(),
+        )
+    }
+
+    pub fn new(
+        site: Site,
+        key: &FederationKey,
+        client: Client,
+        pool: DbPool,
+        notify: Arc<Notify>,
+    ) -> Result<Self, BlogError> {
+        Ok(Self {
+            site,
+            key: signature::parse_private_key(&key.private_key)?,
+            public_key: key.public_key.clone(),
+            client,
+            pool,
+            notify,
+        })
+    }
+
+    pub fn site(&self) -> &Site {
+        &self.site
+    }
+
+    pub fn public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    pub fn pool(&self) -> &DbPool {
+        &self.pool
+    }
+
+    /// Fetches an ActivityPub document along with where it was found after
+    /// redirects. The request is signed, as servers in authorized fetch mode
+    /// want to know who asks.
+    pub async fn fetch(&self, url: &str) -> Result<(Value, Url), BlogError> {
+        let url = Url::parse(url).map_err(|e| anyhow::anyhow!("{}: {}", url, e))?;
+        if !public_net::is_public(&url) {
+            return Err(anyhow::anyhow!("{} is not a public http url", url).into());
+        }
+
+        let mut request = self.client.get(url.clone()).header(
+            ACCEPT,
+            r#"application/activity+json, application/ld+json; profile="https://www.w3.org/ns/activitystreams""#,
+        );
+        let headers = signature::sign(
+            &self.key,
+            &self.site.key_id(),
+            &Method::GET,
+            &url,
+            None,
+            SystemTime::now(),
+        );
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let mut response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow::anyhow!("{} answered {}", url, response.status()).into());
+        }
+        let found_at = response.url().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_DOCUMENT_BYTES {
+                return Err(anyhow::anyhow!("{} is too large", url).into());
+            }
+        }
+
+        Ok((serde_json::from_slice(&body)?, found_at))
+    }
+
+    /// Posts `activity` to `inbox`, signed. Anything but a 2xx is a failure.
+    pub async fn deliver(&self, inbox: &str, activity: &Value) -> Outcome {
+        let url = match Url::parse(inbox) {
+            Ok(url) => url,
+            Err(e) => return Outcome::Failed(None, e.to_string()),
+        };
+        if !public_net::is_public(&url) {
+            return Outcome::Failed(None, format!("{} is not a public http url", url));
+        }
+        let body = activity.to_string();
+
+        let mut request = self
+            .client
+            .post(url.clone())
+            .header(CONTENT_TYPE, activity::CONTENT_TYPE);
+        let headers = signature::sign(
+            &self.key,
+            &self.site.key_id(),
+            &Method::POST,
+            &url,
+            Some(body.as_bytes()),
+            SystemTime::now(),
+        );
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        match request.body(body).send().await {
+            Ok(response) => {
+                let status = response.status();
+                if status.is_success() {
+                    Outcome::Delivered(status.as_u16() as i32)
+                } else {
+                    let text = response.text().await.unwrap_or_default();
+                    let error = format!(
+                        "{}: {}",
+                        status,
+                        text.chars().take(ERROR_CHARS).collect::<String>()
+                    );
+                    Outcome::Failed(Some(status.as_u16() as i32), error)
+                }
+            }
+            Err(e) => Outcome::Failed(None, e.to_string()),
+        }
+    }
+
+    /// The actor `key_id` belongs to, from what we know unless `refresh`.
+    async fn key_owner(
+        &self,
+        key_id: &str,
+        refresh: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<ApActor>, BlogError> {
+        if !refresh {
+            if let Some(actor) = ApActor::find_by_key_id(key_id, conn).await? {
+                return Ok(Some(actor));
+            }
+        }
+
+        // the key is mostly a fragment of the actor, otherwise it names it
+        let (mut document, mut found_at) = self
+            .fetch(key_id.split('#').next().unwrap_or(key_id))
+            .await?;
+        if document.get("publicKey").is_none() {
+            if let Some(owner) = document["owner"].as_str() {
+                (document, found_at) = self.fetch(owner).await?;
+            }
+        }
+
+        let Some(actor) =
+            parse_actor(&document, found_at.as_str()).filter(|actor| actor.key_id == key_id)
+        else {
+            return Ok(None);
+        };
+        actor.upsert(conn).await?;
+
+        Ok(Some(actor))
+    }
+
+    /// Checks the signature of a post to the inbox and returns who signed it.
+    pub async fn verify(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<ApActor, BlogError> {
+        let unauthorized = |message: &str| BlogError::Unauthorized(message.to_string());
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        let signature = header("signature")
+            .and_then(SignatureHeader::parse)
+            .ok_or_else(|| unauthorized("missing or unsupported signature"))?;
+        if let Some(missing) = SIGNED_HEADERS
+            .iter()
+            .find(|name| !signature.headers.iter().any(|h| h == *name))
+        {
+            return Err(unauthorized(&format!("signature must cover {}", missing)));
+        }
+
+        let date = header("date")
+            .and_then(|date| httpdate::parse_http_date(date).ok())
+            .ok_or_else(|| unauthorized("invalid date"))?;
+        let now = SystemTime::now();
+        let skew = now
+            .duration_since(date)
+            .or_else(|_| date.duration_since(now))
+            .unwrap_or_default();
+        if skew > Duration::from_secs(signature::MAX_SKEW_SECS) {
+            return Err(unauthorized("date is too far off"));
+        }
+
+        let digest = signature::digest(body);
+        let digest_matches =
+            header("digest").is_some_and(|value| value.split(',').any(|d| d.trim() == digest));
+        if !digest_matches {
+            return Err(unauthorized("digest does not match"));
+        }
+
+        let signing_string = signature::signing_string(&signature.headers, method, path, header)
+            .ok_or_else(|| unauthorized("signed header is missing"))?;
+
+        let mut conn = self.pool.get().await?;
+        for refresh in [false, true] {
+            let actor = self
+                .key_owner(&signature.key_id, refresh, &mut conn)
+                .await
+                .map_err(|e| unauthorized(&format!("cannot fetch key: {}", e)))?
+                .ok_or_else(|| unauthorized("unknown key"))?;
+            let verified = parse_public_key(&actor.public_key)
+                .is_some_and(|key| signature::verify(&key, &signing_string, &signature.signature));
+            if verified {
+                return Ok(actor);
+            }
+        }
+
+        Err(unauthorized("signature does not match"))
+    }
+
+    /// Acts on an activity `actor` signed. Activities the blog has no use for
+    /// are taken and dropped.
+    pub async fn receive(&self, actor: &ApActor, activity: &Value) -> Result<(), BlogError> {
+        if id_of(&activity["actor"]) != Some(actor.id.as_str()) {
+            return Err(BlogError::Unauthorized(String::from(
+                "activity is not by the signer",
+            )));
+        }
+
+        let object = &activity["object"];
+        match activity["type"].as_str() {
+            Some("Follow") => self.follow(actor, activity).await,
+            Some("Undo") if object["type"] == "Follow" => {
+                let mut conn = self.pool.get().await?;
+                ApFollower::unfollow(&actor.id, &mut conn).await?;
+                Ok(())
+            }
+            Some("Create") => self.reply(actor, object).await,
+            Some("Update") => self.update_reply(actor, object).await,
+            Some("Delete") => self.delete(actor, object).await,
+            _ => Ok(()),
+        }
+    }
+
+    async fn follow(&self, actor: &ApActor, follow: &Value) -> Result<(), BlogError> {
+        if id_of(&follow["object"]) != Some(self.site.actor_id().as_str()) {
+            return Ok(());
+        }
+        let follow_id = follow["id"]
+            .as_str()
+            .ok_or(BlogError::BadRequest(String::from("follow has no id")))?
+            .to_string();
+
+        let accept = self.site.accept(follow);
+        let actor_id = actor.id.clone();
+        let inbox = vec![actor.inbox.clone()];
+        let mut conn = self.pool.get().await?;
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                ApFollower::follow(&actor_id, &follow_id, conn).await?;
+                ApDelivery::enqueue(&inbox, &accept, conn).await
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+        info!("{} follows the blog", actor.id);
+        Ok(())
+    }
+
+    /// Stores a reply to one of our notes, or to a reply stored before, as a
+    /// comm. Replies by actors whose comms were approved before go live, the
+    /// others wait for moderation.
+    async fn reply(&self, actor: &ApActor, object: &Value) -> Result<(), BlogError> {
+        let Some(object_id) = object["id"].as_str().filter(|_| object["type"] == "Note") else {
+            return Ok(());
+        };
+        if !same_origin(object_id, &actor.id) {
+            return Ok(());
+        }
+        let Some(in_reply_to) = id_of(&object["inReplyTo"]) else {
+            return Ok(());
+        };
+
+        let mut conn = self.pool.get_owned().await?;
+        if ApReply::find_by_object_id(object_id, &mut conn)
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let (note_id, parent_id) = match self.site.note_id(in_reply_to) {
+            Some(note_id) => (note_id, None),
+            None => {
+                let Some(parent) = ApReply::find_by_object_id(in_reply_to, &mut conn).await? else {
+                    return Ok(());
+                };
+                let Some(note_id) = Comm::find_comm_by_uuid(&parent.comm_id, &mut conn)
+                    .await?
+                    .and_then(|comm| comm.note_id)
+                else {
+                    return Ok(());
+                };
+                (note_id, Some(parent.comm_id))
+            }
+        };
+        let note = Note::find_note_by_uuid(&note_id, &mut conn).await?;
+        if !note.is_some_and(|note| note.status == Status::Public && note.comm) {
+            return Ok(());
+        }
+
+        let content = self.reply_content(object);
+        if content.is_empty() || actor.id.len() > 256 {
+            return Ok(());
+        }
+        let nickname = actor
+            .name
+            .clone()
+            .or_else(|| actor.username.clone())
+            .unwrap_or_else(|| actor.id.clone());
+        let website_url = actor.url.clone().filter(|url| url.len() <= 256);
+        let status = if CommUser::has_approved_comm(&actor.id, &mut conn).await? {
+            CommStatus::Approved
+        } else {
+            CommStatus::Pending
+        };
+
+        let notify = self.notify.clone();
+        let actor_id = actor.id.clone();
+        let object_id = object_id.to_string();
+        conn.transaction::<_, BlogError, _>(|conn| {
+            async move {
+                // fediverse commenters are known by their actor ID
+                let comm_user_id =
+                    CommUser::find_or_create(&nickname, &actor_id, website_url.as_deref(), conn)
+                        .await?;
+                let comm_id = Comm::create_comm(
+                    &content,
+                    CommAuthor::CommUser(&comm_user_id),
+                    &note_id,
+                    CommType::Note,
+                    parent_id.as_ref(),
+                    &status,
+                    None,
+                    conn,
+                )
+                .await?;
+                ApReply::create(&object_id, &comm_id, &actor_id, conn).await?;
+                notify.enqueue_comm(&comm_id, true, conn).await
+            }
+            .scope_boxed()
+        })
+        .await
+    }
+
+    async fn update_reply(&self, actor: &ApActor, object: &Value) -> Result<(), BlogError> {
+        let Some(object_id) = id_of(object) else {
+            return Ok(());
+        };
+
+        let mut conn = self.pool.get_owned().await?;
+        let Some(reply) = ApReply::find_by_object_id(object_id, &mut conn)
+            .await?
+            .filter(|reply| reply.actor_id == actor.id)
+        else {
+            return Ok(());
+        };
+        let content = self.reply_content(object);
+        if content.is_empty() {
+            return Ok(());
+        }
+
+        Comm::update_comm_by_uuid(&reply.comm_id, &content, &mut conn).await?;
+
+        Ok(())
+    }
+
+    /// Forgets the actor when it is deleted, and its replies when they are.
+    async fn delete(&self, actor: &ApActor, object: &Value) -> Result<(), BlogError> {
+        let Some(object_id) = id_of(object) else {
+            return Ok(());
+        };
+
+        let mut conn = self.pool.get().await?;
+        if object_id == actor.id {
+            return ApActor::delete_by_id(&actor.id, &mut conn).await;
+        }
+
+        match ApReply::find_by_object_id(object_id, &mut conn)
+            .await?
+            .filter(|reply| reply.actor_id == actor.id)
+        {
+            Some(reply) => {
+                Comm::delete_comm_by_uuid(&reply.comm_id, &mut conn).await?;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn reply_content(&self, object: &Value) -> String {
+        let actor_id = self.site.actor_id();
+        let content = reply_text(
+            object["content"].as_str().unwrap_or_default(),
+            &[&actor_id, &self.site.site_url],
+        );
+        content.chars().take(MAX_REPLY_CHARS).collect()
+    }
+}
+
+/// Queues what followers should hear about a write to a note: a `Create` the
+/// first time it is published, an `Update` after that, and a `Delete` once it
+/// is no longer public.
+pub async fn enqueue_note(
+    note_id: &Uuid,
+    first: bool,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), BlogError> {
+    if !CONFIG.federation.enabled() {
+        return Ok(());
+    }
+    let Some((note, short_name)) = Note::find_note_with_short_name(note_id, conn).await? else {
+        return Ok(());
+    };
+
+    let site = Site::from_config();
+    let activity = if note.status == Status::Public {
+        let kind = if first { "Create" } else { "Update" };
+        site.wrap(kind, site.object(&note, &short_name))
+    } else if note.published_at.is_some() {
+        site.delete(&note.id)
+    } else {
+        return Ok(());
+    };
+
+    let inboxes = ApFollower::get_inboxes(conn).await?;
+    ApDelivery::enqueue(&inboxes, &activity, conn).await
+}
+
+/// Queues a `Delete` for a note that is gone, if followers may have seen it.
+pub async fn enqueue_delete(note: &Note, conn: &mut AsyncPgConnection) -> Result<(), BlogError> {
+    if !CONFIG.federation.enabled() || note.published_at.is_none() {
+        return Ok(());
+    }
+
+    let inboxes = ApFollower::get_inboxes(conn).await?;
+    ApDelivery::enqueue(&inboxes, &Site::from_config().delete(&note.id), conn).await
+}
+
+/// Delivers queued activities until `SHUTDOWN` fires, retrying failed ones
+/// with the same backoff as notifications.
+pub async fn run(federation: Arc<Federation>) {
+    let poll_interval = Duration::from_secs(CONFIG.notify.poll_interval());
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+
+        if let Err(e) = deliver_due(&federation).await {
+            error!("federation round failed: {}", e);
+        }
+    }
+
+    info!("federation worker stopped");
+}
+
+async fn deliver_due(federation: &Federation) -> Result<(), BlogError> {
+    let mut conn = federation.pool.get_owned().await?;
+    let deliveries = ApDelivery::claim_due(BATCH_SIZE, LEASE_SECS, &mut conn).await?;
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    let outcomes = join_all(
+        deliveries
+            .iter()
+            .map(|delivery| federation.deliver(&delivery.inbox, &delivery.activity)),
+    )
+    .await;
+
+    let max_attempts = CONFIG.notify.max_attempts();
+    let retry_base = Duration::from_secs(CONFIG.notify.retry_base());
+    for (delivery, outcome) in deliveries.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Delivered(status) => {
+                ApDelivery::mark_delivered(&delivery.id, status, &mut conn).await?
+            }
+            Outcome::Failed(status, e) => {
+                let attempts = delivery.attempts + 1;
+                // a server saying the inbox is gone will not change its mind
+                let gone = status == Some(410);
+                let retry_at = (attempts < max_attempts && !gone).then(|| {
+                    let delay = backoff(attempts, retry_base);
+                    Utc::now().naive_utc() + chrono::Duration::from_std(delay).unwrap_or_default()
+                });
+                if retry_at.is_none() {
+                    warn!(
+                        "activity delivery {} to {} is dead after {} attempts: {}",
+                        delivery.id, delivery.inbox, attempts, e
+                    );
+                }
+
+                ApDelivery::mark_failed(&delivery.id, status, &e, retry_at, &mut conn).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
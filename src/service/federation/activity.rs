@@ -0,0 +1,516 @@
+use chrono::{NaiveDateTime, Utc};
+use reqwest::Url;
+use scraper::{CaseSensitivity, ElementRef, Html, Node};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    config::{ObjectType, CONFIG},
+    db::models::{ap_actors::ApActor, notes::Note},
+};
+
+pub const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+pub const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+/// Audience of public posts.
+pub const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+pub const CONTENT_TYPE: &str = "application/activity+json";
+
+/// The blog as the Fediverse sees it.
+#[derive(Debug, Clone)]
+pub struct Site {
+    /// Base of the ActivityPub URLs
+    pub base: String,
+    /// Base of the note links people open
+    pub site_url: String,
+    pub username: String,
+    pub object_type: ObjectType,
+}
+
+impl Site {
+    pub fn from_config() -> Self {
+        Self {
+            base: CONFIG.federation_url(),
+            site_url: CONFIG.site_url(),
+            username: CONFIG.federation.username().to_string(),
+            object_type: CONFIG.federation.object_type(),
+        }
+    }
+
+    pub fn actor_id(&self) -> String {
+        format!("{}/ap/actor", self.base)
+    }
+
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor_id())
+    }
+
+    pub fn inbox(&self) -> String {
+        format!("{}/ap/inbox", self.base)
+    }
+
+    pub fn outbox(&self) -> String {
+        format!("{}/ap/outbox", self.base)
+    }
+
+    pub fn followers(&self) -> String {
+        format!("{}/ap/followers", self.base)
+    }
+
+    pub fn object_id(&self, note_id: &Uuid) -> String {
+        format!("{}/ap/notes/{}", self.base, note_id)
+    }
+
+    /// Note an object ID of ours stands for.
+    pub fn note_id(&self, object_id: &str) -> Option<Uuid> {
+        let prefix = format!("{}/ap/notes/", self.base);
+        Uuid::parse_str(object_id.strip_prefix(&prefix)?).ok()
+    }
+
+    pub fn link(&self, short_name: &str) -> String {
+        format!("{}/{}", self.site_url, short_name)
+    }
+
+    /// Host part of the handle, `example.com` in `@blog@example.com`.
+    pub fn domain(&self) -> String {
+        Url::parse(&self.base)
+            .ok()
+            .and_then(|url| {
+                let host = url.host_str()?.to_string();
+                Some(match url.port() {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// Answer to a WebFinger query for `resource`, if it names our actor.
+    pub fn webfinger(&self, resource: &str) -> Option<Value> {
+        let acct = format!("acct:{}@{}", self.username, self.domain());
+        let actor_id = self.actor_id();
+        if !resource.eq_ignore_ascii_case(&acct) && resource != actor_id {
+            return None;
+        }
+
+        Some(json!({
+            "subject": acct,
+            "aliases": [actor_id],
+            "links": [
+                {
+                    "rel": "self",
+                    "type": CONTENT_TYPE,
+                    "href": actor_id,
+                },
+                {
+                    "rel": "http://webfinger.net/rel/profile-page",
+                    "type": "text/html",
+                    "href": self.site_url,
+                },
+            ],
+        }))
+    }
+
+    pub fn actor(&self, name: &str, summary: &str, public_key_pem: &str) -> Value {
+        let actor_id = self.actor_id();
+        json!({
+            "@context": [CONTEXT, SECURITY_CONTEXT],
+            "id": actor_id,
+            "type": "Service",
+            "preferredUsername": self.username,
+            "name": name,
+            "summary": escape_html(summary),
+            "url": self.site_url,
+            "inbox": self.inbox(),
+            "outbox": self.outbox(),
+            "followers": self.followers(),
+            "manuallyApprovesFollowers": false,
+            "discoverable": true,
+            "endpoints": { "sharedInbox": self.inbox() },
+            "publicKey": {
+                "id": self.key_id(),
+                "owner": actor_id,
+                "publicKeyPem": public_key_pem,
+            },
+        })
+    }
+
+    /// A public note as an `Article` or `Note`, as configured.
+    pub fn object(&self, note: &Note, short_name: &str) -> Value {
+        let link = self.link(short_name);
+        let published = note.published_at.unwrap_or(note.created_at);
+
+        let mut object = json!({
+            "id": self.object_id(&note.id),
+            "attributedTo": self.actor_id(),
+            "url": link,
+            "published": timestamp(&published),
+            "to": [PUBLIC],
+            "cc": [self.followers()],
+        });
+        if note.updated_at > published {
+            object["updated"] = json!(timestamp(&note.updated_at));
+        }
+
+        match self.object_type {
+            ObjectType::Article => {
+                object["type"] = json!("Article");
+                object["name"] = json!(note.title);
+                object["summary"] = json!(escape_html(&note.summary));
+                object["content"] = json!(note.html());
+                if let Some(fancy_img) = &note.fancy_img {
+                    object["image"] = json!({ "type": "Image", "url": fancy_img });
+                }
+            }
+            ObjectType::Note => {
+                let link = escape_html(&link);
+                object["type"] = json!("Note");
+                object["content"] = json!(format!(
+                    "<p><strong>{}</strong></p><p>{}</p><p><a href=\"{}\">{}</a></p>",
+                    escape_html(&note.title),
+                    escape_html(&note.summary),
+                    link,
+                    link
+                ));
+            }
+        }
+
+        object
+    }
+
+    /// `Create` or `Update` of an object, addressed like it.
+    pub fn wrap(&self, kind: &str, object: Value) -> Value {
+        let id = match kind {
+            "Create" => format!("{}#create", object["id"].as_str().unwrap_or_default()),
+            _ => format!(
+                "{}#{}-{}",
+                object["id"].as_str().unwrap_or_default(),
+                kind.to_lowercase(),
+                Utc::now().timestamp()
+            ),
+        };
+
+        json!({
+            "@context": CONTEXT,
+            "id": id,
+            "type": kind,
+            "actor": self.actor_id(),
+            "published": object.get("updated").or(object.get("published")),
+            "to": object["to"],
+            "cc": object["cc"],
+            "object": object,
+        })
+    }
+
+    pub fn delete(&self, note_id: &Uuid) -> Value {
+        let object_id = self.object_id(note_id);
+        json!({
+            "@context": CONTEXT,
+            "id": format!("{}#delete", object_id),
+            "type": "Delete",
+            "actor": self.actor_id(),
+            "to": [PUBLIC],
+            "cc": [self.followers()],
+            "object": { "id": object_id, "type": "Tombstone" },
+        })
+    }
+
+    pub fn accept(&self, follow: &Value) -> Value {
+        json!({
+            "@context": CONTEXT,
+            "id": format!("{}#accepts/{}", self.actor_id(), Uuid::new_v4()),
+            "type": "Accept",
+            "actor": self.actor_id(),
+            "object": follow,
+        })
+    }
+}
+
+/// ActivityStreams timestamp of a UTC time.
+pub fn timestamp(time: &NaiveDateTime) -> String {
+    time.and_utc().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// ID of a value that is either a link or an object.
+pub fn id_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(id) => Some(id),
+        Value::Object(object) => object.get("id")?.as_str(),
+        _ => None,
+    }
+}
+
+/// First `href` of a value that is a link, an object with one, or a list of
+/// those.
+fn href_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(href) => Some(href),
+        Value::Object(object) => object.get("href")?.as_str(),
+        Value::Array(values) => values.iter().find_map(href_of),
+        _ => None,
+    }
+}
+
+/// The actor document fetched from `from` as far as we keep it. `None`
+/// without a key, or when the actor or its key lives on another origin than
+/// the one that served it.
+pub fn parse_actor(value: &Value, from: &str) -> Option<ApActor> {
+    let id = value["id"].as_str()?;
+    let key = &value["publicKey"];
+    let key = match key {
+        Value::Array(keys) => keys.first()?,
+        key => key,
+    };
+    if key["owner"].as_str() != Some(id) {
+        return None;
+    }
+    let key_id = key["id"].as_str()?;
+    if !same_origin(id, from) || !same_origin(key_id, from) {
+        return None;
+    }
+
+    Some(ApActor {
+        id: id.to_string(),
+        key_id: key_id.to_string(),
+        public_key: key["publicKeyPem"].as_str()?.to_string(),
+        inbox: value["inbox"].as_str()?.to_string(),
+        shared_inbox: value["endpoints"]["sharedInbox"]
+            .as_str()
+            .map(str::to_string),
+        username: value["preferredUsername"]
+            .as_str()
+            .map(|s| s.chars().take(256).collect()),
+        name: value["name"]
+            .as_str()
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().chars().take(256).collect()),
+        url: href_of(&value["url"])
+            .filter(|url| url.len() <= 2048)
+            .map(str::to_string),
+    })
+}
+
+pub fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+/// Plain text of a reply's HTML content, with paragraphs on their own lines
+/// and mentions of `skip` (our actor and its pages) left out.
+pub fn reply_text(content: &str, skip: &[&str]) -> String {
+    let fragment = Html::parse_fragment(content);
+    let mut text = String::new();
+    push_text(fragment.root_element(), skip, &mut text);
+
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if !line.is_empty() || lines.last().is_some_and(|last| !last.is_empty()) {
+            lines.push(line);
+        }
+    }
+    lines.join("\n").trim().to_string()
+}
+
+fn push_text(element: ElementRef, skip: &[&str], text: &mut String) {
+    for node in element.children() {
+        if let Node::Text(t) = node.value() {
+            text.push_str(t);
+        }
+        let Some(child) = ElementRef::wrap(node) else {
+            continue;
+        };
+
+        let value = child.value();
+        let href = value.attr("href").unwrap_or_default().trim_end_matches('/');
+        let mention = value.name() == "a"
+            && value.has_class("mention", CaseSensitivity::AsciiCaseInsensitive)
+            && skip.iter().any(|s| s.trim_end_matches('/') == href);
+        if mention {
+            continue;
+        }
+
+        match value.name() {
+            "br" => text.push('\n'),
+            "p" | "div" | "blockquote" | "li" | "pre" => {
+                push_text(child, skip, text);
+                text.push_str("\n\n");
+            }
+            _ => push_text(child, skip, text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::db::models::notes::Status;
+
+    fn site(object_type: ObjectType) -> Site {
+        Site {
+            base: String::from("https://api.example.com"),
+            site_url: String::from("https://example.com"),
+            username: String::from("blog"),
+            object_type,
+        }
+    }
+
+    fn note() -> Note {
+        let time = |secs| DateTime::from_timestamp(secs, 0).unwrap().naive_utc();
+        Note {
+            id: Uuid::new_v4(),
+            title: String::from("Cats & dogs"),
+            status: Status::Public,
+            summary: String::from("On <cats>"),
+            content: String::from("# Cats\n\nThey **purr**."),
+            views: 0,
+            comm: true,
+            user_id: Uuid::new_v4(),
+            short_id: Uuid::new_v4(),
+            fancy_img: None,
+            created_at: time(1_700_000_000),
+            updated_at: time(1_700_000_600),
+            published_at: Some(time(1_700_000_000)),
+            content_html: None,
+            publish_at: None,
+            unpublish_at: None,
+            recycled_from: None,
+        }
+    }
+
+    #[test]
+    fn test_webfinger() {
+        let site = site(ObjectType::Article);
+        assert_eq!(site.domain(), "api.example.com");
+
+        let jrd = site.webfinger("acct:blog@api.example.com").unwrap();
+        assert_eq!(jrd["subject"], "acct:blog@api.example.com");
+        assert_eq!(jrd["links"][0]["href"], "https://api.example.com/ap/actor");
+        assert!(site.webfinger("https://api.example.com/ap/actor").is_some());
+        assert!(site.webfinger("acct:neko@api.example.com").is_none());
+        assert!(site.webfinger("acct:blog@example.org").is_none());
+    }
+
+    #[test]
+    fn test_object() {
+        let note = note();
+        let site = site(ObjectType::Article);
+        assert_eq!(site.note_id(&site.object_id(&note.id)), Some(note.id));
+        assert_eq!(site.note_id("https://example.org/ap/notes/x"), None);
+
+        let article = site.object(&note, "cats");
+        assert_eq!(article["type"], "Article");
+        assert_eq!(article["name"], "Cats & dogs");
+        assert_eq!(article["summary"], "On &lt;cats&gt;");
+        assert_eq!(
+            article["content"],
+            "<h1>Cats</h1>\n<p>They <strong>purr</strong>.</p>\n"
+        );
+        assert_eq!(article["url"], "https://example.com/cats");
+        assert_eq!(article["published"], "2023-11-14T22:13:20Z");
+        assert_eq!(article["updated"], "2023-11-14T22:23:20Z");
+        assert_eq!(article["to"][0], PUBLIC);
+        assert_eq!(article["cc"][0], "https://api.example.com/ap/followers");
+
+        let status = site_note(&note);
+        assert_eq!(status["type"], "Note");
+        assert_eq!(
+            status["content"],
+            "<p><strong>Cats &amp; dogs</strong></p><p>On &lt;cats&gt;</p>\
+             <p><a href=\"https://example.com/cats\">https://example.com/cats</a></p>"
+        );
+
+        let create = site.wrap("Create", article.clone());
+        assert_eq!(
+            create["id"],
+            format!("{}#create", article["id"].as_str().unwrap())
+        );
+        assert_eq!(create["actor"], "https://api.example.com/ap/actor");
+        assert_eq!(create["object"], article);
+        let update = site.wrap("Update", article);
+        assert_ne!(update["id"], create["id"]);
+        assert_eq!(update["published"], "2023-11-14T22:23:20Z");
+
+        let delete = site.delete(&note.id);
+        assert_eq!(delete["object"]["id"], site.object_id(&note.id));
+        assert_eq!(delete["object"]["type"], "Tombstone");
+    }
+
+    fn site_note(note: &Note) -> Value {
+        site(ObjectType::Note).object(note, "cats")
+    }
+
+    #[test]
+    fn test_parse_actor() {
+        let document = json!({
+            "id": "https://example.org/users/neko",
+            "type": "Person",
+            "preferredUsername": "neko",
+            "name": " Neko ",
+            "url": "https://example.org/@neko",
+            "inbox": "https://example.org/users/neko/inbox",
+            "endpoints": { "sharedInbox": "https://example.org/inbox" },
+            "publicKey": {
+                "id": "https://example.org/users/neko#main-key",
+                "owner": "https://example.org/users/neko",
+                "publicKeyPem": "-----BEGIN PUBLIC KEY-----"
+            }
+        });
+
+        let from = "https://example.org/users/neko";
+        let actor = parse_actor(&document, from).unwrap();
+        assert_eq!(
+            actor,
+            ApActor {
+                id: String::from("https://example.org/users/neko"),
+                key_id: String::from("https://example.org/users/neko#main-key"),
+                public_key: String::from("-----BEGIN PUBLIC KEY-----"),
+                inbox: String::from("https://example.org/users/neko/inbox"),
+                shared_inbox: Some(String::from("https://example.org/inbox")),
+                username: Some(String::from("neko")),
+                name: Some(String::from("Neko")),
+                url: Some(String::from("https://example.org/@neko")),
+            }
+        );
+
+        let mut stolen = document.clone();
+        stolen["publicKey"]["owner"] = json!("https://example.org/users/other");
+        assert!(parse_actor(&stolen, from).is_none());
+        let mut keyless = document.clone();
+        keyless.as_object_mut().unwrap().remove("publicKey");
+        assert!(parse_actor(&keyless, from).is_none());
+
+        // another server cannot speak for a known actor, nor lend it a key
+        assert!(parse_actor(&document, "https://evil.example/users/neko").is_none());
+        let mut foreign_key = document;
+        foreign_key["publicKey"]["id"] = json!("https://evil.example/keys/neko");
+        assert!(parse_actor(&foreign_key, from).is_none());
+        assert!(!ApActor {
+            key_id: String::from("https://evil.example/keys/neko"),
+            ..actor.clone()
+        }
+        .owns_key());
+        assert!(actor.owns_key());
+    }
+
+    #[test]
+    fn test_reply_text() {
+        let content = r#"<p><span class="h-card"><a href="https://api.example.com/ap/actor" class="u-url mention">@<span>blog</span></a></span> Nice post!</p><p>Ask <a href="https://example.org/@cat" class="u-url mention">@<span>cat</span></a> too.<br>Bye<br/><br/><br/>now</p>"#;
+
+        assert_eq!(
+            reply_text(content, &["https://api.example.com/ap/actor"]),
+            "Nice post!\n\nAsk @cat too.\nBye\n\nnow"
+        );
+        assert_eq!(reply_text("<p> </p>", &[]), "");
+        assert_eq!(reply_text("plain &amp; simple", &[]), "plain & simple");
+    }
+}
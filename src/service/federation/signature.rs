@@ -0,0 +1,272 @@
+use std::time::SystemTime;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use reqwest::{Method, Url};
+use rsa::{
+    pkcs1::DecodeRsaPublicKey,
+    pkcs1v15::{Signature, SigningKey, VerifyingKey},
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding},
+    signature::{SignatureEncoding, Signer, Verifier},
+    RsaPrivateKey, RsaPublicKey,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error::BlogError;
+
+/// How far the `Date` of a signed request may be off from ours.
+pub const MAX_SKEW_SECS: u64 = 60 * 60;
+
+/// A fresh key pair as PKCS#8 and SPKI PEM.
+pub fn generate_key(bits: usize) -> Result<(String, String), BlogError> {
+    let key = RsaPrivateKey::new(&mut rand::thread_rng(), bits).map_err(|e| anyhow::anyhow!(e))?;
+    let private_pem = key
+        .to_pkcs8_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!(e))?;
+    let public_pem = key
+        .to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    Ok((private_pem.to_string(), public_pem))
+}
+
+pub fn parse_private_key(pem: &str) -> Result<RsaPrivateKey, BlogError> {
+    Ok(RsaPrivateKey::from_pkcs8_pem(pem).map_err(|e| anyhow::anyhow!(e))?)
+}
+
+/// Reads SPKI (`BEGIN PUBLIC KEY`) as well as PKCS#1 (`BEGIN RSA PUBLIC KEY`)
+/// keys, both are found in the wild.
+pub fn parse_public_key(pem: &str) -> Option<RsaPublicKey> {
+    let pem = pem.trim();
+    RsaPublicKey::from_public_key_pem(pem)
+        .ok()
+        .or_else(|| RsaPublicKey::from_pkcs1_pem(pem).ok())
+}
+
+/// Value of the `Digest` header for `body`.
+pub fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", STANDARD.encode(Sha256::digest(body)))
+}
+
+/// The `Signature` header of a request, as in draft-cavage-http-signatures.
+#[derive(Debug, PartialEq)]
+pub struct SignatureHeader {
+    pub key_id: String,
+    /// Headers signed, in order, lowercase
+    pub headers: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+impl SignatureHeader {
+    /// `None` for anything but RSA with SHA-256 signatures.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut key_id = None;
+        let mut algorithm = None;
+        let mut headers = None;
+        let mut signature = None;
+
+        let mut rest = value.trim();
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once('=')?;
+            let after = after.strip_prefix('"')?;
+            let (param, after) = after.split_once('"')?;
+            match name.trim() {
+                "keyId" => key_id = Some(param),
+                "algorithm" => algorithm = Some(param),
+                "headers" => headers = Some(param),
+                "signature" => signature = Some(param),
+                _ => {}
+            }
+            rest = after.trim_start().trim_start_matches(',').trim_start();
+        }
+
+        // hs2019 leaves the algorithm to the key, which is RSA for all we take
+        if !matches!(algorithm, None | Some("rsa-sha256") | Some("hs2019")) {
+            return None;
+        }
+
+        Some(Self {
+            key_id: key_id?.to_string(),
+            headers: headers
+                .unwrap_or("date")
+                .split_whitespace()
+                .map(str::to_lowercase)
+                .collect(),
+            signature: STANDARD.decode(signature?).ok()?,
+        })
+    }
+}
+
+/// What gets signed: each of `names` with its value, one per line. `None` if
+/// a header is missing.
+pub fn signing_string<'a>(
+    names: &[String],
+    method: &Method,
+    path: &str,
+    header: impl Fn(&str) -> Option<&'a str>,
+) -> Option<String> {
+    names
+        .iter()
+        .map(|name| match name.as_str() {
+            "(request-target)" => Some(format!(
+                "(request-target): {} {}",
+                method.as_str().to_lowercase(),
+                path
+            )),
+            name => header(name).map(|value| format!("{}: {}", name, value.trim())),
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|lines| lines.join("\n"))
+}
+
+pub fn verify(key: &RsaPublicKey, signing_string: &str, signature: &[u8]) -> bool {
+    let Ok(signature) = Signature::try_from(signature) else {
+        return false;
+    };
+
+    VerifyingKey::<Sha256>::new(key.clone())
+        .verify(signing_string.as_bytes(), &signature)
+        .is_ok()
+}
+
+/// Headers signing a request to `url` as `key_id`: `Date`, `Digest` if it has
+/// a body, and `Signature`. `Host` is signed as the client will send it.
+pub fn sign(
+    key: &RsaPrivateKey,
+    key_id: &str,
+    method: &Method,
+    url: &Url,
+    body: Option<&[u8]>,
+    now: SystemTime,
+) -> Vec<(&'static str, String)> {
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    let date = httpdate::fmt_http_date(now);
+    let digest = body.map(digest);
+
+    let mut headers = vec![("host", host), ("date", date)];
+    if let Some(digest) = digest {
+        headers.push(("digest", digest));
+    }
+
+    let names: Vec<String> = std::iter::once("(request-target)")
+        .chain(headers.iter().map(|(name, _)| *name))
+        .map(str::to_string)
+        .collect();
+    let signing_string = signing_string(&names, method, &path, |name| {
+        headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    })
+    .unwrap_or_default();
+    let signature = SigningKey::<Sha256>::new(key.clone()).sign(signing_string.as_bytes());
+
+    headers.remove(0);
+    headers.push((
+        "signature",
+        format!(
+            r#"keyId="{}",algorithm="rsa-sha256",headers="{}",signature="{}""#,
+            key_id,
+            names.join(" "),
+            STANDARD.encode(signature.to_bytes())
+        ),
+    ));
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let header = SignatureHeader::parse(
+            r#"keyId="https://example.org/users/neko#main-key",algorithm="rsa-sha256",headers="(request-target) Host date digest",signature="bmVrbw==""#,
+        )
+        .unwrap();
+        assert_eq!(header.key_id, "https://example.org/users/neko#main-key");
+        assert_eq!(
+            header.headers,
+            vec!["(request-target)", "host", "date", "digest"]
+        );
+        assert_eq!(header.signature, b"neko");
+
+        let header = SignatureHeader::parse(r#"keyId="k", signature="bmVrbw==""#).unwrap();
+        assert_eq!(header.headers, vec!["date"]);
+
+        assert!(SignatureHeader::parse(
+            r#"keyId="k",algorithm="hmac-sha256",signature="bmVrbw==""#
+        )
+        .is_none());
+        assert!(SignatureHeader::parse(r#"keyId="k""#).is_none());
+        assert!(SignatureHeader::parse("nonsense").is_none());
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (private_pem, public_pem) = generate_key(1024).unwrap();
+        let key = parse_private_key(&private_pem).unwrap();
+        let public_key = parse_public_key(&public_pem).unwrap();
+
+        let url = Url::parse("http://127.0.0.1:8080/ap/inbox?x=1").unwrap();
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let headers = sign(
+            &key,
+            "https://example.com/ap/actor#main-key",
+            &Method::POST,
+            &url,
+            Some(b"{}"),
+            now,
+        );
+        // the client adds Host itself
+        let header = |name: &str| match name {
+            "host" => Some("127.0.0.1:8080"),
+            name => headers
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| value.as_str()),
+        };
+        assert!(!headers.iter().any(|(name, _)| *name == "host"));
+        assert_eq!(header("date"), Some("Tue, 14 Nov 2023 22:13:20 GMT"));
+        assert_eq!(header("digest"), Some(digest(b"{}").as_str()));
+
+        let parsed = SignatureHeader::parse(header("signature").unwrap()).unwrap();
+        assert_eq!(parsed.key_id, "https://example.com/ap/actor#main-key");
+        assert_eq!(
+            parsed.headers,
+            vec!["(request-target)", "host", "date", "digest"]
+        );
+
+        let signed =
+            signing_string(&parsed.headers, &Method::POST, "/ap/inbox?x=1", header).unwrap();
+        assert!(signed.starts_with("(request-target): post /ap/inbox?x=1\nhost: 127.0.0.1:8080\n"));
+        assert!(verify(&public_key, &signed, &parsed.signature));
+
+        let forged =
+            signing_string(&parsed.headers, &Method::POST, "/ap/outbox?x=1", header).unwrap();
+        assert!(!verify(&public_key, &forged, &parsed.signature));
+        assert!(signing_string(&parsed.headers, &Method::POST, "/", |_| None).is_none());
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let (_, public_pem) = generate_key(1024).unwrap();
+        let key = parse_public_key(&public_pem).unwrap();
+
+        let pkcs1 = rsa::pkcs1::EncodeRsaPublicKey::to_pkcs1_pem(&key, LineEnding::LF).unwrap();
+        assert_eq!(parse_public_key(&pkcs1), Some(key));
+        assert!(
+            parse_public_key("-----BEGIN PUBLIC KEY-----\nneko\n-----END PUBLIC KEY-----")
+                .is_none()
+        );
+    }
+}
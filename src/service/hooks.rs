@@ -0,0 +1,267 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use reqwest::Client;
+use serde_json::Value;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{webhook_deliveries::WebhookDelivery, webhook_endpoints::WebhookEndpoint},
+        DbPool,
+    },
+    error::BlogError,
+    service::outbox::backoff,
+    utils::{sign::sign_with, SHUTDOWN},
+};
+
+/// Deliveries taken per round.
+const BATCH_SIZE: i64 = 50;
+/// How long a claimed delivery stays hidden from other workers.
+const LEASE_SECS: i64 = 5 * 60;
+/// Response bodies kept as the error of a failed delivery, in chars.
+const ERROR_CHARS: usize = 512;
+
+/// How one attempt at a delivery went.
+pub enum Outcome {
+    Delivered(i32),
+    Failed(Option<i32>, String),
+}
+
+pub fn client() -> Result<Client, BlogError> {
+    Ok(Client::builder().timeout(Duration::from_secs(10)).build()?)
+}
+
+/// Value of `X-Blog-Signature`: HMAC-SHA256 of `"{timestamp}.{body}"` with
+/// the endpoint secret, so a replayed body needs a fresh signature too.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    format!(
+        "sha256={}",
+        sign_with(secret.as_bytes(), &format!("{}.{}", timestamp, body))
+    )
+}
+
+/// Posts `payload` to the endpoint. Anything but a 2xx is a failure.
+pub async fn send(
+    client: &Client,
+    endpoint: &WebhookEndpoint,
+    delivery_id: &Uuid,
+    event: &str,
+    payload: &Value,
+) -> Outcome {
+    let body = payload.to_string();
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(&endpoint.url)
+        .header("Content-Type", "application/json")
+        .header("X-Blog-Event", event)
+        .header("X-Blog-Delivery", delivery_id.to_string())
+        .header("X-Blog-Timestamp", timestamp.to_string())
+        .header(
+            "X-Blog-Signature",
+            signature(&endpoint.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await;
+
+    match response {
+        Ok(response) => {
+            let status = response.status();
+            if status.is_success() {
+                Outcome::Delivered(status.as_u16() as i32)
+            } else {
+                let text = response.text().await.unwrap_or_default();
+                let error = format!(
+                    "{}: {}",
+                    status,
+                    text.chars().take(ERROR_CHARS).collect::<String>()
+                );
+                Outcome::Failed(Some(status.as_u16() as i32), error)
+            }
+        }
+        Err(e) => Outcome::Failed(None, e.to_string()),
+    }
+}
+
+/// Delivers queued content webhooks until `SHUTDOWN` fires, retrying failed
+/// ones with the same backoff as notifications.
+pub async fn run(pool: DbPool) {
+    let client = match client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("webhook worker not started: {}", e);
+            return;
+        }
+    };
+    let poll_interval = Duration::from_secs(CONFIG.notify.poll_interval());
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+
+        if let Err(e) = deliver_due(&pool, &client).await {
+            error!("webhook round failed: {}", e);
+        }
+    }
+
+    info!("webhook worker stopped");
+}
+
+async fn deliver_due(pool: &DbPool, client: &Client) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let deliveries = WebhookDelivery::claim_due(BATCH_SIZE, LEASE_SECS, &mut conn).await?;
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    let mut endpoints = Vec::with_capacity(deliveries.len());
+    for delivery in &deliveries {
+        endpoints.push(WebhookEndpoint::find_by_uuid(&delivery.endpoint_id, &mut conn).await?);
+    }
+
+    let outcomes = join_all(deliveries.iter().zip(&endpoints).map(
+        |(delivery, endpoint)| async move {
+            match endpoint {
+                Some(endpoint) if endpoint.active => {
+                    send(
+                        client,
+                        endpoint,
+                        &delivery.id,
+                        &delivery.event,
+                        &delivery.payload,
+                    )
+                    .await
+                }
+                _ => Outcome::Failed(None, String::from("endpoint is gone or inactive")),
+            }
+        },
+    ))
+    .await;
+
+    let max_attempts = CONFIG.notify.max_attempts();
+    let retry_base = Duration::from_secs(CONFIG.notify.retry_base());
+    for (delivery, outcome) in deliveries.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Delivered(status) => {
+                WebhookDelivery::mark_delivered(&delivery.id, status, &mut conn).await?
+            }
+            Outcome::Failed(status, e) => {
+                let attempts = delivery.attempts + 1;
+                let retry_at = (attempts < max_attempts).then(|| {
+                    let delay = backoff(attempts, retry_base);
+                    Utc::now().naive_utc() + chrono::Duration::from_std(delay).unwrap_or_default()
+                });
+                if retry_at.is_none() {
+                    warn!(
+                        "webhook delivery {} of {} is dead after {} attempts: {}",
+                        delivery.id, delivery.event, attempts, e
+                    );
+                }
+
+                WebhookDelivery::mark_failed(&delivery.id, status, &e, retry_at, &mut conn).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{http::HeaderMap, routing::post, Router};
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::utils::sign::verify_with;
+
+    fn endpoint(url: String) -> WebhookEndpoint {
+        WebhookEndpoint {
+            id: Uuid::new_v4(),
+            url,
+            secret: String::from("nekonya"),
+            events: Vec::new(),
+            active: true,
+            created_at: Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn test_signature() {
+        let signature = signature("nekonya", 1700000000, r#"{"event":"note.created"}"#);
+        let hex = signature.strip_prefix("sha256=").unwrap();
+
+        assert!(verify_with(
+            b"nekonya",
+            r#"1700000000.{"event":"note.created"}"#,
+            hex
+        ));
+        assert!(!verify_with(
+            b"nekonya",
+            r#"1700000001.{"event":"note.created"}"#,
+            hex
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/ok",
+                post(move |headers: HeaderMap, body: String| async move {
+                    tx.send((headers, body)).unwrap();
+                    "ok"
+                }),
+            )
+            .route(
+                "/down",
+                post(|| async { (axum::http::StatusCode::BAD_GATEWAY, "down") }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = client().unwrap();
+        let id = Uuid::new_v4();
+        let payload = json!({ "event": "ping" });
+
+        let ok = endpoint(format!("http://{}/ok", addr));
+        assert!(matches!(
+            send(&client, &ok, &id, "ping", &payload).await,
+            Outcome::Delivered(200)
+        ));
+
+        let (headers, body) = rx.recv().await.unwrap();
+        assert_eq!(body, payload.to_string());
+        assert_eq!(headers["x-blog-event"], "ping");
+        assert_eq!(headers["x-blog-delivery"], id.to_string().as_str());
+        let timestamp: i64 = headers["x-blog-timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            headers["x-blog-signature"],
+            signature("nekonya", timestamp, &body).as_str()
+        );
+
+        let down = endpoint(format!("http://{}/down", addr));
+        match send(&client, &down, &id, "ping", &payload).await {
+            Outcome::Failed(status, error) => {
+                assert_eq!(status, Some(502));
+                assert!(error.ends_with("down"));
+            }
+            Outcome::Delivered(_) => panic!("a 502 is not delivered"),
+        }
+    }
+}
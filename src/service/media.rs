@@ -0,0 +1,121 @@
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use crate::{config::CONFIG, error::BlogError};
+
+/// Kinds of files taken as uploads, by extension. Anything a browser would
+/// run, such as HTML or SVG, is left out.
+const TYPES: &[(&str, &str)] = &[
+    ("jpg", "image/jpeg"),
+    ("png", "image/png"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("pdf", "application/pdf"),
+];
+
+/// Extension an upload is stored with, from its declared type or else from
+/// its file name.
+pub fn extension(content_type: Option<&str>, file_name: Option<&str>) -> Option<&'static str> {
+    let by_type = content_type.and_then(|content_type| {
+        let essence = content_type.split(';').next()?.trim();
+        TYPES
+            .iter()
+            .find(|(_, mime)| mime.eq_ignore_ascii_case(essence))
+    });
+    let by_name = || {
+        let (_, ext) = file_name?.rsplit_once('.')?;
+        let ext = ext.to_ascii_lowercase();
+        let ext = if ext == "jpeg" { "jpg" } else { &ext };
+        TYPES.iter().find(|(known, _)| *known == ext)
+    };
+
+    by_type.or_else(by_name).map(|(ext, _)| *ext)
+}
+
+/// Type a stored file is served as, `None` for names that are not ones we
+/// gave out.
+pub fn content_type(name: &str) -> Option<&'static str> {
+    let (stem, ext) = name.split_once('.')?;
+    Uuid::parse_str(stem).ok()?;
+
+    TYPES
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+pub fn url(name: &str) -> String {
+    format!("{}/api/media/{}", CONFIG.site_url(), name)
+}
+
+/// Keeps an upload under a new name and returns its public URL.
+pub async fn store(
+    data: &[u8],
+    content_type: Option<&str>,
+    file_name: Option<&str>,
+) -> Result<String, BlogError> {
+    if data.is_empty() {
+        return Err(BlogError::BadRequest(String::from("empty file")));
+    }
+    if data.len() > CONFIG.media.max_size() {
+        return Err(BlogError::BadRequest(String::from("file too large")));
+    }
+    let ext = extension(content_type, file_name)
+        .ok_or(BlogError::BadRequest(String::from("unsupported file type")))?;
+
+    let dir = PathBuf::from(CONFIG.media.dir());
+    tokio::fs::create_dir_all(&dir).await?;
+
+    let name = format!("{}.{}", Uuid::new_v4(), ext);
+    tokio::fs::write(dir.join(&name), data).await?;
+
+    Ok(url(&name))
+}
+
+/// A stored file along with its type.
+pub async fn load(name: &str) -> Result<Option<(&'static str, Vec<u8>)>, BlogError> {
+    let Some(content_type) = content_type(name) else {
+        return Ok(None);
+    };
+
+    match tokio::fs::read(PathBuf::from(CONFIG.media.dir()).join(name)).await {
+        Ok(data) => Ok(Some((content_type, data))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension() {
+        assert_eq!(extension(Some("image/png"), Some("cat.jpg")), Some("png"));
+        assert_eq!(extension(Some("IMAGE/JPEG; q=1"), None), Some("jpg"));
+        assert_eq!(
+            extension(Some("application/octet-stream"), Some("Cat.JPEG")),
+            Some("jpg")
+        );
+        assert_eq!(extension(None, Some("clip.webm")), Some("webm"));
+        assert_eq!(extension(Some("image/svg+xml"), Some("cat.svg")), None);
+        assert_eq!(extension(Some("text/html"), Some("index.html")), None);
+        assert_eq!(extension(None, Some("cat")), None);
+    }
+
+    #[test]
+    fn test_content_type() {
+        let name = format!("{}.png", Uuid::new_v4());
+        assert_eq!(content_type(&name), Some("image/png"));
+        assert_eq!(content_type("../config.toml"), None);
+        assert_eq!(content_type("cat.png"), None);
+        assert_eq!(content_type(&format!("{}.html", Uuid::new_v4())), None);
+        assert_eq!(content_type(&format!("{}/../x.png", Uuid::new_v4())), None);
+    }
+}
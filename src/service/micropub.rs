@@ -0,0 +1,556 @@
+use std::collections::BTreeMap;
+
+use axum::http::{header::AUTHORIZATION, HeaderMap};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::models::notes::{Note, Status},
+    utils::is_custom_summary,
+};
+
+/// What publishing tokens may be allowed to do. `draft` lets a client create
+/// drafts only.
+pub const SCOPES: &[&str] = &["create", "draft", "update", "delete", "media"];
+
+/// Characters of the content a title is made of when the entry has no name.
+const TITLE_CHARS: usize = 64;
+
+/// Microformats properties, each with its list of values.
+pub type Properties = BTreeMap<String, Vec<Value>>;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Update {
+    pub replace: Properties,
+    pub add: Properties,
+    /// Properties removed altogether
+    pub delete: Vec<String>,
+    /// Single values removed from properties
+    pub delete_values: Properties,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Create(Properties),
+    Update { url: String, update: Update },
+    Delete(String),
+    Undelete(String),
+}
+
+/// A Micropub entry as a note.
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub title: String,
+    pub content: String,
+    pub categories: Vec<String>,
+    pub status: Status,
+    /// Written by hand, otherwise taken from the content
+    pub summary: Option<String>,
+    pub slug: Option<String>,
+    pub featured: Option<String>,
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Token given as `Authorization: Bearer <token>`.
+pub fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Reads a form-encoded request, along with the token it carries if any.
+pub fn parse_form(pairs: Vec<(String, String)>) -> Result<(Request, Option<String>), String> {
+    let mut properties = Properties::new();
+    let mut access_token = None;
+    let mut action = None;
+    let mut url = None;
+    let mut kind = None;
+
+    for (key, value) in pairs {
+        match key.as_str() {
+            "access_token" => access_token = Some(value),
+            "action" => action = Some(value),
+            "url" => url = Some(value),
+            "h" => kind = Some(value),
+            _ => {
+                let key = key.strip_suffix("[]").unwrap_or(&key);
+                if !key.is_empty() {
+                    properties
+                        .entry(key.to_string())
+                        .or_default()
+                        .push(Value::String(value));
+                }
+            }
+        }
+    }
+
+    let request = match action.as_deref() {
+        None => {
+            if kind.as_deref().is_some_and(|kind| kind != "entry") {
+                return Err(String::from("only h=entry is supported"));
+            }
+            if let Some(url) = url {
+                properties.insert(String::from("url"), vec![Value::String(url)]);
+            }
+            Request::Create(properties)
+        }
+        Some("delete") => Request::Delete(url.ok_or("url is required")?),
+        Some("undelete") => Request::Undelete(url.ok_or("url is required")?),
+        Some("update") => return Err(String::from("updates are sent as JSON")),
+        Some(action) => return Err(format!("unknown action {}", action)),
+    };
+
+    Ok((request, access_token))
+}
+
+/// Reads a JSON request.
+pub fn parse_json(body: &Value) -> Result<Request, String> {
+    let url = || {
+        body["url"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| String::from("url is required"))
+    };
+
+    match body["action"].as_str() {
+        None => {
+            let kind = match &body["type"] {
+                Value::Array(kinds) => kinds.first().and_then(Value::as_str),
+                kind => kind.as_str(),
+            };
+            if kind != Some("h-entry") {
+                return Err(String::from("only h-entry is supported"));
+            }
+            Ok(Request::Create(properties(&body["properties"])?))
+        }
+        Some("update") => {
+            let mut update = Update {
+                replace: properties(&body["replace"])?,
+                add: properties(&body["add"])?,
+                ..Default::default()
+            };
+            match &body["delete"] {
+                Value::Null => {}
+                Value::Array(names) => {
+                    update.delete = names
+                        .iter()
+                        .map(|name| name.as_str().map(str::to_string))
+                        .collect::<Option<_>>()
+                        .ok_or("delete takes property names")?;
+                }
+                values => update.delete_values = properties(values)?,
+            }
+            Ok(Request::Update {
+                url: url()?,
+                update,
+            })
+        }
+        Some("delete") => Ok(Request::Delete(url()?)),
+        Some("undelete") => Ok(Request::Undelete(url()?)),
+        Some(action) => Err(format!("unknown action {}", action)),
+    }
+}
+
+/// Properties out of a JSON object, single values taken as lists of one.
+fn properties(value: &Value) -> Result<Properties, String> {
+    match value {
+        Value::Null => Ok(Properties::new()),
+        Value::Object(object) => Ok(object
+            .iter()
+            .map(|(key, value)| {
+                let values = match value {
+                    Value::Array(values) => values.clone(),
+                    value => vec![value.clone()],
+                };
+                (key.clone(), values)
+            })
+            .collect()),
+        _ => Err(String::from("properties must be an object")),
+    }
+}
+
+impl Update {
+    pub fn apply(&self, properties: &mut Properties) {
+        for (key, values) in &self.replace {
+            properties.insert(key.clone(), values.clone());
+        }
+        for (key, values) in &self.add {
+            properties
+                .entry(key.clone())
+                .or_default()
+                .extend(values.iter().cloned());
+        }
+        for key in &self.delete {
+            properties.remove(key);
+        }
+        for (key, removed) in &self.delete_values {
+            if let Some(values) = properties.get_mut(key) {
+                values.retain(|value| !removed.contains(value));
+            }
+        }
+    }
+
+    /// Whether the update touches the property at all.
+    pub fn touches(&self, key: &str) -> bool {
+        self.replace.contains_key(key)
+            || self.add.contains_key(key)
+            || self.delete.iter().any(|name| name == key)
+            || self.delete_values.contains_key(key)
+    }
+}
+
+/// Plain text of a value: strings as they are, `{"html": ..}` and
+/// `{"value": ..}` objects by what they hold.
+fn text(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(text) => Some(text),
+        Value::Object(object) => object
+            .get("html")
+            .or_else(|| object.get("value"))
+            .and_then(Value::as_str),
+        _ => None,
+    }
+}
+
+fn first_text<'a>(properties: &'a Properties, key: &str) -> Option<&'a str> {
+    properties
+        .get(key)?
+        .iter()
+        .filter_map(text)
+        .map(str::trim)
+        .find(|text| !text.is_empty())
+}
+
+impl Entry {
+    pub fn from_properties(properties: &Properties) -> Result<Self, String> {
+        let text_content = first_text(properties, "content").unwrap_or_default();
+
+        let mut content = text_content.to_string();
+        for photo in properties.get("photo").into_iter().flatten() {
+            let Some(url) = text(photo) else {
+                continue;
+            };
+            let alt = photo["alt"].as_str().unwrap_or_default();
+            if !content.is_empty() {
+                content.push_str("\n\n");
+            }
+            content.push_str(&format!("![{}]({})", alt, url));
+        }
+        for key in ["video", "audio"] {
+            for url in properties.get(key).into_iter().flatten().filter_map(text) {
+                if !content.is_empty() {
+                    content.push_str("\n\n");
+                }
+                content.push_str(&format!("<{}>", url));
+            }
+        }
+
+        let title = match first_text(properties, "name") {
+            Some(name) => name.to_string(),
+            None => {
+                let line = text_content.lines().next().unwrap_or_default();
+                let mut title: String = line.chars().take(TITLE_CHARS).collect();
+                if title.len() < line.len() {
+                    title.push_str("...");
+                }
+                title
+            }
+        };
+        if title.is_empty() && content.is_empty() {
+            return Err(String::from("entry has no name nor content"));
+        }
+
+        let status = match first_text(properties, "post-status") {
+            None | Some("published") => Status::Public,
+            Some("draft") => Status::Draft,
+            Some(status) => return Err(format!("unknown post-status {}", status)),
+        };
+
+        let mut categories: Vec<String> = Vec::new();
+        for category in properties.get("category").into_iter().flatten() {
+            let Some(category) = text(category).map(str::trim) else {
+                continue;
+            };
+            if !category.is_empty() && !categories.iter().any(|c| c == category) {
+                categories.push(category.to_string());
+            }
+        }
+
+        Ok(Self {
+            title,
+            content,
+            categories,
+            status,
+            summary: first_text(properties, "summary").map(str::to_string),
+            slug: first_text(properties, "mp-slug").map(str::to_string),
+            featured: first_text(properties, "featured").map(str::to_string),
+        })
+    }
+
+    /// The note as Micropub properties, what `q=source` returns and updates
+    /// are made to.
+    pub fn properties(note: &Note, tags: Vec<String>, subname: Option<String>) -> Properties {
+        let status = match note.status {
+            Status::Public => "published",
+            Status::Draft | Status::Recycle => "draft",
+        };
+
+        let mut properties = Properties::new();
+        properties.insert(String::from("name"), vec![json!(note.title)]);
+        properties.insert(String::from("content"), vec![json!(note.content)]);
+        properties.insert(String::from("post-status"), vec![json!(status)]);
+        if is_custom_summary(&note.summary, &note.content) {
+            properties.insert(String::from("summary"), vec![json!(note.summary)]);
+        }
+        if !tags.is_empty() {
+            properties.insert(
+                String::from("category"),
+                tags.into_iter().map(Value::String).collect(),
+            );
+        }
+        if let Some(subname) = subname {
+            properties.insert(String::from("mp-slug"), vec![json!(subname)]);
+        }
+        if let Some(fancy_img) = &note.fancy_img {
+            properties.insert(String::from("featured"), vec![json!(fancy_img)]);
+        }
+
+        properties
+    }
+}
+
+/// The `q=source` answer, limited to `names` unless it is empty.
+pub fn source(properties: Properties, names: &[String]) -> Value {
+    let properties: Map<String, Value> = properties
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with("mp-"))
+        .filter(|(key, _)| names.is_empty() || names.contains(key))
+        .map(|(key, values)| (key, Value::Array(values)))
+        .collect();
+
+    if names.is_empty() {
+        json!({ "type": ["h-entry"], "properties": properties })
+    } else {
+        json!({ "properties": properties })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_bearer() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(bearer(&headers), None);
+
+        headers.insert(AUTHORIZATION, "Bearer nekonya".parse().unwrap());
+        assert_eq!(bearer(&headers), Some("nekonya"));
+
+        headers.insert(AUTHORIZATION, "Basic bmVrbzpueWE=".parse().unwrap());
+        assert_eq!(bearer(&headers), None);
+
+        headers.insert(AUTHORIZATION, "bearer  ".parse().unwrap());
+        assert_eq!(bearer(&headers), None);
+    }
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(hash_token("nekonya"), hash_token("nekonya"));
+        assert_ne!(hash_token("nekonya"), hash_token("inuwan"));
+        assert_eq!(hash_token("nekonya").len(), 64);
+    }
+
+    #[test]
+    fn test_parse_form() {
+        let (request, token) = parse_form(pairs(&[
+            ("h", "entry"),
+            ("content", "Hello nya"),
+            ("category[]", "cat"),
+            ("category[]", "life"),
+            ("mp-slug", "hello"),
+            ("access_token", "nekonya"),
+        ]))
+        .unwrap();
+
+        assert_eq!(token.as_deref(), Some("nekonya"));
+        let Request::Create(properties) = request else {
+            panic!("not a create request");
+        };
+        assert_eq!(properties["content"], vec![json!("Hello nya")]);
+        assert_eq!(properties["category"], vec![json!("cat"), json!("life")]);
+        assert_eq!(properties["mp-slug"], vec![json!("hello")]);
+        assert!(!properties.contains_key("h"));
+        assert!(!properties.contains_key("access_token"));
+
+        let (request, token) = parse_form(pairs(&[
+            ("action", "delete"),
+            ("url", "https://example.com/hello"),
+        ]))
+        .unwrap();
+        assert_eq!(
+            request,
+            Request::Delete(String::from("https://example.com/hello"))
+        );
+        assert_eq!(token, None);
+
+        assert!(parse_form(pairs(&[("h", "card"), ("name", "neko")])).is_err());
+        assert!(parse_form(pairs(&[("action", "delete")])).is_err());
+        assert!(parse_form(pairs(&[("action", "update"), ("url", "x")])).is_err());
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let request = parse_json(&json!({
+            "type": ["h-entry"],
+            "properties": {
+                "name": ["Hello"],
+                "content": [{"html": "<p>nya</p>"}],
+                "post-status": "draft"
+            }
+        }))
+        .unwrap();
+        let Request::Create(properties) = request else {
+            panic!("not a create request");
+        };
+        assert_eq!(properties["post-status"], vec![json!("draft")]);
+
+        let request = parse_json(&json!({
+            "action": "update",
+            "url": "https://example.com/hello",
+            "replace": {"content": ["nya nya"]},
+            "add": {"category": ["cat"]},
+            "delete": ["featured"]
+        }))
+        .unwrap();
+        assert_eq!(
+            request,
+            Request::Update {
+                url: String::from("https://example.com/hello"),
+                update: Update {
+                    replace: Properties::from([(String::from("content"), vec![json!("nya nya")])]),
+                    add: Properties::from([(String::from("category"), vec![json!("cat")])]),
+                    delete: vec![String::from("featured")],
+                    delete_values: Properties::new(),
+                },
+            }
+        );
+
+        let request = parse_json(&json!({
+            "action": "update",
+            "url": "https://example.com/hello",
+            "delete": {"category": ["dog"]}
+        }))
+        .unwrap();
+        let Request::Update { update, .. } = request else {
+            panic!("not an update request");
+        };
+        assert_eq!(update.delete_values["category"], vec![json!("dog")]);
+
+        assert!(parse_json(&json!({"type": ["h-card"], "properties": {}})).is_err());
+        assert!(parse_json(&json!({"action": "delete"})).is_err());
+        assert!(parse_json(&json!({"action": "update", "url": "x", "delete": [1]})).is_err());
+    }
+
+    #[test]
+    fn test_update_apply() {
+        let mut properties = Properties::from([
+            (String::from("name"), vec![json!("Hello")]),
+            (String::from("category"), vec![json!("cat"), json!("dog")]),
+            (
+                String::from("featured"),
+                vec![json!("https://example.com/a.png")],
+            ),
+        ]);
+        let update = Update {
+            replace: Properties::from([(String::from("name"), vec![json!("Nya")])]),
+            add: Properties::from([(String::from("category"), vec![json!("bird")])]),
+            delete: vec![String::from("featured")],
+            delete_values: Properties::from([(String::from("category"), vec![json!("dog")])]),
+        };
+        update.apply(&mut properties);
+
+        assert_eq!(properties["name"], vec![json!("Nya")]);
+        assert_eq!(properties["category"], vec![json!("cat"), json!("bird")]);
+        assert!(!properties.contains_key("featured"));
+        assert!(update.touches("category"));
+        assert!(!update.touches("content"));
+    }
+
+    #[test]
+    fn test_entry_from_properties() {
+        let entry = Entry::from_properties(&Properties::from([
+            (String::from("name"), vec![json!("Hello")]),
+            (String::from("content"), vec![json!({"html": "<p>nya</p>"})]),
+            (
+                String::from("category"),
+                vec![json!("cat"), json!(" cat "), json!("")],
+            ),
+            (String::from("post-status"), vec![json!("draft")]),
+            (
+                String::from("photo"),
+                vec![json!({"value": "https://example.com/a.png", "alt": "a cat"})],
+            ),
+        ]))
+        .unwrap();
+
+        assert_eq!(entry.title, "Hello");
+        assert_eq!(
+            entry.content,
+            "<p>nya</p>\n\n![a cat](https://example.com/a.png)"
+        );
+        assert_eq!(entry.categories, vec![String::from("cat")]);
+        assert_eq!(entry.status, Status::Draft);
+        assert_eq!(entry.summary, None);
+        assert_eq!(entry.slug, None);
+
+        let long = "n".repeat(TITLE_CHARS + 1);
+        let entry = Entry::from_properties(&Properties::from([(
+            String::from("content"),
+            vec![json!(format!("{}\nsecond line", long))],
+        )]))
+        .unwrap();
+        assert_eq!(entry.title, format!("{}...", &long[..TITLE_CHARS]));
+        assert_eq!(entry.status, Status::Public);
+
+        assert!(Entry::from_properties(&Properties::new()).is_err());
+        assert!(Entry::from_properties(&Properties::from([
+            (String::from("content"), vec![json!("nya")]),
+            (String::from("post-status"), vec![json!("private")]),
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn test_source() {
+        let properties = Properties::from([
+            (String::from("name"), vec![json!("Hello")]),
+            (String::from("content"), vec![json!("nya")]),
+            (String::from("mp-slug"), vec![json!("hello")]),
+        ]);
+
+        assert_eq!(
+            source(properties.clone(), &[]),
+            json!({
+                "type": ["h-entry"],
+                "properties": {"name": ["Hello"], "content": ["nya"]}
+            })
+        );
+        assert_eq!(
+            source(properties, &[String::from("name")]),
+            json!({"properties": {"name": ["Hello"]}})
+        );
+    }
+}
@@ -1,2 +1,17 @@
+pub mod antispam;
+pub mod comm_feed;
+pub mod digest;
+pub mod federation;
+pub mod hooks;
+pub mod media;
+pub mod micropub;
+pub mod moderation;
+pub mod newsletter;
 pub mod notify;
 pub mod online;
+pub mod outbox;
+pub mod publish;
+pub mod revisions;
+pub mod schedule;
+pub mod webmention;
+pub mod xmlrpc;
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    db::models::comms::{Comm, CommStatus},
+    error::BlogError,
+    service::{antispam::bayes, notify::Notify},
+};
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ModerateAction {
+    Approve,
+    Reject,
+    Spam,
+}
+
+/// Moves comms out of the queue, tells people about the ones going live and
+/// teaches the spam classifier. Returns how many comms were updated.
+pub async fn moderate(
+    ids: &[Uuid],
+    action: ModerateAction,
+    notify: &Arc<Notify>,
+    conn: &mut AsyncPgConnection,
+) -> Result<usize, BlogError> {
+    let status = match action {
+        ModerateAction::Approve => CommStatus::Approved,
+        ModerateAction::Reject => CommStatus::Trash,
+        ModerateAction::Spam => CommStatus::Spam,
+    };
+    let notify = notify.clone();
+    let ids_ = ids.to_vec();
+    let updated = conn
+        .transaction::<_, BlogError, _>(|conn| {
+            async move {
+                // comms that were already live have been announced before
+                let (updated, newly_approved) =
+                    Comm::update_status_by_uuids(&ids_, &status, conn).await?;
+                for comm_id in &newly_approved {
+                    notify.enqueue_comm(comm_id, false, conn).await?;
+                }
+
+                // approve and spam are what the classifier learns from, trash
+                // is not. Learning along with the verdict keeps the two from
+                // drifting apart when either fails.
+                match action {
+                    ModerateAction::Approve => bayes::learn(&ids_, false, conn).await?,
+                    ModerateAction::Spam => bayes::learn(&ids_, true, conn).await?,
+                    ModerateAction::Reject => {}
+                }
+
+                Ok(updated)
+            }
+            .scope_boxed()
+        })
+        .await?;
+
+    Ok(updated)
+}
@@ -0,0 +1,150 @@
+use chrono::Utc;
+use diesel_async::AsyncPgConnection;
+use serde_json::{json, Value};
+
+use crate::{
+    config::CONFIG,
+    db::models::subscribers::Subscriber,
+    error::BlogError,
+    service::notify::{Notify, Recipient},
+    utils::{sign, URLEncode},
+};
+
+/// How long the link of a confirmation mail works.
+const CONFIRM_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+fn confirm_payload(email: &str, expires_at: i64) -> String {
+    format!("newsletter-confirm:{}:{}", email.to_lowercase(), expires_at)
+}
+
+fn unsubscribe_payload(email: &str) -> String {
+    format!("newsletter-unsubscribe:{}", email.to_lowercase())
+}
+
+/// Checks the signature and expiry of a confirmation link.
+fn check_confirm(
+    email: &str,
+    expires_at: i64,
+    token: &str,
+    now: i64,
+    verify: impl Fn(&str, &str) -> bool,
+) -> bool {
+    expires_at > now && verify(&confirm_payload(email, expires_at), token)
+}
+
+pub fn verify_confirm_token(email: &str, expires_at: i64, token: &str) -> bool {
+    check_confirm(
+        email,
+        expires_at,
+        token,
+        Utc::now().timestamp(),
+        sign::verify,
+    )
+}
+
+pub fn verify_unsubscribe_token(email: &str, token: &str) -> bool {
+    sign::verify(&unsubscribe_payload(email), token)
+}
+
+/// A subscriber along with the link that ends the subscription, which is not
+/// the one for notification mails.
+fn recipient(email: String) -> Recipient {
+    let unsubscribe_url = format!(
+        "{}/api/newsletter/unsubscribe?email={}&token={}",
+        CONFIG.site_url(),
+        email.encode(),
+        sign::sign(&unsubscribe_payload(&email))
+    );
+
+    Recipient {
+        email,
+        unsubscribe_url,
+    }
+}
+
+/// Signs `email` up and mails it the confirmation link. Addresses that are
+/// confirmed already get nothing, so nobody learns who subscribed.
+pub async fn subscribe(
+    email: &str,
+    notify: &Notify,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), BlogError> {
+    let subscriber = Subscriber::create(email, conn).await?;
+    if subscriber.confirmed_at.is_some() {
+        return Ok(());
+    }
+
+    let expires_at = Utc::now().timestamp() + CONFIRM_TTL_SECS;
+    let confirm_url = format!(
+        "{}/api/newsletter/confirm?email={}&expires={}&token={}",
+        CONFIG.site_url(),
+        subscriber.email.encode(),
+        expires_at,
+        sign::sign(&confirm_payload(&subscriber.email, expires_at))
+    );
+    let vars = json!({
+        "email": subscriber.email,
+        "confirm_url": confirm_url,
+    });
+
+    notify
+        .enqueue_mail(
+            "newsletter.confirm",
+            &vars,
+            recipient(subscriber.email),
+            conn,
+        )
+        .await
+}
+
+/// Queues the announcement of a freshly published note, described by
+/// `vars`, to every confirmed subscriber.
+pub async fn enqueue_note(
+    vars: &Value,
+    notify: &Notify,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), BlogError> {
+    if !notify.can_mail() {
+        return Ok(());
+    }
+
+    let recipients: Vec<Recipient> = Subscriber::get_confirmed_emails(conn)
+        .await?
+        .into_iter()
+        .map(recipient)
+        .collect();
+
+    notify
+        .enqueue_mails("newsletter.published", vars, recipients, conn)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::sign::{sign_with, verify_with};
+
+    const KEY: &[u8] = b"nekonya";
+
+    #[test]
+    fn test_check_confirm() {
+        let verify = |data: &str, sig: &str| verify_with(KEY, data, sig);
+        let token = sign_with(KEY, &confirm_payload("Neko@example.com", 1_000));
+        let check = |email, expires_at, now| check_confirm(email, expires_at, &token, now, verify);
+
+        assert!(check("neko@example.com", 1_000, 999));
+        assert!(!check("neko@example.com", 1_000, 1_000));
+        assert!(!check("neko@example.com", 2_000, 999));
+        assert!(!check("inu@example.com", 1_000, 999));
+
+        // an unsubscribe token does not confirm anything
+        let token = sign_with(KEY, &unsubscribe_payload("neko@example.com"));
+        assert!(!check_confirm(
+            "neko@example.com",
+            1_000,
+            &token,
+            999,
+            verify
+        ));
+    }
+}
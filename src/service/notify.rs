@@ -1,16 +1,385 @@
-
 mod smtp;
 mod telegram;
+pub mod template;
 mod webhook;
 
-pub struct Notify {}
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use diesel_async::AsyncPgConnection;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    config::{DigestPeriod, NotifyChannel, CONFIG},
+    db::models::{
+        comm_users::CommUser,
+        comms::{Comm, CommStatus},
+        comms_closure::CommsClosure,
+        mail_unsubscribes::MailUnsubscribe,
+        notify_outbox::OutboxMessage,
+        users::User,
+    },
+    error::BlogError,
+    utils::{sign, URLEncode},
+};
+
+pub use smtp::{Mail, Mailer, SmtpNotifier};
+pub use telegram::{comm_keyboard, BotApi, TelegramNotifier};
+pub use webhook::WebhookNotifier;
+
+use template::{excerpt, Rendered, EXCERPT_CHARS};
+
+/// Something the blog owner wants to hear about, before it is put into words.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// What happened, e.g. `comm.created`, which picks the templates
+    pub event: String,
+    /// Variables for the templates
+    pub vars: Value,
+    /// Mailbox for the smtp channel, which stays quiet without one
+    pub mail_to: Option<Recipient>,
+    /// The comm it is about, for channels that can act on it
+    pub comm_id: Option<Uuid>,
+}
+
+/// A notification rendered for every channel, as it waits in the outbox.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub event: String,
+    pub content: Rendered,
+    pub mail_to: Option<Recipient>,
+    #[serde(default)]
+    pub comm_id: Option<Uuid>,
+    /// The outbox row it is delivered from, stable across retries
+    #[serde(skip)]
+    pub outbox_id: Option<Uuid>,
+}
+
+/// A mail recipient along with the link that stops further mails to them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipient {
+    pub email: String,
+    pub unsubscribe_url: String,
+}
+
+impl Recipient {
+    pub fn new(email: String) -> Self {
+        let unsubscribe_url = format!(
+            "{}/api/unsubscribe?email={}&token={}",
+            CONFIG.site_url(),
+            email.encode(),
+            unsubscribe_token(&email)
+        );
+
+        Self {
+            email,
+            unsubscribe_url,
+        }
+    }
+
+    /// Returns `None` for addresses that have unsubscribed.
+    pub async fn subscribed(
+        email: String,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        if MailUnsubscribe::is_unsubscribed(&email, conn).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(Self::new(email)))
+    }
+}
+
+/// A channel notifications can be delivered through.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Channel name, which outbox messages for it are queued under.
+    fn name(&self) -> &str;
+
+    /// Whether this channel has anything to do with `message`.
+    fn accepts(&self, _message: &Message) -> bool {
+        true
+    }
+
+    async fn push(&self, message: &Message) -> Result<(), BlogError>;
+}
+
+/// Outbox channel for mails to visitors, as opposed to the owner's channels.
+const MAIL_CHANNEL: &str = "mail";
+/// Mails queued per insert by [`Notify::enqueue_mails`].
+const MAIL_BATCH_SIZE: usize = 500;
+
+/// Tells people about new comms. Messages are queued in the outbox alongside
+/// the write that caused them and sent later by [`crate::service::outbox`],
+/// so requests never wait for a mail relay or a remote API.
+pub struct Notify {
+    /// Direct mails to visitors, which never go through the owner's channels.
+    mailer: Option<Arc<Mailer>>,
+    notifiers: Vec<Arc<dyn Notifier>>,
+}
+
+impl Notify {
+    pub fn new() -> Result<Self, BlogError> {
+        let mailer = Mailer::from_config(&CONFIG.smtp)?.map(Arc::new);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+
+        let mut notifiers: Vec<Arc<dyn Notifier>> = Vec::new();
+        for channel in CONFIG.notify.channels() {
+            match channel {
+                // the digest tells the owner about it all at once instead
+                NotifyChannel::Smtp if CONFIG.notify.digest() != DigestPeriod::Off => {}
+                NotifyChannel::Smtp => match &mailer {
+                    Some(mailer) => notifiers.push(Arc::new(SmtpNotifier::new(mailer.clone()))),
+                    None => warn!("notify channel smtp skipped, smtp.host is not set"),
+                },
+                NotifyChannel::Telegram => notifiers.push(Arc::new(TelegramNotifier::from_config(
+                    &CONFIG.notify,
+                    client.clone(),
+                )?)),
+                NotifyChannel::Webhook => {
+                    let targets = CONFIG.notify.webhook_targets();
+                    if targets.is_empty() {
+                        return Err(anyhow::anyhow!(
+                            "notify.webhookurl or notify.webhooks is required for webhook"
+                        )
+                        .into());
+                    }
+                    for target in &targets {
+                        notifiers.push(Arc::new(WebhookNotifier::new(client.clone(), target)?));
+                    }
+                }
+            }
+        }
+
+        Ok(Self { mailer, notifiers })
+    }
+
+    /// Renders `notification` and queues it for every configured channel
+    /// that takes it.
+    pub async fn enqueue(
+        &self,
+        notification: &Notification,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        if self.notifiers.is_empty() {
+            return Ok(());
+        }
+
+        let message = Message {
+            event: notification.event.clone(),
+            content: template::render(&notification.event, &notification.vars, conn).await?,
+            mail_to: notification.mail_to.clone(),
+            comm_id: notification.comm_id,
+            outbox_id: None,
+        };
+        let payload = serde_json::to_value(&message)?;
+        for notifier in self.notifiers.iter().filter(|n| n.accepts(&message)) {
+            OutboxMessage::enqueue(notifier.name(), &payload, conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether mails to visitors can be sent at all.
+    pub fn can_mail(&self) -> bool {
+        self.mailer.is_some()
+    }
+
+    /// Renders the mail templates of `event` and queues the mail to a single
+    /// visitor, outside of the owner's channels.
+    pub async fn enqueue_mail(
+        &self,
+        event: &str,
+        vars: &Value,
+        to: Recipient,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        self.enqueue_mails(event, vars, vec![to], conn).await
+    }
+
+    /// Like [`Self::enqueue_mail`] for many visitors. The templates are
+    /// rendered once and the mails queued a batch per insert, one outbox
+    /// message each, so the outbox worker sends them a round at a time and
+    /// retries them one by one.
+    pub async fn enqueue_mails(
+        &self,
+        event: &str,
+        vars: &Value,
+        to: Vec<Recipient>,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        if self.mailer.is_none() || to.is_empty() {
+            return Ok(());
+        }
+
+        let content = template::render(event, vars, conn).await?;
+        let payloads = to
+            .into_iter()
+            .map(|to| {
+                serde_json::to_value(Mail {
+                    to,
+                    subject: content.subject.clone(),
+                    body: content.text.clone(),
+                    html: content.html.clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for batch in payloads.chunks(MAIL_BATCH_SIZE) {
+            OutboxMessage::enqueue_many(MAIL_CHANNEL, batch, conn).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends one queued message, as the outbox worker found it.
+    pub async fn deliver(
+        &self,
+        id: &Uuid,
+        channel: &str,
+        payload: &Value,
+    ) -> Result<(), BlogError> {
+        if channel == MAIL_CHANNEL {
+            let mailer = self
+                .mailer
+                .as_ref()
+                .ok_or(anyhow::anyhow!("smtp is not configured"))?;
+            let mail: Mail = serde_json::from_value(payload.clone())?;
+            return mailer.send(&mail).await;
+        }
+
+        let notifier =
+            self.notifiers
+                .iter()
+                .find(|n| n.name() == channel)
+                .ok_or(anyhow::anyhow!(
+                    "notify channel {} is not configured",
+                    channel
+                ))?;
+        let mut message: Message = serde_json::from_value(payload.clone())?;
+        message.outbox_id = Some(*id);
+
+        notifier.push(&message).await
+    }
+
+    /// Queues what a comm is worth telling. A new comm goes to the owner of
+    /// the note or page unless it is spam. Once it is approved, the commenter
+    /// it replies to gets a mail too, so approving a pending comm later calls
+    /// this again with `created` unset.
+    pub async fn enqueue_comm(
+        &self,
+        comm_id: &Uuid,
+        created: bool,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<(), BlogError> {
+        if self.mailer.is_none() && self.notifiers.is_empty() {
+            return Ok(());
+        }
+
+        let Some(comm) = Comm::find_comm_by_uuid(comm_id, conn).await? else {
+            return Ok(());
+        };
+        let Some(place) = Comm::find_place(&comm, conn).await? else {
+            return Ok(());
+        };
+
+        let (nickname, email) = if let Some(comm_user_id) = &comm.comm_user_id {
+            match CommUser::find_comm_users_by_ids(&[*comm_user_id], conn)
+                .await?
+                .pop()
+            {
+                Some(user) => (user.nickname, user.email),
+                None => return Ok(()),
+            }
+        } else if let Some(blog_user_id) = &comm.blog_user_id {
+            match User::find_user_by_uuid(blog_user_id, conn).await? {
+                Some(user) => (user.nickname, user.email),
+                None => return Ok(()),
+            }
+        } else {
+            return Ok(());
+        };
+
+        let link = format!(
+            "{}/{}#comm-{}",
+            CONFIG.site_url(),
+            place.short_name,
+            comm.id
+        );
+
+        let mut vars = json!({
+            "note_title": place.title,
+            "nickname": nickname,
+            "comment": comm.content,
+            "excerpt": excerpt(&comm.content, EXCERPT_CHARS),
+            "link": link,
+            "pending": comm.status == CommStatus::Pending,
+        });
+
+        if created && comm.status != CommStatus::Spam && comm.blog_user_id != Some(place.owner_id) {
+            let mail_to = match User::find_user_by_uuid(&place.owner_id, conn).await? {
+                Some(owner) => Recipient::subscribed(owner.email, conn).await?,
+                None => None,
+            };
+
+            let notification = Notification {
+                event: String::from("comm.created"),
+                vars: vars.clone(),
+                mail_to,
+                comm_id: Some(comm.id),
+            };
+            self.enqueue(&notification, conn).await?;
+        }
+
+        if comm.status != CommStatus::Approved {
+            return Ok(());
+        }
+
+        let parent_id = CommsClosure::get_parents(&[comm.id], conn)
+            .await?
+            .remove(&comm.id);
+        let parent = match parent_id {
+            Some(parent_id) => Comm::find_comm_by_uuid(&parent_id, conn).await?,
+            None => None,
+        };
+
+        // only visitors get reply mails, and only for comms that are live
+        let Some((parent, parent_user_id)) = parent
+            .filter(|p| p.status == CommStatus::Approved)
+            .and_then(|p| p.comm_user_id.map(|id| (p, id)))
+        else {
+            return Ok(());
+        };
+        let Some(parent_user) = CommUser::find_comm_users_by_ids(&[parent_user_id], conn)
+            .await?
+            .pop()
+            // fediverse commenters go by their actor ID and get no mails
+            .filter(|u| !u.email.eq_ignore_ascii_case(&email) && !u.email.contains("://"))
+        else {
+            return Ok(());
+        };
+        let Some(recipient) = Recipient::subscribed(parent_user.email, conn).await? else {
+            return Ok(());
+        };
+
+        vars["parent_comment"] = json!(parent.content);
+        vars["parent_excerpt"] = json!(excerpt(&parent.content, EXCERPT_CHARS));
+        self.enqueue_mail("comm.replied", &vars, recipient, conn)
+            .await
+    }
+}
 
-// impl Notification {
-//     pub async fn new() -> Result<(), BlogError> {
-//         todo!()
-//     }
+/// Token proving the holder received a mail at `email`, so nobody can
+/// unsubscribe someone else.
+pub fn unsubscribe_token(email: &str) -> String {
+    sign::sign(&format!("unsubscribe:{}", email.to_lowercase()))
+}
 
-//     pub async fn push(&self) -> Result<(), BlogError> {
-//         todo!()
-//     }
-// }
+pub fn verify_unsubscribe_token(email: &str, token: &str) -> bool {
+    sign::verify(&format!("unsubscribe:{}", email.to_lowercase()), token)
+}
@@ -1 +1,296 @@
+use std::sync::Arc;
 
+use async_trait::async_trait;
+use lettre::{
+    message::{
+        header::{ContentType, HeaderName, HeaderValue},
+        Mailbox, MultiPart,
+    },
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Smtp, SmtpTls},
+    error::BlogError,
+};
+
+use super::{Message, Notifier, Recipient};
+
+/// A mail to a single recipient, with an HTML alternative if there is one.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: Recipient,
+    pub subject: String,
+    pub body: String,
+    pub html: Option<String>,
+}
+
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Mailer {
+    pub fn new(transport: AsyncSmtpTransport<Tokio1Executor>, from: Mailbox) -> Self {
+        Self { transport, from }
+    }
+
+    /// Returns `None` while no relay is configured.
+    pub fn from_config(smtp: &Smtp) -> Result<Option<Self>, BlogError> {
+        let Some(host) = &smtp.smtp_host else {
+            return Ok(None);
+        };
+
+        let builder = match smtp.tls() {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        }
+        .port(smtp.port());
+
+        let builder = match (&smtp.smtp_user, &smtp.smtp_passwd) {
+            (Some(user), Some(passwd)) => {
+                builder.credentials(Credentials::new(user.clone(), passwd.clone()))
+            }
+            _ => builder,
+        };
+
+        let from = smtp
+            .smtp_from
+            .as_deref()
+            .ok_or(anyhow::anyhow!("smtp.from is required to send mails"))?
+            .parse()?;
+
+        Ok(Some(Self::new(builder.build(), from)))
+    }
+
+    pub async fn send(&self, mail: &Mail) -> Result<(), BlogError> {
+        let message = build_message(&self.from, mail)?;
+        self.transport.send(message).await?;
+
+        Ok(())
+    }
+}
+
+/// Mails owner notifications to the notification's recipient.
+pub struct SmtpNotifier {
+    mailer: Arc<Mailer>,
+}
+
+impl SmtpNotifier {
+    pub fn new(mailer: Arc<Mailer>) -> Self {
+        Self { mailer }
+    }
+}
+
+#[async_trait]
+impl Notifier for SmtpNotifier {
+    fn name(&self) -> &str {
+        "smtp"
+    }
+
+    fn accepts(&self, message: &Message) -> bool {
+        message.mail_to.is_some()
+    }
+
+    async fn push(&self, message: &Message) -> Result<(), BlogError> {
+        let Some(to) = &message.mail_to else {
+            return Ok(());
+        };
+
+        self.mailer
+            .send(&Mail {
+                to: to.clone(),
+                subject: message.content.subject.clone(),
+                body: message.content.text.clone(),
+                html: message.content.html.clone(),
+            })
+            .await
+    }
+}
+
+fn build_message(from: &Mailbox, mail: &Mail) -> Result<lettre::Message, BlogError> {
+    let builder = lettre::Message::builder()
+        .from(from.clone())
+        .to(mail.to.email.parse()?)
+        .subject(mail.subject.as_str());
+    let text = format!(
+        "{}\n\n--\nUnsubscribe: {}\n",
+        mail.body, mail.to.unsubscribe_url
+    );
+    let mut message = match &mail.html {
+        Some(html) => builder.multipart(MultiPart::alternative_plain_html(
+            text,
+            format!(
+                "{}\n<hr>\n<p><a href=\"{}\">Unsubscribe</a></p>\n",
+                html,
+                mail.to.unsubscribe_url.replace('&', "&amp;")
+            ),
+        ))?,
+        None => builder.header(ContentType::TEXT_PLAIN).body(text)?,
+    };
+
+    // RFC 8058 one-click unsubscribe, POSTed by the mail client itself
+    let headers = message.headers_mut();
+    headers.insert_raw(HeaderValue::new(
+        HeaderName::new_from_ascii_str("List-Unsubscribe"),
+        format!("<{}>", mail.to.unsubscribe_url),
+    ));
+    headers.insert_raw(HeaderValue::new(
+        HeaderName::new_from_ascii_str("List-Unsubscribe-Post"),
+        String::from("List-Unsubscribe=One-Click"),
+    ));
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::*;
+    use crate::service::notify::template::Rendered;
+
+    fn recipient(email: &str) -> Recipient {
+        Recipient {
+            email: email.to_string(),
+            unsubscribe_url: String::from("https://example.com/api/unsubscribe?email=x&token=y"),
+        }
+    }
+
+    /// Just enough of an SMTP server to accept mails, handing every DATA
+    /// section it receives to the returned channel.
+    async fn dummy_smtp() -> (u16, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let (read, mut write) = stream.into_split();
+                    let mut lines = BufReader::new(read).lines();
+                    write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+                    let mut data: Option<String> = None;
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        if let Some(body) = data.as_mut() {
+                            if line == "." {
+                                tx.send(data.take().unwrap()).unwrap();
+                                write.write_all(b"250 queued\r\n").await.unwrap();
+                            } else {
+                                body.push_str(&line);
+                                body.push('\n');
+                            }
+                            continue;
+                        }
+
+                        let command = line.to_uppercase();
+                        let reply: &[u8] = if command.starts_with("EHLO") {
+                            b"250 localhost\r\n"
+                        } else if command.starts_with("DATA") {
+                            data = Some(String::new());
+                            b"354 go ahead\r\n"
+                        } else if command.starts_with("QUIT") {
+                            b"221 bye\r\n"
+                        } else {
+                            b"250 ok\r\n"
+                        };
+                        write.write_all(reply).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        (port, rx)
+    }
+
+    #[test]
+    fn test_build_message() {
+        let from: Mailbox = "Blog <blog@example.com>".parse().unwrap();
+        let mail = Mail {
+            to: recipient("neko@example.com"),
+            subject: String::from("New comment on nya"),
+            body: String::from("nyanya"),
+            html: None,
+        };
+
+        let message = build_message(&from, &mail).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+
+        assert!(formatted.contains("To: neko@example.com"));
+        assert!(formatted
+            .contains("List-Unsubscribe: <https://example.com/api/unsubscribe?email=x&token=y>"));
+        assert!(formatted.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(formatted.contains("Unsubscribe: https://example.com/api/unsubscribe"));
+
+        assert!(!formatted.contains("text/html"));
+
+        let html = Mail {
+            html: Some(String::from("<p>nyanya</p>")),
+            ..mail
+        };
+        let formatted =
+            String::from_utf8(build_message(&from, &html).unwrap().formatted()).unwrap();
+        assert!(formatted.contains("multipart/alternative"));
+        assert!(formatted.contains("<p>nyanya</p>"));
+        // quoted-printable, so only the escaped ampersand is checked
+        assert!(formatted.contains("x&amp;token"));
+
+        let bad = Mail {
+            to: recipient("not an address"),
+            ..html
+        };
+        assert!(build_message(&from, &bad).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_smtp_notifier() {
+        let (port, mut received) = dummy_smtp().await;
+        let transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+            .port(port)
+            .build();
+        let mailer = Mailer::new(transport, "Blog <blog@example.com>".parse().unwrap());
+        let notifier = SmtpNotifier::new(Arc::new(mailer));
+
+        let mut message = Message {
+            event: String::from("comm.created"),
+            content: Rendered {
+                subject: String::from("New comment on nya"),
+                html: None,
+                text: String::from("neko commented\n\nhttps://example.com/nya#comm-1"),
+                telegram: None,
+                webhook: None,
+            },
+            mail_to: None,
+            comm_id: None,
+            outbox_id: None,
+        };
+
+        // nobody to mail, nothing sent
+        assert!(!notifier.accepts(&message));
+        notifier.push(&message).await.unwrap();
+
+        message.mail_to = Some(recipient("owner@example.com"));
+        notifier.push(&message).await.unwrap();
+
+        let data = tokio::time::timeout(Duration::from_secs(5), received.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(data.contains("To: owner@example.com"));
+        assert!(data.contains("Subject: New comment on nya"));
+        assert!(data.contains("https://example.com/nya#comm-1"));
+        assert!(data.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(received.try_recv().is_err());
+    }
+}
@@ -1 +1,275 @@
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
 
+use crate::{config::Notify, error::BlogError};
+
+use super::{Message, Notifier};
+
+/// Longest message the Bot API accepts, in characters.
+const MAX_TEXT_CHARS: usize = 4096;
+
+/// Calls Bot API methods for one bot.
+#[derive(Clone)]
+pub struct BotApi {
+    client: reqwest::Client,
+    /// `<api>/bot<token>`
+    base: String,
+}
+
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+impl BotApi {
+    pub fn new(client: reqwest::Client, api: &str, token: &str) -> Self {
+        Self {
+            client,
+            base: format!("{}/bot{}", api.trim_end_matches('/'), token),
+        }
+    }
+
+    pub fn from_config(notify: &Notify, client: reqwest::Client) -> Result<Self, BlogError> {
+        let token = notify.telegram_token.as_deref().ok_or(anyhow::anyhow!(
+            "notify.telegramtoken is required for telegram"
+        ))?;
+
+        Ok(Self::new(client, notify.telegram_api(), token))
+    }
+
+    /// Calls `method` and returns its `result`, turning refusals into errors.
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &Value,
+    ) -> Result<T, BlogError> {
+        let response: ApiResponse<T> = self
+            .client
+            .post(format!("{}/{}", self.base, method))
+            .json(params)
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        match response {
+            ApiResponse {
+                ok: true,
+                result: Some(result),
+                ..
+            } => Ok(result),
+            ApiResponse { description, .. } => Err(anyhow::anyhow!(
+                "telegram refused {}: {}",
+                method,
+                description.unwrap_or_default()
+            )
+            .into()),
+        }
+    }
+
+    /// Sends MarkdownV2 `text`. Text too long for the Bot API goes as plain
+    /// text cut to fit instead, since a cut could split an escape or entity.
+    pub async fn send_message(
+        &self,
+        chat_id: &Value,
+        text: &str,
+        extra: Value,
+    ) -> Result<(), BlogError> {
+        let mut params = if text.chars().count() <= MAX_TEXT_CHARS {
+            json!({
+                "chat_id": chat_id,
+                "text": text,
+                "parse_mode": "MarkdownV2",
+                "disable_web_page_preview": true,
+            })
+        } else {
+            let text: String = plain_text(text).chars().take(MAX_TEXT_CHARS).collect();
+            json!({
+                "chat_id": chat_id,
+                "text": text,
+                "disable_web_page_preview": true,
+            })
+        };
+        if let (Some(params), Value::Object(extra)) = (params.as_object_mut(), extra) {
+            params.extend(extra);
+        }
+
+        self.call::<Value>("sendMessage", &params).await?;
+
+        Ok(())
+    }
+}
+
+/// MarkdownV2 `text` without its escaping backslashes, to be sent as plain
+/// text. Formatting marks are left as they are.
+fn plain_text(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => plain.extend(chars.next()),
+            c => plain.push(c),
+        }
+    }
+
+    plain
+}
+
+/// Sends notifications to a chat through a Telegram bot. Notifications about
+/// a comm come with buttons to approve or delete it, which the bot in
+/// [`crate::inbox::telegram`] answers.
+pub struct TelegramNotifier {
+    api: BotApi,
+    chat_id: String,
+}
+
+impl TelegramNotifier {
+    pub fn new(client: reqwest::Client, api: &str, token: &str, chat_id: &str) -> Self {
+        Self {
+            api: BotApi::new(client, api, token),
+            chat_id: chat_id.to_string(),
+        }
+    }
+
+    pub fn from_config(notify: &Notify, client: reqwest::Client) -> Result<Self, BlogError> {
+        let (Some(token), Some(chat_id)) = (&notify.telegram_token, &notify.telegram_chat_id)
+        else {
+            return Err(anyhow::anyhow!(
+                "notify.telegramtoken and notify.telegramchatid are required for telegram"
+            )
+            .into());
+        };
+
+        Ok(Self::new(client, notify.telegram_api(), token, chat_id))
+    }
+}
+
+/// Inline keyboard with the moderation buttons for `comm_id`.
+pub fn comm_keyboard(comm_id: &uuid::Uuid, approve: bool) -> Value {
+    let mut buttons = Vec::new();
+    if approve {
+        buttons.push(json!({ "text": "Approve", "callback_data": format!("approve:{}", comm_id) }));
+    }
+    buttons.push(json!({ "text": "Delete", "callback_data": format!("delete:{}", comm_id) }));
+
+    json!({ "inline_keyboard": [buttons] })
+}
+
+#[async_trait]
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    fn accepts(&self, message: &Message) -> bool {
+        message.content.telegram.is_some()
+    }
+
+    async fn push(&self, message: &Message) -> Result<(), BlogError> {
+        let Some(text) = &message.content.telegram else {
+            return Ok(());
+        };
+        let extra = match &message.comm_id {
+            Some(comm_id) => json!({ "reply_markup": comm_keyboard(comm_id, true) }),
+            None => json!({}),
+        };
+
+        self.api
+            .send_message(&json!(self.chat_id), text, extra)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, routing::post, Json, Router};
+    use serde_json::Value;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+    use crate::service::notify::template::Rendered;
+
+    /// A stand-in for the Bot API that only knows the token `nekonya` and
+    /// hands every `sendMessage` body to the returned channel.
+    async fn mock_bot_api() -> (String, mpsc::UnboundedReceiver<Value>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let app = Router::new().route(
+            "/:bot/sendMessage",
+            post(move |Path(bot): Path<String>, Json(body): Json<Value>| {
+                let tx = tx.clone();
+                async move {
+                    if bot != "botnekonya" {
+                        return Json(json!({ "ok": false, "description": "Unauthorized" }));
+                    }
+                    tx.send(body).unwrap();
+                    Json(json!({ "ok": true, "result": {} }))
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let api = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (api, rx)
+    }
+
+    fn message() -> Message {
+        Message {
+            event: String::from("comm.created"),
+            content: Rendered {
+                subject: String::from("New comment on nya"),
+                html: None,
+                text: String::from("neko commented"),
+                telegram: Some(String::from("*New comment on nya*\n\nneko commented")),
+                webhook: None,
+            },
+            mail_to: None,
+            comm_id: None,
+            outbox_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_telegram_notifier() {
+        let (api, mut received) = mock_bot_api().await;
+
+        let notifier = TelegramNotifier::new(reqwest::Client::new(), &api, "nekonya", "42");
+        notifier.push(&message()).await.unwrap();
+
+        let body = received.recv().await.unwrap();
+        assert_eq!(body["chat_id"], "42");
+        assert_eq!(body["text"], "*New comment on nya*\n\nneko commented");
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+        assert!(body.get("reply_markup").is_none());
+
+        let mut about_comm = message();
+        about_comm.comm_id = Some(uuid::Uuid::nil());
+        notifier.push(&about_comm).await.unwrap();
+        let body = received.recv().await.unwrap();
+        assert_eq!(
+            body["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+            "approve:00000000-0000-0000-0000-000000000000"
+        );
+
+        let mut long = message();
+        long.content.telegram = Some(format!("*neko said* {}", "nya\\.".repeat(2000)));
+        notifier.push(&long).await.unwrap();
+        let body = received.recv().await.unwrap();
+        assert!(body.get("parse_mode").is_none());
+        let text = body["text"].as_str().unwrap();
+        assert_eq!(text.chars().count(), MAX_TEXT_CHARS);
+        assert!(text.starts_with("*neko said* nya.nya."));
+
+        let mut mail_only = message();
+        mail_only.content.telegram = None;
+        assert!(!notifier.accepts(&mail_only));
+
+        let notifier = TelegramNotifier::new(reqwest::Client::new(), &api, "wanwan", "42");
+        let err = notifier.push(&message()).await.unwrap_err();
+        assert!(err.to_string().contains("Unauthorized"));
+    }
+}
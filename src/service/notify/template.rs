@@ -0,0 +1,893 @@
+use std::{io::ErrorKind, path::Path};
+
+use chrono::Utc;
+use diesel_async::AsyncPgConnection;
+use minijinja::{escape_formatter, AutoEscape, Environment};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::warn;
+use utoipa::ToSchema;
+
+use crate::{config::CONFIG, db::models::notify_templates::NotifyTemplate, error::BlogError};
+
+/// Every event notifications are sent for, in the order admins see them.
+pub const EVENTS: &[&str] = &[
+    "comm.created",
+    "comm.replied",
+    "contact.received",
+    "contact.reply",
+    "inbox.draft",
+    "note.published",
+    "newsletter.confirm",
+    "newsletter.published",
+    "digest",
+];
+
+/// One piece of a notification, rendered for the channels that need it.
+#[derive(Debug, Clone, Copy)]
+enum Part {
+    Subject,
+    Html,
+    Text,
+    Telegram,
+    Webhook,
+}
+
+impl Part {
+    const ALL: [Part; 5] = [
+        Part::Subject,
+        Part::Html,
+        Part::Text,
+        Part::Telegram,
+        Part::Webhook,
+    ];
+
+    /// File name in the template directory. The extension also decides how
+    /// variables are escaped.
+    fn file_name(self) -> &'static str {
+        match self {
+            Part::Subject => "subject.txt",
+            Part::Html => "body.html",
+            Part::Text => "body.txt",
+            Part::Telegram => "telegram.md",
+            Part::Webhook => "webhook.json",
+        }
+    }
+}
+
+/// minijinja sources for the parts of one event. A missing part is taken
+/// from the next place templates are looked up in, or not sent at all.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Templates {
+    /// Mail subject, also the title elsewhere
+    pub subject: Option<String>,
+    /// HTML mail body, variables are HTML escaped
+    pub html: Option<String>,
+    /// Plain text mail body
+    pub text: Option<String>,
+    /// Telegram message in MarkdownV2, variables are escaped for it
+    pub telegram: Option<String>,
+    /// Webhook body, variables are written as JSON values
+    pub webhook: Option<String>,
+}
+
+/// What the templates of an event came out as.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct Rendered {
+    pub subject: String,
+    pub html: Option<String>,
+    pub text: String,
+    pub telegram: Option<String>,
+    #[schema(value_type = Option<Object>)]
+    pub webhook: Option<Value>,
+}
+
+impl Templates {
+    fn get(&self, part: Part) -> Option<&str> {
+        match part {
+            Part::Subject => self.subject.as_deref(),
+            Part::Html => self.html.as_deref(),
+            Part::Text => self.text.as_deref(),
+            Part::Telegram => self.telegram.as_deref(),
+            Part::Webhook => self.webhook.as_deref(),
+        }
+    }
+
+    fn get_mut(&mut self, part: Part) -> &mut Option<String> {
+        match part {
+            Part::Subject => &mut self.subject,
+            Part::Html => &mut self.html,
+            Part::Text => &mut self.text,
+            Part::Telegram => &mut self.telegram,
+            Part::Webhook => &mut self.webhook,
+        }
+    }
+
+    /// Fills the parts missing here from `fallback`.
+    pub fn or(mut self, fallback: Templates) -> Self {
+        for part in Part::ALL {
+            if self.get(part).is_none() {
+                *self.get_mut(part) = fallback.get(part).map(str::to_string);
+            }
+        }
+
+        self
+    }
+
+    /// Blank parts count as missing.
+    pub fn normalize(mut self) -> Self {
+        for part in Part::ALL {
+            let slot = self.get_mut(part);
+            if slot.as_deref().is_some_and(|s| s.trim().is_empty()) {
+                *slot = None;
+            }
+        }
+
+        self
+    }
+
+    pub fn builtin(event: &str) -> Option<Self> {
+        let (subject, html, text, telegram, webhook) = match event {
+            "comm.created" => (
+                COMM_CREATED_SUBJECT,
+                COMM_CREATED_HTML,
+                COMM_CREATED_TEXT,
+                Some(COMM_CREATED_TELEGRAM),
+                Some(COMM_CREATED_WEBHOOK),
+            ),
+            "comm.replied" => (
+                COMM_REPLIED_SUBJECT,
+                COMM_REPLIED_HTML,
+                COMM_REPLIED_TEXT,
+                None,
+                None,
+            ),
+            "contact.received" => (
+                CONTACT_RECEIVED_SUBJECT,
+                CONTACT_RECEIVED_HTML,
+                CONTACT_RECEIVED_TEXT,
+                Some(CONTACT_RECEIVED_TELEGRAM),
+                Some(CONTACT_RECEIVED_WEBHOOK),
+            ),
+            "contact.reply" => (
+                CONTACT_REPLY_SUBJECT,
+                CONTACT_REPLY_HTML,
+                CONTACT_REPLY_TEXT,
+                None,
+                None,
+            ),
+            "inbox.draft" => (
+                INBOX_DRAFT_SUBJECT,
+                INBOX_DRAFT_HTML,
+                INBOX_DRAFT_TEXT,
+                None,
+                None,
+            ),
+            "note.published" => (
+                NOTE_PUBLISHED_SUBJECT,
+                NOTE_PUBLISHED_HTML,
+                NOTE_PUBLISHED_TEXT,
+                Some(NOTE_PUBLISHED_TELEGRAM),
+                Some(NOTE_PUBLISHED_WEBHOOK),
+            ),
+            "newsletter.confirm" => (
+                NEWSLETTER_CONFIRM_SUBJECT,
+                NEWSLETTER_CONFIRM_HTML,
+                NEWSLETTER_CONFIRM_TEXT,
+                None,
+                None,
+            ),
+            "newsletter.published" => (
+                NEWSLETTER_PUBLISHED_SUBJECT,
+                NEWSLETTER_PUBLISHED_HTML,
+                NEWSLETTER_PUBLISHED_TEXT,
+                None,
+                None,
+            ),
+            "digest" => (DIGEST_SUBJECT, DIGEST_HTML, DIGEST_TEXT, None, None),
+            _ => return None,
+        };
+
+        Some(Self {
+            subject: Some(subject.to_string()),
+            html: Some(html.to_string()),
+            text: Some(text.to_string()),
+            telegram: telegram.map(str::to_string),
+            webhook: webhook.map(str::to_string),
+        })
+    }
+
+    /// Reads `<dir>/<event>/<part file>`. Files that do not exist are missing
+    /// parts, not errors.
+    pub async fn from_dir(dir: &Path, event: &str) -> Result<Self, BlogError> {
+        let mut templates = Self::default();
+        for part in Part::ALL {
+            let path = dir.join(event).join(part.file_name());
+            match tokio::fs::read_to_string(&path).await {
+                Ok(source) => *templates.get_mut(part) = Some(source),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(templates.normalize())
+    }
+
+    /// The templates in effect for `event`: the admin's first, then the
+    /// template directory, then the built-in ones. `None` for unknown events.
+    pub async fn load(
+        event: &str,
+        conn: &mut AsyncPgConnection,
+    ) -> Result<Option<Self>, BlogError> {
+        let Some(builtin) = Self::builtin(event) else {
+            return Ok(None);
+        };
+
+        let stored = NotifyTemplate::find_by_event(event, conn)
+            .await?
+            .map(Self::from)
+            .unwrap_or_default();
+        let from_dir = match &CONFIG.notify.template_dir {
+            Some(dir) => Self::from_dir(Path::new(dir), event).await?,
+            None => Self::default(),
+        };
+
+        Ok(Some(stored.or(from_dir).or(builtin)))
+    }
+
+    /// Renders every part there is with `context`, see [`context`].
+    pub fn render(&self, event: &str, context: &Value) -> Result<Rendered, BlogError> {
+        let env = environment();
+        let render = |part: Part| -> Result<Option<String>, BlogError> {
+            match self.get(part) {
+                Some(source) => Ok(Some(
+                    env.template_from_named_str(part.file_name(), source)?
+                        .render(context)?,
+                )),
+                None => Ok(None),
+            }
+        };
+
+        let subject =
+            render(Part::Subject)?.ok_or(anyhow::anyhow!("no subject template for {}", event))?;
+        let text = render(Part::Text)?.ok_or(anyhow::anyhow!("no text template for {}", event))?;
+        let webhook = match render(Part::Webhook)? {
+            Some(body) => Some(serde_json::from_str(&body)?),
+            None => None,
+        };
+
+        Ok(Rendered {
+            // it ends up in a mail header
+            subject: subject.split_whitespace().collect::<Vec<_>>().join(" "),
+            html: render(Part::Html)?,
+            text: text.trim().to_string(),
+            telegram: render(Part::Telegram)?.map(|t| t.trim().to_string()),
+            webhook,
+        })
+    }
+}
+
+impl From<NotifyTemplate> for Templates {
+    fn from(template: NotifyTemplate) -> Self {
+        Self {
+            subject: template.subject,
+            html: template.html,
+            text: template.text,
+            telegram: template.telegram,
+            webhook: template.webhook,
+        }
+        .normalize()
+    }
+}
+
+/// `vars` along with `site_url`, `event` and `now`, which every template gets.
+pub fn context(event: &str, vars: &Value) -> Value {
+    let mut context = json!({
+        "site_url": CONFIG.site_url(),
+        "event": event,
+        "now": Utc::now().timestamp(),
+    });
+    if let (Some(context), Some(vars)) = (context.as_object_mut(), vars.as_object()) {
+        context.extend(vars.clone());
+    }
+
+    context
+}
+
+/// Renders the templates in effect for `event`. Should the admin's or the
+/// directory's be broken, the built-in ones are used rather than losing the
+/// notification.
+pub async fn render(
+    event: &str,
+    vars: &Value,
+    conn: &mut AsyncPgConnection,
+) -> Result<Rendered, BlogError> {
+    let templates = Templates::load(event, conn)
+        .await?
+        .ok_or(anyhow::anyhow!("unknown notify event {}", event))?;
+
+    let context = context(event, vars);
+    match templates.render(event, &context) {
+        Ok(rendered) => Ok(rendered),
+        Err(e) => {
+            warn!(
+                "templates for {} failed, using the built-in ones: {}",
+                event, e
+            );
+            Templates::builtin(event)
+                .unwrap_or_default()
+                .render(event, &context)
+        }
+    }
+}
+
+/// Variables of `event` with made up values, for previews and to check
+/// templates before they are saved. The ones every template gets are in
+/// there too.
+pub fn sample(event: &str) -> Option<Value> {
+    let comment = "Nice post! The part about\n*markdown* [escaping] was_really_useful.";
+    let created = json!({
+        "site_url": "https://example.com",
+        "event": event,
+        "now": 1767225600,
+        "note_title": "Hello, world!",
+        "nickname": "neko",
+        "comment": comment,
+        "excerpt": excerpt(comment, EXCERPT_CHARS),
+        "link": "https://example.com/hello-world#comm-00000000-0000-0000-0000-000000000000",
+        "pending": true,
+    });
+
+    match event {
+        "comm.created" => Some(created),
+        "comm.replied" => {
+            let mut replied = created;
+            replied["pending"] = Value::Bool(false);
+            replied["parent_comment"] = json!("Does this work with <html> too?");
+            replied["parent_excerpt"] = json!("Does this work with <html> too?");
+            Some(replied)
+        }
+        "contact.received" | "contact.reply" => {
+            let message = "Hi! Could I translate your note about <html> & *markdown*?";
+            Some(json!({
+                "site_url": "https://example.com",
+                "event": event,
+                "now": 1767225600,
+                "name": "neko",
+                "email": "neko@example.com",
+                "message": message,
+                "excerpt": excerpt(message, EXCERPT_CHARS),
+                "reply": "Sure, go ahead!\nJust link back to it.",
+            }))
+        }
+        "inbox.draft" => Some(json!({
+            "site_url": "https://example.com",
+            "event": event,
+            "now": 1767225600,
+            "note_title": "Hello, world!",
+            "summary": "The first note of this blog, about <html> & *markdown*.",
+            "short_name": "hello-world",
+            "link": "https://example.com/hello-world",
+            "attachments": ["https://example.com/api/media/00000000-0000-0000-0000-000000000000.png"],
+            "skipped": ["index.html"],
+        })),
+        "newsletter.confirm" => Some(json!({
+            "site_url": "https://example.com",
+            "event": event,
+            "now": 1767225600,
+            "email": "neko@example.com",
+            "confirm_url": "https://example.com/api/newsletter/confirm?email=neko%40example.com&expires=1767830400&token=0000",
+        })),
+        "note.published" | "newsletter.published" => Some(json!({
+            "site_url": "https://example.com",
+            "event": event,
+            "now": 1767225600,
+            "note_title": "Hello, world!",
+            "summary": "The first note of this blog, about <html> & *markdown*.",
+            "link": "https://example.com/hello-world",
+            "fancy_img": "https://example.com/hello-world.png",
+        })),
+        "digest" => Some(json!({
+            "site_url": "https://example.com",
+            "event": event,
+            "now": 1767225600,
+            "period": "weekly",
+            "start": "2025-12-22 08:00 UTC",
+            "end": "2025-12-29 08:00 UTC",
+            "comments": [{
+                "nickname": "neko",
+                "note_title": "Hello, world!",
+                "excerpt": excerpt(comment, EXCERPT_CHARS),
+                "status": "pending",
+                "link": "https://example.com/hello-world#comm-00000000-0000-0000-0000-000000000000",
+            }],
+            "comments_total": 1,
+            "top_notes": [{
+                "title": "Hello, world!",
+                "views": 42,
+                "link": "https://example.com/hello-world",
+            }],
+            "subscribers_new": 3,
+            "subscribers_total": 12,
+            "failed": [{
+                "channel": "telegram",
+                "attempts": 8,
+                "error": "error sending request",
+            }],
+            "failed_total": 1,
+        })),
+        _ => None,
+    }
+}
+
+/// Characters of a comment quoted in `excerpt` variables.
+pub const EXCERPT_CHARS: usize = 200;
+
+/// `text` on a single line, cut after `max_chars` characters.
+pub fn excerpt(text: &str, max_chars: usize) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= max_chars {
+        return line;
+    }
+
+    let mut cut: String = line.chars().take(max_chars).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+    cut
+}
+
+fn environment() -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_trim_blocks(true);
+    env.set_lstrip_blocks(true);
+    env.set_auto_escape_callback(|name| match name.rsplit('.').next() {
+        Some("html") => AutoEscape::Html,
+        Some("json") => AutoEscape::Json,
+        Some("md") => AutoEscape::Custom("markdownv2"),
+        _ => AutoEscape::None,
+    });
+    env.set_formatter(|out, state, value| match state.auto_escape() {
+        AutoEscape::Custom("markdownv2") => {
+            if value.is_undefined() || value.is_none() {
+                Ok(())
+            } else if value.is_safe() {
+                Ok(out.write_str(&value.to_string())?)
+            } else {
+                Ok(out.write_str(&escape_markdown_v2(&value.to_string()))?)
+            }
+        }
+        _ => escape_formatter(out, state, value),
+    });
+    env
+}
+
+/// Escapes everything MarkdownV2 would otherwise take for formatting.
+pub fn escape_markdown_v2(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+const COMM_CREATED_SUBJECT: &str = "New comment on {{ note_title }}";
+
+const COMM_CREATED_TEXT: &str = "\
+{{ nickname }} commented on {{ note_title }}:
+
+{{ comment }}
+{% if pending %}
+
+It is waiting for your approval.
+{% endif %}
+
+{{ link }}
+";
+
+const COMM_CREATED_HTML: &str = "\
+<p>{{ nickname }} commented on <a href=\"{{ link }}\">{{ note_title }}</a>:</p>
+<blockquote style=\"white-space: pre-wrap\">{{ comment }}</blockquote>
+{% if pending %}
+<p>It is waiting for your approval.</p>
+{% endif %}
+";
+
+const COMM_CREATED_TELEGRAM: &str = "\
+*New comment on {{ note_title }}*
+
+{{ nickname }}: {{ excerpt }}
+{% if pending %}
+
+_Waiting for approval_
+{% endif %}
+
+{{ link }}
+";
+
+const COMM_CREATED_WEBHOOK: &str = r#"{
+  "event": {{ event }},
+  "title": {{ "New comment on " ~ note_title }},
+  "nickname": {{ nickname }},
+  "comment": {{ comment }},
+  "pending": {{ pending }},
+  "link": {{ link }},
+  "created_at": {{ now }}
+}
+"#;
+
+const COMM_REPLIED_SUBJECT: &str = "{{ nickname }} replied to your comment on {{ note_title }}";
+
+const COMM_REPLIED_TEXT: &str = "\
+{{ nickname }} replied to your comment on {{ note_title }}:
+
+> {{ parent_excerpt }}
+
+{{ comment }}
+
+{{ link }}
+";
+
+const COMM_REPLIED_HTML: &str = "\
+<p>{{ nickname }} replied to your comment on <a href=\"{{ link }}\">{{ note_title }}</a>:</p>
+<blockquote>{{ parent_excerpt }}</blockquote>
+<p style=\"white-space: pre-wrap\">{{ comment }}</p>
+<p><a href=\"{{ link }}\">Reply</a></p>
+";
+
+const CONTACT_RECEIVED_SUBJECT: &str = "Message from {{ name }}";
+
+const CONTACT_RECEIVED_TEXT: &str = "\
+{{ name }} <{{ email }}> wrote:
+
+{{ message }}
+";
+
+const CONTACT_RECEIVED_HTML: &str = "\
+<p>{{ name }} &lt;<a href=\"mailto:{{ email }}\">{{ email }}</a>&gt; wrote:</p>
+<blockquote style=\"white-space: pre-wrap\">{{ message }}</blockquote>
+";
+
+const CONTACT_RECEIVED_TELEGRAM: &str = "\
+*Message from {{ name }}*
+
+{{ excerpt }}
+
+{{ email }}
+";
+
+const CONTACT_RECEIVED_WEBHOOK: &str = r#"{
+  "event": {{ event }},
+  "title": {{ "Message from " ~ name }},
+  "name": {{ name }},
+  "email": {{ email }},
+  "message": {{ message }},
+  "created_at": {{ now }}
+}
+"#;
+
+const CONTACT_REPLY_SUBJECT: &str = "Re: your message to {{ site_url }}";
+
+const CONTACT_REPLY_TEXT: &str = "\
+Hi {{ name }},
+
+{{ reply }}
+
+> {{ excerpt }}
+";
+
+const CONTACT_REPLY_HTML: &str = "\
+<p>Hi {{ name }},</p>
+<p style=\"white-space: pre-wrap\">{{ reply }}</p>
+<blockquote>{{ excerpt }}</blockquote>
+";
+
+const INBOX_DRAFT_SUBJECT: &str = "Draft saved: {{ note_title }}";
+
+const INBOX_DRAFT_TEXT: &str = "\
+Your mail is now a draft called {{ note_title }}.
+
+{{ summary }}
+
+It goes live at {{ link }} once published.
+{% if attachments %}
+
+Attachments kept:
+{% for url in attachments %}
+{{ url }}
+{% endfor %}
+{% endif %}
+{% if skipped %}
+
+Attachments left out, of a kind the blog does not take:
+{% for name in skipped %}
+{{ name }}
+{% endfor %}
+{% endif %}
+";
+
+const INBOX_DRAFT_HTML: &str = "\
+<p>Your mail is now a draft called <b>{{ note_title }}</b>.</p>
+<p style=\"white-space: pre-wrap\">{{ summary }}</p>
+<p>It goes live at <a href=\"{{ link }}\">{{ link }}</a> once published.</p>
+{% if attachments %}
+<p>Attachments kept:</p>
+<ul>
+{% for url in attachments %}
+<li><a href=\"{{ url }}\">{{ url }}</a></li>
+{% endfor %}
+</ul>
+{% endif %}
+{% if skipped %}
+<p>Attachments left out, of a kind the blog does not take:</p>
+<ul>
+{% for name in skipped %}
+<li>{{ name }}</li>
+{% endfor %}
+</ul>
+{% endif %}
+";
+
+const NOTE_PUBLISHED_SUBJECT: &str = "Published: {{ note_title }}";
+
+const NOTE_PUBLISHED_TEXT: &str = "\
+{{ note_title }} is out.
+
+{{ summary }}
+
+{{ link }}
+";
+
+const NOTE_PUBLISHED_HTML: &str = "\
+<p><a href=\"{{ link }}\">{{ note_title }}</a> is out.</p>
+<p style=\"white-space: pre-wrap\">{{ summary }}</p>
+";
+
+const NOTE_PUBLISHED_TELEGRAM: &str = "\
+*Published: {{ note_title }}*
+
+{{ summary }}
+
+{{ link }}
+";
+
+const NOTE_PUBLISHED_WEBHOOK: &str = r#"{
+  "event": {{ event }},
+  "title": {{ note_title }},
+  "summary": {{ summary }},
+  "link": {{ link }},
+  "published_at": {{ now }}
+}
+"#;
+
+const NEWSLETTER_CONFIRM_SUBJECT: &str = "Confirm your subscription";
+
+const NEWSLETTER_CONFIRM_TEXT: &str = "\
+Someone, hopefully you, asked for a mail whenever {{ site_url }} publishes a new note.
+
+Follow this link to confirm:
+
+{{ confirm_url }}
+
+If it was not you, just ignore this mail.
+";
+
+const NEWSLETTER_CONFIRM_HTML: &str = "\
+<p>Someone, hopefully you, asked for a mail whenever <a href=\"{{ site_url }}\">{{ site_url }}</a> publishes a new note.</p>
+<p><a href=\"{{ confirm_url }}\">Confirm your subscription</a></p>
+<p>If it was not you, just ignore this mail.</p>
+";
+
+const NEWSLETTER_PUBLISHED_SUBJECT: &str = "{{ note_title }}";
+
+const NEWSLETTER_PUBLISHED_TEXT: &str = "\
+{{ note_title }}
+
+{{ summary }}
+
+Read more: {{ link }}
+";
+
+const NEWSLETTER_PUBLISHED_HTML: &str = "\
+<h1><a href=\"{{ link }}\">{{ note_title }}</a></h1>
+{% if fancy_img %}
+<p><img src=\"{{ fancy_img }}\" alt=\"\" style=\"max-width: 100%\"></p>
+{% endif %}
+<p style=\"white-space: pre-wrap\">{{ summary }}</p>
+<p><a href=\"{{ link }}\">Read more</a></p>
+";
+
+const DIGEST_SUBJECT: &str = "Your {{ period }} digest for {{ site_url }}";
+
+const DIGEST_TEXT: &str = "\
+From {{ start }} to {{ end }}.
+
+Comments: {{ comments_total }} new
+{% for comm in comments %}
+- {{ comm.nickname }} on {{ comm.note_title }} ({{ comm.status }}): {{ comm.excerpt }}
+  {{ comm.link }}
+{% endfor %}
+{% if comments_total > comments|length %}
+- and {{ comments_total - comments|length }} more
+{% endif %}
+
+Most viewed notes:
+{% for note in top_notes %}
+- {{ note.title }}, {{ note.views }} views
+  {{ note.link }}
+{% endfor %}
+
+Subscribers: {{ subscribers_new }} new, {{ subscribers_total }} in all
+{% if failed_total %}
+
+Failed notifications: {{ failed_total }}
+{% for message in failed %}
+- {{ message.channel }} after {{ message.attempts }} attempts: {{ message.error }}
+{% endfor %}
+{% endif %}
+";
+
+const DIGEST_HTML: &str = "\
+<p>From {{ start }} to {{ end }}.</p>
+<h2>Comments: {{ comments_total }} new</h2>
+<ul>
+{% for comm in comments %}
+<li>{{ comm.nickname }} on <a href=\"{{ comm.link }}\">{{ comm.note_title }}</a> ({{ comm.status }}): {{ comm.excerpt }}</li>
+{% endfor %}
+{% if comments_total > comments|length %}
+<li>and {{ comments_total - comments|length }} more</li>
+{% endif %}
+</ul>
+<h2>Most viewed notes</h2>
+<ol>
+{% for note in top_notes %}
+<li><a href=\"{{ note.link }}\">{{ note.title }}</a>, {{ note.views }} views</li>
+{% endfor %}
+</ol>
+<h2>Subscribers</h2>
+<p>{{ subscribers_new }} new, {{ subscribers_total }} in all</p>
+{% if failed_total %}
+<h2>Failed notifications: {{ failed_total }}</h2>
+<ul>
+{% for message in failed %}
+<li>{{ message.channel }} after {{ message.attempts }} attempts: {{ message.error }}</li>
+{% endfor %}
+</ul>
+{% endif %}
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_templates_render() {
+        for event in EVENTS {
+            let templates = Templates::builtin(event).unwrap();
+            let rendered = templates.render(event, &sample(event).unwrap()).unwrap();
+            assert!(!rendered.subject.contains('\n'));
+            assert!(rendered.html.is_some());
+        }
+
+        let rendered = Templates::builtin("comm.created")
+            .unwrap()
+            .render("comm.created", &sample("comm.created").unwrap())
+            .unwrap();
+        assert_eq!(rendered.subject, "New comment on Hello, world!");
+        assert!(rendered.text.contains(
+            "was_really_useful.\n\nIt is waiting for your approval.\n\nhttps://example.com/"
+        ));
+        assert!(rendered.html.unwrap().contains("Hello, world!</a>"));
+        assert!(rendered
+            .telegram
+            .unwrap()
+            .starts_with("*New comment on Hello, world\\!*\n\nneko: Nice post\\! The part about \\*markdown\\* \\[escaping\\] was\\_really\\_useful\\."));
+        let webhook = rendered.webhook.unwrap();
+        assert_eq!(webhook["event"], "comm.created");
+        assert_eq!(webhook["pending"], true);
+        assert!(webhook["created_at"].is_i64());
+
+        let mut approved = sample("comm.created").unwrap();
+        approved["pending"] = Value::Bool(false);
+        let rendered = Templates::builtin("comm.created")
+            .unwrap()
+            .render("comm.created", &approved)
+            .unwrap();
+        assert!(rendered
+            .text
+            .ends_with("was_really_useful.\n\nhttps://example.com/hello-world#comm-00000000-0000-0000-0000-000000000000"));
+
+        assert!(Templates::builtin("nya").is_none());
+    }
+
+    #[test]
+    fn test_escaping() {
+        let templates = Templates {
+            subject: Some(String::from("{{ title }}")),
+            html: Some(String::from("<b>{{ title }}</b>{{ raw|safe }}")),
+            text: Some(String::from("{{ title }}")),
+            telegram: Some(String::from("*{{ title }}* {{ raw|safe }}")),
+            webhook: Some(String::from("{\"title\": {{ title }}}")),
+        };
+        let vars = json!({ "title": "a \"b\" <c> 1.5!", "raw": "<i>_x_</i>" });
+
+        let rendered = templates.render("comm.created", &vars).unwrap();
+        assert_eq!(rendered.text, "a \"b\" <c> 1.5!");
+        assert_eq!(
+            rendered.html.unwrap(),
+            "<b>a &quot;b&quot; &lt;c&gt; 1.5!</b><i>_x_</i>"
+        );
+        assert_eq!(
+            rendered.telegram.unwrap(),
+            "*a \"b\" <c\\> 1\\.5\\!* <i>_x_</i>"
+        );
+        assert_eq!(rendered.webhook.unwrap()["title"], "a \"b\" <c> 1.5!");
+    }
+
+    #[test]
+    fn test_broken_templates() {
+        let vars = sample("comm.created").unwrap();
+        let broken = Templates {
+            text: Some(String::from("{% if %}")),
+            ..Templates::builtin("comm.created").unwrap()
+        };
+        assert!(broken.render("comm.created", &vars).is_err());
+
+        let not_json = Templates {
+            webhook: Some(String::from("{{ nickname }}: {{ comment }}")),
+            ..Templates::builtin("comm.created").unwrap()
+        };
+        assert!(not_json.render("comm.created", &vars).is_err());
+
+        let no_subject = Templates {
+            subject: None,
+            ..Templates::builtin("comm.created").unwrap()
+        };
+        assert!(no_subject.render("comm.created", &vars).is_err());
+    }
+
+    #[test]
+    fn test_or_and_normalize() {
+        let admin = Templates {
+            subject: Some(String::from("mine")),
+            text: Some(String::from("  \n")),
+            ..Default::default()
+        }
+        .normalize();
+        let merged = admin.or(Templates::builtin("comm.replied").unwrap());
+
+        assert_eq!(merged.subject.as_deref(), Some("mine"));
+        assert_eq!(merged.text.as_deref(), Some(COMM_REPLIED_TEXT));
+        assert!(merged.telegram.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_from_dir() {
+        let dir = std::env::temp_dir().join(format!("notify-templates-{}", std::process::id()));
+        tokio::fs::create_dir_all(dir.join("comm.created"))
+            .await
+            .unwrap();
+        tokio::fs::write(dir.join("comm.created/subject.txt"), "Psst, {{ nickname }}")
+            .await
+            .unwrap();
+
+        let templates = Templates::from_dir(&dir, "comm.created").await.unwrap();
+        assert_eq!(templates.subject.as_deref(), Some("Psst, {{ nickname }}"));
+        assert!(templates.text.is_none());
+        assert_eq!(
+            Templates::from_dir(&dir, "comm.replied").await.unwrap(),
+            Templates::default()
+        );
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("  short\n\ntext ", 200), "short text");
+        assert_eq!(excerpt("one two three", 7), "one two…");
+        assert_eq!(excerpt("一二三四五", 3), "一二三…");
+    }
+}
@@ -1 +1,390 @@
+use async_trait::async_trait;
+use serde_json::{json, Value};
 
+use crate::{
+    config::{WebhookFormat, WebhookTarget},
+    error::BlogError,
+    utils::URLEncode,
+};
+
+use super::{Message, Notifier};
+
+/// Discord embed limits.
+const DISCORD_TITLE_CHARS: usize = 256;
+const DISCORD_DESCRIPTION_CHARS: usize = 4096;
+/// Slack block limits.
+const SLACK_HEADER_CHARS: usize = 150;
+const SLACK_SECTION_CHARS: usize = 3000;
+
+/// Sends notifications to a webhook target in the format it was configured
+/// with: the rendered webhook template for `json`, or a chat message built
+/// from the subject and text for Discord, Slack and Matrix.
+pub struct WebhookNotifier {
+    client: reqwest::Client,
+    name: String,
+    url: String,
+    format: WebhookFormat,
+    room: Option<String>,
+    token: Option<String>,
+}
+
+impl WebhookNotifier {
+    pub fn new(client: reqwest::Client, target: &WebhookTarget) -> Result<Self, BlogError> {
+        let format = target.format.unwrap_or(WebhookFormat::Json);
+        if format == WebhookFormat::Matrix && (target.room.is_none() || target.token.is_none()) {
+            return Err(anyhow::anyhow!(
+                "webhook target {} needs a room and a token for matrix",
+                target.url
+            )
+            .into());
+        }
+
+        Ok(Self {
+            client,
+            name: target
+                .name
+                .clone()
+                .unwrap_or_else(|| String::from("webhook")),
+            url: target.url.trim_end_matches('/').to_string(),
+            format,
+            room: target.room.clone(),
+            token: target.token.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn accepts(&self, message: &Message) -> bool {
+        match self.format {
+            WebhookFormat::Json => message.content.webhook.is_some(),
+            _ => true,
+        }
+    }
+
+    async fn push(&self, message: &Message) -> Result<(), BlogError> {
+        let request = match self.format {
+            WebhookFormat::Json => {
+                let Some(payload) = &message.content.webhook else {
+                    return Ok(());
+                };
+                self.client.post(&self.url).json(payload)
+            }
+            WebhookFormat::Discord => self.client.post(&self.url).json(&discord_payload(message)),
+            WebhookFormat::Slack => self.client.post(&self.url).json(&slack_payload(message)),
+            WebhookFormat::Matrix => {
+                // the same transaction ID for every attempt, so retries of a
+                // message the homeserver did take are not posted twice, and
+                // a new one for every message, even with the same content
+                let url = format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                    self.url,
+                    self.room.as_deref().unwrap_or_default().encode(),
+                    matrix_txn_id(message)?
+                );
+                self.client
+                    .put(url)
+                    .bearer_auth(self.token.as_deref().unwrap_or_default())
+                    .json(&matrix_payload(message))
+            }
+        };
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// `text` cut to `max_chars` characters, marked with an ellipsis if it was.
+fn clip(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let mut cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+fn discord_payload(message: &Message) -> Value {
+    json!({
+        "embeds": [{
+            "title": clip(&message.content.subject, DISCORD_TITLE_CHARS),
+            "description": clip(&message.content.text, DISCORD_DESCRIPTION_CHARS),
+        }],
+        "allowed_mentions": { "parse": [] },
+    })
+}
+
+/// Slack's mrkdwn only needs `&`, `<` and `>` escaped.
+fn escape_slack(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn slack_payload(message: &Message) -> Value {
+    let subject = &message.content.subject;
+    json!({
+        "text": subject,
+        "blocks": [
+            {
+                "type": "header",
+                "text": {
+                    "type": "plain_text",
+                    "text": clip(subject, SLACK_HEADER_CHARS),
+                },
+            },
+            {
+                "type": "section",
+                "text": {
+                    "type": "mrkdwn",
+                    "text": clip(&escape_slack(&message.content.text), SLACK_SECTION_CHARS),
+                },
+            },
+        ],
+    })
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn matrix_payload(message: &Message) -> Value {
+    let content = &message.content;
+    let html = match &content.html {
+        Some(html) => html.clone(),
+        None => escape_html(&content.text).replace('\n', "<br>"),
+    };
+
+    json!({
+        "msgtype": "m.notice",
+        "body": format!("{}\n\n{}", content.subject, content.text),
+        "format": "org.matrix.custom.html",
+        "formatted_body": format!("<strong>{}</strong><br>\n{}", escape_html(&content.subject), html),
+    })
+}
+
+fn matrix_txn_id(message: &Message) -> Result<String, BlogError> {
+    let id = message
+        .outbox_id
+        .ok_or(anyhow::anyhow!("matrix messages are sent from the outbox"))?;
+    Ok(id.simple().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        extract::Path,
+        http::{HeaderMap, StatusCode},
+        routing::{post, put},
+        Json, Router,
+    };
+    use tokio::{net::TcpListener, sync::mpsc};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::service::notify::{
+        template::{sample, Templates},
+        Recipient,
+    };
+
+    /// A receiver that accepts on `/hook` and on the Matrix send endpoint,
+    /// fails on `/broken` and hands every accepted body to the returned
+    /// channel, along with the Matrix path and authorization if any.
+    async fn mock_receiver() -> (String, mpsc::UnboundedReceiver<(Value, Option<String>)>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let matrix_tx = tx.clone();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(move |Json(body): Json<Value>| {
+                    let tx = tx.clone();
+                    async move {
+                        tx.send((body, None)).unwrap();
+                        StatusCode::NO_CONTENT
+                    }
+                }),
+            )
+            .route(
+                "/_matrix/client/v3/rooms/:room/send/m.room.message/:txn",
+                put(
+                    move |Path((room, txn)): Path<(String, String)>,
+                          headers: HeaderMap,
+                          Json(body): Json<Value>| {
+                        let tx = matrix_tx.clone();
+                        async move {
+                            let auth = headers["authorization"].to_str().unwrap();
+                            tx.send((body, Some(format!("{} {} {}", room, txn, auth))))
+                                .unwrap();
+                            Json(json!({ "event_id": "$nya" }))
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/broken",
+                post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        (base, rx)
+    }
+
+    fn message() -> Message {
+        Message {
+            event: String::from("comm.created"),
+            content: Templates::builtin("comm.created")
+                .unwrap()
+                .render("comm.created", &sample("comm.created").unwrap())
+                .unwrap(),
+            mail_to: Some(Recipient {
+                email: String::from("owner@example.com"),
+                unsubscribe_url: String::from("https://example.com/api/unsubscribe"),
+            }),
+            comm_id: None,
+            outbox_id: Some(Uuid::from_u128(1)),
+        }
+    }
+
+    fn target(url: String, format: WebhookFormat) -> WebhookTarget {
+        WebhookTarget {
+            name: None,
+            url,
+            format: Some(format),
+            room: None,
+            token: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhook_notifier() {
+        let (base, mut received) = mock_receiver().await;
+        let message = message();
+        let client = reqwest::Client::new();
+
+        let notifier = WebhookNotifier::new(
+            client.clone(),
+            &target(format!("{}/hook", base), WebhookFormat::Json),
+        )
+        .unwrap();
+        assert_eq!(notifier.name(), "webhook");
+        assert!(notifier.accepts(&message));
+        notifier.push(&message).await.unwrap();
+
+        let (body, _) = received.recv().await.unwrap();
+        assert_eq!(body["event"], "comm.created");
+        assert_eq!(body["title"], "New comment on Hello, world!");
+        assert_eq!(body["pending"], true);
+        assert!(body["created_at"].is_i64());
+        assert!(!body.to_string().contains("owner@example.com"));
+
+        let notifier = WebhookNotifier::new(
+            client.clone(),
+            &target(format!("{}/broken", base), WebhookFormat::Json),
+        )
+        .unwrap();
+        assert!(notifier.push(&message).await.is_err());
+
+        let notifier = WebhookNotifier::new(
+            client.clone(),
+            &target(format!("{}/hook", base), WebhookFormat::Discord),
+        )
+        .unwrap();
+        notifier.push(&message).await.unwrap();
+        let (body, _) = received.recv().await.unwrap();
+        assert_eq!(body["embeds"][0]["title"], "New comment on Hello, world!");
+        assert!(body["embeds"][0]["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("neko commented on Hello, world!:"));
+
+        let notifier = WebhookNotifier::new(
+            client.clone(),
+            &target(format!("{}/hook", base), WebhookFormat::Slack),
+        )
+        .unwrap();
+        notifier.push(&message).await.unwrap();
+        let (body, _) = received.recv().await.unwrap();
+        assert_eq!(body["text"], "New comment on Hello, world!");
+        assert_eq!(body["blocks"][0]["type"], "header");
+        assert_eq!(body["blocks"][1]["text"]["type"], "mrkdwn");
+
+        let mut matrix = target(format!("{}/", base), WebhookFormat::Matrix);
+        assert!(WebhookNotifier::new(client.clone(), &matrix).is_err());
+        matrix.name = Some(String::from("team"));
+        matrix.room = Some(String::from("!room:example.com"));
+        matrix.token = Some(String::from("secret"));
+        let notifier = WebhookNotifier::new(client, &matrix).unwrap();
+        assert_eq!(notifier.name(), "team");
+        notifier.push(&message).await.unwrap();
+        notifier.push(&message).await.unwrap();
+        // the same content queued again is another message
+        let again = Message {
+            outbox_id: Some(Uuid::from_u128(2)),
+            ..message.clone()
+        };
+        notifier.push(&again).await.unwrap();
+        let (body, first) = received.recv().await.unwrap();
+        let (_, second) = received.recv().await.unwrap();
+        let (_, third) = received.recv().await.unwrap();
+        assert_eq!(body["msgtype"], "m.notice");
+        assert!(body["formatted_body"]
+            .as_str()
+            .unwrap()
+            .starts_with("<strong>New comment on Hello, world!</strong>"));
+        let first = first.unwrap();
+        assert_eq!(
+            first,
+            "!room:example.com 00000000000000000000000000000001 Bearer secret"
+        );
+        assert_eq!(Some(first), second);
+        assert_eq!(
+            third.unwrap(),
+            "!room:example.com 00000000000000000000000000000002 Bearer secret"
+        );
+    }
+
+    #[test]
+    fn test_chat_payloads() {
+        let mut message = message();
+        message.content.text = String::from("<b>&</b>");
+        message.content.html = None;
+
+        let slack = slack_payload(&message);
+        assert_eq!(
+            slack["blocks"][1]["text"]["text"],
+            "&lt;b&gt;&amp;&lt;/b&gt;"
+        );
+
+        let matrix = matrix_payload(&message);
+        assert_eq!(matrix["body"], "New comment on Hello, world!\n\n<b>&</b>");
+        assert!(matrix["formatted_body"]
+            .as_str()
+            .unwrap()
+            .ends_with("&lt;b&gt;&amp;&lt;/b&gt;"));
+
+        assert_eq!(clip("nyanya", 6), "nyanya");
+        assert_eq!(clip("nyanyanya", 6), "nyany…");
+        message.content.subject = "nya".repeat(100);
+        let discord = discord_payload(&message);
+        assert_eq!(
+            discord["embeds"][0]["title"]
+                .as_str()
+                .unwrap()
+                .chars()
+                .count(),
+            DISCORD_TITLE_CHARS
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{error::BlogError, utils::SHUTDOWN};
+
+/// How often a connected reader is marked as still there.
+pub const HEARTBEAT: Duration = Duration::from_secs(20);
+/// A reader not seen for this long is dropped from the counts.
+const EXPIRY: Duration = Duration::from_secs(60);
+/// Readers counted at once, later ones are turned away.
+const MAX_SESSIONS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    /// Readers anywhere on the site
+    pub site: usize,
+    /// Readers of the note or page asked about
+    pub page: Option<usize>,
+}
+
+struct Session {
+    short_id: Option<String>,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Sessions {
+    sessions: HashMap<Uuid, Session>,
+    pages: HashMap<String, usize>,
+}
+
+impl Sessions {
+    fn remove(&mut self, id: &Uuid) -> bool {
+        let Some(session) = self.sessions.remove(id) else {
+            return false;
+        };
+        if let Some(short_id) = session.short_id {
+            if let Some(count) = self.pages.get_mut(&short_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.pages.remove(&short_id);
+                }
+            }
+        }
+        true
+    }
+}
+
+/// Who is reading right now, kept in memory only. Every change wakes the
+/// receivers of [`Online::subscribe`].
+pub struct Online {
+    sessions: Mutex<Sessions>,
+    changed: watch::Sender<()>,
+}
+
+impl Default for Online {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Online {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(Sessions::default()),
+            changed: watch::channel(()).0,
+        }
+    }
+
+    /// Counts a new reader, of `short_id` when given, until it is dropped.
+    pub fn join(self: &Arc<Self>, short_id: Option<String>) -> Result<Presence, BlogError> {
+        let id = Uuid::new_v4();
+        {
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions.sessions.len() >= MAX_SESSIONS {
+                return Err(BlogError::TooManyRequests(String::from(
+                    "too many readers online",
+                )));
+            }
+            if let Some(short_id) = &short_id {
+                *sessions.pages.entry(short_id.clone()).or_default() += 1;
+            }
+            sessions.sessions.insert(
+                id,
+                Session {
+                    short_id,
+                    last_seen: Instant::now(),
+                },
+            );
+        }
+        self.changed.send_modify(|_| {});
+
+        Ok(Presence {
+            online: self.clone(),
+            id,
+        })
+    }
+
+    pub fn counts(&self, short_id: Option<&str>) -> Counts {
+        let sessions = self.sessions.lock().unwrap();
+        Counts {
+            site: sessions.sessions.len(),
+            page: short_id.map(|short_id| sessions.pages.get(short_id).copied().unwrap_or(0)),
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.changed.subscribe()
+    }
+
+    fn touch(&self, id: &Uuid) -> bool {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.sessions.get_mut(id) {
+            Some(session) => {
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn leave(&self, id: &Uuid) {
+        let removed = self.sessions.lock().unwrap().remove(id);
+        if removed {
+            self.changed.send_modify(|_| {});
+        }
+    }
+
+    /// Drops readers not seen since [`EXPIRY`] before `now`. Returns how many.
+    fn expire(&self, now: Instant) -> usize {
+        let expired = {
+            let mut sessions = self.sessions.lock().unwrap();
+            let stale: Vec<Uuid> = sessions
+                .sessions
+                .iter()
+                .filter(|(_, session)| now.saturating_duration_since(session.last_seen) > EXPIRY)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in &stale {
+                sessions.remove(id);
+            }
+            stale.len()
+        };
+        if expired > 0 {
+            self.changed.send_modify(|_| {});
+        }
+
+        expired
+    }
+}
+
+/// One counted reader, who leaves when this is dropped.
+pub struct Presence {
+    online: Arc<Online>,
+    id: Uuid,
+}
+
+impl Presence {
+    /// Marks the reader as still there. `false` once it has expired.
+    pub fn heartbeat(&self) -> bool {
+        self.online.touch(&self.id)
+    }
+}
+
+impl Drop for Presence {
+    fn drop(&mut self) {
+        self.online.leave(&self.id);
+    }
+}
+
+/// Expires readers whose connection went away without being noticed, until
+/// `SHUTDOWN` fires.
+pub async fn run(online: Arc<Online>) {
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(HEARTBEAT) => {}
+        }
+
+        online.expire(Instant::now());
+    }
+
+    info!("online sweeper stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let online = Arc::new(Online::new());
+        let a = online.join(Some(String::from("neko"))).unwrap();
+        let b = online.join(Some(String::from("neko"))).unwrap();
+        let c = online.join(None).unwrap();
+
+        assert_eq!(
+            online.counts(Some("neko")),
+            Counts {
+                site: 3,
+                page: Some(2)
+            }
+        );
+        assert_eq!(online.counts(Some("inu")).page, Some(0));
+        assert_eq!(online.counts(None).page, None);
+
+        drop(a);
+        assert_eq!(online.counts(Some("neko")).page, Some(1));
+        drop(b);
+        drop(c);
+        assert_eq!(online.counts(Some("neko")).site, 0);
+        assert!(online.sessions.lock().unwrap().pages.is_empty());
+    }
+
+    #[test]
+    fn test_expire() {
+        let online = Arc::new(Online::new());
+        let mut changed = online.subscribe();
+        let presence = online.join(Some(String::from("neko"))).unwrap();
+        assert!(changed.has_changed().unwrap());
+        changed.mark_unchanged();
+
+        assert_eq!(online.expire(Instant::now()), 0);
+        assert!(presence.heartbeat());
+        assert_eq!(online.expire(Instant::now() + EXPIRY * 2), 1);
+        assert!(changed.has_changed().unwrap());
+
+        assert!(!presence.heartbeat());
+        assert_eq!(
+            online.counts(Some("neko")),
+            Counts {
+                site: 0,
+                page: Some(0)
+            }
+        );
+    }
+}
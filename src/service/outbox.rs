@@ -0,0 +1,105 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use futures_util::future::join_all;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::CONFIG,
+    db::{models::notify_outbox::OutboxMessage, DbPool},
+    error::BlogError,
+    service::notify::Notify,
+    utils::SHUTDOWN,
+};
+
+/// Messages taken from the outbox per round.
+const BATCH_SIZE: i64 = 50;
+/// How long a claimed message stays hidden from other workers.
+const LEASE_SECS: i64 = 5 * 60;
+/// Longest wait between two attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Delivers queued notifications until `SHUTDOWN` fires. A round that is
+/// under way when it does is finished first, so nothing is left half sent.
+pub async fn run(pool: DbPool, notify: Arc<Notify>) {
+    let poll_interval = Duration::from_secs(CONFIG.notify.poll_interval());
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+
+        if let Err(e) = deliver_due(&pool, &notify).await {
+            error!("outbox round failed: {}", e);
+        }
+    }
+
+    info!("outbox worker stopped");
+}
+
+async fn deliver_due(pool: &DbPool, notify: &Notify) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let messages = OutboxMessage::claim_due(BATCH_SIZE, LEASE_SECS, &mut conn).await?;
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let results = join_all(
+        messages
+            .iter()
+            .map(|message| notify.deliver(&message.id, &message.channel, &message.payload)),
+    )
+    .await;
+
+    let max_attempts = CONFIG.notify.max_attempts();
+    let retry_base = Duration::from_secs(CONFIG.notify.retry_base());
+    for (message, result) in messages.iter().zip(results) {
+        match result {
+            Ok(()) => OutboxMessage::mark_delivered(&message.id, &mut conn).await?,
+            Err(e) => {
+                let attempts = message.attempts + 1;
+                let retry_at = (attempts < max_attempts).then(|| {
+                    let delay = backoff(attempts, retry_base);
+                    Utc::now().naive_utc() + chrono::Duration::from_std(delay).unwrap_or_default()
+                });
+                if retry_at.is_none() {
+                    warn!(
+                        "outbox message {} via {} is dead after {} attempts: {}",
+                        message.id, message.channel, attempts, e
+                    );
+                }
+
+                OutboxMessage::mark_failed(&message.id, &e.to_string(), retry_at, &mut conn)
+                    .await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Wait before the next attempt once `attempts` have failed: `base`, then
+/// doubling each time, up to [`MAX_BACKOFF`].
+pub fn backoff(attempts: i32, base: Duration) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 30) as u32;
+    base.saturating_mul(2u32.pow(exponent)).min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(30);
+
+        assert_eq!(backoff(1, base), Duration::from_secs(30));
+        assert_eq!(backoff(2, base), Duration::from_secs(60));
+        assert_eq!(backoff(4, base), Duration::from_secs(240));
+        assert_eq!(backoff(20, base), MAX_BACKOFF);
+        assert_eq!(backoff(i32::MAX, base), MAX_BACKOFF);
+    }
+}
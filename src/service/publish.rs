@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection, AsyncPgConnection};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    config::CONFIG,
+    db::models::notes::Note,
+    error::BlogError,
+    service::{
+        federation, newsletter,
+        notify::{Notification, Notify},
+        webmention,
+    },
+};
+
+/// Sets off what publishing a note does, once per note: the first time it is
+/// found public. Meant to run after every write that may have made it so.
+/// Returns whether this was that first time. Webmentions and Fediverse
+/// followers are the exception, they hear about every write to a public note
+/// so updates reach them too.
+pub async fn published(
+    note_id: &Uuid,
+    notify: &Arc<Notify>,
+    conn: &mut AsyncPgConnection,
+) -> Result<bool, BlogError> {
+    let notify = notify.clone();
+    let note_id = *note_id;
+
+    conn.transaction::<_, BlogError, _>(|conn| {
+        async move {
+            webmention::enqueue_note(&note_id, conn).await?;
+
+            let first = Note::mark_published(&note_id, conn).await?;
+            federation::enqueue_note(&note_id, first, conn).await?;
+            if !first {
+                return Ok(false);
+            }
+            let Some((note, short_name)) = Note::find_note_with_short_name(&note_id, conn).await?
+            else {
+                return Ok(false);
+            };
+
+            let vars = json!({
+                "note_title": note.title,
+                "summary": note.summary,
+                "link": format!("{}/{}", CONFIG.site_url(), short_name),
+                "fancy_img": note.fancy_img,
+            });
+            let notification = Notification {
+                event: String::from("note.published"),
+                vars: vars.clone(),
+                mail_to: None,
+                comm_id: None,
+            };
+            notify.enqueue(&notification, conn).await?;
+            newsletter::enqueue_note(&vars, &notify, conn).await?;
+
+            Ok(true)
+        }
+        .scope_boxed()
+    })
+    .await
+}
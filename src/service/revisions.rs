@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, TextDiff};
+use utoipa::ToSchema;
+
+/// How two versions of a note are compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMode {
+    /// Lines, as `diff -u` prints them
+    #[default]
+    Unified,
+    /// Words, as runs of kept, added and removed text
+    Words,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// A run of text that is in both versions, or only in one of them.
+#[derive(Debug, PartialEq, Serialize, ToSchema)]
+pub struct Change {
+    pub op: Op,
+    pub text: String,
+}
+
+/// Line by line differences of `old` and `new` in unified format, with three
+/// lines of context. Empty when they are the same.
+pub fn unified(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Word by word differences of `old` and `new`. Neighbouring words with the
+/// same fate are merged into one change, spaces included.
+pub fn words(old: &str, new: &str) -> Vec<Change> {
+    let diff = TextDiff::from_words(old, new);
+    let mut changes: Vec<Change> = Vec::new();
+
+    for change in diff.iter_all_changes() {
+        let op = match change.tag() {
+            ChangeTag::Equal => Op::Equal,
+            ChangeTag::Insert => Op::Insert,
+            ChangeTag::Delete => Op::Delete,
+        };
+        match changes.last_mut() {
+            Some(last) if last.op == op => last.text.push_str(change.value()),
+            _ => changes.push(Change {
+                op,
+                text: change.value().to_string(),
+            }),
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified() {
+        assert_eq!(unified("same\n", "same\n", "a", "b"), "");
+        assert_eq!(
+            unified("one\ntwo\nthree\n", "one\n2\nthree\n", "a", "b"),
+            "--- a\n+++ b\n@@ -1,3 +1,3 @@\n one\n-two\n+2\n three\n"
+        );
+    }
+
+    #[test]
+    fn test_words() {
+        let change = |op, text: &str| Change {
+            op,
+            text: text.to_string(),
+        };
+        assert_eq!(
+            words("the quick brown fox", "the slow brown cat"),
+            vec![
+                change(Op::Equal, "the "),
+                change(Op::Delete, "quick"),
+                change(Op::Insert, "slow"),
+                change(Op::Equal, " brown "),
+                change(Op::Delete, "fox"),
+                change(Op::Insert, "cat"),
+            ]
+        );
+        assert_eq!(words("", ""), vec![]);
+    }
+}
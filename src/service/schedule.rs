@@ -0,0 +1,66 @@
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use diesel_async::{scoped_futures::ScopedFutureExt, AsyncConnection};
+use tracing::{error, info};
+
+use crate::{
+    config::CONFIG,
+    db::{models::notes::Note, DbPool},
+    error::BlogError,
+    service::{notify::Notify, publish},
+    utils::SHUTDOWN,
+};
+
+/// Notes published and taken down per round, each.
+const BATCH_SIZE: i64 = 50;
+
+/// Publishes and takes down notes at the times they are scheduled for until
+/// `SHUTDOWN` fires. Schedules only live in the database, so the first round,
+/// right on start, catches up on whatever fell due while the blog was down.
+pub async fn run(pool: DbPool, notify: Arc<Notify>) {
+    let poll_interval = Duration::from_secs(CONFIG.schedule.poll_interval());
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        if let Err(e) = apply_due(&pool, &notify).await {
+            error!("note schedule round failed: {}", e);
+        }
+
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+    }
+
+    info!("note scheduler stopped");
+}
+
+/// Moves the notes that are due and sets off what a manual publish or
+/// unpublish would: feeds, webhooks, newsletters and followers. Both happen
+/// in one transaction, so a round that fails leaves the notes due for the
+/// next one instead of moved without anyone hearing of it.
+async fn apply_due(pool: &DbPool, notify: &Arc<Notify>) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let notify = notify.clone();
+    let moved = conn
+        .transaction::<_, BlogError, _>(|conn| {
+            async move {
+                let moved =
+                    Note::apply_due_schedules(Utc::now().naive_utc(), BATCH_SIZE, conn).await?;
+                for id in &moved {
+                    publish::published(id, &notify, conn).await?;
+                }
+
+                Ok(moved)
+            }
+            .scope_boxed()
+        })
+        .await?;
+    if !moved.is_empty() {
+        info!("{} scheduled notes moved", moved.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,722 @@
+use std::{collections::HashSet, time::Duration};
+
+use chrono::Utc;
+use diesel_async::AsyncPgConnection;
+use futures_util::future::join_all;
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{html, Parser};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE, LINK},
+    Client, ClientBuilder, StatusCode, Url,
+};
+use scraper::{ElementRef, Html, Selector};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::CONFIG,
+    db::{
+        models::{
+            notes::{Note, Status},
+            webmention_sends::WebmentionSend,
+            webmentions::{MentionDetails, Webmention},
+        },
+        DbPool,
+    },
+    error::BlogError,
+    service::{
+        notify::template::{excerpt, EXCERPT_CHARS},
+        outbox::backoff,
+    },
+    utils::{public_net, SHUTDOWN},
+};
+
+/// Mentions verified or sent per round.
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed mention stays hidden from other workers.
+const LEASE_SECS: i64 = 5 * 60;
+/// Bytes of a fetched page looked at, the rest is ignored.
+const MAX_PAGE_BYTES: usize = 1 << 20;
+/// Links of a note mentioned at most.
+const MAX_TARGETS: usize = 100;
+/// Longest URL kept, as in the `webmentions` columns.
+const MAX_URL_LEN: usize = 2048;
+
+/// A page as fetched, cut at [`MAX_PAGE_BYTES`].
+struct Page {
+    /// Where it ended up after redirects
+    url: Url,
+    status: StatusCode,
+    links: Vec<String>,
+    html: Option<String>,
+}
+
+/// How verifying a mention went, short of errors worth another try.
+#[derive(Debug)]
+pub enum Checked {
+    Links(MentionDetails),
+    /// The source is gone or does not link to the target (anymore)
+    NoLink(String),
+}
+
+/// How sending a mention went.
+#[derive(Debug)]
+pub enum Sent {
+    /// The target does not take webmentions
+    NoEndpoint,
+    Accepted(Url, i32),
+    Failed(Option<i32>, String),
+}
+
+pub fn client() -> Result<Client, BlogError> {
+    Ok(builder().build()?)
+}
+
+fn builder() -> ClientBuilder {
+    public_net::public_only(Client::builder())
+        .timeout(Duration::from_secs(10))
+        .user_agent(concat!(
+            "tsuiiblog/",
+            env!("CARGO_PKG_VERSION"),
+            " webmention"
+        ))
+}
+
+fn is_http(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// `url` without its fragment or a trailing slash, which do not make it
+/// another page.
+fn page_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.as_str().trim_end_matches('/').to_string()
+}
+
+/// Short name of the note or page `target` points at, if it is one of ours.
+pub fn short_name(target: &Url, site: &Url) -> Option<String> {
+    if target.origin() != site.origin() {
+        return None;
+    }
+
+    let rest = target
+        .path()
+        .strip_prefix(site.path().trim_end_matches('/'))?
+        .trim_matches('/');
+    if rest.is_empty() || rest.contains('/') {
+        return None;
+    }
+
+    percent_decode_str(rest)
+        .decode_utf8()
+        .ok()
+        .map(|name| name.into_owned())
+}
+
+/// Links of an HTML page, resolved against `base`.
+fn links(html: &str, base: &Url) -> Vec<Url> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("a[href]").unwrap();
+
+    document
+        .select(&selector)
+        .filter_map(|a| base.join(a.value().attr("href")?.trim()).ok())
+        .filter(is_http)
+        .collect()
+}
+
+/// Pages elsewhere a note links to, in the order they first appear.
+pub fn outgoing_links(markdown: &str, site: &Url) -> Vec<String> {
+    let mut html = String::new();
+    html::push_html(&mut html, Parser::new(markdown));
+
+    let mut seen = HashSet::new();
+    links(&html, site)
+        .into_iter()
+        .filter(|url| url.origin() != site.origin())
+        .map(|url| url.to_string())
+        .filter(|url| url.len() <= MAX_URL_LEN && seen.insert(url.clone()))
+        .take(MAX_TARGETS)
+        .collect()
+}
+
+fn has_rel(rel: &str, name: &str) -> bool {
+    rel.split_whitespace().any(|r| r.eq_ignore_ascii_case(name))
+}
+
+/// Webmention endpoint in `Link` header values, resolved against `base`.
+fn endpoint_from_links(values: &[String], base: &Url) -> Option<Url> {
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let (target, params) = link.trim().strip_prefix('<')?.split_once('>')?;
+            let webmention = params.split(';').any(|param| {
+                param
+                    .trim()
+                    .strip_prefix("rel=")
+                    .is_some_and(|rel| has_rel(rel.trim_matches('"'), "webmention"))
+            });
+            webmention.then(|| base.join(target.trim()).ok()).flatten()
+        })
+}
+
+/// Webmention endpoint in `<link>` or `<a>` tags, the first one there is.
+fn endpoint_from_html(html: &str, base: &Url) -> Option<Url> {
+    let document = Html::parse_document(html);
+    let selector = Selector::parse("link[rel][href], a[rel][href]").unwrap();
+
+    document
+        .select(&selector)
+        .find(|element| {
+            has_rel(
+                element.value().attr("rel").unwrap_or_default(),
+                "webmention",
+            )
+        })
+        .and_then(|element| base.join(element.value().attr("href")?.trim()).ok())
+}
+
+fn text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn clip(text: String, max_chars: usize) -> Option<String> {
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.chars().take(max_chars).collect())
+}
+
+/// What can be told about the mention from the source page: an `h-entry`
+/// with its author and content where it has one, its title otherwise.
+fn details(html: &str, base: &Url) -> MentionDetails {
+    let document = Html::parse_document(html);
+    let first = |selectors: &str| document.select(&Selector::parse(selectors).unwrap()).next();
+
+    let title = first(".h-entry .p-name")
+        .or_else(|| first("title"))
+        .and_then(|e| clip(text(e), 512));
+    let author = first(".h-entry .p-author");
+    let author_name = author
+        .and_then(|a| {
+            a.select(&Selector::parse(".p-name").unwrap())
+                .next()
+                .or(Some(a))
+        })
+        .and_then(|e| clip(text(e), 256));
+    let author_url = author
+        .and_then(|a| {
+            a.value().attr("href").or_else(|| {
+                a.select(&Selector::parse(".u-url[href]").unwrap())
+                    .next()?
+                    .value()
+                    .attr("href")
+            })
+        })
+        .and_then(|href| base.join(href.trim()).ok())
+        .filter(|url| is_http(url) && url.as_str().len() <= MAX_URL_LEN)
+        .map(|url| url.to_string());
+    let content = first(".h-entry .e-content")
+        .or_else(|| first(".h-entry .p-summary"))
+        .map(|e| excerpt(&text(e), EXCERPT_CHARS))
+        .filter(|content| !content.is_empty());
+
+    MentionDetails {
+        author_name,
+        author_url,
+        title,
+        content,
+    }
+}
+
+async fn fetch(client: &Client, url: &Url) -> Result<Page, BlogError> {
+    if !public_net::is_public(url) {
+        return Err(anyhow::anyhow!("{} is not a public http url", url).into());
+    }
+
+    let mut response = client
+        .get(url.clone())
+        .header(ACCEPT, "text/html")
+        .send()
+        .await?;
+
+    let url = response.url().clone();
+    let status = response.status();
+    let links = response
+        .headers()
+        .get_all(LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::to_string)
+        .collect();
+    let is_html = match response.headers().get(CONTENT_TYPE) {
+        Some(value) => value.to_str().is_ok_and(|value| value.contains("html")),
+        None => true,
+    };
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_PAGE_BYTES {
+            body.truncate(MAX_PAGE_BYTES);
+            break;
+        }
+    }
+
+    Ok(Page {
+        url,
+        status,
+        links,
+        html: is_html.then(|| String::from_utf8_lossy(&body).into_owned()),
+    })
+}
+
+/// Checks that `source` links to `target`. Errors are worth another try,
+/// the source may be down for now.
+pub async fn verify(client: &Client, source: &Url, target: &Url) -> Result<Checked, BlogError> {
+    let page = fetch(client, source).await?;
+    if page.status.is_server_error() || page.status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(anyhow::anyhow!("source answered {}", page.status).into());
+    }
+    if !page.status.is_success() {
+        return Ok(Checked::NoLink(format!("source answered {}", page.status)));
+    }
+    let Some(html) = page.html else {
+        return Ok(Checked::NoLink(String::from("source is not HTML")));
+    };
+
+    let target = page_key(target);
+    if !links(&html, &page.url)
+        .iter()
+        .any(|link| page_key(link) == target)
+    {
+        return Ok(Checked::NoLink(String::from(
+            "source does not link to target",
+        )));
+    }
+
+    Ok(Checked::Links(details(&html, &page.url)))
+}
+
+/// Tells `target` that `source` mentions it, if it has an endpoint for that.
+pub async fn send(client: &Client, source: &Url, target: &Url) -> Sent {
+    let page = match fetch(client, target).await {
+        Ok(page) => page,
+        Err(e) => return Sent::Failed(None, e.to_string()),
+    };
+    let endpoint = endpoint_from_links(&page.links, &page.url).or_else(|| {
+        page.html
+            .as_deref()
+            .and_then(|html| endpoint_from_html(html, &page.url))
+    });
+    let Some(endpoint) = endpoint.filter(is_http) else {
+        return Sent::NoEndpoint;
+    };
+    if !public_net::is_public(&endpoint) {
+        return Sent::Failed(None, format!("{} is not a public http url", endpoint));
+    }
+
+    let response = client
+        .post(endpoint.clone())
+        .form(&[("source", source.as_str()), ("target", target.as_str())])
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            Sent::Accepted(endpoint, response.status().as_u16() as i32)
+        }
+        Ok(response) => {
+            let status = response.status();
+            Sent::Failed(
+                Some(status.as_u16() as i32),
+                format!("endpoint answered {}", status),
+            )
+        }
+        Err(e) => Sent::Failed(None, e.to_string()),
+    }
+}
+
+/// Queues webmentions for what a public note links to, and for what it
+/// linked to before so those pages see the link is gone.
+pub async fn enqueue_note(
+    note_id: &uuid::Uuid,
+    conn: &mut AsyncPgConnection,
+) -> Result<(), BlogError> {
+    let Some((note, _)) = Note::find_note_with_short_name(note_id, conn).await? else {
+        return Ok(());
+    };
+    if note.status != Status::Public {
+        return Ok(());
+    }
+    let site = Url::parse(&CONFIG.site_url()).map_err(|e| anyhow::anyhow!(e))?;
+
+    let mut targets = outgoing_links(&note.content, &site);
+    for target in WebmentionSend::get_targets(&note.id, conn).await? {
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    WebmentionSend::enqueue(&note.id, &targets, conn).await
+}
+
+/// Verifies received mentions and sends ours until `SHUTDOWN` fires, retrying
+/// failures with the same backoff as notifications.
+pub async fn run(pool: DbPool) {
+    let client = match client() {
+        Ok(client) => client,
+        Err(e) => {
+            error!("webmention worker not started: {}", e);
+            return;
+        }
+    };
+    let poll_interval = Duration::from_secs(CONFIG.notify.poll_interval());
+    let shutdown = SHUTDOWN.wait_for_shutdown();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = tokio::time::sleep(poll_interval) => {}
+        }
+
+        if let Err(e) = verify_due(&pool, &client).await {
+            error!("webmention verify round failed: {}", e);
+        }
+        if let Err(e) = send_due(&pool, &client).await {
+            error!("webmention send round failed: {}", e);
+        }
+    }
+
+    info!("webmention worker stopped");
+}
+
+fn retry_at(attempts: i32) -> Option<chrono::NaiveDateTime> {
+    (attempts < CONFIG.notify.max_attempts()).then(|| {
+        let delay = backoff(attempts, Duration::from_secs(CONFIG.notify.retry_base()));
+        Utc::now().naive_utc() + chrono::Duration::from_std(delay).unwrap_or_default()
+    })
+}
+
+async fn verify_due(pool: &DbPool, client: &Client) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let mentions = Webmention::claim_due(BATCH_SIZE, LEASE_SECS, &mut conn).await?;
+    if mentions.is_empty() {
+        return Ok(());
+    }
+
+    let results = join_all(mentions.iter().map(|mention| async move {
+        let source = Url::parse(&mention.source).map_err(|e| anyhow::anyhow!(e))?;
+        let target = Url::parse(&mention.target).map_err(|e| anyhow::anyhow!(e))?;
+        verify(client, &source, &target).await
+    }))
+    .await;
+
+    for (mention, result) in mentions.iter().zip(results) {
+        match result {
+            Ok(Checked::Links(details)) => {
+                Webmention::mark_verified(&mention.id, &details, &mut conn).await?
+            }
+            Ok(Checked::NoLink(reason)) => {
+                Webmention::mark_failed(&mention.id, &reason, None, &mut conn).await?
+            }
+            Err(e) => {
+                let retry_at = retry_at(mention.attempts + 1);
+                if retry_at.is_none() {
+                    warn!(
+                        "webmention from {} rejected after {} attempts: {}",
+                        mention.source,
+                        mention.attempts + 1,
+                        e
+                    );
+                }
+                Webmention::mark_failed(&mention.id, &e.to_string(), retry_at, &mut conn).await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn send_due(pool: &DbPool, client: &Client) -> Result<(), BlogError> {
+    let mut conn = pool.get_owned().await?;
+    let sends = WebmentionSend::claim_due(BATCH_SIZE, LEASE_SECS, &mut conn).await?;
+    if sends.is_empty() {
+        return Ok(());
+    }
+
+    let mut sources = Vec::with_capacity(sends.len());
+    for send in &sends {
+        let source = Note::find_note_with_short_name(&send.note_id, &mut conn)
+            .await?
+            .and_then(|(_, short_name)| {
+                Url::parse(&format!("{}/{}", CONFIG.site_url(), short_name)).ok()
+            });
+        sources.push(source);
+    }
+
+    let results = join_all(sends.iter().zip(&sources).map(|(send, source)| async move {
+        let (Some(source), Ok(target)) = (source, Url::parse(&send.target)) else {
+            return Sent::Failed(None, String::from("invalid source or target"));
+        };
+        self::send(client, source, &target).await
+    }))
+    .await;
+
+    for (send, result) in sends.iter().zip(results) {
+        match result {
+            Sent::NoEndpoint => WebmentionSend::mark_sent(&send.id, None, None, &mut conn).await?,
+            Sent::Accepted(endpoint, status) => {
+                WebmentionSend::mark_sent(
+                    &send.id,
+                    Some(endpoint.as_str()),
+                    Some(status),
+                    &mut conn,
+                )
+                .await?
+            }
+            Sent::Failed(status, e) => {
+                let retry_at = retry_at(send.attempts + 1);
+                if retry_at.is_none() {
+                    warn!(
+                        "webmention to {} given up after {} attempts: {}",
+                        send.target,
+                        send.attempts + 1,
+                        e
+                    );
+                }
+                WebmentionSend::mark_failed(&send.id, status, &e, retry_at, &mut conn).await?
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{header, StatusCode as AxumStatus},
+        response::Redirect,
+        routing::{get, post},
+        Form, Router,
+    };
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn test_short_name() {
+        let site = Url::parse("https://example.com/blog/").unwrap();
+        let name = |target: &str| short_name(&Url::parse(target).unwrap(), &site);
+
+        assert_eq!(name("https://example.com/blog/hello").unwrap(), "hello");
+        assert_eq!(
+            name("https://example.com/blog/hello/?a=1#c").unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            name("https://example.com/blog/%E3%81%AD%E3%81%93").unwrap(),
+            "ねこ"
+        );
+        assert!(name("https://example.com/blog/").is_none());
+        assert!(name("https://example.com/blog/a/b").is_none());
+        assert!(name("https://example.com/other").is_none());
+        assert!(name("https://example.org/blog/hello").is_none());
+        assert!(name("http://example.com/blog/hello").is_none());
+    }
+
+    #[test]
+    fn test_outgoing_links() {
+        let site = Url::parse("https://example.com/").unwrap();
+        let markdown = "See [one](https://example.org/a), [two](https://example.org/a), \
+            [ours](/hello), [mail](mailto:neko@example.org) and <https://example.net/b>.\n\n\
+            [ref]: https://example.org/unused";
+
+        assert_eq!(
+            outgoing_links(markdown, &site),
+            vec!["https://example.org/a", "https://example.net/b"]
+        );
+    }
+
+    #[test]
+    fn test_endpoint_from_links() {
+        let base = Url::parse("https://example.org/post").unwrap();
+        let values = vec![
+            String::from(r#"<https://example.org/style.css>; rel="stylesheet""#),
+            String::from(r#"</hub>; rel="hub", </mention?x=1>; rel="webmention other""#),
+        ];
+
+        assert_eq!(
+            endpoint_from_links(&values, &base).unwrap().as_str(),
+            "https://example.org/mention?x=1"
+        );
+        assert!(endpoint_from_links(&values[..1], &base).is_none());
+    }
+
+    #[test]
+    fn test_endpoint_from_html() {
+        let base = Url::parse("https://example.org/post/").unwrap();
+        let html = r#"<html><head><link rel="stylesheet" href="/s.css">
+            <link rel="Webmention" href="mention"></head>
+            <body><a rel="webmention" href="/other">x</a></body></html>"#;
+
+        assert_eq!(
+            endpoint_from_html(html, &base).unwrap().as_str(),
+            "https://example.org/post/mention"
+        );
+        assert!(endpoint_from_html("<a href='/mention'>x</a>", &base).is_none());
+    }
+
+    #[test]
+    fn test_details() {
+        let base = Url::parse("https://example.org/reply").unwrap();
+        let html = r#"<html><head><title>Page title</title></head><body>
+            <article class="h-entry">
+              <h1 class="p-name">Re:   hello</h1>
+              <a class="p-author h-card" href="/neko">Neko</a>
+              <div class="e-content"><p>Nice <b>post</b>!</p></div>
+            </article></body></html>"#;
+
+        assert_eq!(
+            details(html, &base),
+            MentionDetails {
+                author_name: Some(String::from("Neko")),
+                author_url: Some(String::from("https://example.org/neko")),
+                title: Some(String::from("Re: hello")),
+                content: Some(String::from("Nice post!")),
+            }
+        );
+        assert_eq!(
+            details("<title>Just a page</title>", &base),
+            MentionDetails {
+                title: Some(String::from("Just a page")),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_and_send() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let app = Router::new()
+            .route(
+                "/reply",
+                get(|| async {
+                    (
+                        [(header::CONTENT_TYPE, "text/html")],
+                        r#"<p class="h-entry"><a href="https://example.com/hello/#c">hi</a></p>"#,
+                    )
+                }),
+            )
+            .route(
+                "/plain",
+                get(|| async { ([(header::CONTENT_TYPE, "text/html")], "<p>hi</p>") }),
+            )
+            .route(
+                "/away",
+                get(|| async { Redirect::temporary("http://127.0.0.1/reply") }),
+            )
+            .route("/gone", get(|| async { AxumStatus::GONE }))
+            .route("/down", get(|| async { AxumStatus::SERVICE_UNAVAILABLE }))
+            .route(
+                "/header",
+                get(|| async { [(header::LINK, r#"</mention>; rel="webmention""#)] }),
+            )
+            .route(
+                "/local",
+                get(|| async {
+                    [(
+                        header::LINK,
+                        r#"<http://127.0.0.1/mention>; rel="webmention""#,
+                    )]
+                }),
+            )
+            .route(
+                "/html",
+                get(|| async {
+                    (
+                        [(header::CONTENT_TYPE, "text/html")],
+                        r#"<link rel="webmention" href="/mention">"#,
+                    )
+                }),
+            )
+            .route(
+                "/mention",
+                post(move |Form(form): Form<Vec<(String, String)>>| async move {
+                    tx.send(form).unwrap();
+                    AxumStatus::ACCEPTED
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // pinned, the guarded resolver would not give out a loopback address
+        let client = builder().resolve("mention.test", addr).build().unwrap();
+        let url = |path: &str| {
+            Url::parse(&format!("http://mention.test:{}{}", addr.port(), path)).unwrap()
+        };
+        let target = Url::parse("https://example.com/hello").unwrap();
+
+        assert!(matches!(
+            verify(&client, &url("/reply"), &target).await.unwrap(),
+            Checked::Links(_)
+        ));
+        assert!(matches!(
+            verify(&client, &url("/plain"), &target).await.unwrap(),
+            Checked::NoLink(_)
+        ));
+        assert!(matches!(
+            verify(&client, &url("/gone"), &target).await.unwrap(),
+            Checked::NoLink(_)
+        ));
+        assert!(verify(&client, &url("/down"), &target).await.is_err());
+
+        // the blog's own network is out of reach, however it is asked for
+        for source in [
+            format!("http://{}/reply", addr),
+            format!("http://localhost:{}/reply", addr.port()),
+            url("/away").to_string(),
+        ] {
+            let source = Url::parse(&source).unwrap();
+            assert!(
+                verify(&client, &source, &target).await.is_err(),
+                "{}",
+                source
+            );
+        }
+
+        let source = Url::parse("https://example.com/hello").unwrap();
+        for path in ["/header", "/html"] {
+            match send(&client, &source, &url(path)).await {
+                Sent::Accepted(endpoint, status) => {
+                    assert_eq!(endpoint, url("/mention"));
+                    assert_eq!(status, 202);
+                }
+                sent => panic!("unexpected {:?}", sent),
+            }
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                vec![
+                    (String::from("source"), source.to_string()),
+                    (String::from("target"), url(path).to_string()),
+                ]
+            );
+        }
+        assert!(matches!(
+            send(&client, &source, &url("/plain")).await,
+            Sent::NoEndpoint
+        ));
+        assert!(matches!(
+            send(&client, &source, &url("/local")).await,
+            Sent::Failed(None, _)
+        ));
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::NaiveDateTime;
+use roxmltree::{Document, Node};
+
+/// XML-RPC values. Strings are also what untyped values are read as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i32),
+    Bool(bool),
+    Double(f64),
+    String(String),
+    DateTime(NaiveDateTime),
+    Base64(Vec<u8>),
+    Struct(BTreeMap<String, Value>),
+    Array(Vec<Value>),
+    Nil,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Call {
+    pub method: String,
+    pub params: Vec<Value>,
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            Value::Int(i) => Some(*i != 0),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Base64(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Member of a struct.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(members) => members.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn object<'a>(members: impl IntoIterator<Item = (&'a str, Value)>) -> Self {
+        Value::Struct(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    fn write(&self, out: &mut String) {
+        out.push_str("<value>");
+        match self {
+            Value::Int(i) => out.push_str(&format!("<int>{}</int>", i)),
+            Value::Bool(b) => out.push_str(&format!("<boolean>{}</boolean>", u8::from(*b))),
+            Value::Double(d) => out.push_str(&format!("<double>{}</double>", d)),
+            Value::String(s) => out.push_str(&format!("<string>{}</string>", escape(s))),
+            Value::DateTime(time) => out.push_str(&format!(
+                "<dateTime.iso8601>{}</dateTime.iso8601>",
+                time.format("%Y%m%dT%H:%M:%S")
+            )),
+            Value::Base64(data) => {
+                out.push_str(&format!("<base64>{}</base64>", STANDARD.encode(data)))
+            }
+            Value::Struct(members) => {
+                out.push_str("<struct>");
+                for (name, value) in members {
+                    out.push_str(&format!("<member><name>{}</name>", escape(name)));
+                    value.write(out);
+                    out.push_str("</member>");
+                }
+                out.push_str("</struct>");
+            }
+            Value::Array(values) => {
+                out.push_str("<array><data>");
+                for value in values {
+                    value.write(out);
+                }
+                out.push_str("</data></array>");
+            }
+            Value::Nil => out.push_str("<nil/>"),
+        }
+        out.push_str("</value>");
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|child| child.is_element() && child.has_tag_name(name))
+}
+
+fn elements<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(Node::is_element)
+}
+
+fn text(node: Node) -> String {
+    node.children()
+        .filter(Node::is_text)
+        .filter_map(|child| child.text())
+        .collect()
+}
+
+fn parse_time(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim().trim_end_matches('Z');
+    ["%Y%m%dT%H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y%m%dT%H%M%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+}
+
+fn parse_value(value: Node) -> Result<Value, String> {
+    let Some(typed) = elements(value).next() else {
+        return Ok(Value::String(text(value)));
+    };
+
+    let invalid = || format!("invalid {}", typed.tag_name().name());
+    let parsed = match typed.tag_name().name() {
+        "i4" | "int" => Value::Int(text(typed).trim().parse().map_err(|_| invalid())?),
+        "boolean" => match text(typed).trim() {
+            "1" => Value::Bool(true),
+            "0" => Value::Bool(false),
+            _ => return Err(invalid()),
+        },
+        "double" => Value::Double(text(typed).trim().parse().map_err(|_| invalid())?),
+        "string" => Value::String(text(typed)),
+        "dateTime.iso8601" => Value::DateTime(parse_time(&text(typed)).ok_or_else(invalid)?),
+        "base64" => {
+            let encoded: String = text(typed).split_whitespace().collect();
+            Value::Base64(STANDARD.decode(encoded).map_err(|_| invalid())?)
+        }
+        "struct" => {
+            let mut members = BTreeMap::new();
+            for member in elements(typed).filter(|node| node.has_tag_name("member")) {
+                let name = child(member, "name").ok_or_else(invalid)?;
+                let value = child(member, "value").ok_or_else(invalid)?;
+                members.insert(text(name), parse_value(value)?);
+            }
+            Value::Struct(members)
+        }
+        "array" => {
+            let data = child(typed, "data").ok_or_else(invalid)?;
+            Value::Array(
+                elements(data)
+                    .filter(|node| node.has_tag_name("value"))
+                    .map(parse_value)
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+        "nil" => Value::Nil,
+        name => return Err(format!("unknown type {}", name)),
+    };
+
+    Ok(parsed)
+}
+
+/// Reads a `methodCall` document.
+pub fn parse_call(xml: &str) -> Result<Call, String> {
+    let document = Document::parse(xml).map_err(|e| e.to_string())?;
+    let root = document.root_element();
+    if !root.has_tag_name("methodCall") {
+        return Err(String::from("not a methodCall"));
+    }
+
+    let method = child(root, "methodName")
+        .map(text)
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or("missing methodName")?;
+
+    let params = match child(root, "params") {
+        Some(params) => elements(params)
+            .filter(|node| node.has_tag_name("param"))
+            .map(|param| {
+                child(param, "value")
+                    .ok_or_else(|| String::from("param without value"))
+                    .and_then(parse_value)
+            })
+            .collect::<Result<_, _>>()?,
+        None => Vec::new(),
+    };
+
+    Ok(Call { method, params })
+}
+
+/// A `methodResponse` carrying `value`.
+pub fn response(value: &Value) -> String {
+    let mut out =
+        String::from(r#"<?xml version="1.0" encoding="UTF-8"?><methodResponse><params><param>"#);
+    value.write(&mut out);
+    out.push_str("</param></params></methodResponse>");
+    out
+}
+
+/// A `methodResponse` carrying a fault.
+pub fn fault(code: i32, message: &str) -> String {
+    let mut out = String::from(r#"<?xml version="1.0" encoding="UTF-8"?><methodResponse><fault>"#);
+    Value::object([
+        ("faultCode", Value::Int(code)),
+        ("faultString", Value::from(message)),
+    ])
+    .write(&mut out);
+    out.push_str("</fault></methodResponse>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_call() {
+        let call = parse_call(
+            r#"<?xml version="1.0"?>
+            <methodCall>
+              <methodName>metaWeblog.newPost</methodName>
+              <params>
+                <param><value>1</value></param>
+                <param><value><string>neko</string></value></param>
+                <param><value><i4>42</i4></value></param>
+                <param><value><struct>
+                  <member><name>title</name><value>Hello &amp; nya</value></member>
+                  <member><name>categories</name><value><array><data>
+                    <value>cat</value><value><string>life</string></value>
+                  </data></array></value></member>
+                  <member><name>dateCreated</name>
+                    <value><dateTime.iso8601>20240101T12:30:00</dateTime.iso8601></value>
+                  </member>
+                  <member><name>bits</name><value><base64>bmVr
+                  bw==</base64></value></member>
+                </struct></value></param>
+                <param><value><boolean>1</boolean></value></param>
+              </params>
+            </methodCall>"#,
+        )
+        .unwrap();
+
+        assert_eq!(call.method, "metaWeblog.newPost");
+        assert_eq!(call.params.len(), 5);
+        assert_eq!(call.params[0].as_str(), Some("1"));
+        assert_eq!(call.params[1].as_str(), Some("neko"));
+        assert_eq!(call.params[2].as_i32(), Some(42));
+        assert_eq!(call.params[4].as_bool(), Some(true));
+
+        let post = &call.params[3];
+        assert_eq!(
+            post.get("title").and_then(Value::as_str),
+            Some("Hello & nya")
+        );
+        assert_eq!(
+            post.get("categories").and_then(Value::as_array),
+            Some(&[Value::from("cat"), Value::from("life")][..])
+        );
+        assert_eq!(
+            post.get("dateCreated"),
+            Some(&Value::DateTime(
+                NaiveDateTime::parse_from_str("2024-01-01 12:30:00", "%Y-%m-%d %H:%M:%S").unwrap()
+            ))
+        );
+        assert_eq!(
+            post.get("bits").and_then(Value::as_bytes),
+            Some(&b"neko"[..])
+        );
+    }
+
+    #[test]
+    fn test_parse_call_rejects() {
+        assert!(parse_call("<methodResponse/>").is_err());
+        assert!(parse_call("<methodCall><params/></methodCall>").is_err());
+        assert!(parse_call(
+            "<methodCall><methodName>x</methodName><params><param><value><i4>x</i4></value></param></params></methodCall>"
+        )
+        .is_err());
+        assert!(parse_call(
+            r#"<!DOCTYPE lol [<!ENTITY lol "lol">]><methodCall><methodName>&lol;</methodName></methodCall>"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let value = Value::object([
+            ("postid", Value::from("1")),
+            ("title", Value::from("<Hello>")),
+            ("publish", Value::Bool(true)),
+            ("count", Value::Int(3)),
+            ("categories", Value::Array(vec![Value::from("cat")])),
+        ]);
+        let xml = response(&value);
+        assert!(xml.contains("<string>&lt;Hello&gt;</string>"));
+
+        let document = Document::parse(&xml).unwrap();
+        let param = document
+            .descendants()
+            .find(|node| node.has_tag_name("param"))
+            .unwrap();
+        assert_eq!(parse_value(child(param, "value").unwrap()).unwrap(), value);
+
+        let xml = fault(403, "invalid token");
+        assert!(xml.contains("<name>faultCode</name><value><int>403</int></value>"));
+        assert!(xml.contains("<string>invalid token</string>"));
+    }
+}
@@ -0,0 +1,56 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+
+use crate::config::CONFIG;
+
+/// Best-effort address of the client that sent the request.
+///
+/// Proxy headers are only honoured when `antispam.trustproxy` is set, since
+/// anyone can send them when the blog is exposed directly.
+pub struct ClientIp(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if CONFIG.antispam.trust_proxy() {
+            if let Some(ip) = forwarded_ip(&parts.headers) {
+                return Ok(Self(ip));
+            }
+        }
+
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_else(|| String::from("unknown"));
+
+        Ok(Self(ip))
+    }
+}
+
+fn forwarded_ip(headers: &HeaderMap) -> Option<String> {
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+
+    let real_ip = headers
+        .get("x-real-ip")
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty());
+
+    forwarded_for.or(real_ip).map(String::from)
+}
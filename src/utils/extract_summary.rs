@@ -1,8 +1,221 @@
-pub fn extract_summary(content: &str, max_length: usize) -> String {
-    if content.len() <= max_length {
-        content.to_string()
-    } else {
-        let summary: String = content.chars().take(max_length).collect();
-        summary + "..."
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+
+/// Marks the end of the summary in the content, e.g. `<!--more-->`.
+const MORE: &str = "more";
+/// Length summaries taken from the content are cut near.
+const SUMMARY_CHARS: usize = 40;
+/// Ends of sentences a summary may be cut after.
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '。', '！', '？'];
+
+/// The summary of a note: its plain text up to a `<!--more-->` marker, if
+/// there is one, or else cut after a sentence or word near `max_chars`
+/// characters.
+pub fn extract_summary(content: &str, max_chars: usize) -> String {
+    if let Some(summary) = before_more(content) {
+        return plain_text(summary);
+    }
+
+    cut(&plain_text(content), max_chars)
+}
+
+/// The summary a note is saved with: the one given, or else one taken from
+/// the content. On an edit, `stored` is the summary and content before it,
+/// and a summary written by hand there stays unless another one is given.
+/// Getting the stored summary back, as editors that read it first do, is
+/// the same as getting none.
+pub fn summary_of(given: Option<&str>, content: &str, stored: Option<(&str, &str)>) -> String {
+    let given = given.map(str::trim).filter(|given| !given.is_empty());
+    if let Some((summary, stored_content)) = stored {
+        if given.is_none() || given == Some(summary.trim()) {
+            if is_custom_summary(summary, stored_content) {
+                return summary.to_string();
+            }
+            return extract_summary(content, SUMMARY_CHARS);
+        }
+    }
+
+    match given {
+        Some(given) => given.to_string(),
+        None => extract_summary(content, SUMMARY_CHARS),
+    }
+}
+
+/// Whether `summary` was written by hand rather than taken from `content`.
+pub fn is_custom_summary(summary: &str, content: &str) -> bool {
+    summary != extract_summary(content, SUMMARY_CHARS)
+}
+
+/// What comes before the first `<!--more-->`, spaces and case aside. Only
+/// HTML counts, so the marker shown in code is just text.
+fn before_more(content: &str) -> Option<&str> {
+    Parser::new_ext(content, options())
+        .into_offset_iter()
+        .find_map(|(event, range)| match event {
+            Event::Html(_) | Event::InlineHtml(_) => {
+                more_at(&content[range.clone()]).map(|at| &content[..range.start + at])
+            }
+            _ => None,
+        })
+}
+
+/// Where the first `<!--more-->` starts in some HTML.
+fn more_at(html: &str) -> Option<usize> {
+    html.match_indices("<!--").find_map(|(start, _)| {
+        let inner = &html[start + 4..];
+        let end = inner.find("-->")?;
+        inner[..end]
+            .trim()
+            .eq_ignore_ascii_case(MORE)
+            .then_some(start)
+    })
+}
+
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES
+}
+
+/// The words of some Markdown on one line, without markup, code blocks,
+/// images, HTML or footnotes.
+fn plain_text(markdown: &str) -> String {
+    let mut text = String::new();
+    let mut skip = 0;
+
+    for event in Parser::new_ext(markdown, options()) {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::Image { .. } | Tag::FootnoteDefinition(_)) => {
+                skip += 1
+            }
+            Event::End(TagEnd::CodeBlock | TagEnd::Image | TagEnd::FootnoteDefinition) => {
+                skip -= 1
+            }
+            _ if skip > 0 => {}
+            Event::Text(t) | Event::Code(t) => text.push_str(&t),
+            Event::SoftBreak
+            | Event::HardBreak
+            | Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::Item
+                | TagEnd::BlockQuote
+                | TagEnd::TableCell,
+            ) => text.push(' '),
+            _ => {}
+        }
+    }
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Scripts written without spaces between words, where any character
+/// boundary is a word boundary.
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303f}'   // CJK punctuation
+        | '\u{3040}'..='\u{30ff}' // kana
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{ff00}'..='\u{ffef}' // full width forms
+    )
+}
+
+/// `text` cut to at most `max_chars` characters. The cut goes after the
+/// last sentence that ends past half of that, or else at the last word
+/// boundary, where `...` is added.
+fn cut(text: &str, max_chars: usize) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    if chars.len() <= max_chars {
+        return text.to_string();
+    }
+
+    let sentence = (max_chars / 2..max_chars).rev().find(|&i| {
+        let next = chars[i + 1];
+        SENTENCE_ENDS.contains(&chars[i]) && (next.is_whitespace() || is_cjk(chars[i]))
+    });
+    if let Some(end) = sentence {
+        return chars[..=end].iter().collect();
+    }
+
+    let word = (1..=max_chars)
+        .rev()
+        .find(|&i| chars[i].is_whitespace() || is_cjk(chars[i - 1]) || is_cjk(chars[i]))
+        .unwrap_or(max_chars);
+    let summary: String = chars[..word].iter().collect();
+    summary.trim_end().to_string() + "..."
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_more_marker() {
+        assert_eq!(
+            extract_summary("# Hi\n\nSome **bold** [link](https://example.com).\n\n<!-- More -->\n\nRest", 5),
+            "Hi Some bold link."
+        );
+        assert_eq!(
+            extract_summary("Short. <!--less--> Still short.", 40),
+            "Short. Still short."
+        );
+    }
+
+    #[test]
+    fn test_more_marker_in_code() {
+        assert_eq!(
+            extract_summary("Cut with `<!--more-->` anywhere.\n\nMore.", 40),
+            "Cut with <!--more--> anywhere. More."
+        );
+        assert_eq!(
+            extract_summary(
+                "Like so:\n\n```html\n<!--more-->\n```\n\nDone. <!--more--> Rest",
+                40
+            ),
+            "Like so: Done."
+        );
+    }
+
+    #[test]
+    fn test_summary_of() {
+        assert_eq!(summary_of(Some(" Mine "), "Nya.", None), "Mine");
+        assert_eq!(summary_of(Some(""), "Nya.", None), "Nya.");
+        assert_eq!(summary_of(None, "Nya.", None), "Nya.");
+
+        // a summary taken from the old content follows the new one
+        let stored = Some(("Old.", "Old."));
+        assert_eq!(summary_of(None, "New.", stored), "New.");
+        assert_eq!(summary_of(Some("Old."), "New.", stored), "New.");
+        assert_eq!(summary_of(Some("Mine"), "New.", stored), "Mine");
+
+        // one written by hand stays
+        let stored = Some(("Mine", "Old."));
+        assert_eq!(summary_of(None, "New.", stored), "Mine");
+        assert_eq!(summary_of(Some("Mine"), "New.", stored), "Mine");
+        assert_eq!(summary_of(Some("Other"), "New.", stored), "Other");
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(
+            plain_text("## Title\n\n- `code` and *em*[^1]\n- ![cat](cat.png) two\n\n```rust\nfn x() {}\n```\n\nEnd<br>\n\n[^1]: Note"),
+            "Title code and em two End"
+        );
+    }
+
+    #[test]
+    fn test_cut() {
+        assert_eq!(cut("short", 40), "short");
+        assert_eq!(
+            cut("The first sentence. The second one is much longer.", 30),
+            "The first sentence."
+        );
+        assert_eq!(cut("Hello wonderful world", 12), "Hello...");
+        assert_eq!(cut("Supercalifragilistic", 5), "Super...");
+        assert_eq!(cut("猫が好きです。犬も好きです。", 10), "猫が好きです。");
+        assert_eq!(cut("猫が好きです犬も好きです", 5), "猫が好きで...");
+        assert_eq!(cut("ねこねこ ねこ", 4), "ねこねこ...");
     }
 }
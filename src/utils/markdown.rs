@@ -0,0 +1,224 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Options, Parser, Tag, TagEnd};
+use syntect::{
+    html::{ClassStyle, ClassedHTMLGenerator},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// Prefix of the classes highlighted code is marked up with. Any syntect
+/// theme turned into CSS with the same prefix styles it.
+pub const HIGHLIGHT_PREFIX: &str = "hl-";
+/// Prefix of footnote IDs, so notes cannot clobber IDs of the page around
+/// them.
+const FOOTNOTE_PREFIX: &str = "fn-";
+/// Classes that survive sanitizing, by prefix. Any other is dropped.
+const CLASS_PREFIXES: &[&str] = &[HIGHLIGHT_PREFIX, "language-", "footnote-"];
+const ALIGNMENTS: &[&str] = &[
+    "text-align: left",
+    "text-align: center",
+    "text-align: right",
+];
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+
+/// Keeps what Markdown renders to, along with the marks we add to it, and
+/// drops scripts, event handlers, `javascript:` links and the like.
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::default();
+    builder
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .add_tag_attributes("div", ["id"])
+        .add_tag_attributes("th", ["style"])
+        .add_tag_attributes("td", ["style"])
+        .add_generic_attributes(["class"])
+        .attribute_filter(|element, attribute, value| match (element, attribute) {
+            (_, "class") => {
+                let classes = value
+                    .split_whitespace()
+                    .filter(|class| CLASS_PREFIXES.iter().any(|p| class.starts_with(p)))
+                    .collect::<Vec<_>>();
+                (!classes.is_empty()).then(|| Cow::Owned(classes.join(" ")))
+            }
+            ("div", "id") => value.starts_with(FOOTNOTE_PREFIX).then_some(value.into()),
+            ("input", "type") => (value == "checkbox").then_some(value.into()),
+            (_, "style") => ALIGNMENTS.contains(&value).then_some(value.into()),
+            _ => Some(value.into()),
+        });
+    builder
+});
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn footnote_id(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join("-");
+    format!("{}{}", FOOTNOTE_PREFIX, escape_html(&name))
+}
+
+/// A code block, highlighted when its language is one we know.
+fn highlight(lang: &str, code: &str) -> String {
+    let plain = || match lang {
+        "" => format!("<pre><code>{}</code></pre>\n", escape_html(code)),
+        lang => format!(
+            "<pre><code class=\"language-{}\">{}</code></pre>\n",
+            escape_html(lang),
+            escape_html(code)
+        ),
+    };
+    let Some(syntax) = SYNTAXES.find_syntax_by_token(lang).filter(|_| !lang.is_empty()) else {
+        return plain();
+    };
+
+    let mut generator = ClassedHTMLGenerator::new_with_class_style(
+        syntax,
+        &SYNTAXES,
+        ClassStyle::SpacedPrefixed {
+            prefix: HIGHLIGHT_PREFIX,
+        },
+    );
+    for line in LinesWithEndings::from(code) {
+        if generator
+            .parse_html_for_line_which_includes_newline(line)
+            .is_err()
+        {
+            return plain();
+        }
+    }
+
+    format!(
+        "<pre class=\"{}code\"><code class=\"language-{}\">{}</code></pre>\n",
+        HIGHLIGHT_PREFIX,
+        escape_html(lang),
+        generator.finalize()
+    )
+}
+
+/// Renders CommonMark with GFM tables, task lists, strikethrough and
+/// footnotes to HTML that is safe to put on a page as is.
+pub fn render(content: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_FOOTNOTES;
+
+    // footnotes are numbered in the order they are first mentioned
+    let mut footnotes = HashMap::new();
+    let mut number = |name: &str| {
+        let next = footnotes.len() + 1;
+        *footnotes.entry(name.to_string()).or_insert(next)
+    };
+    let mut code: Option<(String, String)> = None;
+    let mut events = Vec::new();
+
+    for event in Parser::new_ext(content, options) {
+        match event {
+            Event::Start(Tag::CodeBlock(kind)) => {
+                let lang = match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().unwrap_or_default().to_string()
+                    }
+                    CodeBlockKind::Indented => String::new(),
+                };
+                code = Some((lang, String::new()));
+            }
+            Event::Text(text) if code.is_some() => {
+                if let Some((_, block)) = &mut code {
+                    block.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((lang, block)) = code.take() {
+                    events.push(Event::Html(CowStr::from(highlight(&lang, &block))));
+                }
+            }
+            Event::FootnoteReference(name) => {
+                events.push(Event::InlineHtml(CowStr::from(format!(
+                    "<sup class=\"footnote-reference\"><a href=\"#{}\">{}</a></sup>",
+                    footnote_id(&name),
+                    number(&name)
+                ))));
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => {
+                events.push(Event::Html(CowStr::from(format!(
+                    "<div class=\"footnote-definition\" id=\"{}\"><sup class=\"footnote-definition-label\">{}</sup>",
+                    footnote_id(&name),
+                    number(&name)
+                ))));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                events.push(Event::Html(CowStr::from("</div>\n")));
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, events.into_iter());
+
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("# Hello\n\n*nya* ~~no~~"),
+            "<h1>Hello</h1>\n<p><em>nya</em> <del>no</del></p>\n"
+        );
+
+        let table = render("| a | b |\n|:--|--:|\n| 1 | 2 |");
+        assert!(table.contains("<th style=\"text-align: left\">a</th>"));
+        assert!(table.contains("<td style=\"text-align: right\">2</td>"));
+
+        let tasks = render("- [x] done\n- [ ] todo");
+        assert!(tasks.contains("<input disabled=\"\" type=\"checkbox\" checked=\"\">"));
+        assert!(tasks.contains("<input disabled=\"\" type=\"checkbox\">"));
+
+        let footnotes = render("Nya[^cat].\n\n[^cat]: A cat.");
+        assert!(footnotes.contains("<a href=\"#fn-cat\" rel=\"noopener noreferrer\">1</a>"));
+        assert!(footnotes.contains("<div class=\"footnote-definition\" id=\"fn-cat\">"));
+    }
+
+    #[test]
+    fn test_highlight() {
+        let html = render("```rust\nfn main() {}\n```");
+        assert!(html.starts_with("<pre class=\"hl-code\"><code class=\"language-rust\">"));
+        assert!(html.contains("<span class=\"hl-"));
+        assert!(html.contains("main"));
+
+        assert!(render("```nya\n<b>\n```")
+            .starts_with("<pre><code class=\"language-nya\">&lt;b&gt;"));
+        assert!(render("    <b>").starts_with("<pre><code>&lt;b&gt;"));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let html = render(
+            "<script>alert(1)</script>\n\n\
+             [x](javascript:alert(1)) <img src=\"x.png\" onerror=\"alert(1)\"> \
+             <span class=\"evil hl-keyword\" style=\"position: fixed\">y</span> \
+             <div id=\"main\">z</div> <input type=\"text\">",
+        );
+        assert!(!html.contains("script"));
+        assert!(!html.contains("javascript"));
+        assert!(!html.contains("onerror"));
+        assert!(!html.contains("evil"));
+        assert!(!html.contains("position"));
+        assert!(!html.contains("main"));
+        assert!(!html.contains("text"));
+        assert!(html.contains("<img src=\"x.png\">"));
+        assert!(html.contains("<span class=\"hl-keyword\">y</span>"));
+    }
+}
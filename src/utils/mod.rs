@@ -5,5 +5,10 @@ pub use urlencode::URLEncode;
 mod rand_str;
 pub use rand_str::generate_random_string;
 mod extract_summary;
-pub use extract_summary::extract_summary;
+pub use extract_summary::{extract_summary, is_custom_summary, summary_of};
+mod client_ip;
 pub mod jwt;
+pub mod markdown;
+pub mod public_net;
+pub mod sign;
+pub use client_ip::ClientIp;
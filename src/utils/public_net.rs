@@ -0,0 +1,195 @@
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, ClientBuilder, Url,
+};
+
+/// Redirects followed at most, as many as reqwest follows by default.
+const MAX_REDIRECTS: usize = 10;
+
+/// Whether `ip` can be reached from anywhere on the internet, unlike
+/// loopback, private, link-local and other special-purpose addresses.
+pub fn is_global(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_global_v4(&ip),
+            None => is_global_v6(ip),
+        },
+    }
+}
+
+fn is_global_v4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // this network, 0.0.0.0/8
+        || a == 0
+        // shared address space, 100.64.0.0/10
+        || (a == 100 && (b & 0xc0) == 64)
+        // protocol assignments, 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // benchmarking, 198.18.0.0/15
+        || (a == 198 && (b & 0xfe) == 18)
+        // reserved, 240.0.0.0/4
+        || a >= 240)
+}
+
+fn is_global_v6(ip: &Ipv6Addr) -> bool {
+    let s = ip.segments();
+
+    // NAT64 (64:ff9b::/96) and 6to4 (2002::/16) carry an IPv4 address
+    let embedded = match s {
+        [0x64, 0xff9b, 0, 0, 0, 0, hi, lo] => Some((hi, lo)),
+        [0x2002, hi, lo, ..] => Some((hi, lo)),
+        _ => None,
+    };
+    if let Some((hi, lo)) = embedded {
+        return is_global_v4(&Ipv4Addr::from((u32::from(hi) << 16) | u32::from(lo)));
+    }
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // IPv4-compatible, ::/96
+        || s[..6] == [0; 6]
+        // unique local, fc00::/7
+        || (s[0] & 0xfe00) == 0xfc00
+        // link-local and site-local, fe80::/10 and fec0::/10
+        || (s[0] & 0xff80) == 0xfe80
+        // documentation, 2001:db8::/32
+        || (s[0] == 0x2001 && s[1] == 0x0db8))
+}
+
+/// Whether `url` is http(s) and, if its host is an address, a global one.
+/// Names are checked as they resolve, by a client built with
+/// [`public_only`].
+pub fn is_public(url: &Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    match url.host_str() {
+        Some(host) => match host.trim_start_matches('[').trim_end_matches(']').parse() {
+            Ok(ip) => is_global(&ip),
+            Err(_) => true,
+        },
+        None => false,
+    }
+}
+
+/// The system resolver, leaving out addresses that are not global. A name
+/// with none left fails to resolve.
+struct GlobalResolver;
+
+impl Resolve for GlobalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(resolve_global(name))
+    }
+}
+
+async fn resolve_global(name: Name) -> Result<Addrs, Box<dyn Error + Send + Sync>> {
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+        .await?
+        .filter(|addr| is_global(&addr.ip()))
+        .collect();
+    if addrs.is_empty() {
+        return Err(format!("{} has no public address", name.as_str()).into());
+    }
+
+    Ok(Box::new(addrs.into_iter()))
+}
+
+/// Keeps a client for URLs others hand us off the blog's own network: names
+/// only resolve to global addresses, and redirects only go to public URLs.
+/// A URL with an address for a host is not resolved, check it with
+/// [`is_public`] before asking.
+pub fn public_only(builder: ClientBuilder) -> ClientBuilder {
+    builder
+        .dns_resolver(Arc::new(GlobalResolver))
+        .redirect(redirect::Policy::custom(|attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !is_public(attempt.url()) {
+                let error = format!("redirected to {}, which is not public", attempt.url());
+                attempt.error(error)
+            } else {
+                attempt.follow()
+            }
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_global() {
+        for ip in [
+            "1.1.1.1",
+            "93.184.216.34",
+            "100.128.0.1",
+            "2606:4700:4700::1111",
+            "::ffff:1.1.1.1",
+            "64:ff9b::101:101",
+        ] {
+            assert!(is_global(&ip.parse().unwrap()), "{}", ip);
+        }
+
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "127.0.0.1",
+            "10.0.0.1",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "192.0.0.1",
+            "198.18.0.1",
+            "192.0.2.1",
+            "224.0.0.1",
+            "255.255.255.255",
+            "::",
+            "::1",
+            "::127.0.0.1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+            "2002:7f00:1::",
+            "fc00::1",
+            "fd12:3456::1",
+            "fe80::1",
+            "ff02::1",
+            "2001:db8::1",
+        ] {
+            assert!(!is_global(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_is_public() {
+        let public = |url: &str| is_public(&Url::parse(url).unwrap());
+
+        assert!(public("https://example.com/users/neko"));
+        assert!(public("http://1.1.1.1/"));
+        assert!(public("https://[2606:4700:4700::1111]/"));
+        assert!(!public("http://127.0.0.1:8080/"));
+        assert!(!public("http://[::1]/"));
+        assert!(!public("http://[::ffff:10.0.0.1]/"));
+        assert!(!public("http://169.254.169.254/latest/meta-data/"));
+        assert!(!public("ftp://example.com/"));
+        assert!(!public("file:///etc/passwd"));
+    }
+}
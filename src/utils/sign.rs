@@ -0,0 +1,67 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::CONFIG;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs `data` with the server secret, returning a hex HMAC-SHA256.
+pub fn sign(data: &str) -> String {
+    sign_with(secret(), data)
+}
+
+/// Checks a signature produced by [`sign`] in constant time.
+pub fn verify(data: &str, signature: &str) -> bool {
+    verify_with(secret(), data, signature)
+}
+
+pub fn sign_with(key: &[u8], data: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_with(key: &[u8], data: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
+    mac.update(data.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn secret() -> &'static [u8] {
+    CONFIG.web.jwt_secret.as_ref().unwrap().as_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = b"nekonya";
+        let payload = "1700000000.16.nyanyanyanyanyany";
+        let signature = sign_with(key, payload);
+
+        assert_eq!(signature.len(), 64);
+        assert!(verify_with(key, payload, &signature));
+        assert!(!verify_with(
+            key,
+            "1700000000.8.nyanyanyanyanyany",
+            &signature
+        ));
+        assert!(!verify_with(b"wanwan", payload, &signature));
+        assert!(!verify_with(key, payload, "not hex"));
+    }
+
+    #[test]
+    fn test_unsubscribe_payloads() {
+        let key = b"nekonya";
+        let signature = sign_with(key, "unsubscribe:neko@example.com");
+
+        assert!(verify_with(key, "unsubscribe:neko@example.com", &signature));
+        assert!(!verify_with(key, "unsubscribe:inu@example.com", &signature));
+    }
+}